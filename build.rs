@@ -35,6 +35,49 @@ fn compile_asm_helpers() {
         .compile("asm_helper");
 }
 
+/// Collect the files of the pmu-events tree rooted at `dir` with paths relative to `root`.
+fn collect_event_files(root: &std::path::Path, dir: &std::path::Path, files: &mut Vec<String>) {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)
+        .expect("Unable to read pmu-events tree")
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_event_files(root, &path, files);
+        } else if matches!(path.extension(), Some(e) if e == "json")
+            || matches!(path.file_name(), Some(f) if f == "mapfile.csv")
+        {
+            let rel = path.strip_prefix(root).unwrap();
+            files.push(rel.to_str().unwrap().replace('\\', "/"));
+        }
+    }
+}
+
+/// Embed the pmu-events tree pointed to by `PMU_EVENTS` into the library.
+fn embed_pmu_events() {
+    println!("cargo:rerun-if-env-changed=PMU_EVENTS");
+    let root = std::path::PathBuf::from(
+        std::env::var("PMU_EVENTS").expect("PMU_EVENTS must point to the pmu-events tree to embed"),
+    );
+    println!("cargo:rerun-if-changed={}", root.display());
+    let mut files = Vec::new();
+    collect_event_files(&root, &root, &mut files);
+    let mut out = String::from("pub(crate) static EMBEDDED_EVENTS: &[(&str, &str)] = &[\n");
+    for f in files.iter() {
+        let full_path = std::fs::canonicalize(root.join(f)).unwrap();
+        out.push_str(&format!(
+            "    ({:?}, include_str!({:?})),\n",
+            f,
+            full_path.to_str().unwrap()
+        ));
+    }
+    out.push_str("];\n");
+    let out_path = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    std::fs::write(out_path.join("embedded_events.rs"), out).expect("Couldn't write events!");
+}
+
 fn main() {
     // Generate bindings for headers listed in kernel-wrapper.h.
     if cfg!(target_os = "linux") {
@@ -43,4 +86,9 @@ fn main() {
 
     // Compile asm helpers file into the rust library.
     compile_asm_helpers();
+
+    // Embed the pmu-events JSON files into the rust library.
+    if std::env::var("CARGO_FEATURE_EMBED_EVENTS").is_ok() {
+        embed_pmu_events();
+    }
 }
@@ -31,7 +31,7 @@ fn main() -> perf_utils::Result<()> {
         .pop()
         .unwrap()
         .to_perf_event_attr(Some(&pmu.events))?;
-    let mut events = PerfEvent::build()
+    let mut group = PerfEvent::build()
         .set_period(100)
         .enable_sampling()
         .start_disabled()
@@ -40,22 +40,18 @@ fn main() -> perf_utils::Result<()> {
 
     for run in 0..2 {
         // Start counting
-        for evt in events.iter() {
-            evt.reset()?;
-            evt.enable()?;
-        }
+        group.reset()?;
+        group.enable()?;
 
         // Workload
         info!("Starting workload. Run {}.", run);
         fibonacci(10);
 
         // Stop counting
-        for evt in events.iter() {
-            evt.disable()?;
-        }
+        group.disable()?;
 
         // Read counters
-        for evt in events.iter_mut() {
+        for evt in group.iter_mut() {
             let samples: Vec<String> = evt
                 .read_samples()
                 .iter()
@@ -22,6 +22,21 @@ pub trait Counter<V> {
 
     /// Read the latest value of the counter.
     fn read_sync(&self) -> Result<V>;
+
+    /// Measure the execution of `f`.
+    ///
+    /// The counter is reset and enabled before calling `f`, and disabled and read afterwards.
+    /// Returns the result of `f` and the value of the counter.
+    fn measure<R>(&self, f: impl FnOnce() -> R) -> Result<(R, V)>
+    where
+        Self: Sized,
+    {
+        self.reset()?;
+        self.enable()?;
+        let res = f();
+        self.disable()?;
+        Ok((res, self.read_sync()?))
+    }
 }
 
 /// A generic sampled performance counter.
@@ -49,6 +64,9 @@ where
     Self: SampledCounter<V>,
 {
     /// Read the latest value of the counter.
+    ///
+    /// Fails with `Error::DirectReadUnavailable` if the counter cannot be read without a system
+    /// call, in which case it has to be read through `Counter` instead.
     fn read_direct(&self) -> Result<V>;
 }
 
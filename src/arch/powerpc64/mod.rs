@@ -2,3 +2,6 @@
 
 mod pvr_cpustr;
 pub use pvr_cpustr::get_cpu_string;
+
+mod perf_regs;
+pub use perf_regs::*;
@@ -0,0 +1,59 @@
+//! Register layout used by perf when sampling registers.
+
+/// Indices of the registers in the register dumps of sampled perf events.
+///
+/// See `arch/powerpc/include/uapi/asm/perf_regs.h` in the Linux kernel source for details.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types, missing_docs)]
+pub enum PerfRegister {
+    PERF_REG_POWERPC_R0 = 0,
+    PERF_REG_POWERPC_R1 = 1,
+    PERF_REG_POWERPC_R2 = 2,
+    PERF_REG_POWERPC_R3 = 3,
+    PERF_REG_POWERPC_R4 = 4,
+    PERF_REG_POWERPC_R5 = 5,
+    PERF_REG_POWERPC_R6 = 6,
+    PERF_REG_POWERPC_R7 = 7,
+    PERF_REG_POWERPC_R8 = 8,
+    PERF_REG_POWERPC_R9 = 9,
+    PERF_REG_POWERPC_R10 = 10,
+    PERF_REG_POWERPC_R11 = 11,
+    PERF_REG_POWERPC_R12 = 12,
+    PERF_REG_POWERPC_R13 = 13,
+    PERF_REG_POWERPC_R14 = 14,
+    PERF_REG_POWERPC_R15 = 15,
+    PERF_REG_POWERPC_R16 = 16,
+    PERF_REG_POWERPC_R17 = 17,
+    PERF_REG_POWERPC_R18 = 18,
+    PERF_REG_POWERPC_R19 = 19,
+    PERF_REG_POWERPC_R20 = 20,
+    PERF_REG_POWERPC_R21 = 21,
+    PERF_REG_POWERPC_R22 = 22,
+    PERF_REG_POWERPC_R23 = 23,
+    PERF_REG_POWERPC_R24 = 24,
+    PERF_REG_POWERPC_R25 = 25,
+    PERF_REG_POWERPC_R26 = 26,
+    PERF_REG_POWERPC_R27 = 27,
+    PERF_REG_POWERPC_R28 = 28,
+    PERF_REG_POWERPC_R29 = 29,
+    PERF_REG_POWERPC_R30 = 30,
+    PERF_REG_POWERPC_R31 = 31,
+    PERF_REG_POWERPC_NIP = 32,
+    PERF_REG_POWERPC_MSR = 33,
+    PERF_REG_POWERPC_ORIG_R3 = 34,
+    PERF_REG_POWERPC_CTR = 35,
+    PERF_REG_POWERPC_LINK = 36,
+    PERF_REG_POWERPC_XER = 37,
+    PERF_REG_POWERPC_CCR = 38,
+    PERF_REG_POWERPC_SOFTE = 39,
+    PERF_REG_POWERPC_TRAP = 40,
+    PERF_REG_POWERPC_DAR = 41,
+    PERF_REG_POWERPC_DSISR = 42,
+    PERF_REG_POWERPC_SIER = 43,
+    PERF_REG_POWERPC_MMCRA = 44,
+}
+
+/// Default mask of user registers to sample, e.g., for user-space unwinding.
+pub const SAMPLE_REGS_USER_DEFAULT: u64 =
+    (1 << (PerfRegister::PERF_REG_POWERPC_MMCRA as u64 + 1)) - 1;
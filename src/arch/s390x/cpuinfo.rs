@@ -0,0 +1,108 @@
+//! Utilities to identify the machine from the files in `/proc`.
+
+use log::debug;
+
+/// Get the machine type, e.g., `3906`, from the contents of `/proc/cpuinfo`.
+///
+/// Older kernels list it as `machine = 3906` in the line of each processor, newer ones also in a
+/// separate `machine type` line.
+fn _machine_type(cpuinfo: &str) -> Option<&str> {
+    cpuinfo.lines().find_map(|l| {
+        if let Some((key, val)) = l.split_once(':') {
+            if key.trim() == "machine type" {
+                return Some(val.trim());
+            }
+        }
+        let idx = l.find("machine = ")?;
+        l[idx + "machine = ".len()..].split_whitespace().next()
+    })
+}
+
+/// Get the model capacity identifier and the model, e.g., `("704", "M03")`, from the contents of
+/// `/proc/sysinfo`.
+fn _model(sysinfo: &str) -> Option<(&str, &str)> {
+    let model = sysinfo
+        .lines()
+        .find_map(|l| l.strip_prefix("Model:"))?
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    match model.as_slice() {
+        [capacity, model] => Some((capacity, model)),
+        [model] => Some(("", model)),
+        _ => None,
+    }
+}
+
+/// Get the version and authorization of the counter facility, e.g., `3.5.2f`, from the contents
+/// of `/proc/service_levels`.
+fn _counter_facility(service_levels: &str) -> Option<String> {
+    let line = service_levels
+        .lines()
+        .find_map(|l| l.strip_prefix("CPU-MF: Counter facility:"))?;
+    let mut version = None;
+    let mut auth = None;
+    for field in line.split_whitespace() {
+        if let Some(v) = field.strip_prefix("version=") {
+            version = Some(v);
+        } else if let Some(a) = field.strip_prefix("authorization=") {
+            auth = Some(u64::from_str_radix(a, 16).ok()?);
+        }
+    }
+    Some(format!("{}.{:x}", version?, auth?))
+}
+
+/// Build the CPU model string from the contents of `/proc/cpuinfo`, `/proc/sysinfo` and
+/// `/proc/service_levels`.
+fn _cpu_string(cpuinfo: &str, sysinfo: &str, service_levels: &str) -> String {
+    let (capacity, model) = _model(sysinfo).unwrap_or(("", ""));
+    let mut cpu = format!(
+        "IBM,{},{},{}",
+        _machine_type(cpuinfo).unwrap_or_default(),
+        capacity,
+        model
+    );
+    if let Some(cf) = _counter_facility(service_levels) {
+        cpu.push(',');
+        cpu.push_str(&cf);
+    }
+    cpu
+}
+
+/// Get CPU model string for s390x processors, e.g., `IBM,3906,704,M03,3.5.2f`.
+///
+/// The string is built like the one of the `perf` tool from the machine type, the model, and
+/// the version of the CPU measurement counter facility.
+pub fn get_cpu_string() -> String {
+    let read = |path| std::fs::read_to_string(path).unwrap_or_default();
+    let cpu = _cpu_string(
+        &read("/proc/cpuinfo"),
+        &read("/proc/sysinfo"),
+        &read("/proc/service_levels"),
+    );
+    debug!("Detected s390x processor - {}", cpu);
+    cpu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_str() {
+        let cpuinfo = "vendor_id       : IBM/S390\n\
+                       # processors    : 2\n\
+                       processor 0: version = FF,  identification = 0133E8,  machine = 3906\n";
+        let sysinfo = "Manufacturer:         IBM\nType:                 3906\n\
+                       Model:                704              M03\n";
+        let service_levels = "CPU-MF: Counter facility: version=3.5 authorization=002f\n\
+                              CPU-MF: Sampling facility: min_rate=4444 max_rate=1777777\n";
+        assert_eq!(_machine_type(cpuinfo), Some("3906"));
+        assert_eq!(_machine_type("machine type    : 8561\n"), Some("8561"));
+        assert_eq!(
+            _cpu_string(cpuinfo, sysinfo, service_levels),
+            "IBM,3906,704,M03,3.5.2f"
+        );
+        assert_eq!(_cpu_string(cpuinfo, "", ""), "IBM,3906,,");
+        assert!(!get_cpu_string().is_empty());
+    }
+}
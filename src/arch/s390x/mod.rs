@@ -0,0 +1,7 @@
+//! Utilities specific to the s390x architecture.
+
+mod cpuinfo;
+pub use cpuinfo::*;
+
+mod perf_regs;
+pub use perf_regs::*;
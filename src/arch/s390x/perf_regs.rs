@@ -0,0 +1,47 @@
+//! Register layout used by perf when sampling registers.
+
+/// Indices of the registers in the register dumps of sampled perf events.
+///
+/// See `arch/s390/include/uapi/asm/perf_regs.h` in the Linux kernel source for details.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types, missing_docs)]
+pub enum PerfRegister {
+    PERF_REG_S390_R0 = 0,
+    PERF_REG_S390_R1 = 1,
+    PERF_REG_S390_R2 = 2,
+    PERF_REG_S390_R3 = 3,
+    PERF_REG_S390_R4 = 4,
+    PERF_REG_S390_R5 = 5,
+    PERF_REG_S390_R6 = 6,
+    PERF_REG_S390_R7 = 7,
+    PERF_REG_S390_R8 = 8,
+    PERF_REG_S390_R9 = 9,
+    PERF_REG_S390_R10 = 10,
+    PERF_REG_S390_R11 = 11,
+    PERF_REG_S390_R12 = 12,
+    PERF_REG_S390_R13 = 13,
+    PERF_REG_S390_R14 = 14,
+    PERF_REG_S390_R15 = 15,
+    PERF_REG_S390_FP0 = 16,
+    PERF_REG_S390_FP1 = 17,
+    PERF_REG_S390_FP2 = 18,
+    PERF_REG_S390_FP3 = 19,
+    PERF_REG_S390_FP4 = 20,
+    PERF_REG_S390_FP5 = 21,
+    PERF_REG_S390_FP6 = 22,
+    PERF_REG_S390_FP7 = 23,
+    PERF_REG_S390_FP8 = 24,
+    PERF_REG_S390_FP9 = 25,
+    PERF_REG_S390_FP10 = 26,
+    PERF_REG_S390_FP11 = 27,
+    PERF_REG_S390_FP12 = 28,
+    PERF_REG_S390_FP13 = 29,
+    PERF_REG_S390_FP14 = 30,
+    PERF_REG_S390_FP15 = 31,
+    PERF_REG_S390_MASK = 32,
+    PERF_REG_S390_PC = 33,
+}
+
+/// Default mask of user registers to sample, e.g., for user-space unwinding.
+pub const SAMPLE_REGS_USER_DEFAULT: u64 = (1 << (PerfRegister::PERF_REG_S390_PC as u64 + 1)) - 1;
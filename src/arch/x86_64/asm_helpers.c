@@ -12,3 +12,15 @@ int64_t rdpmc(uint32_t counter) {
   asm volatile("rdpmc" : "=a" (low), "=d" (high) : "c" (counter));
   return low | ((uint64_t)high) << 32;
 }
+
+uint64_t rdtsc_ordered() {
+  uint32_t high, low;
+  asm volatile("lfence; rdtsc" : "=a"(low), "=d"(high) : : "memory");
+  return low | (((uint64_t)high) << 32);
+}
+
+uint64_t rdtscp(uint32_t *aux) {
+  uint32_t high, low;
+  asm volatile("rdtscp" : "=a"(low), "=d"(high), "=c"(*aux));
+  return low | (((uint64_t)high) << 32);
+}
@@ -44,13 +44,184 @@ pub fn get_cpu_string() -> String {
     cpu
 }
 
+/// Get the number of general purpose and fixed function performance counters of each logical
+/// processor from the architectural performance monitoring leaf of `cpuid`.
+///
+/// Returns `None` if architectural performance monitoring is not supported, e.g., on AMD
+/// processors or in some virtual machines.
+pub fn get_num_counters() -> Option<(u32, u32)> {
+    ArchPerfmon::detect().map(|p| (p.general, p.fixed))
+}
+
+/// Architectural performance events, in the order of their bits in the mask of unavailable events
+/// of `cpuid` leaf 0xA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchEvent {
+    /// `UnHalted Core Cycles`.
+    CoreCycles,
+    /// `Instruction Retired`.
+    InstructionsRetired,
+    /// `UnHalted Reference Cycles`.
+    ReferenceCycles,
+    /// `LLC Reference`.
+    LlcReferences,
+    /// `LLC Misses`.
+    LlcMisses,
+    /// `Branch Instruction Retired`.
+    BranchInstructionsRetired,
+    /// `Branch Misses Retired`.
+    BranchMissesRetired,
+    /// `Topdown Slots`.
+    TopdownSlots,
+}
+
+impl ArchEvent {
+    /// All architectural events, indexed by their bit in the mask of unavailable events.
+    pub const ALL: [ArchEvent; 8] = [
+        ArchEvent::CoreCycles,
+        ArchEvent::InstructionsRetired,
+        ArchEvent::ReferenceCycles,
+        ArchEvent::LlcReferences,
+        ArchEvent::LlcMisses,
+        ArchEvent::BranchInstructionsRetired,
+        ArchEvent::BranchMissesRetired,
+        ArchEvent::TopdownSlots,
+    ];
+
+    /// Get the event code and umask of the event on a general purpose counter.
+    pub fn code(self) -> (u64, u64) {
+        match self {
+            ArchEvent::CoreCycles => (0x3c, 0x00),
+            ArchEvent::InstructionsRetired => (0xc0, 0x00),
+            ArchEvent::ReferenceCycles => (0x3c, 0x01),
+            ArchEvent::LlcReferences => (0x2e, 0x4f),
+            ArchEvent::LlcMisses => (0x2e, 0x41),
+            ArchEvent::BranchInstructionsRetired => (0xc4, 0x00),
+            ArchEvent::BranchMissesRetired => (0xc5, 0x00),
+            ArchEvent::TopdownSlots => (0xa4, 0x01),
+        }
+    }
+
+    /// Get the architectural event with event code `code` and `umask`, if any.
+    pub fn from_code(code: u64, umask: u64) -> Option<Self> {
+        ArchEvent::ALL
+            .iter()
+            .copied()
+            .find(|e| e.code() == (code, umask))
+    }
+}
+
+/// Performance monitoring resources of each logical processor, as enumerated by the architectural
+/// performance monitoring leaf 0xA of `cpuid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchPerfmon {
+    /// Version of architectural performance monitoring.
+    pub version: u32,
+    /// Number of general purpose counters.
+    pub general: u32,
+    /// Width of the general purpose counters in bits.
+    pub general_width: u32,
+    /// Number of fixed function counters.
+    pub fixed: u32,
+    /// Width of the fixed function counters in bits.
+    pub fixed_width: u32,
+    /// Number of architectural events enumerated in `unavailable_events`.
+    pub num_events: u32,
+    /// Mask of the architectural events that are not available, with bit `i` corresponding to
+    /// `ArchEvent::ALL[i]`.
+    pub unavailable_events: u32,
+}
+
+impl ArchPerfmon {
+    /// Enumerate the performance monitoring resources of the local CPU.
+    ///
+    /// Returns `None` if architectural performance monitoring is not supported.
+    #[allow(unused_unsafe)]
+    pub fn detect() -> Option<Self> {
+        let res = unsafe { __cpuid(0) };
+        if res.eax < 0xA {
+            return None;
+        }
+        let res = unsafe { __cpuid(0xA) };
+        let perfmon = ArchPerfmon::from_registers(res.eax, res.ebx, res.edx)?;
+        debug!(
+            "Detected {} general and {} fixed counters",
+            perfmon.general, perfmon.fixed
+        );
+        Some(perfmon)
+    }
+
+    /// Decode the registers `eax`, `ebx` and `edx` returned by leaf 0xA of `cpuid`.
+    ///
+    /// Returns `None` if architectural performance monitoring is not supported.
+    pub fn from_registers(eax: u32, ebx: u32, edx: u32) -> Option<Self> {
+        let version = eax & 0xff;
+        if version == 0 {
+            return None;
+        }
+        let num_events = (eax >> 24) & 0xff;
+        let (fixed, fixed_width) = if version > 1 {
+            (edx & 0x1f, (edx >> 5) & 0xff)
+        } else {
+            (0, 0)
+        };
+        Some(ArchPerfmon {
+            version,
+            general: (eax >> 8) & 0xff,
+            general_width: (eax >> 16) & 0xff,
+            fixed,
+            fixed_width,
+            num_events,
+            // EBX has 32 bits, but the length of the mask is reported in 8 bits
+            unavailable_events: ebx & ((1u64 << num_events.min(32)) - 1) as u32,
+        })
+    }
+
+    /// Check if the architectural event `evt` is available.
+    pub fn is_available(&self, evt: ArchEvent) -> bool {
+        let bit = evt as u32;
+        bit < self.num_events && self.unavailable_events & (1 << bit) == 0
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::get_cpu_string;
+    use super::*;
 
     #[test]
     fn test_cpu_str() {
         let cpu_str = get_cpu_string();
         assert!(!cpu_str.is_empty());
     }
+
+    #[test]
+    fn test_num_counters() {
+        if let Some((general, fixed)) = get_num_counters() {
+            assert!(general <= 32 && fixed <= 32);
+        }
+    }
+
+    #[test]
+    fn test_arch_perfmon() {
+        // Skylake with the branch misses event disabled
+        let perfmon = ArchPerfmon::from_registers(0x0730_0804, 0x40, 0x0603).unwrap();
+        assert_eq!(perfmon.version, 4);
+        assert_eq!((perfmon.general, perfmon.general_width), (8, 48));
+        assert_eq!((perfmon.fixed, perfmon.fixed_width), (3, 48));
+        assert!(perfmon.is_available(ArchEvent::CoreCycles));
+        assert!(!perfmon.is_available(ArchEvent::BranchMissesRetired));
+        assert!(!perfmon.is_available(ArchEvent::TopdownSlots));
+        assert!(ArchPerfmon::from_registers(0, 0, 0).is_none());
+        let perfmon = ArchPerfmon::from_registers(0xff30_0805, 0xffff_ffff, 0).unwrap();
+        assert_eq!(perfmon.unavailable_events, 0xffff_ffff);
+        assert!(!perfmon.is_available(ArchEvent::CoreCycles));
+        assert_eq!(
+            ArchEvent::from_code(0x3c, 0x01),
+            Some(ArchEvent::ReferenceCycles)
+        );
+        assert_eq!(ArchEvent::from_code(0x3c, 0x02), None);
+        if let Some(perfmon) = ArchPerfmon::detect() {
+            assert_eq!(get_num_counters(), Some((perfmon.general, perfmon.fixed)));
+        }
+    }
 }
@@ -0,0 +1,214 @@
+//! Utilities to configure and read the Last Branch Records (LBRs) through MSRs.
+
+use crate::arch::{MsrAddress, MsrSet, PerfCapabilities};
+use crate::perf::BranchEntry;
+use crate::{Error, Result};
+
+/// Bit of `IA32_DEBUGCTL` enabling the LBRs.
+const DEBUGCTL_LBR: u64 = 1;
+
+/// Format of the LBRs reported by `IA32_PERF_CAPABILITIES` on CPUs with architectural LBRs.
+const LBR_FORMAT_ARCH: u8 = 0x3f;
+
+/// Value of `MSR_LBR_SELECT` recording branches in all privilege levels.
+pub const LBR_SELECT_ALL: u64 = 0;
+/// Value of `MSR_LBR_SELECT` recording only branches in ring 3, i.e., in user space.
+pub const LBR_SELECT_USER: u64 = 0x1;
+/// Value of `MSR_LBR_SELECT` recording only branches in ring 0, i.e., in the kernel.
+pub const LBR_SELECT_KERNEL: u64 = 0x2;
+
+/// Last Branch Records of a set of CPUs, programmed through `IA32_DEBUGCTL` and `MSR_LBR_SELECT`
+/// and read from `MSR_LASTBRANCH_x_FROM_IP` and `MSR_LASTBRANCH_x_TO_IP`.
+///
+/// By default only branches in user space are recorded, so the system calls reading the MSRs do
+/// not evict the branches of the measured code. Use `PerfEventBuilder::sample_branch_stack` to
+/// capture the LBRs through perf instead, which does not require the `msr` kernel module.
+#[derive(Debug)]
+pub struct Lbr {
+    /// Handles to the MSRs of the CPUs whose LBRs are used.
+    msrs: MsrSet,
+    /// Number of entries of the LBR stack.
+    depth: u32,
+    /// Capabilities of the CPUs, which determine the format of the LBR entries.
+    caps: PerfCapabilities,
+    /// Value of `MSR_LBR_SELECT` filtering the recorded branches.
+    select: u64,
+}
+
+impl Lbr {
+    /// Prepare using the LBR stacks with `depth` entries of all CPUs of `msrs`.
+    ///
+    /// The depth depends on the microarchitecture, e.g., 16 on Haswell and 32 on Skylake. Fails
+    /// with `Error::NotImplemented` on CPUs with architectural LBRs, which are programmed through
+    /// a different set of MSRs.
+    pub fn new(msrs: MsrSet, depth: u32) -> Result<Self> {
+        if depth == 0 || depth > 32 {
+            return Err(Error::InvalidSetting {
+                setting: "depth",
+                reason: "must be between 1 and 32".into(),
+            });
+        }
+        let cpu = *msrs.cpus().first().ok_or(Error::NoneError)?;
+        let caps = PerfCapabilities::read(msrs.handle(cpu).ok_or(Error::NoneError)?)?;
+        if caps.lbr_format == LBR_FORMAT_ARCH {
+            return Err(Error::NotImplemented);
+        }
+        Ok(Lbr {
+            msrs,
+            depth,
+            caps,
+            select: LBR_SELECT_USER,
+        })
+    }
+
+    /// Set the value of `MSR_LBR_SELECT`, e.g., `LBR_SELECT_ALL`, used by `enable`.
+    ///
+    /// Each set bit suppresses recording of a kind of branch, see the "Last Branch Recording
+    /// Filtering" section of the Intel SDM.
+    pub fn set_select(mut self, select: u64) -> Self {
+        self.select = select;
+        self
+    }
+
+    /// Get the number of entries of the LBR stack.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Start recording branches.
+    pub fn enable(&self) -> Result<()> {
+        self.msrs
+            .write_all(&[(MsrAddress::MSR_LBR_SELECT as i64, self.select)])?;
+        self._set_debugctl(true)
+    }
+
+    /// Stop recording branches, keeping the recorded entries.
+    pub fn disable(&self) -> Result<()> {
+        self._set_debugctl(false)
+    }
+
+    /// Set or clear the LBR bit of `IA32_DEBUGCTL` on every CPU, keeping its other bits.
+    fn _set_debugctl(&self, enable: bool) -> Result<()> {
+        let debugctl = MsrAddress::IA32_DEBUGCTL as i64;
+        for cpu in self.msrs.cpus() {
+            let handle = self.msrs.handle(cpu).ok_or(Error::NoneError)?;
+            let value = handle.read(debugctl)?;
+            let value = if enable {
+                value | DEBUGCTL_LBR
+            } else {
+                value & !DEBUGCTL_LBR
+            };
+            handle.write(debugctl, value)?;
+        }
+        Ok(())
+    }
+
+    /// Read the recorded branches of every CPU, from the most to the least recent one.
+    ///
+    /// Entries that have not been recorded yet are skipped. Whether a branch was mispredicted is
+    /// only reported if the format of the LBRs supports it.
+    pub fn read(&self) -> Result<Vec<(u32, Vec<BranchEntry>)>> {
+        let with_info = self.caps.lbr_has_info();
+        let mut res = Vec::new();
+        for cpu in self.msrs.cpus() {
+            let handle = self.msrs.handle(cpu).ok_or(Error::NoneError)?;
+            let tos = handle.read(MsrAddress::MSR_LASTBRANCH_TOS as i64)?;
+            let mut entries = Vec::with_capacity(self.depth as usize);
+            for idx in _entry_indices(tos, self.depth) {
+                let from = handle.read(MsrAddress::MSR_LASTBRANCH_0_FROM_IP as i64 + idx)?;
+                if from == 0 {
+                    continue;
+                }
+                let to = handle.read(MsrAddress::MSR_LASTBRANCH_0_TO_IP as i64 + idx)?;
+                let info = if with_info {
+                    handle.read(MsrAddress::MSR_LBR_INFO_0 as i64 + idx)?
+                } else {
+                    0
+                };
+                entries.push(_decode_entry(self.caps.lbr_format, from, to, info));
+            }
+            res.push((cpu, entries));
+        }
+        Ok(res)
+    }
+}
+
+/// Get the indices of the entries of an LBR stack with `depth` entries, from the most recent one
+/// at `tos` to the least recent one.
+fn _entry_indices(tos: u64, depth: u32) -> Vec<i64> {
+    let (tos, depth) = (tos as i64 % i64::from(depth), i64::from(depth));
+    (0..depth).map(|i| (tos + depth - i) % depth).collect()
+}
+
+/// Decode the values of the `from`, `to` and `info` MSRs of an LBR entry in `format`.
+///
+/// The details of the branch are stored in the flags in the layout of `ffi::perf_branch_entry`.
+fn _decode_entry(format: u8, from: u64, to: u64, info: u64) -> BranchEntry {
+    let (mispred, cycles) = match format {
+        // LBR_FORMAT_EIP_FLAGS and LBR_FORMAT_EIP_FLAGS2
+        3 | 4 => (Some(from >> 63 != 0), 0),
+        // LBR_FORMAT_INFO and LBR_FORMAT_INFO2 with MSR_LBR_INFO_x
+        5 | 7 => (Some(info >> 63 != 0), info & 0xffff),
+        // LBR_FORMAT_TIME stores the cycle count in the upper bits of the to address
+        6 => (Some(from >> 63 != 0), (to >> 48) & 0xffff),
+        _ => (None, 0),
+    };
+    let flags = match mispred {
+        Some(true) => 0x1,
+        Some(false) => 0x2,
+        None => 0,
+    };
+    BranchEntry {
+        from: _sign_extend(from),
+        to: _sign_extend(to),
+        flags: flags | (cycles << 4),
+    }
+}
+
+/// Sign extend the 48 bit address `addr`, dropping the flags in its upper bits.
+fn _sign_extend(addr: u64) -> u64 {
+    (((addr << 16) as i64) >> 16) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lbr() {
+        assert_eq!(_entry_indices(2, 4), vec![2, 1, 0, 3]);
+        assert_eq!(_entry_indices(5, 4), vec![1, 0, 3, 2]);
+        assert_eq!(_sign_extend(0x8000_7fff_ffff_1000), 0x7fff_ffff_1000);
+        assert_eq!(_sign_extend(0xffff_8000_0000_1000), 0xffff_8000_0000_1000);
+
+        let entry = _decode_entry(4, (1 << 63) | 0x40_1000, 0x40_2000, 0);
+        assert_eq!((entry.from, entry.to), (0x40_1000, 0x40_2000));
+        assert!(entry.mispredicted() && !entry.predicted());
+        let entry = _decode_entry(5, 0x40_1000, 0x40_2000, 0x12);
+        assert!(entry.predicted() && !entry.mispredicted());
+        assert_eq!(entry.cycles(), 0x12);
+        let entry = _decode_entry(6, (1 << 63) | 0x40_1000, (0x34 << 48) | 0x40_2000, 0x12);
+        assert_eq!((entry.from, entry.to), (0x40_1000, 0x40_2000));
+        assert!(entry.mispredicted() && !entry.predicted());
+        assert_eq!(entry.cycles(), 0x34);
+        let entry = _decode_entry(7, 0x40_1000, 0x40_2000, (1 << 63) | 0x12);
+        assert!(entry.mispredicted() && !entry.predicted());
+        assert_eq!(entry.cycles(), 0x12);
+        let entry = _decode_entry(1, 0x40_1000, 0x40_2000, 0);
+        assert!(!entry.predicted() && !entry.mispredicted());
+
+        // The msr kernel module is not loaded
+        let msrs = match MsrSet::new(&[0]) {
+            Ok(msrs) => msrs,
+            Err(_) => return,
+        };
+        assert!(Lbr::new(MsrSet::new(&[0]).unwrap(), 0).is_err());
+        if let Ok(lbr) = Lbr::new(msrs, 16) {
+            lbr.enable().unwrap();
+            lbr.disable().unwrap();
+            let branches = lbr.read().unwrap();
+            assert_eq!(branches[0].0, 0);
+            assert!(branches[0].1.len() <= 16);
+        }
+    }
+}
@@ -3,11 +3,29 @@
 mod msr;
 pub use msr::*;
 
+mod msr_counters;
+pub use msr_counters::MsrCounters;
+
+mod perf_capabilities;
+pub use perf_capabilities::PerfCapabilities;
+
+mod lbr;
+pub use lbr::*;
+
 mod cpuid;
 pub use cpuid::*;
 
+mod perf_regs;
+pub use perf_regs::*;
+
 //mod pci;
 //pub use pci::*;
 
 mod rdpmc;
 pub use rdpmc::read_counter_rdpmc;
+
+mod topology;
+pub use topology::*;
+
+mod tsc;
+pub use tsc::*;
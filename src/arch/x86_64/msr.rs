@@ -1,6 +1,8 @@
 //! Utilities to read and write model specific registers (MSRs).
 
-use crate::Result;
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
 use std::os::unix::io::AsRawFd;
 
 #[derive(Debug)]
@@ -15,15 +17,44 @@ pub struct MsrHandle {
 impl MsrHandle {
     /// Get a handle to the CPU specific MSR.
     ///
-    /// This will require loading the `msr` kernel module.
+    /// This will require loading the `msr` kernel module. Fails with `Error::MsrUnavailable`
+    /// explaining why the MSRs of `cpuid` cannot be opened.
     pub fn new(cpuid: u32) -> crate::Result<Self> {
+        let path = format!("/dev/cpu/{}/msr", cpuid);
         let file = std::fs::OpenOptions::new()
             .read(true)
             .write(true)
-            .open(format!("/dev/cpu/{}/msr", cpuid))?;
+            .open(&path)
+            .map_err(|e| {
+                let reason = match e.kind() {
+                    ErrorKind::NotFound if std::path::Path::new("/dev/cpu/0/msr").exists() => {
+                        format!("{} does not exist, the CPU may be offline", path)
+                    }
+                    ErrorKind::NotFound => {
+                        String::from("the msr kernel module is not loaded, run `modprobe msr`")
+                    }
+                    ErrorKind::PermissionDenied => format!(
+                        "opening {} requires CAP_SYS_RAWIO and write access to it",
+                        path
+                    ),
+                    _ => format!("cannot open {} -- {}", path, e),
+                };
+                Error::MsrUnavailable { cpu: cpuid, reason }
+            })?;
         Ok(MsrHandle { file })
     }
 
+    /// Get handles to the MSRs of all online CPUs, indexed by the CPU.
+    ///
+    /// Each CPU whose MSRs cannot be opened is mapped to its `Error::MsrUnavailable`, so that the
+    /// CPUs that can be used are still available. Only fails if the online CPUs cannot be read.
+    pub fn for_all_cpus() -> crate::Result<BTreeMap<u32, crate::Result<Self>>> {
+        Ok(crate::util::online_cpus()?
+            .into_iter()
+            .map(|c| (c as u32, MsrHandle::new(c as u32)))
+            .collect())
+    }
+
     /// Write `value` to `msr`.
     pub fn write(&self, msr: i64, value: u64) -> Result<usize> {
         nix::sys::uio::pwrite(self.file.as_raw_fd(), &value.to_ne_bytes(), msr)
@@ -38,6 +69,84 @@ impl MsrHandle {
     }
 }
 
+/// Handles to the model specific registers of a set of CPUs.
+///
+/// Allows programming registers consistently on all CPUs, e.g., the control registers of the
+/// fixed counters or of uncore PMON boxes.
+#[derive(Debug)]
+pub struct MsrSet {
+    /// Handle of each CPU in the set, in ascending order of the CPUs.
+    handles: Vec<(u32, MsrHandle)>,
+}
+
+impl MsrSet {
+    /// Get handles to the MSRs of `cpus`.
+    ///
+    /// Fails if the MSRs of any of the CPUs cannot be opened.
+    pub fn new(cpus: &[u32]) -> Result<Self> {
+        let mut cpus = cpus.to_vec();
+        cpus.sort_unstable();
+        cpus.dedup();
+        let handles = cpus
+            .into_iter()
+            .map(|c| Ok((c, MsrHandle::new(c)?)))
+            .collect::<Result<_>>()?;
+        Ok(MsrSet { handles })
+    }
+
+    /// Get handles to the MSRs of the CPUs in `list`, given in the kernel's `cpulist` format,
+    /// e.g., `0,18` as in the `cpumask` of an uncore PMU.
+    pub fn from_cpu_list(list: &str) -> Result<Self> {
+        let cpus: Vec<u32> = crate::util::parse_cpu_list(list)?
+            .into_iter()
+            .map(|c| c as u32)
+            .collect();
+        MsrSet::new(&cpus)
+    }
+
+    /// Get handles to the MSRs of all online CPUs.
+    pub fn online() -> Result<Self> {
+        let cpus: Vec<u32> = crate::util::online_cpus()?
+            .into_iter()
+            .map(|c| c as u32)
+            .collect();
+        MsrSet::new(&cpus)
+    }
+
+    /// Get the CPUs in the set.
+    pub fn cpus(&self) -> Vec<u32> {
+        self.handles.iter().map(|(c, _)| *c).collect()
+    }
+
+    /// Get the handle to the MSRs of `cpu`, if it is in the set.
+    pub fn handle(&self, cpu: u32) -> Option<&MsrHandle> {
+        self.handles.iter().find(|(c, _)| *c == cpu).map(|(_, h)| h)
+    }
+
+    /// Read the values of `msrs` on every CPU.
+    ///
+    /// Returns the values of each CPU in the order of `msrs`, together with the CPU.
+    pub fn read_all(&self, msrs: &[i64]) -> Result<Vec<(u32, Vec<u64>)>> {
+        self.handles
+            .iter()
+            .map(|(c, h)| Ok((*c, msrs.iter().map(|&m| h.read(m)).collect::<Result<_>>()?)))
+            .collect()
+    }
+
+    /// Write the `(msr, value)` pairs of `regs` on every CPU, in their order on each CPU.
+    ///
+    /// Stops at the first failed write, so the registers of the CPUs before it are already
+    /// written.
+    pub fn write_all(&self, regs: &[(i64, u64)]) -> Result<()> {
+        for (_, h) in self.handles.iter() {
+            for &(msr, value) in regs.iter() {
+                h.write(msr, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 /// MSR addresses.
 ///
 /// See "Intel 64 and IA-32 Architectures Software Developers Manual Volume 3B: System
@@ -61,10 +170,17 @@ pub enum MsrAddress {
     PERF_MAX_CUSTOM_COUNTERS = 8,
     PERF_MAX_COUNTERS = 3 /* PERF_MAX_FIXED_COUNTERS */ + 8, /* PERF_MAX_CUSTOM_COUNTERS */
     IA32_DEBUGCTL = 0x1D9,
+    IA32_PERF_CAPABILITIES = 0x345,
+    MSR_LBR_SELECT = 0x1C8,
+    MSR_LASTBRANCH_TOS = 0x1C9,
+    MSR_LASTBRANCH_0_FROM_IP = 0x680,
+    MSR_LASTBRANCH_0_TO_IP = 0x6C0,
+    MSR_LBR_INFO_0 = 0xDC0,
     IA32_PMC0 = 0xC1,
     IA32_PMC1 = 0xC1 + 1,
     IA32_PMC2 = 0xC1 + 2,
     IA32_PMC3 = 0xC1 + 3,
+    IA32_A_PMC0 = 0x4C1,
     MSR_OFFCORE_RSP0 = 0x1A6,
     MSR_OFFCORE_RSP1 = 0x1A7,
     PLATFORM_INFO_ADDR = 0xCE,
@@ -83,3 +199,49 @@ impl MsrAddress {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msr_for_all_cpus() {
+        let online = crate::util::online_cpus().unwrap();
+        let handles = MsrHandle::for_all_cpus().unwrap();
+        assert_eq!(handles.len(), online.len());
+        for (&c, handle) in handles.iter() {
+            match handle {
+                Ok(_) => {}
+                Err(Error::MsrUnavailable { cpu, reason }) => {
+                    assert_eq!(*cpu, c);
+                    assert!(!reason.is_empty());
+                }
+                Err(e) => panic!("Unexpected error {}", e),
+            }
+        }
+        match MsrHandle::new(1 << 20) {
+            Err(Error::MsrUnavailable { cpu, .. }) => assert_eq!(cpu, 1 << 20),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_msr_set() {
+        let online = crate::util::online_cpus().unwrap();
+        let set = match MsrSet::online() {
+            Ok(set) => set,
+            // The msr kernel module is not loaded
+            Err(_) => {
+                assert!(MsrHandle::new(online[0] as u32).is_err());
+                return;
+            }
+        };
+        assert_eq!(set.cpus().len(), online.len());
+        assert!(set.handle(online[0] as u32).is_some());
+        let tsc = MsrAddress::IA32_TIME_STAMP_COUNTER as i64;
+        let values = set.read_all(&[tsc, tsc]).unwrap();
+        assert_eq!(values.len(), online.len());
+        assert!(values.iter().all(|(_, v)| v.len() == 2 && v[1] >= v[0]));
+        assert!(set.write_all(&[]).is_ok());
+    }
+}
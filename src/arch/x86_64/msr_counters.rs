@@ -0,0 +1,202 @@
+//! Counting events by programming the performance counters directly through MSRs.
+
+use crate::arch::{get_num_counters, MsrAddress, MsrSet};
+use crate::{Error, Result};
+
+/// Bit of `IA32_PERFEVTSELx` enabling counting in user mode.
+const EVTSEL_USR: u64 = 1 << 16;
+/// Bit of `IA32_PERFEVTSELx` enabling counting in kernel mode.
+const EVTSEL_OS: u64 = 1 << 17;
+/// Bit of `IA32_PERFEVTSELx` enabling the counter.
+const EVTSEL_EN: u64 = 1 << 22;
+
+/// Core performance counters programmed through `IA32_FIXED_CTR_CTRL` and `IA32_PERFEVTSELx`
+/// and read from `IA32_FIXED_CTRx` and `IA32_PMCx`, without using `perf_event_open`.
+///
+/// This is meant for environments where perf events are not available, e.g., bare-metal
+/// measurement harnesses. The counters are shared with the kernel, so programming them while
+/// perf events, including the NMI watchdog, are active corrupts the counts of both.
+///
+/// All fixed counters and one general purpose counter per event are enabled, counting in user and
+/// kernel mode. The control registers are restored to their previous values when this is dropped.
+#[derive(Debug)]
+pub struct MsrCounters {
+    /// Handles to the MSRs of the CPUs the counters are programmed on.
+    msrs: MsrSet,
+    /// Number of fixed counters of each CPU.
+    fixed: u32,
+    /// Raw configuration of the event counted by each general purpose counter.
+    events: Vec<u64>,
+    /// Values of the control registers, see `_control_msrs`, of every CPU before they were
+    /// programmed.
+    saved: Vec<(u32, Vec<u64>)>,
+}
+
+impl MsrCounters {
+    /// Prepare counting `events` on all CPUs of `msrs`.
+    ///
+    /// Each event is given as the raw configuration of perf's `cpu` PMU, i.e., in the layout of
+    /// `IA32_PERFEVTSELx` with the event code in bits 0-7 and the umask in bits 8-15. Fails if
+    /// there are more events than general purpose counters.
+    pub fn new(msrs: MsrSet, events: &[u64]) -> Result<Self> {
+        let (general, fixed) = get_num_counters().ok_or_else(|| {
+            Error::DirectReadUnavailable(
+                "architectural performance monitoring is not supported".into(),
+            )
+        })?;
+        _check_events(events, general)?;
+        let saved = msrs.read_all(&_control_msrs(events.len() as u32))?;
+        Ok(MsrCounters {
+            msrs,
+            fixed,
+            events: events.to_vec(),
+            saved,
+        })
+    }
+
+    /// Get the CPUs the counters are programmed on.
+    pub fn cpus(&self) -> Vec<u32> {
+        self.msrs.cpus()
+    }
+
+    /// Reset the counters to `0` and start counting.
+    pub fn start(&self) -> Result<()> {
+        self.stop()?;
+        let mut regs: Vec<(i64, u64)> = self.counters().into_iter().map(|c| (c, 0)).collect();
+        regs.push((
+            MsrAddress::IA32_CR_FIXED_CTR_CTRL as i64,
+            _fixed_ctrl(self.fixed),
+        ));
+        regs.extend(
+            self.events
+                .iter()
+                .enumerate()
+                .map(|(i, &e)| (_perfevtsel(i as u32), _evtsel(e))),
+        );
+        regs.push((
+            MsrAddress::IA32_CR_PERF_GLOBAL_CTRL as i64,
+            _global_ctrl(self.events.len() as u32, self.fixed),
+        ));
+        self.msrs.write_all(&regs)
+    }
+
+    /// Stop counting, keeping the values of the counters.
+    pub fn stop(&self) -> Result<()> {
+        self.msrs
+            .write_all(&[(MsrAddress::IA32_CR_PERF_GLOBAL_CTRL as i64, 0)])
+    }
+
+    /// Read the counters of every CPU.
+    ///
+    /// Returns the values of the fixed counters followed by those of `events`, together with the
+    /// CPU.
+    pub fn read(&self) -> Result<Vec<(u32, Vec<u64>)>> {
+        self.msrs.read_all(&self.counters())
+    }
+
+    /// Get the MSRs of the fixed counters followed by those of the general purpose counters.
+    fn counters(&self) -> Vec<i64> {
+        let fixed =
+            (0..self.fixed).map(|i| MsrAddress::INST_RETIRED_ANY_ADDR as i64 + i64::from(i));
+        let general = (0..self.events.len()).map(|i| MsrAddress::IA32_PMC0 as i64 + i as i64);
+        fixed.chain(general).collect()
+    }
+}
+
+impl Drop for MsrCounters {
+    fn drop(&mut self) {
+        let msrs = _control_msrs(self.events.len() as u32);
+        for (cpu, values) in self.saved.iter() {
+            if let Some(handle) = self.msrs.handle(*cpu) {
+                for (&msr, &value) in msrs.iter().zip(values.iter()) {
+                    let _ = handle.write(msr, value);
+                }
+            }
+        }
+    }
+}
+
+/// Check that `events` fit onto `general` general purpose counters.
+fn _check_events(events: &[u64], general: u32) -> Result<()> {
+    if events.len() > general as usize {
+        return Err(Error::CounterConflict(
+            events.iter().map(|e| format!("r{:x}", e)).collect(),
+        ));
+    }
+    Ok(())
+}
+
+/// Get the control registers changed when counting `general` events, i.e.,
+/// `IA32_PERF_GLOBAL_CTRL` first, so that it disables the counters when restored, followed by
+/// `IA32_FIXED_CTR_CTRL` and the `IA32_PERFEVTSELx` of the general purpose counters.
+fn _control_msrs(general: u32) -> Vec<i64> {
+    let mut msrs = vec![
+        MsrAddress::IA32_CR_PERF_GLOBAL_CTRL as i64,
+        MsrAddress::IA32_CR_FIXED_CTR_CTRL as i64,
+    ];
+    msrs.extend((0..general).map(_perfevtsel));
+    msrs
+}
+
+/// Get the address of `IA32_PERFEVTSELx` of the general purpose counter `idx`.
+fn _perfevtsel(idx: u32) -> i64 {
+    MsrAddress::IA32_PERFEVTSEL0_ADDR as i64 + i64::from(idx)
+}
+
+/// Get the value of `IA32_PERFEVTSELx` counting the raw event `config` in user and kernel mode.
+fn _evtsel(config: u64) -> u64 {
+    config | EVTSEL_USR | EVTSEL_OS | EVTSEL_EN
+}
+
+/// Get the value of `IA32_FIXED_CTR_CTRL` enabling `fixed` counters in user and kernel mode.
+fn _fixed_ctrl(fixed: u32) -> u64 {
+    (0..fixed).fold(0, |ctrl, i| ctrl | (0x3 << (4 * i)))
+}
+
+/// Get the value of `IA32_PERF_GLOBAL_CTRL` enabling `general` general purpose counters and
+/// `fixed` fixed counters.
+fn _global_ctrl(general: u32, fixed: u32) -> u64 {
+    ((1u64 << general) - 1) | (((1u64 << fixed) - 1) << 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msr_counters() {
+        assert_eq!(_evtsel(0x01c2), 0x4301c2);
+        assert_eq!(_perfevtsel(2), 0x188);
+        assert_eq!(_fixed_ctrl(3), 0x333);
+        assert_eq!(_global_ctrl(2, 3), 0x7_0000_0003);
+        assert_eq!(_global_ctrl(0, 0), 0);
+        assert!(_check_events(&[0xc0, 0x3c], 4).is_ok());
+        match _check_events(&[0xc0, 0x3c], 1) {
+            Err(Error::CounterConflict(names)) => assert_eq!(names, vec!["rc0", "r3c"]),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        assert_eq!(_control_msrs(2), vec![0x38f, 0x38d, 0x186, 0x187]);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_msr_counters_hardware() {
+        // Programs the counters shared with the kernel, so this only runs when requested.
+        // The msr kernel module is not loaded
+        let msrs = match MsrSet::new(&[0]) {
+            Ok(msrs) => msrs,
+            Err(_) => return,
+        };
+        if let Ok(counters) = MsrCounters::new(msrs, &[0xc0]) {
+            let saved = counters.saved.clone();
+            counters.start().unwrap();
+            counters.stop().unwrap();
+            let values = counters.read().unwrap();
+            assert_eq!(values[0].0, 0);
+            assert!(*values[0].1.last().unwrap() > 0);
+            drop(counters);
+            let msrs = MsrSet::new(&[0]).unwrap();
+            assert_eq!(msrs.read_all(&_control_msrs(1)).unwrap(), saved);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Decoding the performance monitoring capabilities of a CPU from `IA32_PERF_CAPABILITIES`.
+
+use crate::arch::{MsrAddress, MsrHandle, MsrSet};
+use crate::Result;
+
+/// Performance monitoring capabilities of a CPU, as reported by the `IA32_PERF_CAPABILITIES` MSR.
+///
+/// See "Intel 64 and IA-32 Architectures Software Developers Manual Volume 3B", Section 18.4.1
+/// "IA32_PERF_CAPABILITIES MSR Enumeration" for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerfCapabilities {
+    /// Format of the LBR entries, e.g., `3` if the mispredict flag is stored in the `FROM_IP`
+    /// MSRs and `5` if it is stored in the `MSR_LBR_INFO_x` MSRs.
+    pub lbr_format: u8,
+    /// PEBS records are generated after the instruction causing the event (trap-like) instead of
+    /// before it (fault-like).
+    pub pebs_trap: bool,
+    /// PEBS records contain the architectural registers.
+    pub pebs_arch_regs: bool,
+    /// Format of the PEBS records.
+    pub pebs_format: u8,
+    /// Counters can be frozen during System Management Mode.
+    pub smm_freeze: bool,
+    /// General purpose counters can be written with their full width through `IA32_A_PMCx`.
+    pub full_width_writes: bool,
+    /// PEBS records can be configured with adaptive PEBS.
+    pub pebs_baseline: bool,
+    /// The top-down metrics are available in `MSR_PERF_METRICS`.
+    pub perf_metrics: bool,
+    /// PEBS records can be written into the Intel PT trace.
+    pub pebs_output_pt: bool,
+}
+
+impl PerfCapabilities {
+    /// Decode the `raw` value of `IA32_PERF_CAPABILITIES`.
+    pub fn from_raw(raw: u64) -> Self {
+        let bit = |b: u32| raw & (1 << b) != 0;
+        PerfCapabilities {
+            lbr_format: (raw & 0x3f) as u8,
+            pebs_trap: bit(6),
+            pebs_arch_regs: bit(7),
+            pebs_format: ((raw >> 8) & 0xf) as u8,
+            smm_freeze: bit(12),
+            full_width_writes: bit(13),
+            pebs_baseline: bit(14),
+            perf_metrics: bit(15),
+            pebs_output_pt: bit(16),
+        }
+    }
+
+    /// Read the capabilities of the CPU of `handle`.
+    pub fn read(handle: &MsrHandle) -> Result<Self> {
+        let raw = handle.read(MsrAddress::IA32_PERF_CAPABILITIES as i64)?;
+        Ok(PerfCapabilities::from_raw(raw))
+    }
+
+    /// Read the capabilities of every CPU of `msrs`, together with the CPU.
+    pub fn read_all(msrs: &MsrSet) -> Result<Vec<(u32, Self)>> {
+        let values = msrs.read_all(&[MsrAddress::IA32_PERF_CAPABILITIES as i64])?;
+        Ok(values
+            .into_iter()
+            .map(|(cpu, v)| (cpu, PerfCapabilities::from_raw(v[0])))
+            .collect())
+    }
+
+    /// Check if the LBR entries report whether a branch was mispredicted.
+    pub fn lbr_has_mispredict(&self) -> bool {
+        self.lbr_format >= 3
+    }
+
+    /// Check if the LBR entries are accompanied by `MSR_LBR_INFO_x` MSRs.
+    pub fn lbr_has_info(&self) -> bool {
+        matches!(self.lbr_format, 5 | 7)
+    }
+
+    /// Get the MSR through which the general purpose counter `idx` is written.
+    ///
+    /// This is `IA32_A_PMCx` if full-width writes are supported, as writes to `IA32_PMCx` only
+    /// set the lower 32 bits of the counter and sign extend them.
+    pub fn pmc_write_address(&self, idx: u32) -> i64 {
+        let base = if self.full_width_writes {
+            MsrAddress::IA32_A_PMC0
+        } else {
+            MsrAddress::IA32_PMC0
+        };
+        base as i64 + i64::from(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perf_capabilities() {
+        // Skylake server
+        let caps = PerfCapabilities::from_raw(0x33c5);
+        assert_eq!(caps.lbr_format, 5);
+        assert!(caps.pebs_trap && caps.pebs_arch_regs);
+        assert_eq!(caps.pebs_format, 3);
+        assert!(caps.smm_freeze && caps.full_width_writes);
+        assert!(!caps.pebs_baseline && !caps.perf_metrics && !caps.pebs_output_pt);
+        assert!(caps.lbr_has_mispredict() && caps.lbr_has_info());
+        assert_eq!(caps.pmc_write_address(1), 0x4c2);
+        let caps = PerfCapabilities::from_raw(0x3);
+        assert!(caps.lbr_has_mispredict() && !caps.lbr_has_info());
+        assert_eq!(caps.pmc_write_address(1), 0xc2);
+        for &(format, info) in &[(4, false), (6, false), (7, true), (0x3f, false)] {
+            assert_eq!(PerfCapabilities::from_raw(format).lbr_has_info(), info);
+        }
+
+        // The msr kernel module is not loaded
+        if let Ok(msrs) = MsrSet::new(&[0]) {
+            let caps = PerfCapabilities::read_all(&msrs).unwrap();
+            assert_eq!(caps[0].0, 0);
+            assert_eq!(
+                caps[0].1,
+                PerfCapabilities::read(msrs.handle(0).unwrap()).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Register layout used by perf when sampling registers.
+
+/// Indices of the registers in the register dumps of sampled perf events.
+///
+/// See `arch/x86/include/uapi/asm/perf_regs.h` in the Linux kernel source for details.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types, missing_docs)]
+pub enum PerfRegister {
+    PERF_REG_X86_AX = 0,
+    PERF_REG_X86_BX = 1,
+    PERF_REG_X86_CX = 2,
+    PERF_REG_X86_DX = 3,
+    PERF_REG_X86_SI = 4,
+    PERF_REG_X86_DI = 5,
+    PERF_REG_X86_BP = 6,
+    PERF_REG_X86_SP = 7,
+    PERF_REG_X86_IP = 8,
+    PERF_REG_X86_FLAGS = 9,
+    PERF_REG_X86_CS = 10,
+    PERF_REG_X86_SS = 11,
+    PERF_REG_X86_DS = 12,
+    PERF_REG_X86_ES = 13,
+    PERF_REG_X86_FS = 14,
+    PERF_REG_X86_GS = 15,
+    PERF_REG_X86_R8 = 16,
+    PERF_REG_X86_R9 = 17,
+    PERF_REG_X86_R10 = 18,
+    PERF_REG_X86_R11 = 19,
+    PERF_REG_X86_R12 = 20,
+    PERF_REG_X86_R13 = 21,
+    PERF_REG_X86_R14 = 22,
+    PERF_REG_X86_R15 = 23,
+}
+
+/// Default mask of user registers to sample, e.g., for user-space unwinding.
+///
+/// The segment registers `DS`, `ES`, `FS` and `GS` are not saved in 64-bit mode and are skipped.
+pub const SAMPLE_REGS_USER_DEFAULT: u64 = ((1 << (PerfRegister::PERF_REG_X86_R15 as u64 + 1)) - 1)
+    & !((1 << PerfRegister::PERF_REG_X86_DS as u64)
+        | (1 << PerfRegister::PERF_REG_X86_ES as u64)
+        | (1 << PerfRegister::PERF_REG_X86_FS as u64)
+        | (1 << PerfRegister::PERF_REG_X86_GS as u64));
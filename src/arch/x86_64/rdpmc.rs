@@ -5,16 +5,20 @@ use crate::perf::*;
 use crate::{Error, Result};
 
 extern "C" {
-    fn rdtsc() -> u64;
     fn rdpmc(counter: u32) -> i64;
 }
 
 /// Read a counter using the `rdpmc` instruction from it's `perf_event_mmap_page`.
 ///
-/// The function returns (value, time_enabled, time_running).
+/// The function returns (value, time_enabled, time_running). Fails with
+/// `Error::DirectReadUnavailable` if the kernel does not allow reading the counter with `rdpmc`,
+/// e.g., for software events or if `/sys/devices/cpu/rdpmc` is `0`. The counter has to be read
+/// with `read` instead.
 pub fn read_counter_rdpmc(buf: &ffi::perf_event_mmap_page) -> Result<(u64, u64, u64)> {
     if unsafe { buf.__bindgen_anon_1.__bindgen_anon_1.cap_user_rdpmc() == 0 } {
-        return Err(Error::KernelCapabilityError);
+        return Err(Error::DirectReadUnavailable(
+            "cap_user_rdpmc is not set for the event".into(),
+        ));
     }
     let mut res: u64;
     let mut enabled = std::num::Wrapping(0u64);
@@ -31,7 +35,7 @@ pub fn read_counter_rdpmc(buf: &ffi::perf_event_mmap_page) -> Result<(u64, u64,
         let delta: u64 = if unsafe { buf.__bindgen_anon_1.__bindgen_anon_1.cap_user_time() } == 1
             && enabled != running
         {
-            let cycles = unsafe { rdtsc() };
+            let cycles = super::rdtsc();
             let time_shift = volatile!(buf.time_shift);
             let time_offset = volatile!(buf.time_offset);
             let time_mult = volatile!(buf.time_mult) as u64;
@@ -43,28 +47,24 @@ pub fn read_counter_rdpmc(buf: &ffi::perf_event_mmap_page) -> Result<(u64, u64,
         };
         enabled += std::num::Wrapping(delta);
 
-        // Check of index of register to be read. 0 means counter is not active.
+        // Check of index of register to be read. 0 means counter is not active, in which case
+        // the kernel keeps the whole count in offset.
         let idx = volatile!(buf.index);
-        if idx == 0 {
-            res = !0;
-            break;
-        }
-
-        // Do the measurement + sign extend result
-        let mut val = unsafe { rdpmc(idx - 1) };
-        let width = volatile!(buf.pmc_width);
-        val <<= 64 - width;
-        val >>= 64 - width;
-        // count is the counter value read by the kernel in the previously + sign extend result
         let mut count = volatile!(buf.offset);
-        count <<= 64 - width;
-        count >>= 64 - width;
-        res = (count + val) as _;
-        running += std::num::Wrapping(delta);
+        if idx != 0 {
+            // Do the measurement + sign extend result
+            let mut val = unsafe { rdpmc(idx - 1) };
+            let width = volatile!(buf.pmc_width);
+            val <<= 64 - width;
+            val >>= 64 - width;
+            count = count.wrapping_add(val);
+            running += std::num::Wrapping(delta);
+        }
+        res = count as _;
         std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
 
-        // Check if an update happened while this loop was executing and rety
-        if seq != volatile!(buf.lock) {
+        // Retry if an update happened while this loop was executing
+        if seq == volatile!(buf.lock) {
             break;
         }
     }
@@ -82,7 +82,9 @@ impl HardwareCounter<PerfEventValue> for PerfEvent {
                 id: !0,
             })
         } else {
-            Err(Error::NoneError)
+            Err(Error::DirectReadUnavailable(
+                "the event has no mapped metadata page, open it with enable_sampling".into(),
+            ))
         }
     }
 }
@@ -120,4 +122,17 @@ mod tests {
         assert!(count.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn test_rdpmc_unavailable() {
+        let unavailable =
+            |evt: &PerfEvent| matches!(evt.read_direct(), Err(Error::DirectReadUnavailable(_)));
+        let evt = PerfEvent::software(SwId::TaskClock).open(None).unwrap();
+        assert!(unavailable(&evt));
+        let evt = PerfEvent::software(SwId::TaskClock)
+            .enable_sampling()
+            .open(None)
+            .unwrap();
+        assert!(unavailable(&evt));
+    }
 }
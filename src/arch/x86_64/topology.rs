@@ -0,0 +1,260 @@
+//! Enumeration of the topology of the logical processors from the `cpuid` instruction.
+
+use crate::{Error, Result};
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use log::debug;
+use nix::sched::{sched_getaffinity, sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+
+/// Level type of the SMT level in the extended topology leaves of `cpuid`.
+const LEVEL_SMT: u32 = 1;
+/// Level type of the core level in the extended topology leaves of `cpuid`.
+const LEVEL_CORE: u32 = 2;
+/// Level type of the module level in the extended topology leaves of `cpuid`.
+const LEVEL_MODULE: u32 = 3;
+/// Level type of the tile level in the extended topology leaves of `cpuid`.
+const LEVEL_TILE: u32 = 4;
+
+/// Layout of the IDs of the topology levels in the x2APIC ID of a logical processor.
+///
+/// Each shift is the number of low bits of the x2APIC ID identifying a logical processor within
+/// the next level, e.g., `package` bits identify it within its package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopologyShifts {
+    /// Bits identifying a thread within its core.
+    pub smt: u32,
+    /// Bits identifying a thread within its die, including modules and tiles.
+    pub core: u32,
+    /// Bits identifying a thread within its package.
+    pub package: u32,
+    /// Bits identifying a thread among the threads sharing its last level cache.
+    pub llc: u32,
+}
+
+impl TopologyShifts {
+    /// Get the shifts from the `(level type, shift)` of each level reported by leaf 0x1F or 0xB of
+    /// `cpuid`, starting with the lowest level, and the shift of the last level cache `llc`.
+    ///
+    /// Returns `None` if no level is reported.
+    pub fn from_levels(levels: &[(u32, u32)], llc: Option<u32>) -> Option<Self> {
+        let package = levels.last()?.1;
+        let shift_of = |types: &[u32]| {
+            levels
+                .iter()
+                .filter(|(t, _)| types.contains(t))
+                .map(|&(_, s)| s)
+                .max()
+        };
+        let smt = shift_of(&[LEVEL_SMT]).unwrap_or(0);
+        let core = shift_of(&[LEVEL_SMT, LEVEL_CORE, LEVEL_MODULE, LEVEL_TILE]).unwrap_or(smt);
+        Some(TopologyShifts {
+            smt,
+            core,
+            package,
+            llc: llc.unwrap_or(package),
+        })
+    }
+
+    /// Get the shifts of the logical processor executing this function, with its x2APIC ID.
+    ///
+    /// Returns `None` if the extended topology leaves are not supported.
+    #[allow(unused_unsafe)]
+    pub fn detect() -> Option<(Self, u32)> {
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        let leaf = [0x1F, 0xB]
+            .iter()
+            .copied()
+            .find(|&l| max_leaf >= l && unsafe { __cpuid_count(l, 0) }.ebx != 0)?;
+        let mut levels = Vec::new();
+        let mut apic_id = 0;
+        for subleaf in 0.. {
+            let res = unsafe { __cpuid_count(leaf, subleaf) };
+            let level_type = (res.ecx >> 8) & 0xff;
+            if level_type == 0 {
+                break;
+            }
+            levels.push((level_type, res.eax & 0x1f));
+            apic_id = res.edx;
+        }
+        let llc = if max_leaf >= 4 { _llc_shift() } else { None };
+        Some((TopologyShifts::from_levels(&levels, llc)?, apic_id))
+    }
+}
+
+/// Get the number of bits of the x2APIC ID identifying a thread among the threads sharing the
+/// last level cache, from the deterministic cache parameters leaf 4 of `cpuid`.
+#[allow(unused_unsafe)]
+fn _llc_shift() -> Option<u32> {
+    let mut llc = None;
+    for subleaf in 0.. {
+        let res = unsafe { __cpuid_count(4, subleaf) };
+        // No more caches
+        if res.eax & 0x1f == 0 {
+            break;
+        }
+        let level = (res.eax >> 5) & 0x7;
+        let sharing = ((res.eax >> 14) & 0xfff) + 1;
+        if !matches!(llc, Some((l, _)) if level < l) {
+            llc = Some((level, 32 - (sharing - 1).leading_zeros()));
+        }
+    }
+    llc.map(|(_, shift)| shift)
+}
+
+/// Location of a logical processor in the topology of the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuTopology {
+    /// Number of the logical processor used by the kernel.
+    pub cpu: u32,
+    /// x2APIC ID of the logical processor.
+    pub apic_id: u32,
+    /// ID of the package (socket).
+    pub package: u32,
+    /// ID of the die within the package.
+    pub die: u32,
+    /// ID of the core within the die.
+    pub core: u32,
+    /// ID of the thread within the core.
+    pub thread: u32,
+    /// ID of the last level cache within the package.
+    pub llc: u32,
+}
+
+impl CpuTopology {
+    /// Get the location of `cpu` from its x2APIC ID `apic_id`.
+    pub fn from_apic_id(cpu: u32, apic_id: u32, shifts: &TopologyShifts) -> Self {
+        let bits = |id: u32, lo: u32, hi: u32| {
+            if hi <= lo {
+                0
+            } else {
+                (id >> lo) & (((1u64 << (hi - lo)) - 1) as u32)
+            }
+        };
+        CpuTopology {
+            cpu,
+            apic_id,
+            package: apic_id.checked_shr(shifts.package).unwrap_or(0),
+            die: bits(apic_id, shifts.core, shifts.package),
+            core: bits(apic_id, shifts.smt, shifts.core),
+            thread: bits(apic_id, 0, shifts.smt),
+            llc: bits(apic_id, shifts.llc, shifts.package),
+        }
+    }
+}
+
+/// Topology of the online logical processors, enumerated with `cpuid`.
+///
+/// Unlike the topology reported in sysfs, this is also available in containers and on systems
+/// that hide `/sys/devices/system/cpu/cpuN/topology`.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    /// Location of each online logical processor, sorted by the number of the processor.
+    cpus: Vec<CpuTopology>,
+}
+
+impl Topology {
+    /// Enumerate the topology of the online logical processors the calling thread may run on.
+    ///
+    /// Each processor is queried from a helper thread pinned to it, so the affinity of the calling
+    /// thread is not changed. Processors outside of the affinity of the calling thread, e.g., those
+    /// excluded by `taskset` or the cpuset of a container, cannot be queried and are skipped. Fails with `Error::NotImplemented` if the CPU does not support the
+    /// extended topology leaves of `cpuid`.
+    pub fn detect() -> Result<Self> {
+        let online = crate::util::online_cpus()?;
+        let cpus = std::thread::spawn(move || -> Result<Vec<CpuTopology>> {
+            let allowed = sched_getaffinity(Pid::from_raw(0))?;
+            let mut cpus = Vec::with_capacity(online.len());
+            for cpu in online {
+                if !allowed.is_set(cpu as usize)? {
+                    continue;
+                }
+                let mut set = CpuSet::new();
+                set.set(cpu as usize)?;
+                sched_setaffinity(Pid::from_raw(0), &set)?;
+                let (shifts, apic_id) = TopologyShifts::detect().ok_or(Error::NotImplemented)?;
+                cpus.push(CpuTopology::from_apic_id(cpu as u32, apic_id, &shifts));
+            }
+            Ok(cpus)
+        })
+        .join()
+        .map_err(|_| Error::NoneError)??;
+        debug!("Detected topology with cpuid - {:?}", cpus);
+        Ok(Topology { cpus })
+    }
+
+    /// Create the topology from the locations of the logical processors.
+    pub fn from_cpus(mut cpus: Vec<CpuTopology>) -> Self {
+        cpus.sort_unstable_by_key(|c| c.cpu);
+        Topology { cpus }
+    }
+
+    /// Get the location of each online logical processor.
+    pub fn cpus(&self) -> &[CpuTopology] {
+        &self.cpus
+    }
+
+    /// Get the first logical processor of each package as `(package, cpu)`, sorted by package.
+    pub fn package_cpus(&self) -> Vec<(u32, u32)> {
+        let mut packages: Vec<(u32, u32)> = Vec::new();
+        for c in self.cpus.iter() {
+            if !packages.iter().any(|&(p, _)| p == c.package) {
+                packages.push((c.package, c.cpu));
+            }
+        }
+        packages.sort_unstable();
+        packages
+    }
+
+    /// Get the number of physical cores.
+    pub fn num_cores(&self) -> usize {
+        let mut cores: Vec<(u32, u32, u32)> = self
+            .cpus
+            .iter()
+            .map(|c| (c.package, c.die, c.core))
+            .collect();
+        cores.sort_unstable();
+        cores.dedup();
+        cores.len()
+    }
+
+    /// Check if some core runs more than one online logical processor.
+    pub fn smt_active(&self) -> bool {
+        self.num_cores() < self.cpus.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology() {
+        // Two threads per core, 16 cores and 2 dies per package, 8 cores sharing an LLC
+        let levels = [(LEVEL_SMT, 1), (LEVEL_CORE, 5), (5, 6)];
+        let shifts = TopologyShifts::from_levels(&levels, Some(4)).unwrap();
+        assert_eq!((shifts.smt, shifts.core, shifts.package), (1, 5, 6));
+        // Bit 6 is the package, bit 5 the die, bits 4:1 the core and bit 0 the thread
+        let cpu = CpuTopology::from_apic_id(7, 0b110_1011, &shifts);
+        assert_eq!((cpu.package, cpu.die, cpu.core, cpu.thread), (1, 1, 5, 1));
+        assert_eq!(cpu.llc, 0b10);
+        assert!(TopologyShifts::from_levels(&[], None).is_none());
+
+        let cpus = (0..4)
+            .map(|c| CpuTopology::from_apic_id(c, [0, 1, 64, 66][c as usize], &shifts))
+            .collect();
+        let topo = Topology::from_cpus(cpus);
+        assert_eq!(topo.package_cpus(), vec![(0, 0), (1, 2)]);
+        assert_eq!(topo.num_cores(), 3);
+        assert!(topo.smt_active());
+
+        if let Ok(topo) = Topology::detect() {
+            let allowed = sched_getaffinity(Pid::from_raw(0)).unwrap();
+            let online = crate::util::online_cpus().unwrap();
+            let online = online
+                .iter()
+                .filter(|&&c| allowed.is_set(c as usize).unwrap());
+            assert_eq!(topo.cpus().len(), online.count());
+            assert!(topo.num_cores() <= topo.cpus().len());
+        }
+    }
+}
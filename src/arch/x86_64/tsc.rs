@@ -0,0 +1,188 @@
+//! Utilities to read the time stamp counter (TSC) and convert its cycles to nanoseconds.
+
+use crate::perf::{ffi, PerfEvent, SwId};
+use crate::{Error, Result};
+use log::debug;
+use nix::libc;
+use std::time::Duration;
+
+extern "C" {
+    #[link_name = "rdtsc"]
+    fn _rdtsc() -> u64;
+    #[link_name = "rdtsc_ordered"]
+    fn _rdtsc_ordered() -> u64;
+    #[link_name = "rdtscp"]
+    fn _rdtscp(aux: *mut u32) -> u64;
+}
+
+/// Read the TSC with the `rdtsc` instruction.
+///
+/// The read is not ordered with respect to the surrounding instructions, use `rdtsc_ordered` or
+/// `rdtscp` to time short code regions.
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Read the TSC with the `rdtsc` instruction once all previous instructions have completed.
+pub fn rdtsc_ordered() -> u64 {
+    unsafe { _rdtsc_ordered() }
+}
+
+/// Read the TSC with the `rdtscp` instruction, which waits for all previous instructions to
+/// complete.
+///
+/// Returns the TSC and the value of `IA32_TSC_AUX`, in which Linux stores the CPU and the NUMA
+/// node the instruction was executed on.
+pub fn rdtscp() -> (u64, u32) {
+    let mut aux = 0;
+    let tsc = unsafe { _rdtscp(&mut aux) };
+    (tsc, aux)
+}
+
+/// Conversion of TSC cycles to nanoseconds, as described by the `time_*` fields of the
+/// `perf_event_mmap_page` of an event.
+///
+/// Nanoseconds are calculated as `zero + cycles * mult / 2^shift`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TscClock {
+    /// Multiplier of the cycles.
+    pub mult: u32,
+    /// Shift of the product of the cycles with `mult`.
+    pub shift: u16,
+    /// Time in nanoseconds at which the TSC was `0`.
+    pub zero: u64,
+}
+
+impl TscClock {
+    /// Get the conversion published by the kernel in `buf`, if the TSC is usable as a clock.
+    ///
+    /// The resulting nanoseconds are those of the clock used by perf for timestamps of records.
+    pub fn from_mmap_page(buf: &ffi::perf_event_mmap_page) -> Option<Self> {
+        let caps = unsafe { &buf.__bindgen_anon_1.__bindgen_anon_1 };
+        if caps.cap_user_time() == 0 || caps.cap_user_time_zero() == 0 {
+            return None;
+        }
+        let clock = TscClock {
+            mult: volatile!(buf.time_mult),
+            shift: volatile!(buf.time_shift),
+            zero: volatile!(buf.time_zero),
+        };
+        Some(clock).filter(|c| c.mult != 0)
+    }
+
+    /// Determine the frequency of the TSC and its relation to nanoseconds.
+    ///
+    /// The conversion is read from the metadata page of a perf event if the kernel publishes it.
+    /// Otherwise, the TSC is measured against `CLOCK_MONOTONIC_RAW`, which blocks for about 10ms.
+    pub fn calibrate() -> Result<Self> {
+        match TscClock::_from_perf() {
+            Ok(clock) => Ok(clock),
+            Err(e) => {
+                debug!("Calibrating TSC against CLOCK_MONOTONIC_RAW -- {:?}", e);
+                TscClock::from_monotonic_raw(Duration::from_millis(10))
+            }
+        }
+    }
+
+    /// Get the conversion from the metadata page of a software event.
+    fn _from_perf() -> Result<Self> {
+        let evt = PerfEvent::software(SwId::TaskClock)
+            .set_period(1_000_000_000)
+            .enable_sampling()
+            .requested_size(0)
+            .open(None)?;
+        let rb = evt.ring_buffer.as_ref().ok_or(Error::NoneError)?;
+        TscClock::from_mmap_page(unsafe { &*rb.header }).ok_or_else(|| {
+            Error::DirectReadUnavailable("cap_user_time_zero is not set for the event".into())
+        })
+    }
+
+    /// Determine the conversion by measuring the cycles of the TSC during `interval` of
+    /// `CLOCK_MONOTONIC_RAW`.
+    pub fn from_monotonic_raw(interval: Duration) -> Result<Self> {
+        let (start_ns, start_tsc) = _monotonic_raw_with_tsc()?;
+        std::thread::sleep(interval);
+        let (end_ns, end_tsc) = _monotonic_raw_with_tsc()?;
+        let (ns, cycles) = (end_ns - start_ns, end_tsc.wrapping_sub(start_tsc));
+        if ns == 0 || cycles == 0 {
+            return Err(Error::NoneError);
+        }
+
+        // Use the largest shift for which the multiplier fits into 32 bits
+        let shift = (0..=32u16)
+            .rev()
+            .find(|&s| ((u128::from(ns) << s) / u128::from(cycles)) <= u128::from(u32::MAX))
+            .ok_or(Error::NoneError)?;
+        let mut clock = TscClock {
+            mult: ((u128::from(ns) << shift) / u128::from(cycles)) as u32,
+            shift,
+            zero: 0,
+        };
+        clock.zero = end_ns.wrapping_sub(clock.to_nanos(end_tsc));
+        Ok(clock)
+    }
+
+    /// Get the frequency of the TSC in Hz.
+    pub fn frequency(&self) -> u64 {
+        ((1_000_000_000u128 << self.shift) / u128::from(self.mult)) as u64
+    }
+
+    /// Convert the TSC value `cycles` to nanoseconds.
+    pub fn to_nanos(&self, cycles: u64) -> u64 {
+        let quot = cycles >> self.shift;
+        let rem = cycles & ((1u64 << self.shift) - 1);
+        let mult = u64::from(self.mult);
+        self.zero
+            .wrapping_add(quot.wrapping_mul(mult))
+            .wrapping_add((rem * mult) >> self.shift)
+    }
+
+    /// Convert the difference of two TSC values to a `Duration`.
+    pub fn to_duration(&self, cycles: u64) -> Duration {
+        let clock = TscClock { zero: 0, ..*self };
+        Duration::from_nanos(clock.to_nanos(cycles))
+    }
+}
+
+/// Read `CLOCK_MONOTONIC_RAW` in nanoseconds together with the TSC at the same time.
+fn _monotonic_raw_with_tsc() -> Result<(u64, u64)> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let before = rdtsc_ordered();
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut ts) } != 0 {
+        return Err(Error::from_errno());
+    }
+    let after = rdtsc_ordered();
+    let ns = ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+    Ok((ns, before + (after - before) / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tsc_clock() {
+        let start = rdtsc_ordered();
+        let (end, _) = rdtscp();
+        assert!(end >= start && rdtsc() >= end);
+
+        let clock = TscClock {
+            mult: 1 << 31,
+            shift: 31,
+            zero: 100,
+        };
+        assert_eq!(clock.frequency(), 1_000_000_000);
+        assert_eq!(clock.to_nanos(1_000), 1_100);
+        assert_eq!(clock.to_duration(1_000), Duration::from_nanos(1_000));
+
+        let raw = TscClock::from_monotonic_raw(Duration::from_millis(10)).unwrap();
+        let clock = TscClock::calibrate().unwrap();
+        let (f1, f2) = (raw.frequency() as f64, clock.frequency() as f64);
+        assert!((f1 - f2).abs() / f2 < 0.05, "{} vs {}", f1, f2);
+        let elapsed = raw.to_duration(rdtsc_ordered() - start);
+        assert!(elapsed > Duration::from_millis(10) && elapsed < Duration::from_secs(10));
+    }
+}
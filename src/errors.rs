@@ -30,6 +30,9 @@ pub enum Error {
     /// Errors caused by malformed metric expression strings for PMU events.
     #[fail(display = "Parse Error - {}", _0)]
     ParseMetricExpr(#[cause] pest::error::Error<crate::registry::Rule>),
+    /// Errors caused by malformed JSON data.
+    #[fail(display = "JSON Error - {}", _0)]
+    Json(#[cause] serde_json::Error),
     /// Errors originating from calls to `libc` or other system utilties.
     #[fail(display = "System Error - {}", _0)]
     System(#[cause] nix::Error),
@@ -39,9 +42,62 @@ pub enum Error {
     /// unimplemented.
     #[fail(display = "Error while parsing PMU JSON files - {:?}", _0)]
     ParseEvent(crate::registry::RawEvent),
+    /// Errors caused by malformed event names or descriptions.
+    #[fail(display = "Invalid event - {}", _0)]
+    #[from(ignore)]
+    InvalidEvent(String),
     /// Caused when a `None` value is read.
     #[fail(display = "Tried to read a None value")]
     NoneError,
+    /// Errors caused by events that cannot be scheduled onto the counters together.
+    ///
+    /// Contains the names of the conflicting events.
+    #[fail(display = "Events cannot be scheduled together - {:?}", _0)]
+    #[from(ignore)]
+    CounterConflict(Vec<String>),
+    /// Errors caused by invalid settings of a `PerfEventBuilder`.
+    #[fail(display = "Invalid setting {} - {}", setting, reason)]
+    #[from(ignore)]
+    InvalidSetting {
+        /// Name of the builder method used to configure the setting.
+        setting: &'static str,
+        /// Why the setting is invalid.
+        reason: String,
+    },
+    /// Errors caused by operations the current process is not permitted to perform.
+    #[fail(display = "Not permitted to {} - {}", operation, hint)]
+    #[from(ignore)]
+    NotPermitted {
+        /// The operation that is not permitted.
+        operation: crate::perf::Operation,
+        /// How to permit the operation.
+        hint: String,
+    },
+    /// Errors caused by counters that cannot be read from user space, e.g., with `rdpmc`.
+    #[fail(display = "Counter cannot be read directly - {}", _0)]
+    #[from(ignore)]
+    DirectReadUnavailable(String),
+    /// Errors caused by model specific registers that cannot be accessed.
+    #[fail(display = "Cannot access MSRs of CPU {} - {}", cpu, reason)]
+    #[from(ignore)]
+    MsrUnavailable {
+        /// CPU whose MSRs cannot be accessed.
+        cpu: u32,
+        /// Why the MSRs cannot be accessed.
+        reason: String,
+    },
+    /// Errors caused by malformed Intel PT trace data.
+    #[fail(
+        display = "Cannot decode Intel PT packet at offset {} - {}",
+        offset, reason
+    )]
+    #[from(ignore)]
+    PtDecode {
+        /// Offset of the malformed packet in the trace data.
+        offset: usize,
+        /// Why the packet cannot be decoded.
+        reason: String,
+    },
     /// Errors caused by capability checks on the kernel.
     #[fail(display = "Not allowed by kernel")]
     KernelCapabilityError,
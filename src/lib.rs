@@ -30,5 +30,9 @@ pub mod arch;
 #[path = "arch/powerpc64/mod.rs"]
 pub mod arch;
 
+#[cfg(target_arch = "s390x")]
+#[path = "arch/s390x/mod.rs"]
+pub mod arch;
+
 mod pci;
 pub use pci::PciHandle;
@@ -0,0 +1,217 @@
+//! Checking which perf operations the current process is permitted to perform.
+
+use crate::perf::tracefs;
+use crate::{Error, Result};
+use std::fmt;
+
+/// Bit of `CAP_SYS_ADMIN` in the capability sets of a process.
+const CAP_SYS_ADMIN: u32 = 21;
+/// Bit of `CAP_SYSLOG` in the capability sets of a process.
+const CAP_SYSLOG: u32 = 34;
+/// Bit of `CAP_PERFMON` in the capability sets of a process, available since Linux 5.8.
+const CAP_PERFMON: u32 = 38;
+
+/// Operation whose permission is reported by `Capabilities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Count and sample events of the current user in the kernel, i.e., without `exclude_kernel`.
+    KernelSampling,
+    /// Monitor all processes on a CPU, e.g., for system-wide or uncore events.
+    SystemWide,
+    /// Sample tracepoints together with their raw data.
+    Tracepoints,
+    /// Count events of the current user by their raw hardware codes.
+    RawEvents,
+    /// Read the addresses of kernel symbols from `/proc/kallsyms`, e.g., to symbolize samples.
+    KernelSymbols,
+}
+
+impl Operation {
+    /// All operations, in the order they are reported in.
+    pub const ALL: [Operation; 5] = [
+        Operation::RawEvents,
+        Operation::KernelSampling,
+        Operation::SystemWide,
+        Operation::Tracepoints,
+        Operation::KernelSymbols,
+    ];
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::KernelSampling => "monitor the kernel",
+            Operation::SystemWide => "monitor all processes on a CPU",
+            Operation::Tracepoints => "sample tracepoints",
+            Operation::RawEvents => "open raw events",
+            Operation::KernelSymbols => "read kernel symbol addresses",
+        })
+    }
+}
+
+/// Permissions of the current process to use the perf interface.
+///
+/// Allows tools to fail early with an explanation of how to grant a missing permission, instead of
+/// with an `EACCES` from `perf_event_open`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Value of `kernel.perf_event_paranoid`.
+    pub paranoid: i32,
+    /// Value of `kernel.kptr_restrict`.
+    pub kptr_restrict: i32,
+    /// The process has `CAP_PERFMON`.
+    pub perfmon: bool,
+    /// The process has `CAP_SYS_ADMIN`.
+    pub sys_admin: bool,
+    /// The process has `CAP_SYSLOG`.
+    pub syslog: bool,
+    /// The tracing filesystem is mounted and readable.
+    pub tracefs: bool,
+}
+
+impl Capabilities {
+    /// Detect the permissions of the current process.
+    ///
+    /// Fails if the kernel does not support perf events.
+    pub fn detect() -> Result<Self> {
+        let paranoid =
+            _read_sysctl("perf_event_paranoid").map_err(|_| Error::KernelCapabilityError)?;
+        let caps = _effective_caps().unwrap_or(0);
+        let has = |cap: u32| caps & (1 << cap) != 0;
+        Ok(Capabilities {
+            paranoid,
+            kptr_restrict: _read_sysctl("kptr_restrict").unwrap_or(2),
+            perfmon: has(CAP_PERFMON),
+            sys_admin: has(CAP_SYS_ADMIN),
+            syslog: has(CAP_SYSLOG),
+            tracefs: tracefs::tracefs_root()
+                .ok()
+                .and_then(|p| std::fs::read_dir(p.join("events")).ok())
+                .is_some(),
+        })
+    }
+
+    /// Check if the process is exempt from `perf_event_paranoid`.
+    pub fn is_privileged(&self) -> bool {
+        self.perfmon || self.sys_admin
+    }
+
+    /// Check if `op` is permitted.
+    pub fn allows(&self, op: Operation) -> bool {
+        let privileged = self.is_privileged();
+        match op {
+            Operation::RawEvents => privileged || self.paranoid <= 2,
+            Operation::KernelSampling => privileged || self.paranoid <= 1,
+            Operation::SystemWide => privileged || self.paranoid <= 0,
+            Operation::Tracepoints => self.tracefs && (privileged || self.paranoid < 0),
+            Operation::KernelSymbols => {
+                (self.kptr_restrict == 0 && self.paranoid <= 1)
+                    || (self.kptr_restrict <= 1 && self.syslog)
+            }
+        }
+    }
+
+    /// Check if `op` is permitted, failing with a description of how to permit it otherwise.
+    pub fn check(&self, op: Operation) -> Result<()> {
+        if self.allows(op) {
+            return Ok(());
+        }
+        let paranoid = |level: i32| {
+            format!(
+                "kernel.perf_event_paranoid is {}, set it to {} or lower, or grant CAP_PERFMON",
+                self.paranoid, level
+            )
+        };
+        let hint = match op {
+            Operation::RawEvents => paranoid(2),
+            Operation::KernelSampling => paranoid(1),
+            Operation::SystemWide => paranoid(0),
+            Operation::Tracepoints if !self.tracefs => {
+                String::from("tracefs is not mounted at /sys/kernel/tracing or is not readable")
+            }
+            Operation::Tracepoints => paranoid(-1),
+            Operation::KernelSymbols => format!(
+                "kernel.kptr_restrict is {}, set it to 0 and kernel.perf_event_paranoid to 1 or \
+                 lower, or set it to 1 and grant CAP_SYSLOG",
+                self.kptr_restrict
+            ),
+        };
+        Err(Error::NotPermitted {
+            operation: op,
+            hint,
+        })
+    }
+
+    /// Get whether each operation is permitted.
+    pub fn report(&self) -> Vec<(Operation, bool)> {
+        Operation::ALL
+            .iter()
+            .map(|&op| (op, self.allows(op)))
+            .collect()
+    }
+}
+
+/// Read the integer value of the sysctl `kernel.{name}`.
+fn _read_sysctl(name: &str) -> Result<i32> {
+    let path = format!("/proc/sys/kernel/{}", name);
+    Ok(std::fs::read_to_string(path)?.trim().parse()?)
+}
+
+/// Get the effective capability set of the current process.
+fn _effective_caps() -> Result<u64> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    let caps = status
+        .lines()
+        .find_map(|l| l.strip_prefix("CapEff:"))
+        .ok_or(Error::NoneError)?;
+    Ok(u64::from_str_radix(caps.trim(), 16)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = Capabilities::detect().unwrap();
+        assert_eq!(caps.report().len(), Operation::ALL.len());
+        assert_eq!(
+            caps.check(Operation::RawEvents).is_ok(),
+            caps.allows(Operation::RawEvents)
+        );
+
+        let caps = Capabilities {
+            paranoid: 2,
+            kptr_restrict: 1,
+            perfmon: false,
+            sys_admin: false,
+            syslog: false,
+            tracefs: true,
+        };
+        let allowed: Vec<Operation> = caps
+            .report()
+            .into_iter()
+            .filter(|&(_, a)| a)
+            .map(|(op, _)| op)
+            .collect();
+        assert_eq!(allowed, vec![Operation::RawEvents]);
+        match caps.check(Operation::SystemWide) {
+            Err(Error::NotPermitted { operation, hint }) => {
+                assert_eq!(operation, Operation::SystemWide);
+                assert!(hint.contains("set it to 0"));
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        let caps = Capabilities {
+            perfmon: true,
+            syslog: true,
+            tracefs: false,
+            ..caps
+        };
+        assert!(caps.allows(Operation::SystemWide));
+        assert!(caps.allows(Operation::KernelSymbols));
+        assert!(!caps.allows(Operation::Tracepoints));
+        assert!(caps.check(Operation::KernelSampling).is_ok());
+    }
+}
@@ -8,6 +8,7 @@ use byteorder::NativeEndian;
 use byteorder::ReadBytesExt;
 use log::debug;
 use nix::libc;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 
@@ -49,6 +50,37 @@ impl PerfEventValue {
             id: ptr.read_u64::<NativeEndian>()?,
         })
     }
+
+    /// Sum `values` of the same event measured by different counters.
+    ///
+    /// The `id` of the result is that of the first value.
+    pub(crate) fn _sum<I: IntoIterator<Item = PerfEventValue>>(values: I) -> Self {
+        let mut total = PerfEventValue {
+            value: 0,
+            time_enabled: 0,
+            time_running: 0,
+            id: 0,
+        };
+        for (i, val) in values.into_iter().enumerate() {
+            total.value += val.value;
+            total.time_enabled += val.time_enabled;
+            total.time_running += val.time_running;
+            if i == 0 {
+                total.id = val.id;
+            }
+        }
+        total
+    }
+
+    /// Get the change of this value since the value `prev` read earlier from the same counter.
+    pub fn delta(&self, prev: &PerfEventValue) -> Self {
+        PerfEventValue {
+            value: self.value.wrapping_sub(prev.value),
+            time_enabled: self.time_enabled.wrapping_sub(prev.time_enabled),
+            time_running: self.time_running.wrapping_sub(prev.time_running),
+            id: self.id,
+        }
+    }
 }
 
 impl ScaledValue<u64> for PerfEventValue {
@@ -56,11 +88,16 @@ impl ScaledValue<u64> for PerfEventValue {
         self.value
     }
 
+    /// Estimate the count over the whole time the event was enabled, assuming the event occurred
+    /// at the same rate while it was not running.
     fn scaled_value(&self) -> u64 {
+        if self.time_running == 0 {
+            return 0;
+        }
         let v = self.value as f64;
         let r = self.time_running as f64;
         let e = self.time_enabled as f64;
-        (v * r / e) as u64
+        (v * e / r) as u64
     }
 }
 
@@ -85,6 +122,10 @@ pub struct PerfEvent {
     file: std::fs::File,
     /// Ring buffer corresponding to underlying perf event.
     pub(crate) ring_buffer: Option<crate::perf::RingBuffer>,
+    /// Overflow notifications of this event are delivered to a callback.
+    notify_overflow: bool,
+    /// Wait for samples by spinning on the ring buffer instead of calling `poll`.
+    busy_poll: bool,
 }
 
 impl Counter<PerfEventValue> for PerfEvent {
@@ -93,27 +134,15 @@ impl Counter<PerfEventValue> for PerfEvent {
     }
 
     fn enable(&self) -> Result<()> {
-        unsafe {
-            ffi::perf_event_ioc_enable(self.file.as_raw_fd())?;
-        }
-        debug!("PerfEvent enabled: {:?}", self.attr);
-        Ok(())
+        self._enable(0)
     }
 
     fn disable(&self) -> Result<()> {
-        unsafe {
-            ffi::perf_event_ioc_disable(self.file.as_raw_fd())?;
-        }
-        debug!("PerfEvent disabled: {:?}", self.attr);
-        Ok(())
+        self._disable(0)
     }
 
     fn reset(&self) -> Result<()> {
-        unsafe {
-            ffi::perf_event_ioc_reset(self.file.as_raw_fd())?;
-        }
-        debug!("PerfEvent reset: {:?}", self.attr);
-        Ok(())
+        self._reset(0)
     }
 
     fn is_closed(&self) -> Result<bool> {
@@ -130,30 +159,23 @@ impl Counter<PerfEventValue> for PerfEvent {
 
 impl SampledCounter<PerfEventValue> for PerfEvent {
     fn read_samples(&mut self) -> Vec<PerfEventValue> {
+        let attr = self.attr;
         if let Some(ref mut rb) = self.ring_buffer {
-            let mut num_evts = 0usize;
-            let evts: Vec<PerfEventValue> = rb
-                .events()
-                .filter_map(|e| {
-                    num_evts += 1;
-                    if e.is_sample() {
-                        match e.parse().unwrap() {
-                            crate::perf::ParsedRecord::Sample(s) => Some(s.value),
-                            _ => unreachable!(),
-                        }
-                    } else {
-                        None
-                    }
+            rb.drain(&attr)
+                .filter_map(|e| match e {
+                    Ok(crate::perf::ParsedRecord::Sample(s)) => s.value,
+                    _ => None,
                 })
-                .collect();
-            self.advance(Some(num_evts));
-            evts
+                .collect()
         } else {
             vec![]
         }
     }
 
     fn unread_events(&self, timeout: libc::c_int) -> Result<bool> {
+        if let (true, Some(ref rb)) = (self.busy_poll, &self.ring_buffer) {
+            return Ok(rb.spin_wait(timeout));
+        }
         let p = self.poll(timeout)?;
         Ok(p.intersects(nix::poll::PollFlags::POLLIN))
     }
@@ -171,6 +193,80 @@ impl PerfEvent {
         PerfEventBuilder::default()
     }
 
+    /// Construct a new generic hardware perf event using the associated builder.
+    pub fn hardware(id: crate::perf::HwId) -> PerfEventBuilder {
+        PerfEventBuilder {
+            name: id.name().into(),
+            event_attr: Some(id.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Construct a new generic software perf event using the associated builder.
+    pub fn software(id: crate::perf::SwId) -> PerfEventBuilder {
+        PerfEventBuilder {
+            name: id.name().into(),
+            event_attr: Some(id.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Enable the event with the ioctl `flags`.
+    fn _enable(&self, flags: libc::c_int) -> Result<()> {
+        unsafe {
+            ffi::perf_event_ioc_enable(self.file.as_raw_fd(), flags)?;
+        }
+        debug!("PerfEvent enabled (flags={}): {:?}", flags, self.attr);
+        Ok(())
+    }
+
+    /// Disable the event with the ioctl `flags`.
+    fn _disable(&self, flags: libc::c_int) -> Result<()> {
+        unsafe {
+            ffi::perf_event_ioc_disable(self.file.as_raw_fd(), flags)?;
+        }
+        debug!("PerfEvent disabled (flags={}): {:?}", flags, self.attr);
+        Ok(())
+    }
+
+    /// Reset the event with the ioctl `flags`.
+    fn _reset(&self, flags: libc::c_int) -> Result<()> {
+        unsafe {
+            ffi::perf_event_ioc_reset(self.file.as_raw_fd(), flags)?;
+        }
+        debug!("PerfEvent reset (flags={}): {:?}", flags, self.attr);
+        Ok(())
+    }
+
+    /// Reset and enable the event, returning a guard that disables it when dropped.
+    ///
+    /// The event is also disabled if the measured code panics, which makes measuring a region of
+    /// code as simple as keeping the guard in scope.
+    pub fn start(&self) -> Result<CountingGuard<'_>> {
+        self.reset()?;
+        self.enable()?;
+        Ok(CountingGuard {
+            event: self,
+            dest: None,
+            stopped: false,
+        })
+    }
+
+    /// Enable all events in the group led by this event with a single atomic ioctl.
+    pub fn enable_group(&self) -> Result<()> {
+        self._enable(ffi::perf_event_ioc_flags::PERF_IOC_FLAG_GROUP as _)
+    }
+
+    /// Disable all events in the group led by this event with a single atomic ioctl.
+    pub fn disable_group(&self) -> Result<()> {
+        self._disable(ffi::perf_event_ioc_flags::PERF_IOC_FLAG_GROUP as _)
+    }
+
+    /// Reset all events in the group led by this event with a single atomic ioctl.
+    pub fn reset_group(&self) -> Result<()> {
+        self._reset(ffi::perf_event_ioc_flags::PERF_IOC_FLAG_GROUP as _)
+    }
+
     /// Write modifications to `self.attr` to the kernel.
     pub fn modify_event_attributes(&mut self) -> Result<()> {
         unsafe {
@@ -195,6 +291,134 @@ impl PerfEvent {
         Ok(())
     }
 
+    /// Enable the event for `count` overflows after which it is disabled again.
+    ///
+    /// Each overflow results in a notification to the callback registered with `on_overflow`.
+    pub fn refresh(&self, count: libc::c_int) -> Result<()> {
+        unsafe {
+            ffi::perf_event_ioc_refresh(self.file.as_raw_fd(), count)?;
+        }
+        debug!("PerfEvent refreshed: {:?}", self.attr);
+        Ok(())
+    }
+
+    /// Deliver overflow notifications of this event to `callback`.
+    ///
+    /// The event's file is configured for asynchronous IO and the resulting `SIGIO` signals are
+    /// handled by this crate. The callback runs on a dispatcher thread shared by all events, so it
+    /// should return quickly, e.g., by sending a message on a channel to a worker thread.
+    ///
+    /// # Note
+    /// The crate installs its own handler for `SIGIO`, replacing any existing handler.
+    pub fn on_overflow<F>(&mut self, callback: F) -> Result<()>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        crate::perf::overflow::register(self.file.as_raw_fd(), Box::new(callback))?;
+        self.notify_overflow = true;
+        Ok(())
+    }
+
+    /// Pause or resume writing of records to the ring buffer of this event.
+    ///
+    /// Records generated while the output is paused are dropped and accounted as lost. This allows
+    /// taking a consistent snapshot of the ring buffer.
+    pub fn pause_output(&self, pause: bool) -> Result<()> {
+        unsafe {
+            ffi::perf_event_ioc_pause_output(self.file.as_raw_fd(), pause as _)?;
+        }
+        debug!("PerfEvent output paused={}: {:?}", pause, self.attr);
+        Ok(())
+    }
+
+    /// Move the ring buffer of this event into a reader that can be used from another thread.
+    ///
+    /// Returns `None` if this event is not sampled or the reader has already been taken. The ring
+    /// buffer is no longer accessible through the event afterwards, e.g., `read_samples` returns
+    /// no samples.
+    pub fn take_reader(&mut self) -> Result<Option<crate::perf::RingBufferReader>> {
+        match self.ring_buffer.take() {
+            Some(rb) => Ok(Some(crate::perf::RingBufferReader::new(
+                rb,
+                self.attr,
+                self.file.as_raw_fd(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Take a snapshot of the records held in the overwrite ring buffer of this event.
+    ///
+    /// The output of the event is paused while the records are copied and resumed afterwards.
+    /// Records are returned newest first. Returns an empty list if the event does not use an
+    /// overwrite ring buffer, see `PerfEventBuilder::use_overwrite_buffer`.
+    pub fn snapshot(&self) -> Result<Vec<crate::perf::ParsedRecord>> {
+        match self.ring_buffer {
+            Some(ref rb) if rb.is_overwrite() => {
+                self.pause_output(true)?;
+                let records = rb.snapshot(&self.attr);
+                self.pause_output(false)?;
+                records
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Attach the BPF program with file descriptor `prog_fd` to this event.
+    ///
+    /// The event must be a kprobe, uprobe or tracepoint event and the program must be of a
+    /// matching type. The program runs in the kernel every time the event fires.
+    pub fn attach_bpf(&self, prog_fd: std::os::unix::io::RawFd) -> Result<()> {
+        unsafe {
+            ffi::perf_event_ioc_set_bpf(self.file.as_raw_fd(), prog_fd as _)?;
+        }
+        debug!(
+            "BPF program {} attached to PerfEvent: {:?}",
+            prog_fd, self.attr
+        );
+        Ok(())
+    }
+
+    /// Get the globally unique id assigned to this event by the kernel.
+    ///
+    /// The id identifies the event in records written to a ring buffer shared by several events.
+    pub fn id(&self) -> Result<u64> {
+        let mut id = 0u64;
+        unsafe {
+            ffi::perf_event_ioc_id(self.file.as_raw_fd(), &mut id as *mut u64)?;
+        }
+        Ok(id)
+    }
+
+    /// Get the name of the event with kernel id `id` writing to the ring buffer of this event.
+    ///
+    /// Returns `None` if this event is not sampled or `id` is unknown.
+    pub fn event_name(&self, id: u64) -> Option<&str> {
+        self.ring_buffer.as_ref().and_then(|rb| rb.event_name(id))
+    }
+
+    /// Get the ids of the BPF programs attached to this event.
+    pub fn query_bpf(&self) -> Result<Vec<u32>> {
+        let mut len = 16usize;
+        loop {
+            // Layout of `perf_event_query_bpf` followed by space for `len` ids
+            let mut buf = vec![0u32; 2 + len];
+            buf[0] = len as u32;
+            let res = unsafe {
+                ffi::perf_event_ioc_query_bpf(
+                    self.file.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut ffi::perf_event_query_bpf,
+                )
+            };
+            let count = buf[1] as usize;
+            match res {
+                Ok(_) => return Ok(buf[2..2 + count].to_vec()),
+                Err(nix::Error::Sys(nix::errno::Errno::ENOSPC)) if count > len => len = count,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
     /// Poll for new events.
     fn poll(&self, timeout: libc::c_int) -> Result<nix::poll::PollFlags> {
         let mut pollfd = [nix::poll::PollFd::new(
@@ -209,237 +433,1140 @@ impl PerfEvent {
     }
 }
 
-/// Helper struct to build a `PerfEvent` object.
-#[derive(Debug)]
-pub struct PerfEventBuilder {
-    /// Name of the event.
-    name: String,
-    /// Target process ID.
-    ///
-    /// Defaults to current process.
-    pid: libc::pid_t,
-    /// Target CPU ID.
-    ///
-    /// Defaults to all CPUs.
-    cpuid: libc::c_int,
-    /// File descriptor for the group leader event.
-    ///
-    /// Defaults to none.
-    leader: libc::c_int,
-    /// Use sampling frequency instead of sampling period.
-    ///
-    /// Defaults to `false`.
-    use_freq: bool,
-    /// Sampling frequency or period based on `use_freq`.
-    ///
-    /// Defaults to period `1`.
-    freq_or_period: u64,
-    /// Should
-    ///
-    /// Defaults to `false`.
-    inherit: bool,
-    /// Should start the counter disabled.
-    ///
-    /// Defaults to  `false`.
-    start_disabled: bool,
-    /// Count for kernel code.
-    ///
-    /// Defaults to `false`.
-    collect_kernel: bool,
-    /// Gather information on context switches.
-    ///
-    /// Defaults to `false`.
-    gather_context_switches: bool,
-    /// This corresponds to a sampled event that is accessed through a ring buffer.
-    ///
-    /// Defaults to false.
-    is_sampled: bool,
-    /// Size of requested ring buffer.
-    ///
-    /// Defaults to 128 * native page size..
-    requested_size: usize,
+impl AsRawFd for PerfEvent {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.file.as_raw_fd()
+    }
 }
 
-impl Default for PerfEventBuilder {
-    fn default() -> Self {
-        PerfEventBuilder {
-            name: String::new(),
-            pid: 0,
-            cpuid: -1,
-            leader: -1,
-            use_freq: false,
-            freq_or_period: 1,
-            inherit: false,
-            start_disabled: false,
-            collect_kernel: false,
-            gather_context_switches: false,
-            is_sampled: false,
-            requested_size: (1 << 7) * *PAGE_SIZE,
+impl Drop for PerfEvent {
+    fn drop(&mut self) {
+        if self.notify_overflow {
+            crate::perf::overflow::unregister(self.file.as_raw_fd());
         }
     }
 }
 
-macro_rules! builder_pattern {
-    ($(#[$outer:meta])* $var_name: ident : $var_type: ty) => {
-        builder_pattern!($(#[$outer])* $var_name => $var_name: $var_type);
-    };
-    ($(#[$outer:meta])* $var_name: ident : $var_type: ty = $val: expr) => {
-        builder_pattern!($(#[$outer])* $var_name => $var_name: $var_type = $val);
-    };
-    ($(#[$outer:meta])* $name: ident => $var_name: ident : $var_type: ty) => {
-        $(#[$outer])*
-        pub fn $name(mut self, $var_name: $var_type) -> Self {
-            self.$var_name = $var_name;
-            self
-        }
-    };
-    ($(#[$outer:meta])* $name: ident => $var_name: ident : $var_type: ty = $val: expr) => {
-        $(#[$outer])*
-        pub fn $name(mut self) -> Self {
-            self.$var_name = $val;
-            self
-        }
-    };
+/// Guard returned by `PerfEvent::start` that disables the event when it is dropped.
+///
+/// `'e` corresponds to the lifetime of the borrow of the `PerfEvent`.
+#[derive(Debug)]
+pub struct CountingGuard<'e> {
+    /// Event counting while the guard is alive.
+    event: &'e PerfEvent,
+    /// Destination of the value of the event read when the guard is dropped.
+    dest: Option<&'e mut Option<PerfEventValue>>,
+    /// The event has already been disabled by `stop`.
+    stopped: bool,
 }
 
-impl PerfEventBuilder {
-    /// Get the maximum allowed sampling frequency of the system.
-    fn _max_sampling_freq() -> Result<u64> {
-        let data = std::fs::read_to_string("/proc/sys/kernel/perf_event_max_sample_rate")?;
-        Ok(data.trim().parse::<u64>().unwrap())
+impl<'e> CountingGuard<'e> {
+    /// Read the value of the event into `dest` when the guard is dropped.
+    ///
+    /// `dest` is set to `None` if reading the value fails.
+    pub fn read_into(mut self, dest: &'e mut Option<PerfEventValue>) -> Self {
+        self.dest = Some(dest);
+        self
     }
 
-    /// Check capabilities of the current system and the configuration of the current builder.
-    ///
-    /// Returns `true` if the check resulted in failure.
-    fn _check_capabilities(&self) -> Result<()> {
-        let freq_check = if self.use_freq {
-            self.freq_or_period > PerfEventBuilder::_max_sampling_freq()?
-        } else {
-            false
-        };
-        if (self.cpuid == -1 && self.inherit) || freq_check {
-            Err(Error::KernelCapabilityError)
-        } else {
-            Ok(())
+    /// Disable the event and read its value.
+    pub fn stop(mut self) -> Result<PerfEventValue> {
+        self.stopped = true;
+        self.event.disable()?;
+        let val = self.event.read_sync()?;
+        if let Some(dest) = self.dest.take() {
+            *dest = Some(val.clone());
         }
+        Ok(val)
     }
+}
 
-    /// Set the fields of an perf_event_attr based on this builder.
-    fn _set_attr_config(&self, attr: &mut ffi::perf_event_attr) {
-        use ffi::perf_event_read_format::*;
-        use ffi::perf_event_sample_format::*;
-        attr.size = std::mem::size_of::<ffi::perf_event_attr>()
-            .try_into()
-            .unwrap();
-        if self.is_sampled {
-            attr.read_format = PERF_FORMAT_ID as u64
-                | PERF_FORMAT_TOTAL_TIME_RUNNING as u64
-                | PERF_FORMAT_TOTAL_TIME_ENABLED as u64;
-            attr.sample_type = PERF_SAMPLE_IP as u64
-                | PERF_SAMPLE_TID as u64
-                | PERF_SAMPLE_TIME as u64
-                | PERF_SAMPLE_CPU as u64
-                | PERF_SAMPLE_PERIOD as u64
-                | PERF_SAMPLE_READ as u64;
-            attr.__bindgen_anon_1.sample_period = self.freq_or_period;
-            if self.use_freq {
-                attr.set_freq(1);
-            }
-            attr.set_mmap(1);
-            attr.set_mmap2(1);
-            attr.set_mmap_data(1);
-            if self.gather_context_switches {
-                attr.set_context_switch(1);
-            }
-            attr.set_comm(1);
-            attr.set_comm_exec(1);
-        }
-        attr.set_task(1);
-        attr.set_sample_id_all(1);
-        attr.set_exclude_callchain_user(1);
-        attr.set_exclude_guest(1);
-        attr.set_exclude_hv(1); // Maybe this should also be an option. Dont have hypervisors now.
-        if self.start_disabled {
-            attr.set_disabled(1);
-        }
-        if !self.collect_kernel {
-            attr.set_exclude_kernel(1);
+impl Drop for CountingGuard<'_> {
+    fn drop(&mut self) {
+        if self.stopped {
+            return;
         }
-        if self.inherit {
-            attr.set_inherit(1);
+        let _ = self.event.disable();
+        if let Some(dest) = self.dest.take() {
+            *dest = self.event.read_sync().ok();
         }
     }
+}
 
-    /// Internal implementation of open so as to not consume self.
-    fn _open(&mut self, base_event_attr: Option<ffi::perf_event_attr>) -> Result<PerfEvent> {
-        // Check validity
-        self._check_capabilities()?;
+/// A perf event monitoring the whole system with one counter per CPU.
+///
+/// Either all online CPUs are monitored or, e.g., for uncore PMUs, only the CPUs through which the
+/// PMU is accessed. Values of the individual CPUs are aggregated with `sum` and `max`.
+#[derive(Debug)]
+pub struct PerCpuEvent {
+    /// Name of the event.
+    name: String,
+    /// Per CPU events and the CPU they monitor.
+    events: Vec<(libc::c_int, PerfEvent)>,
+}
 
-        // Setup perf_event_attr
-        let mut attr = if let Some(bea) = base_event_attr {
-            bea
-        } else {
-            ffi::perf_event_attr::default()
-        };
-        self._set_attr_config(&mut attr);
+impl Counter<PerfEventValue> for PerCpuEvent {
+    fn name(&self) -> &String {
+        &self.name
+    }
 
-        // Open file corresponding to perf_event_attr
-        let fd = ffi::perf_event_open(
-            &attr,
-            self.pid,
-            self.cpuid,
-            self.leader,
-            ffi::PERF_FLAG_FD_CLOEXEC as _,
-        )?;
+    fn enable(&self) -> Result<()> {
+        self.events.iter().try_for_each(|(_, e)| e.enable())
+    }
+
+    fn disable(&self) -> Result<()> {
+        self.events.iter().try_for_each(|(_, e)| e.disable())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.events.iter().try_for_each(|(_, e)| e.reset())
+    }
+
+    fn is_closed(&self) -> Result<bool> {
+        for (_, e) in self.events.iter() {
+            if e.is_closed()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Read the sum of the values of all CPUs (see `sum`).
+    fn read_sync(&self) -> Result<PerfEventValue> {
+        self.sum()
+    }
+}
+
+/// A group of perf events that are scheduled onto the PMU together.
+///
+/// All events of a group are counting at the same time, so their values can be compared with each
+/// other, e.g., to compute ratios. The group owns its leader and keeps it open as long as any of
+/// the other members are.
+#[derive(Debug)]
+pub struct PerfEventGroup {
+    /// Members of the group other than the leader.
+    ///
+    /// Declared before `leader` so that the members are closed first.
+    members: Vec<PerfEvent>,
+    /// Leader of the group.
+    leader: PerfEvent,
+    /// Index of the events in the group (the leader being `0`) by their kernel ids.
+    ids: HashMap<u64, usize>,
+}
+
+impl Counter<Vec<PerfEventValue>> for PerfEventGroup {
+    fn name(&self) -> &String {
+        &self.leader.name
+    }
+
+    /// Enable all events of the group atomically, so that they start counting at the same time.
+    fn enable(&self) -> Result<()> {
+        self.leader.enable_group()
+    }
+
+    /// Disable all events of the group atomically, so that they stop counting at the same time.
+    fn disable(&self) -> Result<()> {
+        self.leader.disable_group()
+    }
+
+    /// Reset all events of the group atomically.
+    fn reset(&self) -> Result<()> {
+        self.leader.reset_group()
+    }
+
+    fn is_closed(&self) -> Result<bool> {
+        self.leader.is_closed()
+    }
+
+    /// Read the values of all events with the leader first.
+    fn read_sync(&self) -> Result<Vec<PerfEventValue>> {
+        self.iter().map(|e| e.read_sync()).collect()
+    }
+}
+
+impl PerfEventGroup {
+    /// Create a new group from the `leader` and the other `members`.
+    ///
+    /// The members must have been opened with the file descriptor of `leader` as group leader.
+    fn new(leader: PerfEvent, members: Vec<PerfEvent>) -> Result<Self> {
+        let mut group = PerfEventGroup {
+            members,
+            leader,
+            ids: HashMap::new(),
+        };
+        let ids = group.iter().map(|e| e.id()).collect::<Result<Vec<_>>>()?;
+        group.ids = ids.into_iter().enumerate().map(|(i, id)| (id, i)).collect();
+        Ok(group)
+    }
+
+    /// Get the leader of the group.
+    pub fn leader(&self) -> &PerfEvent {
+        &self.leader
+    }
+
+    /// Get the members of the group other than the leader.
+    pub fn members(&self) -> &[PerfEvent] {
+        &self.members
+    }
+
+    /// Get the number of events in the group, including the leader.
+    pub fn len(&self) -> usize {
+        self.members.len() + 1
+    }
+
+    /// Check if the group is empty, which is never the case as it always has a leader.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Get an iterator over all events in the group with the leader first.
+    pub fn iter(&self) -> impl Iterator<Item = &PerfEvent> {
+        std::iter::once(&self.leader).chain(self.members.iter())
+    }
+
+    /// Get a mutable iterator over all events in the group with the leader first, e.g., to read
+    /// their ring buffers.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PerfEvent> {
+        std::iter::once(&mut self.leader).chain(self.members.iter_mut())
+    }
+
+    /// Get the event in the group with the kernel id `id`, e.g., from the `id` of a sample.
+    pub fn event_by_id(&self, id: u64) -> Option<&PerfEvent> {
+        match self.ids.get(&id) {
+            Some(0) => Some(&self.leader),
+            Some(i) => self.members.get(i - 1),
+            None => None,
+        }
+    }
+}
+
+/// Perf events attached to all threads of an already running process.
+///
+/// An event opened for a `pid` only monitors that single thread. Here one group of events is
+/// opened for every thread of the process instead. Threads created after attaching, e.g., the
+/// threads of the new program after the process calls `exec`, are picked up by `refresh`. Events
+/// of threads that have exited are kept, so their counts are still part of the totals.
+#[derive(Debug)]
+pub struct AttachedProcess {
+    /// Name of the event.
+    name: String,
+    /// ID of the monitored process.
+    pid: libc::pid_t,
+    /// Builder used to open the events of newly discovered threads.
+    builder: PerfEventBuilder,
+    /// Attributes of the events opened on every thread with the group leader first.
+    attrs: Vec<Option<ffi::perf_event_attr>>,
+    /// Event groups and the thread they monitor.
+    threads: Vec<(libc::pid_t, PerfEventGroup)>,
+    /// Whether the events are supposed to be counting.
+    enabled: std::cell::Cell<bool>,
+}
+
+impl Counter<Vec<PerfEventValue>> for AttachedProcess {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn enable(&self) -> Result<()> {
+        self.enabled.set(true);
+        self.threads.iter().try_for_each(|(_, g)| g.enable())
+    }
+
+    fn disable(&self) -> Result<()> {
+        self.enabled.set(false);
+        self.threads.iter().try_for_each(|(_, g)| g.disable())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.threads.iter().try_for_each(|(_, g)| g.reset())
+    }
+
+    /// Check if the process has exited.
+    fn is_closed(&self) -> Result<bool> {
+        crate::util::process_exited(self.pid)
+    }
+
+    /// Read the sum of the values of all threads, with one value per event of the group.
+    fn read_sync(&self) -> Result<Vec<PerfEventValue>> {
+        let per_thread = self.read_sync_per_thread()?;
+        Ok((0..self.attrs.len())
+            .map(|i| PerfEventValue::_sum(per_thread.iter().map(|(_, v)| v[i].clone())))
+            .collect())
+    }
+}
+
+impl AttachedProcess {
+    /// Get the ID of the monitored process.
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Get the event groups and the thread they monitor.
+    pub fn threads(&self) -> &[(libc::pid_t, PerfEventGroup)] {
+        &self.threads
+    }
+
+    /// Get mutable access to the event groups, e.g., to read their ring buffers.
+    pub fn threads_mut(&mut self) -> &mut [(libc::pid_t, PerfEventGroup)] {
+        &mut self.threads
+    }
+
+    /// Read the values of the events of each thread.
+    pub fn read_sync_per_thread(&self) -> Result<Vec<(libc::pid_t, Vec<PerfEventValue>)>> {
+        self.threads
+            .iter()
+            .map(|(tid, g)| Ok((*tid, g.read_sync()?)))
+            .collect()
+    }
+
+    /// Attach events to the threads of the process that are not monitored yet.
+    ///
+    /// This should be called after the process created new threads or called `exec`. New events
+    /// are enabled if the other events are currently enabled. Returns the number of threads the
+    /// events were attached to.
+    pub fn refresh(&mut self) -> Result<usize> {
+        let mut attached = 0;
+        for tid in crate::util::process_threads(self.pid)? {
+            if self.threads.iter().any(|(t, _)| *t == tid) {
+                continue;
+            }
+            self.builder.pid = tid;
+            let group = match self.builder._open_group(&self.attrs) {
+                Ok(g) => g,
+                // The thread exited before the events could be opened
+                Err(Error::System(nix::Error::Sys(nix::errno::Errno::ESRCH))) => continue,
+                Err(e) => return Err(e),
+            };
+            if self.enabled.get() {
+                group.enable()?;
+            }
+            debug!("Attached {} to thread {} of {}", self.name, tid, self.pid);
+            self.threads.push((tid, group));
+            attached += 1;
+        }
+        Ok(attached)
+    }
+
+    /// Stop monitoring the process and read the final values of the events.
+    pub fn detach(self) -> Result<Vec<PerfEventValue>> {
+        self.disable()?;
+        self.read_sync()
+    }
+}
+
+impl PerCpuEvent {
+    /// Read the values of the counters of each CPU.
+    pub fn per_cpu(&self) -> Result<Vec<(libc::c_int, PerfEventValue)>> {
+        self.events
+            .iter()
+            .map(|(cpu, e)| Ok((*cpu, e.read_sync()?)))
+            .collect()
+    }
+
+    /// Read the values of the counters of each CPU.
+    #[deprecated(note = "use `per_cpu` instead")]
+    pub fn read_sync_per_cpu(&self) -> Result<Vec<(libc::c_int, PerfEventValue)>> {
+        self.per_cpu()
+    }
+
+    /// Read the sum of the values of all CPUs.
+    ///
+    /// The `id` of the returned value is that of the event on the first CPU.
+    pub fn sum(&self) -> Result<PerfEventValue> {
+        let values = self.per_cpu()?;
+        Ok(PerfEventValue::_sum(values.into_iter().map(|(_, v)| v)))
+    }
+
+    /// Read the sum of the values of the CPUs of each package (socket), together with the package,
+    /// sorted by package.
+    pub fn per_package(&self) -> Result<Vec<(libc::c_int, PerfEventValue)>> {
+        let packages = crate::util::cpu_packages()?;
+        let mut values: Vec<(libc::c_int, Vec<PerfEventValue>)> = Vec::new();
+        for (cpu, v) in self.per_cpu()? {
+            let pkg = packages
+                .iter()
+                .find(|&&(c, _)| c == cpu)
+                .map(|&(_, p)| p)
+                .ok_or(Error::NoneError)?;
+            match values.iter_mut().find(|(p, _)| *p == pkg) {
+                Some((_, vals)) => vals.push(v),
+                None => values.push((pkg, vec![v])),
+            }
+        }
+        values.sort_unstable_by_key(|(p, _)| *p);
+        Ok(values
+            .into_iter()
+            .map(|(p, vals)| (p, PerfEventValue::_sum(vals)))
+            .collect())
+    }
+
+    /// Read the largest scaled value of all CPUs together with the CPU it was measured on.
+    pub fn max(&self) -> Result<(libc::c_int, PerfEventValue)> {
+        self.per_cpu()?
+            .into_iter()
+            .max_by_key(|(_, v)| v.scaled_value())
+            .ok_or(Error::NoneError)
+    }
+
+    /// Get the per CPU events and the CPU they monitor.
+    pub fn events(&self) -> &[(libc::c_int, PerfEvent)] {
+        &self.events
+    }
+
+    /// Get mutable access to the per CPU events, e.g., to read their ring buffers.
+    pub fn events_mut(&mut self) -> &mut [(libc::c_int, PerfEvent)] {
+        &mut self.events
+    }
+}
+
+/// A perf event counted by every box of a box-replicated uncore PMU, e.g., all `uncore_cbox_N`.
+///
+/// Each box is monitored through the CPUs in its `cpumask`, usually one per package. Values are
+/// aggregated over all boxes with `sum`, or reported for each box with `per_box`.
+#[derive(Debug)]
+pub struct UncoreEvent {
+    /// Name of the event.
+    name: String,
+    /// Events of each box and the name of the box.
+    boxes: Vec<(String, PerCpuEvent)>,
+}
+
+impl Counter<PerfEventValue> for UncoreEvent {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn enable(&self) -> Result<()> {
+        self.boxes.iter().try_for_each(|(_, e)| e.enable())
+    }
+
+    fn disable(&self) -> Result<()> {
+        self.boxes.iter().try_for_each(|(_, e)| e.disable())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.boxes.iter().try_for_each(|(_, e)| e.reset())
+    }
+
+    fn is_closed(&self) -> Result<bool> {
+        for (_, e) in self.boxes.iter() {
+            if e.is_closed()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Read the sum of the values of all boxes (see `sum`).
+    fn read_sync(&self) -> Result<PerfEventValue> {
+        self.sum()
+    }
+}
+
+impl UncoreEvent {
+    /// Read the value of each box, summed over the CPUs it is monitored through.
+    pub fn per_box(&self) -> Result<Vec<(&str, PerfEventValue)>> {
+        self.boxes
+            .iter()
+            .map(|(b, e)| Ok((b.as_str(), e.sum()?)))
+            .collect()
+    }
+
+    /// Read the sum of the values of all boxes.
+    pub fn sum(&self) -> Result<PerfEventValue> {
+        let values = self.per_box()?;
+        Ok(PerfEventValue::_sum(values.into_iter().map(|(_, v)| v)))
+    }
+
+    /// Get the events of each box and the name of the box.
+    pub fn boxes(&self) -> &[(String, PerCpuEvent)] {
+        &self.boxes
+    }
+}
+
+/// Helper struct to build a `PerfEvent` object.
+#[derive(Debug, Clone)]
+pub struct PerfEventBuilder {
+    /// Name of the event.
+    name: String,
+    /// Attributes of the event to be opened.
+    ///
+    /// Defaults to none. Used only if no `base_event_attr` is passed to `open`.
+    event_attr: Option<ffi::perf_event_attr>,
+    /// Symbol or binary path probed by a kprobe or uprobe event.
+    ///
+    /// Defaults to none. This has to outlive the call to `perf_event_open`.
+    probe_target: Option<std::ffi::CString>,
+    /// Target process ID.
+    ///
+    /// Defaults to current process.
+    pid: libc::pid_t,
+    /// Target CPU ID.
+    ///
+    /// Defaults to all CPUs.
+    cpuid: libc::c_int,
+    /// File descriptor for the group leader event.
+    ///
+    /// Defaults to none.
+    leader: libc::c_int,
+    /// Use sampling frequency instead of sampling period.
+    ///
+    /// Defaults to `false`.
+    use_freq: bool,
+    /// Sampling frequency or period based on `use_freq`.
+    ///
+    /// Defaults to period `1`.
+    freq_or_period: u64,
+    /// Use a watermark in bytes instead of a number of events to wake up readers of the ring
+    /// buffer.
+    ///
+    /// Defaults to `false`.
+    use_watermark: bool,
+    /// Number of events or bytes based on `use_watermark` after which readers are woken up.
+    ///
+    /// Defaults to `0`, i.e., wake up on every event.
+    wakeup_events_or_watermark: u32,
+    /// Should
+    ///
+    /// Defaults to `false`.
+    inherit: bool,
+    /// Should start the counter disabled.
+    ///
+    /// Defaults to  `false`.
+    start_disabled: bool,
+    /// Enable the counter when the monitored process calls `exec`.
+    ///
+    /// Defaults to `false`.
+    enable_on_exec: bool,
+    /// Count for kernel code.
+    ///
+    /// Defaults to `false`.
+    collect_kernel: bool,
+    /// Exclude counts in user space.
+    ///
+    /// Defaults to `false`.
+    exclude_user: bool,
+    /// Exclude counts in the hypervisor.
+    ///
+    /// Defaults to `true`.
+    exclude_hv: bool,
+    /// Exclude counts when the CPU is idle.
+    ///
+    /// Defaults to `false`.
+    exclude_idle: bool,
+    /// Exclude counts in the host when running virtual machines.
+    ///
+    /// Defaults to `false`.
+    exclude_host: bool,
+    /// Exclude counts in guest virtual machines.
+    ///
+    /// Defaults to `true`.
+    exclude_guest: bool,
+    /// Gather information on context switches.
+    ///
+    /// Defaults to `false`.
+    gather_context_switches: bool,
+    /// This corresponds to a sampled event that is accessed through a ring buffer.
+    ///
+    /// Defaults to false.
+    is_sampled: bool,
+    /// Size of requested ring buffer.
+    ///
+    /// Defaults to 128 * native page size..
+    requested_size: usize,
+    /// Size of requested AUX area of the ring buffer.
+    ///
+    /// Defaults to `0`, i.e., no AUX area is mapped.
+    requested_aux_size: usize,
+    /// The kernel overwrites the oldest records in the ring buffer once it is full.
+    ///
+    /// Defaults to false.
+    overwrite: bool,
+    /// Wait for samples by spinning on the ring buffer instead of calling `poll`.
+    ///
+    /// Defaults to false.
+    busy_poll: bool,
+    /// Mask of user-space registers captured with each sample.
+    ///
+    /// Defaults to `0`, i.e., no registers are captured.
+    sample_regs_user: u64,
+    /// Size in bytes of the user-space stack captured with each sample.
+    ///
+    /// Defaults to `0`, i.e., the stack is not captured.
+    sample_stack_user: u32,
+    /// Types of branches captured in the branch stack of each sample.
+    ///
+    /// Defaults to `0`, i.e., the branch stack is not captured.
+    branch_sample_type: u64,
+    /// Capture the raw data of each sample.
+    ///
+    /// Defaults to `false`.
+    sample_raw: bool,
+    /// Path to the directory of the cgroup to be monitored.
+    ///
+    /// Defaults to none, i.e., `pid` is used to select the monitored process.
+    cgroup: Option<std::path::PathBuf>,
+    /// Gather information on cgroups.
+    ///
+    /// Defaults to `false`.
+    gather_cgroups: bool,
+    /// Gather information on registration of kernel symbols.
+    ///
+    /// Defaults to `false`.
+    gather_ksymbols: bool,
+    /// Gather information on loading and unloading of BPF programs.
+    ///
+    /// Defaults to `false`.
+    gather_bpf_events: bool,
+    /// Clock used for timestamps of the records.
+    ///
+    /// Defaults to none, i.e., the kernel's default perf clock.
+    clockid: Option<libc::clockid_t>,
+    /// Requested precision of the instruction pointer of samples, from `0` to `3`.
+    ///
+    /// Defaults to `0`, i.e., that of the attributes the event is opened with.
+    precise_ip: u8,
+    /// Use the highest precision of the instruction pointer supported for the event.
+    ///
+    /// Defaults to `false`.
+    max_precise: bool,
+}
+
+impl Default for PerfEventBuilder {
+    fn default() -> Self {
+        PerfEventBuilder {
+            name: String::new(),
+            event_attr: None,
+            probe_target: None,
+            pid: 0,
+            cpuid: -1,
+            leader: -1,
+            use_freq: false,
+            freq_or_period: 1,
+            use_watermark: false,
+            wakeup_events_or_watermark: 0,
+            inherit: false,
+            start_disabled: false,
+            enable_on_exec: false,
+            collect_kernel: false,
+            exclude_user: false,
+            exclude_hv: true,
+            exclude_idle: false,
+            exclude_host: false,
+            exclude_guest: true,
+            gather_context_switches: false,
+            is_sampled: false,
+            requested_size: (1 << 7) * *PAGE_SIZE,
+            requested_aux_size: 0,
+            overwrite: false,
+            busy_poll: false,
+            sample_regs_user: 0,
+            sample_stack_user: 0,
+            branch_sample_type: 0,
+            sample_raw: false,
+            cgroup: None,
+            gather_cgroups: false,
+            gather_ksymbols: false,
+            gather_bpf_events: false,
+            clockid: None,
+            precise_ip: 0,
+            max_precise: false,
+        }
+    }
+}
+
+macro_rules! builder_pattern {
+    ($(#[$outer:meta])* $var_name: ident : $var_type: ty) => {
+        builder_pattern!($(#[$outer])* $var_name => $var_name: $var_type);
+    };
+    ($(#[$outer:meta])* $var_name: ident : $var_type: ty = $val: expr) => {
+        builder_pattern!($(#[$outer])* $var_name => $var_name: $var_type = $val);
+    };
+    ($(#[$outer:meta])* $name: ident => $var_name: ident : $var_type: ty) => {
+        $(#[$outer])*
+        pub fn $name(mut self, $var_name: $var_type) -> Self {
+            self.$var_name = $var_name;
+            self
+        }
+    };
+    ($(#[$outer:meta])* $name: ident => $var_name: ident : $var_type: ty = $val: expr) => {
+        $(#[$outer])*
+        pub fn $name(mut self) -> Self {
+            self.$var_name = $val;
+            self
+        }
+    };
+}
+
+impl PerfEventBuilder {
+    /// Get the maximum allowed sampling frequency of the system.
+    fn _max_sampling_freq() -> Result<u64> {
+        let data = std::fs::read_to_string("/proc/sys/kernel/perf_event_max_sample_rate")?;
+        Ok(data.trim().parse::<u64>().unwrap())
+    }
+
+    /// Get the smallest power of 2 number of pages that can hold `size` bytes.
+    fn _num_pages(size: usize) -> usize {
+        let log_num_pages = (1u32..26).find(|x| (1 << *x) * *PAGE_SIZE >= size).unwrap();
+        1 << log_num_pages
+    }
+
+    /// Check capabilities of the current system and the configuration of the current builder.
+    ///
+    /// Fails with `Error::InvalidSetting` for the first setting found to be invalid.
+    fn _check_capabilities(&self) -> Result<()> {
+        let invalid = |setting, reason: &str| {
+            Err(Error::InvalidSetting {
+                setting,
+                reason: reason.into(),
+            })
+        };
+
+        // Sampling rate
+        if self.use_freq {
+            let max = PerfEventBuilder::_max_sampling_freq()?;
+            if self.freq_or_period == 0 || self.freq_or_period > max {
+                let reason = format!("must be between 1 and {} (perf_event_max_sample_rate)", max);
+                return invalid("set_frequency", &reason);
+            }
+        } else if self.is_sampled && self.freq_or_period == 0 {
+            return invalid("set_period", "must not be 0 for sampled events");
+        }
+
+        // Settings that only affect the ring buffer
+        if !self.is_sampled {
+            let sampling_only = [
+                ("use_frequency", self.use_freq),
+                ("set_wakeup_events", self.wakeup_events_or_watermark != 0),
+                ("use_wakeup_watermark", self.use_watermark),
+                (
+                    "requested_size",
+                    self.requested_size != PerfEventBuilder::default().requested_size,
+                ),
+                ("requested_aux_size", self.requested_aux_size != 0),
+                ("use_overwrite_buffer", self.overwrite),
+                ("use_busy_poll", self.busy_poll),
+                ("sample_regs_user", self.sample_regs_user != 0),
+                ("sample_stack_user", self.sample_stack_user != 0),
+                ("sample_branch_stack", self.branch_sample_type != 0),
+                ("sample_raw", self.sample_raw),
+                ("gather_context_switches", self.gather_context_switches),
+                ("gather_cgroups", self.gather_cgroups),
+                ("gather_ksymbols", self.gather_ksymbols),
+                ("gather_bpf_events", self.gather_bpf_events),
+            ];
+            if let Some((setting, _)) = sampling_only.iter().find(|(_, set)| *set) {
+                return invalid(setting, "requires enable_sampling");
+            }
+        }
+
+        // Targets
+        // Only the ring buffers of sampled events cannot be inherited across CPUs
+        if self.inherit && self.is_sampled && self.cpuid == -1 {
+            return invalid("inherit", "requires a specific cpuid when sampling");
+        }
+        if self.cgroup.is_some() && self.cpuid == -1 {
+            return invalid("cgroup", "requires a specific cpuid");
+        }
+
+        // Sizes of buffers
+        let max_size = (1 << 25) * *PAGE_SIZE;
+        if self.requested_size > max_size {
+            return invalid("requested_size", &format!("must be at most {}", max_size));
+        }
+        if self.requested_aux_size > max_size {
+            return invalid(
+                "requested_aux_size",
+                &format!("must be at most {}", max_size),
+            );
+        }
+        if self.requested_aux_size != 0 && self.overwrite {
+            return invalid(
+                "requested_aux_size",
+                "cannot be combined with use_overwrite_buffer",
+            );
+        }
+        if self.is_sampled && self.use_watermark {
+            let size =
+                std::cmp::max(PerfEventBuilder::_num_pages(self.requested_size), 16) * *PAGE_SIZE;
+            if self.wakeup_events_or_watermark as usize >= size {
+                let reason = format!("must be smaller than the ring buffer ({} bytes)", size);
+                return invalid("set_wakeup_watermark", &reason);
+            }
+        }
+        if self.sample_stack_user & 7 != 0 {
+            return invalid("sample_stack_user", "must be a multiple of 8");
+        }
+        if self.precise_ip > 3 {
+            return invalid("set_precise_ip", "must be at most 3");
+        }
+        Ok(())
+    }
+
+    /// Set the fields of an perf_event_attr based on this builder.
+    fn _set_attr_config(&self, attr: &mut ffi::perf_event_attr) {
+        use ffi::perf_event_read_format::*;
+        use ffi::perf_event_sample_format::*;
+        attr.size = std::mem::size_of::<ffi::perf_event_attr>()
+            .try_into()
+            .unwrap();
+        attr.read_format = PERF_FORMAT_ID as u64
+            | PERF_FORMAT_TOTAL_TIME_RUNNING as u64
+            | PERF_FORMAT_TOTAL_TIME_ENABLED as u64;
+        if self.is_sampled {
+            attr.sample_type = PERF_SAMPLE_IP as u64
+                | PERF_SAMPLE_ID as u64
+                | PERF_SAMPLE_TID as u64
+                | PERF_SAMPLE_TIME as u64
+                | PERF_SAMPLE_CPU as u64
+                | PERF_SAMPLE_PERIOD as u64;
+            // Kernels before 6.12 reject inherited events reading their value in samples
+            if !self.inherit {
+                attr.sample_type |= PERF_SAMPLE_READ as u64;
+            }
+            if self.sample_regs_user != 0 {
+                attr.sample_type |= PERF_SAMPLE_REGS_USER as u64;
+                attr.sample_regs_user = self.sample_regs_user;
+            }
+            if self.sample_stack_user != 0 {
+                attr.sample_type |= PERF_SAMPLE_STACK_USER as u64;
+                attr.sample_stack_user = self.sample_stack_user;
+            }
+            if self.branch_sample_type != 0 {
+                attr.sample_type |= PERF_SAMPLE_BRANCH_STACK as u64;
+                attr.branch_sample_type = self.branch_sample_type;
+            }
+            if self.sample_raw {
+                attr.sample_type |= PERF_SAMPLE_RAW as u64;
+            }
+            if self.gather_cgroups {
+                attr.sample_type |= PERF_SAMPLE_CGROUP as u64;
+                attr.set_cgroup(1);
+            }
+            if self.gather_ksymbols {
+                attr.set_ksymbol(1);
+            }
+            if self.gather_bpf_events {
+                attr.set_bpf_event(1);
+            }
+            attr.__bindgen_anon_1.sample_period = self.freq_or_period;
+            if self.use_freq {
+                attr.set_freq(1);
+            }
+            attr.__bindgen_anon_2.wakeup_events = self.wakeup_events_or_watermark;
+            if self.use_watermark {
+                attr.set_watermark(1);
+            }
+            if self.overwrite {
+                attr.set_write_backward(1);
+            }
+            attr.set_mmap(1);
+            attr.set_mmap2(1);
+            attr.set_mmap_data(1);
+            if self.gather_context_switches {
+                attr.set_context_switch(1);
+            }
+            attr.set_comm(1);
+            attr.set_comm_exec(1);
+        }
+        if let Some(clockid) = self.clockid {
+            attr.set_use_clockid(1);
+            attr.clockid = clockid;
+        }
+        attr.set_task(1);
+        attr.set_sample_id_all(1);
+        attr.set_exclude_callchain_user(1);
+        if self.exclude_user {
+            attr.set_exclude_user(1);
+        }
+        if self.exclude_hv {
+            attr.set_exclude_hv(1);
+        }
+        if self.exclude_idle {
+            attr.set_exclude_idle(1);
+        }
+        if self.exclude_host {
+            attr.set_exclude_host(1);
+        }
+        if self.exclude_guest {
+            attr.set_exclude_guest(1);
+        }
+        if self.start_disabled {
+            attr.set_disabled(1);
+        }
+        if self.enable_on_exec {
+            attr.set_enable_on_exec(1);
+        }
+        if !self.collect_kernel {
+            attr.set_exclude_kernel(1);
+        }
+        if self.inherit {
+            attr.set_inherit(1);
+        }
+        if self.precise_ip != 0 {
+            attr.set_precise_ip(self.precise_ip.into());
+        }
+    }
+
+    /// Internal implementation of open so as to not consume self.
+    fn _open(&mut self, base_event_attr: Option<ffi::perf_event_attr>) -> Result<PerfEvent> {
+        // Check validity
+        self._check_capabilities()?;
+
+        // Setup perf_event_attr
+        let mut attr = base_event_attr.or(self.event_attr).unwrap_or_default();
+        self._set_attr_config(&mut attr);
+        if let (None, Some(ref target)) = (base_event_attr, &self.probe_target) {
+            attr.__bindgen_anon_3.config1 = target.as_ptr() as u64;
+        }
+        if self.max_precise {
+            attr.set_precise_ip(crate::perf::max_precise_ip(&attr).into());
+        }
+
+        // Monitoring a cgroup requires passing the fd of its directory instead of a pid
+        let cgroup = match self.cgroup {
+            Some(ref path) => Some(std::fs::File::open(path)?),
+            None => None,
+        };
+        let (pid, flags) = if let Some(ref cg) = cgroup {
+            (
+                cg.as_raw_fd(),
+                ffi::PERF_FLAG_FD_CLOEXEC | ffi::PERF_FLAG_PID_CGROUP,
+            )
+        } else {
+            (self.pid, ffi::PERF_FLAG_FD_CLOEXEC)
+        };
+
+        // Open file corresponding to perf_event_attr
+        let fd = ffi::perf_event_open(&attr, pid, self.cpuid, self.leader, flags as _)?;
 
         debug!("Opened PerfEvent with attributes {:?}", attr);
 
         // Get ringbuffer corresponding to the fd
         let ring_buffer = if self.is_sampled {
-            let req_space = self.requested_size;
-            let log_num_pages = (1u32..26)
-                .find(|x| (1 << *x) * *PAGE_SIZE >= req_space as _)
-                .unwrap();
-            let page_count = std::cmp::max(1 << log_num_pages, 16);
-            Some(crate::perf::RingBuffer::new(fd, page_count as _)?)
+            let page_count = std::cmp::max(PerfEventBuilder::_num_pages(self.requested_size), 16);
+            let mut rb = if self.overwrite {
+                crate::perf::RingBuffer::new_overwrite(fd, page_count)?
+            } else {
+                crate::perf::RingBuffer::new(fd, page_count)?
+            };
+            if self.requested_aux_size != 0 && !self.overwrite {
+                rb.map_aux(fd, PerfEventBuilder::_num_pages(self.requested_aux_size))?;
+            }
+            Some(rb)
         } else {
             None
         };
 
         // Ok... We are done
-        Ok(PerfEvent {
+        let mut evt = PerfEvent {
             name: self.name.clone(),
             attr,
             file: unsafe { std::fs::File::from_raw_fd(fd) },
             ring_buffer,
+            notify_overflow: false,
+            busy_poll: self.busy_poll,
+        };
+        if evt.ring_buffer.is_some() {
+            let id = evt.id()?;
+            if let Some(ref mut rb) = evt.ring_buffer {
+                rb.register_event(id, &evt.name);
+            }
+        }
+        Ok(evt)
+    }
+
+    /// Generate the `PerfEvent` from this builder.
+    ///
+    /// If a `base_event_attr` is provided, all fields set in the builder will be overwritten.
+    /// Otherwise the attributes of the event selected in the builder (e.g., using `tracepoint`)
+    /// are used.
+    pub fn open(mut self, base_event_attr: Option<ffi::perf_event_attr>) -> Result<PerfEvent> {
+        self._open(base_event_attr)
+    }
+
+    /// Generate a `PerCpuEvent` monitoring all processes on every online CPU from this builder.
+    ///
+    /// The `pid` and `cpuid` set in the builder are ignored.
+    pub fn open_per_cpu(
+        self,
+        base_event_attr: Option<ffi::perf_event_attr>,
+    ) -> Result<PerCpuEvent> {
+        let cpus = crate::util::online_cpus()?;
+        self.open_on_cpus(&cpus, base_event_attr)
+    }
+
+    /// Generate a `PerCpuEvent` monitoring all processes on each of the CPUs `cpus`.
+    ///
+    /// Use `sysfs::pmu_cpus` to get the CPUs through which uncore PMUs should be accessed. The
+    /// `pid` and `cpuid` set in the builder are ignored.
+    pub fn open_on_cpus(
+        mut self,
+        cpus: &[libc::c_int],
+        base_event_attr: Option<ffi::perf_event_attr>,
+    ) -> Result<PerCpuEvent> {
+        if cpus.is_empty() {
+            return Err(Error::InvalidEvent("Empty list of CPUs".into()));
+        }
+        self.pid = -1;
+        let mut events = Vec::new();
+        for &cpu in cpus {
+            self.cpuid = cpu;
+            events.push((cpu, self._open(base_event_attr)?));
+        }
+        Ok(PerCpuEvent {
+            name: self.name,
+            events,
+        })
+    }
+
+    /// Generate an `UncoreEvent` counting on each of the `boxes` of an uncore PMU.
+    ///
+    /// `boxes` holds the name of each box together with the `perf_event_attr` of the event for
+    /// the box. Each box is monitored through the CPUs in its `cpumask` (see `sysfs::pmu_cpus`).
+    /// The `pid` and `cpuid` set in the builder are ignored. As these PMUs cannot tell apart the
+    /// privilege levels or guests, all `exclude_*` settings are ignored as well.
+    pub fn open_uncore(
+        mut self,
+        boxes: Vec<(String, ffi::perf_event_attr)>,
+    ) -> Result<UncoreEvent> {
+        if boxes.is_empty() {
+            return Err(Error::InvalidEvent("Empty list of boxes".into()));
+        }
+        self.pid = -1;
+        self.collect_kernel = true;
+        self.exclude_user = false;
+        self.exclude_hv = false;
+        self.exclude_idle = false;
+        self.exclude_host = false;
+        self.exclude_guest = false;
+        let mut events = Vec::new();
+        for (name, attr) in boxes {
+            let mut cpu_events = Vec::new();
+            for cpu in crate::perf::sysfs::pmu_cpus(&name)? {
+                self.cpuid = cpu;
+                cpu_events.push((cpu, self._open(Some(attr))?));
+            }
+            let evt = PerCpuEvent {
+                name: self.name.clone(),
+                events: cpu_events,
+            };
+            events.push((name, evt));
+        }
+        Ok(UncoreEvent {
+            name: self.name,
+            boxes: events,
         })
     }
 
-    /// Generate the `PerfEvent` from this builder.
+    /// Internal implementation of open_group so as to not consume self.
+    fn _open_group(
+        &mut self,
+        base_event_attrs: &[Option<ffi::perf_event_attr>],
+    ) -> Result<PerfEventGroup> {
+        // Create leader first
+        let (leader, attrs) = match base_event_attrs.split_first() {
+            Some((attr, attrs)) => (self._open(*attr)?, attrs),
+            None => return Err(Error::InvalidEvent("Empty event group".into())),
+        };
+        // Create group using leader's fd
+        let leader_fd = std::mem::replace(&mut self.leader, leader.file.as_raw_fd());
+        let members = attrs
+            .iter()
+            .map(|attr| self._open(*attr))
+            .collect::<Result<Vec<_>>>();
+        self.leader = leader_fd;
+        PerfEventGroup::new(leader, members?)
+    }
+
+    /// Generate a group of perf events from this builder.
+    ///
+    /// The first element of `base_event_attrs` is assumed to be the group leader.
+    pub fn open_group(
+        mut self,
+        base_event_attrs: Vec<ffi::perf_event_attr>,
+    ) -> Result<PerfEventGroup> {
+        let attrs: Vec<_> = base_event_attrs.into_iter().map(Some).collect();
+        self._open_group(&attrs)
+    }
+
+    /// Generate a group of perf events monitoring all processes on each of the CPUs `cpus`.
+    ///
+    /// Returns the groups together with the CPU they are opened on. The `pid` and `cpuid` set in
+    /// the builder are ignored.
+    pub fn open_group_on_cpus(
+        mut self,
+        cpus: &[libc::c_int],
+        base_event_attrs: Vec<ffi::perf_event_attr>,
+    ) -> Result<Vec<(libc::c_int, PerfEventGroup)>> {
+        if cpus.is_empty() {
+            return Err(Error::InvalidEvent("Empty list of CPUs".into()));
+        }
+        let attrs: Vec<_> = base_event_attrs.into_iter().map(Some).collect();
+        self.pid = -1;
+        let mut groups = Vec::new();
+        for &cpu in cpus {
+            self.cpuid = cpu;
+            groups.push((cpu, self._open_group(&attrs)?));
+        }
+        Ok(groups)
+    }
+
+    /// Attach events to all threads of the running process `pid`.
     ///
-    /// If a `base_event_attr` is provided, all fields set in the builder will be overwritten.
-    pub fn open(mut self, base_event_attr: Option<ffi::perf_event_attr>) -> Result<PerfEvent> {
-        self._open(base_event_attr)
+    /// `base_event_attr` is handled as in `open`. The `pid` set in the builder is ignored and `0`
+    /// selects the current process. The events are enabled unless `start_disabled` is set.
+    ///
+    /// # Note
+    /// Do not combine with `inherit`, as threads created later would be counted twice after a
+    /// call to `AttachedProcess::refresh`.
+    pub fn attach(
+        self,
+        pid: libc::pid_t,
+        base_event_attr: Option<ffi::perf_event_attr>,
+    ) -> Result<AttachedProcess> {
+        self._attach(pid, vec![base_event_attr])
     }
 
-    /// Generate a group of perf events from this builder.
+    /// Attach a group of events to all threads of the running process `pid`.
     ///
-    /// The first element of `base_event_attrs` is assumed to be the group leader.
-    pub fn open_group(
-        mut self,
-        mut base_event_attrs: Vec<ffi::perf_event_attr>,
-    ) -> Result<Vec<PerfEvent>> {
-        // Create leader first
-        let leader = self._open(Some(base_event_attrs.pop().unwrap()))?;
-        // Create group using leader's fd
-        self.leader = leader.file.as_raw_fd();
-        let mut out = Vec::with_capacity(base_event_attrs.len() + 1);
-        out.push(leader);
-        for attr in base_event_attrs.iter_mut() {
-            out.push(self._open(Some(*attr))?);
+    /// The first element of `base_event_attrs` is assumed to be the group leader. Otherwise this
+    /// behaves like `attach`.
+    pub fn attach_group(
+        self,
+        pid: libc::pid_t,
+        base_event_attrs: Vec<ffi::perf_event_attr>,
+    ) -> Result<AttachedProcess> {
+        if base_event_attrs.is_empty() {
+            return Err(Error::InvalidEvent("Empty event group".into()));
         }
-        Ok(out)
+        self._attach(pid, base_event_attrs.into_iter().map(Some).collect())
+    }
+
+    /// Common implementation of `attach` and `attach_group`.
+    fn _attach(
+        mut self,
+        pid: libc::pid_t,
+        attrs: Vec<Option<ffi::perf_event_attr>>,
+    ) -> Result<AttachedProcess> {
+        let pid = match pid {
+            0 => nix::unistd::getpid().as_raw(),
+            p if p > 0 => p,
+            p => return Err(Error::InvalidEvent(format!("Cannot attach to pid {}", p))),
+        };
+        // Events of new threads are enabled explicitly, depending on the state of the others
+        let enabled = !self.start_disabled;
+        self.start_disabled = true;
+        let mut process = AttachedProcess {
+            name: self.name.clone(),
+            pid,
+            builder: self,
+            attrs,
+            threads: Vec::new(),
+            enabled: std::cell::Cell::new(enabled),
+        };
+        process.refresh()?;
+        Ok(process)
     }
 
     builder_pattern!(
@@ -447,6 +1574,163 @@ impl PerfEventBuilder {
         name: String
     );
 
+    /// Select the tracepoint `name` of the form `subsystem:event`, e.g., `sched:sched_switch`.
+    ///
+    /// The id of the tracepoint is resolved from the tracing filesystem, which requires it to be
+    /// mounted and readable.
+    pub fn tracepoint(mut self, name: &str) -> Result<Self> {
+        let attr = ffi::perf_event_attr {
+            type_: ffi::perf_type_id::PERF_TYPE_TRACEPOINT as _,
+            config: crate::perf::tracefs::tracepoint_id(name)?,
+            ..Default::default()
+        };
+        if self.name.is_empty() {
+            self.name = name.into();
+        }
+        self.event_attr = Some(attr);
+        Ok(self)
+    }
+
+    /// Select tracing with Intel Processor Trace, configured with the comma separated `terms` of
+    /// the `intel_pt` PMU, e.g., `cyc,noretcomp=1`.
+    ///
+    /// The trace is written to the AUX area of the ring buffer, which is mapped with 4 MiB unless
+    /// `requested_aux_size` is set. Use `PtTrace` to collect it together with the records required
+    /// to decode it.
+    pub fn intel_pt(mut self, terms: &str) -> Result<Self> {
+        let attr = crate::perf::sysfs::encode_terms(crate::perf::INTEL_PT_PMU, terms)?;
+        if self.name.is_empty() {
+            self.name = if terms.is_empty() {
+                crate::perf::INTEL_PT_PMU.into()
+            } else {
+                format!("{}/{}/", crate::perf::INTEL_PT_PMU, terms)
+            };
+        }
+        self.event_attr = Some(attr);
+        self.is_sampled = true;
+        self.freq_or_period = 1;
+        if self.requested_aux_size == 0 {
+            self.requested_aux_size = 4 << 20;
+        }
+        Ok(self)
+    }
+
+    /// Select instruction fetch sampling with AMD Instruction Based Sampling (IBS).
+    ///
+    /// If `rand_en` is set, the low 4 bits of the sampling period are randomized to avoid
+    /// sampling the same fetches repeatedly. The samples are parsed with `IbsFetchSample`.
+    pub fn ibs_fetch(self, rand_en: bool) -> Result<Self> {
+        self._ibs(
+            crate::perf::IBS_FETCH_PMU,
+            if rand_en { "rand_en" } else { "" },
+        )
+    }
+
+    /// Select micro-op sampling with AMD Instruction Based Sampling (IBS).
+    ///
+    /// The period counts dispatched micro-ops if `count_ops` is set and cycles otherwise. The
+    /// samples are parsed with `IbsOpSample`.
+    pub fn ibs_op(self, count_ops: bool) -> Result<Self> {
+        self._ibs(
+            crate::perf::IBS_OP_PMU,
+            if count_ops { "cnt_ctl" } else { "" },
+        )
+    }
+
+    /// Select sampling with the IBS PMU `pmu` configured with `terms`.
+    ///
+    /// IBS cannot filter by privilege level or guests, so all `exclude_*` settings are cleared and
+    /// samples in the kernel are collected as well. The sampling period must be a multiple of 16
+    /// and defaults to `IBS_DEFAULT_PERIOD`.
+    fn _ibs(mut self, pmu: &str, terms: &str) -> Result<Self> {
+        let attr = crate::perf::sysfs::encode_terms(pmu, terms)?;
+        if self.name.is_empty() {
+            self.name = pmu.into();
+        }
+        self.event_attr = Some(attr);
+        self.is_sampled = true;
+        self.sample_raw = true;
+        self.collect_kernel = true;
+        self.exclude_user = false;
+        self.exclude_hv = false;
+        self.exclude_idle = false;
+        self.exclude_host = false;
+        self.exclude_guest = false;
+        if !self.use_freq && self.freq_or_period == PerfEventBuilder::default().freq_or_period {
+            self.freq_or_period = crate::perf::IBS_DEFAULT_PERIOD;
+        }
+        Ok(self)
+    }
+
+    /// Select the event `spec` given in the syntax of the `perf` tool, e.g., `cycles:u`,
+    /// `instructions:kpp` or `r01c4:u` (see `parse_event`).
+    ///
+    /// Modifiers selecting the privilege levels to be counted override the corresponding settings
+    /// of the builder, e.g., `collect_kernel`.
+    pub fn event(mut self, spec: &str) -> Result<Self> {
+        let attr = crate::perf::parse_event(spec)?;
+        let modifiers = spec.split_once(':').map(|(_, m)| m).unwrap_or_default();
+        if modifiers.contains(|m| "ukh".contains(m)) {
+            self.exclude_user = attr.exclude_user() == 1;
+            self.collect_kernel = attr.exclude_kernel() == 0;
+            self.exclude_hv = attr.exclude_hv() == 1;
+        }
+        if modifiers.contains(|m| "GH".contains(m)) {
+            self.exclude_guest = attr.exclude_guest() == 1;
+            self.exclude_host = attr.exclude_host() == 1;
+        }
+        if modifiers.contains('I') {
+            self.exclude_idle = true;
+        }
+        if self.name.is_empty() {
+            self.name = spec.into();
+        }
+        self.event_attr = Some(attr);
+        Ok(self)
+    }
+
+    /// Select a dynamic probe event from the PMU `pmu` (`kprobe` or `uprobe`).
+    fn _probe(mut self, pmu: &str, target: &str, offset: u64, retprobe: bool) -> Result<Self> {
+        let mut attr = ffi::perf_event_attr {
+            type_: crate::perf::sysfs::pmu_type(pmu)?,
+            ..Default::default()
+        };
+        if retprobe {
+            attr.config |= 1 << crate::perf::sysfs::pmu_config_bit(pmu, "retprobe")?;
+        }
+        attr.__bindgen_anon_4.config2 = offset;
+        self.probe_target =
+            Some(std::ffi::CString::new(target).map_err(|_| Error::InvalidEvent(target.into()))?);
+        if self.name.is_empty() {
+            self.name = format!("{}:{}+{:#x}", pmu, target, offset);
+        }
+        self.event_attr = Some(attr);
+        Ok(self)
+    }
+
+    /// Select a kprobe at `offset` bytes into the kernel function `symbol`.
+    pub fn kprobe(self, symbol: &str, offset: u64) -> Result<Self> {
+        self._probe("kprobe", symbol, offset, false)
+    }
+
+    /// Select a kretprobe on the return of the kernel function `symbol`.
+    pub fn kretprobe(self, symbol: &str) -> Result<Self> {
+        self._probe("kprobe", symbol, 0, true)
+    }
+
+    /// Select a uprobe at file offset `offset` in the binary at `path`.
+    pub fn uprobe<P: AsRef<std::path::Path>>(self, path: P, offset: u64) -> Result<Self> {
+        let path = path.as_ref().to_string_lossy();
+        self._probe("uprobe", &path, offset, false)
+    }
+
+    /// Select a uretprobe on the return of the function at file offset `offset` in the binary at
+    /// `path`.
+    pub fn uretprobe<P: AsRef<std::path::Path>>(self, path: P, offset: u64) -> Result<Self> {
+        let path = path.as_ref().to_string_lossy();
+        self._probe("uprobe", &path, offset, true)
+    }
+
     builder_pattern!(
         /// Set process to be monitored.
         ///
@@ -454,6 +1738,19 @@ impl PerfEventBuilder {
         pid: libc::pid_t
     );
 
+    /// Set cgroup to be monitored.
+    ///
+    /// `path` is the directory of the cgroup in the cgroup filesystem, e.g.,
+    /// `/sys/fs/cgroup/system.slice`. All processes in the cgroup are monitored and the value of
+    /// `pid` is ignored.
+    ///
+    /// # Note
+    /// The kernel only supports cgroup monitoring on a single CPU, i.e., `cpuid` must not be `-1`.
+    pub fn cgroup<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.cgroup = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     builder_pattern!(
         /// Set CPU to be monitored.
         ///
@@ -461,6 +1758,15 @@ impl PerfEventBuilder {
         cpuid: libc::c_int
     );
 
+    /// Set the clock used for the timestamps of records, e.g., `libc::CLOCK_MONOTONIC`.
+    ///
+    /// This allows correlating the timestamps of samples with those taken by the application
+    /// using `clock_gettime`.
+    pub fn clockid(mut self, clockid: libc::clockid_t) -> Self {
+        self.clockid = Some(clockid);
+        self
+    }
+
     builder_pattern!(
         /// Set group leader for this perf event
         leader: libc::c_int
@@ -481,13 +1787,78 @@ impl PerfEventBuilder {
         use_frequency => use_freq: bool = true
     );
 
+    builder_pattern!(
+        /// Set number of samples after which readers of the ring buffer are woken up.
+        set_wakeup_events => wakeup_events_or_watermark: u32
+    );
+
+    /// Set number of bytes in the ring buffer after which readers are woken up.
+    ///
+    /// Implies `use_wakeup_watermark`.
+    pub fn set_wakeup_watermark(mut self, wakeup_watermark: u32) -> Self {
+        self.wakeup_events_or_watermark = wakeup_watermark;
+        self.use_watermark = true;
+        self
+    }
+
+    builder_pattern!(
+        /// Use the wakeup watermark instead of the number of wakeup events.
+        use_wakeup_watermark => use_watermark: bool = true
+    );
+
+    builder_pattern!(
+        /// Request the precision of the instruction pointer of samples, i.e., the allowed skid.
+        ///
+        /// Ranges from `0` for arbitrary skid to `3` for zero skid. Levels above `0` usually
+        /// require PEBS on Intel or IBS on AMD processors.
+        set_precise_ip => precise_ip: u8
+    );
+
+    builder_pattern!(
+        /// Request the highest precision of the instruction pointer the event can be opened with.
+        ///
+        /// The precision is probed when the event is opened, see `max_precise_ip`, so the event
+        /// falls back to samples with skid where PEBS is unavailable, e.g., in virtual machines.
+        use_max_precise => max_precise: bool = true
+    );
+
     builder_pattern!(
         /// Turns on kernel measurements.
         collect_kernel: bool = true
     );
 
+    builder_pattern!(
+        /// Exclude counts in user space.
+        exclude_user: bool
+    );
+
+    builder_pattern!(
+        /// Exclude counts in the hypervisor.
+        exclude_hv: bool
+    );
+
+    builder_pattern!(
+        /// Exclude counts when the CPU is idle.
+        exclude_idle: bool
+    );
+
+    builder_pattern!(
+        /// Exclude counts in the host, i.e., only count in guest virtual machines.
+        exclude_host: bool
+    );
+
+    builder_pattern!(
+        /// Exclude counts in guest virtual machines.
+        exclude_guest: bool
+    );
+
     builder_pattern!(
         /// Inherit to children processes.
+        ///
+        /// # Note
+        /// Samples of inherited events do not contain the value of the event, so
+        /// `read_samples` returns no values. Sampled events can only be inherited on a specific
+        /// `cpuid`.
         inherit: bool = true
     );
 
@@ -496,11 +1867,37 @@ impl PerfEventBuilder {
         start_disabled: bool = true
     );
 
+    builder_pattern!(
+        /// Enable the counter when the monitored process calls `exec`.
+        ///
+        /// Use together with `start_disabled` to only measure the executed program.
+        enable_on_exec: bool = true
+    );
+
     builder_pattern!(
         /// Gather data about context switches.
         gather_context_switches: bool = true
     );
 
+    builder_pattern!(
+        /// Gather data about cgroups.
+        ///
+        /// Samples are tagged with the id of the cgroup they were taken in and the ring buffer
+        /// receives records mapping cgroup ids to paths. Requires Linux 5.7 or newer.
+        gather_cgroups: bool = true
+    );
+
+    builder_pattern!(
+        /// Gather data about kernel symbols being registered and unregistered, e.g., JIT compiled
+        /// BPF programs. Requires Linux 5.1 or newer.
+        gather_ksymbols: bool = true
+    );
+
+    builder_pattern!(
+        /// Gather data about BPF programs being loaded and unloaded. Requires Linux 5.1 or newer.
+        gather_bpf_events: bool = true
+    );
+
     builder_pattern!(
         /// This performance counter will be sampled and accessed through the RingBuffer.
         enable_sampling => is_sampled: bool = true
@@ -513,6 +1910,71 @@ impl PerfEventBuilder {
         /// This will be rounded of to the next multiple of native page size.
         requested_size: usize
     );
+
+    builder_pattern!(
+        /// Let the kernel overwrite the oldest records once the ring buffer is full.
+        ///
+        /// The ring buffer then always holds the latest records, which can be read using
+        /// `PerfEvent::snapshot`. The AUX area is not supported in this mode.
+        use_overwrite_buffer => overwrite: bool = true
+    );
+
+    builder_pattern!(
+        /// Wait for samples in `unread_events` by spinning on the ring buffer instead of calling
+        /// `poll`.
+        ///
+        /// This avoids the latency of a system call and of being woken up by the kernel, at the
+        /// cost of keeping a CPU busy while waiting. See `RingBuffer::spin_wait`.
+        use_busy_poll => busy_poll: bool = true
+    );
+
+    builder_pattern!(
+        /// Size requested for the AUX area of the ring buffer.
+        ///
+        /// The AUX area is only used by PMUs that produce bulk trace data, e.g., Intel PT.
+        ///
+        /// # Note
+        /// This will be rounded of to the next power of 2 multiple of native page size.
+        requested_aux_size: usize
+    );
+
+    builder_pattern!(
+        /// Capture the user-space registers selected by the mask with each sample.
+        ///
+        /// Bit `i` of the mask selects the register with index `i` in the `PerfRegister` enum of
+        /// the current architecture.
+        sample_regs_user: u64
+    );
+
+    builder_pattern!(
+        /// Capture the default set of user-space registers of the current architecture with each
+        /// sample.
+        sample_default_regs_user => sample_regs_user: u64 = crate::arch::SAMPLE_REGS_USER_DEFAULT
+    );
+
+    builder_pattern!(
+        /// Capture `sample_stack_user` bytes of the user-space stack with each sample.
+        ///
+        /// # Note
+        /// The kernel requires the size to be a multiple of 8 and smaller than 64KB. Use together
+        /// with `sample_regs_user` to allow unwinding of the captured stack.
+        sample_stack_user: u32
+    );
+
+    builder_pattern!(
+        /// Capture the last branches taken before each sample, selected by a mask of
+        /// `ffi::perf_branch_sample_type`, e.g., `PERF_SAMPLE_BRANCH_ANY | PERF_SAMPLE_BRANCH_USER`.
+        ///
+        /// The branches are recorded by the LBRs of the CPU and are available in
+        /// `SampleRecord::branch_stack`. On x86_64, `arch::Lbr` reads the LBRs without perf.
+        sample_branch_stack => branch_sample_type: u64
+    );
+
+    builder_pattern!(
+        /// Capture the raw data of each sample in `SampleRecord::raw`, e.g., the fields of
+        /// tracepoints or the IBS registers of AMD processors.
+        sample_raw: bool = true
+    );
 }
 
 #[cfg(test)]
@@ -530,6 +1992,36 @@ mod tests {
         assert!(paranoid <= 2);
     }
 
+    #[test]
+    fn test_perf_sample_stack_user() {
+        let mut attr = ffi::perf_event_attr::default();
+        PerfEvent::build()
+            .enable_sampling()
+            .sample_stack_user(8192)
+            ._set_attr_config(&mut attr);
+        assert_ne!(
+            attr.sample_type & ffi::perf_event_sample_format::PERF_SAMPLE_STACK_USER as u64,
+            0
+        );
+        assert_eq!(attr.sample_stack_user, 8192);
+    }
+
+    #[test]
+    fn test_perf_inherit_sample_read() {
+        let read = ffi::perf_event_sample_format::PERF_SAMPLE_READ as u64;
+        let mut attr = ffi::perf_event_attr::default();
+        PerfEvent::build()
+            .enable_sampling()
+            ._set_attr_config(&mut attr);
+        assert_ne!(attr.sample_type & read, 0);
+        let mut attr = ffi::perf_event_attr::default();
+        PerfEvent::build()
+            .enable_sampling()
+            .inherit()
+            ._set_attr_config(&mut attr);
+        assert_eq!(attr.sample_type & read, 0);
+    }
+
     #[test]
     fn test_perf_read_fd() {
         // Create event
@@ -555,6 +2047,405 @@ mod tests {
         assert!(count.scaled_value() > 0);
     }
 
+    #[test]
+    fn test_perf_event_id() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .enable_sampling()
+            .open(None)
+            .unwrap();
+        let id = evt.id().unwrap();
+        assert_eq!(evt.read_sync().unwrap().id, id);
+        assert_eq!(evt.event_name(id), Some("task-clock"));
+    }
+
+    #[test]
+    fn test_perf_max_precise() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .set_period(10_000)
+            .enable_sampling()
+            .use_max_precise()
+            .open(None)
+            .unwrap();
+        let level = crate::perf::max_precise_ip(&evt.attr);
+        assert_eq!(evt.attr.precise_ip(), u64::from(level));
+        let builder = PerfEvent::software(crate::perf::SwId::TaskClock).set_precise_ip(4);
+        assert!(builder._check_capabilities().is_err());
+    }
+
+    #[test]
+    fn test_perf_wakeup_watermark() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .enable_sampling()
+            .set_wakeup_watermark(4096)
+            .open(None)
+            .unwrap();
+        assert_eq!(evt.attr.watermark(), 1);
+        assert_eq!(unsafe { evt.attr.__bindgen_anon_2.wakeup_watermark }, 4096);
+    }
+
+    #[test]
+    fn test_perf_snapshot() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .set_period(10_000)
+            .enable_sampling()
+            .requested_size(4096)
+            .use_overwrite_buffer()
+            .open(None);
+        assert!(evt.is_ok());
+        let evt = evt.unwrap();
+        assert!(evt.enable().is_ok());
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(50) {}
+        assert!(evt.disable().is_ok());
+
+        let records = evt.snapshot();
+        assert!(records.is_ok());
+        let times: Vec<u64> = records
+            .unwrap()
+            .into_iter()
+            .filter_map(|r| match r {
+                crate::perf::ParsedRecord::Sample(s) => s.time,
+                _ => None,
+            })
+            .collect();
+        assert!(!times.is_empty());
+        assert!(times.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn test_perf_ringbuffer_reader() {
+        let mut evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .set_period(100_000)
+            .set_wakeup_events(1)
+            .enable_sampling()
+            .open(None)
+            .unwrap();
+        let mut reader = evt.take_reader().unwrap().unwrap();
+        assert!(evt.take_reader().unwrap().is_none());
+
+        let consumer = std::thread::spawn(move || {
+            assert!(reader.wait(1000).unwrap());
+            reader
+                .drain()
+                .filter(|r| matches!(r, Ok(crate::perf::ParsedRecord::Sample(_))))
+                .count()
+        });
+        assert!(evt.enable().is_ok());
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(10) {}
+        assert!(evt.disable().is_ok());
+        assert!(consumer.join().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_perf_busy_poll() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .set_period(100_000)
+            .enable_sampling()
+            .use_busy_poll()
+            .open(None)
+            .unwrap();
+        assert!(!evt.unread_events(10).unwrap());
+        assert!(evt.enable().is_ok());
+        assert!(evt.unread_events(1000).unwrap());
+        assert!(evt.disable().is_ok());
+    }
+
+    #[test]
+    fn test_perf_event_group() {
+        use crate::perf::SwId;
+        let group = PerfEvent::build()
+            .start_disabled()
+            .open_group(vec![SwId::TaskClock.into(), SwId::PageFaults.into()]);
+        assert!(group.is_ok());
+        let group = group.unwrap();
+        assert_eq!(group.len(), 2);
+        assert_eq!(
+            group.leader().attr.config,
+            ffi::perf_event_attr::from(SwId::TaskClock).config
+        );
+        assert!(group.reset().is_ok());
+        assert!(group.enable().is_ok());
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(10) {}
+        assert!(group.disable().is_ok());
+
+        let values = group.read_sync().unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(values[0].value > 0);
+        assert!(values[1].time_enabled > 0);
+        let member = group.event_by_id(values[1].id);
+        assert!(member.is_some());
+        assert_eq!(
+            member.unwrap().attr.config,
+            ffi::perf_event_attr::from(SwId::PageFaults).config
+        );
+        assert!(PerfEvent::build().open_group(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_perf_measure() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .open(None)
+            .unwrap();
+        let (res, val) = evt
+            .measure(|| (0u64..100_000).filter(|x| x % 3 == 0).sum::<u64>())
+            .unwrap();
+        assert_eq!(res, 1_666_683_333);
+        assert!(val.value > 0);
+        let after = evt.read_sync().unwrap();
+        assert_eq!(after.value, val.value);
+    }
+
+    #[test]
+    fn test_perf_counting_guard() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .open(None)
+            .unwrap();
+        let val = evt.start().unwrap().stop().unwrap();
+        assert_eq!(evt.read_sync().unwrap().value, val.value);
+
+        // The event is disabled and read even if the measured code panics
+        let mut dest = None;
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = evt.start().unwrap().read_into(&mut dest);
+            panic!("Measured code failed");
+        }));
+        assert!(res.is_err());
+        let val = dest.unwrap();
+        assert!(val.value > 0);
+        assert_eq!(evt.read_sync().unwrap().value, val.value);
+    }
+
+    #[test]
+    fn test_perf_attach() {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "read x; exec sleep 0.1"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        let mut proc = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .attach(pid, None)
+            .unwrap();
+        assert_eq!(proc.pid(), pid);
+        assert_eq!(proc.threads().len(), 1);
+        assert!(!proc.is_closed().unwrap());
+
+        // The events stay attached when the process calls exec
+        drop(child.stdin.take());
+        assert!(child.wait().unwrap().success());
+        assert!(proc.is_closed().unwrap());
+        assert!(proc.refresh().is_err());
+        let vals = proc.detach().unwrap();
+        assert_eq!(vals.len(), 1);
+        assert!(vals[0].value > 0);
+
+        assert!(PerfEvent::software(crate::perf::SwId::TaskClock)
+            .attach(-1, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_perf_attach_new_threads() {
+        let mut proc = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .attach(0, None)
+            .unwrap();
+        let (tid_tx, tid_rx) = std::sync::mpsc::channel();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            tid_tx.send(nix::unistd::gettid().as_raw()).unwrap();
+            rx.recv()
+        });
+        let tid = tid_rx.recv().unwrap();
+        assert!(proc.refresh().unwrap() >= 1);
+        assert!(proc.threads().iter().any(|(t, _)| *t == tid));
+        assert_eq!(proc.read_sync().unwrap()[0].value, 0);
+
+        assert!(proc.enable().is_ok());
+        tx.send(()).unwrap();
+        handle.join().unwrap().unwrap();
+        assert!(proc.disable().is_ok());
+        assert!(proc.read_sync().unwrap()[0].value > 0);
+    }
+
+    #[test]
+    fn test_perf_check_capabilities() {
+        use crate::perf::SwId;
+        let check = |builder: PerfEventBuilder| match builder._check_capabilities() {
+            Err(Error::InvalidSetting { setting, .. }) => setting,
+            Err(e) => panic!("Unexpected error {}", e),
+            Ok(()) => "",
+        };
+        assert_eq!(check(PerfEvent::software(SwId::TaskClock)), "");
+        assert_eq!(
+            check(PerfEvent::software(SwId::TaskClock).enable_sampling()),
+            ""
+        );
+        assert_eq!(
+            check(PerfEvent::software(SwId::TaskClock).requested_size(1 << 20)),
+            "requested_size"
+        );
+        assert_eq!(
+            check(PerfEvent::software(SwId::TaskClock).gather_cgroups()),
+            "gather_cgroups"
+        );
+        assert_eq!(
+            check(
+                PerfEvent::software(SwId::TaskClock)
+                    .enable_sampling()
+                    .set_period(0)
+            ),
+            "set_period"
+        );
+        assert_eq!(
+            check(
+                PerfEvent::software(SwId::TaskClock)
+                    .enable_sampling()
+                    .use_frequency()
+                    .set_frequency(u64::MAX)
+            ),
+            "set_frequency"
+        );
+        assert_eq!(
+            check(
+                PerfEvent::software(SwId::TaskClock)
+                    .enable_sampling()
+                    .cpuid(0)
+                    .inherit()
+            ),
+            ""
+        );
+        assert_eq!(check(PerfEvent::software(SwId::TaskClock).inherit()), "");
+        assert_eq!(
+            check(
+                PerfEvent::software(SwId::TaskClock)
+                    .enable_sampling()
+                    .inherit()
+            ),
+            "inherit"
+        );
+        assert_eq!(
+            check(
+                PerfEvent::software(SwId::TaskClock)
+                    .enable_sampling()
+                    .use_wakeup_watermark()
+                    .set_wakeup_watermark(1 << 30)
+            ),
+            "set_wakeup_watermark"
+        );
+        assert_eq!(
+            check(
+                PerfEvent::software(SwId::TaskClock)
+                    .enable_sampling()
+                    .sample_stack_user(100)
+            ),
+            "sample_stack_user"
+        );
+        assert_eq!(
+            check(PerfEvent::software(SwId::TaskClock).sample_branch_stack(8)),
+            "sample_branch_stack"
+        );
+        assert_eq!(
+            check(PerfEvent::software(SwId::TaskClock).sample_raw()),
+            "sample_raw"
+        );
+        assert!(PerfEvent::software(SwId::TaskClock)
+            .requested_size(1 << 20)
+            .open(None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_perf_event_spec() {
+        let builder = PerfEvent::build().event("task-clock:k").unwrap();
+        assert!(builder.collect_kernel && builder.exclude_user);
+        let evt = builder.open(None).unwrap();
+        assert_eq!(evt.name(), "task-clock:k");
+        assert_eq!(evt.attr.exclude_kernel(), 0);
+        assert_eq!(evt.attr.exclude_user(), 1);
+        assert!(PerfEvent::build().event("task-clock:q").is_err());
+    }
+
+    #[test]
+    fn test_perf_per_cpu() {
+        let evt = PerfEvent::software(crate::perf::SwId::CpuClock)
+            .start_disabled()
+            .open_per_cpu(None)
+            .unwrap();
+        let (_, total) = evt
+            .measure(|| {
+                let start = std::time::Instant::now();
+                while start.elapsed() < std::time::Duration::from_millis(10) {}
+            })
+            .unwrap();
+        let per_cpu = evt.per_cpu().unwrap();
+        assert_eq!(per_cpu.len(), crate::util::online_cpus().unwrap().len());
+        assert_eq!(
+            total.value,
+            per_cpu.iter().map(|(_, v)| v.value).sum::<u64>()
+        );
+        let (cpu, max) = evt.max().unwrap();
+        assert!(per_cpu.iter().all(|(_, v)| v.value <= max.value));
+        assert!(per_cpu.iter().any(|(c, _)| *c == cpu));
+        let per_package = evt.per_package().unwrap();
+        assert_eq!(
+            per_package.len(),
+            crate::util::package_cpus().unwrap().len()
+        );
+        assert!(per_package.iter().map(|(_, v)| v.value).sum::<u64>() >= total.value);
+
+        let cpus = crate::util::online_cpus().unwrap();
+        let evt = PerfEvent::software(crate::perf::SwId::CpuClock)
+            .open_on_cpus(&cpus[..1], None)
+            .unwrap();
+        assert_eq!(evt.events().len(), 1);
+        assert_eq!(evt.events()[0].0, cpus[0]);
+        assert!(PerfEvent::software(crate::perf::SwId::CpuClock)
+            .open_on_cpus(&[], None)
+            .is_err());
+
+        let attr: ffi::perf_event_attr = crate::perf::SwId::CpuClock.into();
+        let groups = PerfEvent::software(crate::perf::SwId::CpuClock)
+            .open_group_on_cpus(&cpus, vec![attr, attr])
+            .unwrap();
+        assert_eq!(groups.len(), cpus.len());
+        assert!(groups.iter().zip(cpus.iter()).all(|((c, _), cpu)| c == cpu));
+
+        let uncore = PerfEvent::software(crate::perf::SwId::CpuClock)
+            .open_uncore(vec![("software".into(), attr), ("software".into(), attr)])
+            .unwrap();
+        assert_eq!(uncore.boxes().len(), 2);
+        assert_eq!(uncore.per_box().unwrap()[1].0, "software");
+        assert!(uncore.sum().is_ok());
+        assert!(PerfEvent::software(crate::perf::SwId::CpuClock)
+            .open_uncore(vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn test_perf_overflow_notification() {
+        let evt = PerfEvent::software(crate::perf::SwId::TaskClock)
+            .start_disabled()
+            .set_period(100_000)
+            .enable_sampling()
+            .open(None);
+        assert!(evt.is_ok());
+        let mut evt = evt.unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        assert!(evt.on_overflow(move || tx.send(()).unwrap()).is_ok());
+        assert!(evt.refresh(1).is_ok());
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(10) {}
+        assert!(rx.recv_timeout(std::time::Duration::from_secs(1)).is_ok());
+    }
+
     #[test]
     fn test_perf_read_ringbuffer() {
         // Create Event
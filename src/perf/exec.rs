@@ -0,0 +1,171 @@
+//! Utilities to launch and monitor a child process, similar to `perf stat -- cmd`.
+
+use crate::api::Counter;
+use crate::perf::{PerfEvent, PerfEventBuilder, PerfEventValue};
+use crate::Result;
+use log::debug;
+use nix::libc;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+use std::ffi::CString;
+
+/// Launches a command with a set of perf events attached to it.
+///
+/// The events are opened disabled on the child process before it calls `exec` and are enabled by
+/// the kernel when `exec` succeeds, so only the command itself is measured. The events are
+/// inherited by any process the command forks.
+#[derive(Debug)]
+pub struct PerfCommand {
+    /// Program to be executed (looked up in `PATH`).
+    program: String,
+    /// Arguments passed to the program.
+    args: Vec<String>,
+    /// Builders for the events measuring the program.
+    events: Vec<(PerfEventBuilder, Option<crate::perf::ffi::perf_event_attr>)>,
+}
+
+/// Outcome of running a `PerfCommand`.
+#[derive(Debug)]
+pub struct CommandResult {
+    /// Exit status of the command.
+    pub status: WaitStatus,
+    /// Events that measured the command.
+    pub events: Vec<PerfEvent>,
+}
+
+impl CommandResult {
+    /// Read the final values of all events.
+    pub fn values(&self) -> Result<Vec<PerfEventValue>> {
+        self.events.iter().map(|e| e.read_sync()).collect()
+    }
+}
+
+impl PerfCommand {
+    /// Create a new command for `program`.
+    pub fn new<S: Into<String>>(program: S) -> Self {
+        PerfCommand {
+            program: program.into(),
+            args: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Add an argument to the command.
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add arguments to the command.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Add an event to measure the command with.
+    ///
+    /// `base_event_attr` is handled as in `PerfEventBuilder::open`.
+    pub fn event(
+        mut self,
+        event: PerfEventBuilder,
+        base_event_attr: Option<crate::perf::ffi::perf_event_attr>,
+    ) -> Self {
+        self.events.push((event, base_event_attr));
+        self
+    }
+
+    /// Run the command to completion and collect the events measuring it.
+    pub fn run(self) -> Result<CommandResult> {
+        // Prepare everything the child needs before forking; it must not allocate.
+        let mut argv = Vec::with_capacity(self.args.len() + 1);
+        argv.push(CString::new(self.program.as_str()).map_err(std::io::Error::from)?);
+        for arg in self.args.iter() {
+            argv.push(CString::new(arg.as_str()).map_err(std::io::Error::from)?);
+        }
+        let mut argv_ptrs: Vec<*const libc::c_char> = argv.iter().map(|a| a.as_ptr()).collect();
+        argv_ptrs.push(std::ptr::null());
+        let (rd, wr) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?;
+
+        match fork()? {
+            ForkResult::Child => unsafe {
+                // Wait for the parent to open the events before calling exec.
+                libc::close(wr);
+                let mut go = 0u8;
+                if libc::read(rd, &mut go as *mut u8 as *mut libc::c_void, 1) == 1 {
+                    libc::execvp(argv_ptrs[0], argv_ptrs.as_ptr());
+                }
+                libc::_exit(127);
+            },
+            ForkResult::Parent { child } => {
+                let _ = nix::unistd::close(rd);
+                let events: Result<Vec<PerfEvent>> = self
+                    .events
+                    .into_iter()
+                    .map(|(builder, attr)| {
+                        builder
+                            .pid(child.as_raw())
+                            .inherit()
+                            .start_disabled()
+                            .enable_on_exec()
+                            .open(attr)
+                    })
+                    .collect();
+                // The child exits without calling exec if it does not receive the go signal.
+                if events.is_ok() {
+                    let _ = nix::unistd::write(wr, &[1u8]);
+                }
+                let _ = nix::unistd::close(wr);
+                let status = waitpid(child, None)?;
+                debug!("Command {:?} finished with {:?}", self.program, status);
+                Ok(CommandResult {
+                    status,
+                    events: events?,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perf_command() {
+        let res = PerfCommand::new("true")
+            .event(PerfEvent::software(crate::perf::SwId::TaskClock), None)
+            .run();
+        assert!(res.is_ok());
+        let res = res.unwrap();
+        match res.status {
+            WaitStatus::Exited(_, code) => assert_eq!(code, 0),
+            _ => panic!("Command did not exit normally"),
+        }
+        let values = res.values();
+        assert!(values.is_ok());
+        assert!(values.unwrap()[0].value > 0);
+    }
+
+    #[test]
+    fn test_perf_command_inherit() {
+        // The busy loop runs in a forked subshell and must be counted too
+        let res = PerfCommand::new("sh")
+            .args([
+                "-c",
+                "(i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done); true",
+            ])
+            .event(PerfEvent::software(crate::perf::SwId::TaskClock), None)
+            .run()
+            .unwrap();
+        match res.status {
+            WaitStatus::Exited(_, code) => assert_eq!(code, 0),
+            _ => panic!("Command did not exit normally"),
+        }
+        // The loop takes well over 10ms of task clock
+        assert!(res.values().unwrap()[0].value > 10_000_000);
+    }
+}
@@ -9,38 +9,122 @@ non_snake_case
 )]
 
 use crate::{Error, Result};
+use log::debug;
+use nix::errno::Errno;
 use nix::libc;
-use nix::{ioctl_none, ioctl_write_int, ioctl_write_ptr};
+use nix::{
+    ioctl_none, ioctl_read_bad, ioctl_readwrite_bad, ioctl_write_int, ioctl_write_int_bad,
+    ioctl_write_ptr, request_code_none, request_code_read, request_code_readwrite,
+};
 
 // Read Bindgen wrappers
 include!(concat!(env!("OUT_DIR"), "/kernel_headers.rs"));
 
 // The Ioctls are defined as macro functions and skipped by bindgen.
 // Details at https://elixir.bootlin.com/linux/v5.3.10/source/include/uapi/linux/perf_event.h#L456
-ioctl_none!(perf_event_ioc_enable, b'$', 0);
-ioctl_none!(perf_event_ioc_disable, b'$', 1);
-ioctl_none!(perf_event_ioc_refresh, b'$', 2);
-ioctl_none!(perf_event_ioc_reset, b'$', 3);
+ioctl_write_int_bad!(perf_event_ioc_enable, request_code_none!(b'$', 0));
+ioctl_write_int_bad!(perf_event_ioc_disable, request_code_none!(b'$', 1));
+ioctl_write_int_bad!(perf_event_ioc_refresh, request_code_none!(b'$', 2));
+ioctl_write_int_bad!(perf_event_ioc_reset, request_code_none!(b'$', 3));
 ioctl_write_int!(perf_event_ioc_period, b'$', 4);
 ioctl_none!(perf_event_ioc_set_output, b'$', 5);
 ioctl_write_ptr!(perf_event_ioc_set_filter, b'$', 6, libc::c_char);
-ioctl_write_ptr!(perf_event_ioc_id, b'$', 7, libc::c_ulong);
+ioctl_read_bad!(
+    perf_event_ioc_id,
+    request_code_read!(b'$', 7, std::mem::size_of::<*mut u64>()),
+    u64
+);
 ioctl_write_int!(perf_event_ioc_set_bpf, b'$', 8);
 ioctl_write_int!(perf_event_ioc_pause_output, b'$', 9);
+ioctl_readwrite_bad!(
+    perf_event_ioc_query_bpf,
+    request_code_readwrite!(b'$', 10, std::mem::size_of::<*mut perf_event_query_bpf>()),
+    perf_event_query_bpf
+);
 ioctl_write_ptr!(perf_event_ioc_modify_attributes, b'$', 11, perf_event_attr);
 
+/// Sizes of the revisions of `perf_event_attr`, i.e., `PERF_ATTR_SIZE_VER0` and later.
+pub(crate) const PERF_ATTR_SIZES: [u32; 9] = [64, 72, 80, 96, 104, 112, 120, 128, 136];
+
+/// Name, offset and size of the fields of `perf_event_attr` added after `PERF_ATTR_SIZE_VER0`.
+const PERF_ATTR_LATER_FIELDS: [(&str, usize, usize); 11] = [
+    ("config2", 64, 8),
+    ("branch_sample_type", 72, 8),
+    ("sample_regs_user", 80, 8),
+    ("sample_stack_user", 88, 4),
+    ("clockid", 92, 4),
+    ("sample_regs_intr", 96, 8),
+    ("aux_watermark", 104, 4),
+    ("sample_max_stack", 108, 2),
+    ("aux_sample_size", 112, 4),
+    ("sig_data", 120, 8),
+    ("config3", 128, 8),
+];
+
 /// Rust wrapper for the `perf_event_open` system call.
+///
+/// Kernels older than the headers this crate is built against reject attributes larger than
+/// their own with `E2BIG`. The call is then retried with the older sizes of `perf_event_attr`,
+/// as long as none of the fields beyond them are set. Otherwise, the attributes cannot be
+/// honoured and an `InvalidSetting` error naming the unsupported fields is returned.
 pub fn perf_event_open(
     attr: &perf_event_attr,
     pid: libc::pid_t,
     cpu: libc::c_int,
     group_fd: libc::c_int,
     flags: libc::c_ulong,
+) -> Result<std::os::unix::io::RawFd> {
+    let mut attr = *attr;
+    loop {
+        let size = attr.size;
+        match _perf_event_open(&mut attr, pid, cpu, group_fd, flags) {
+            Err(Error::System(nix::Error::Sys(Errno::E2BIG))) => {
+                // The kernel reports the size it supports, try the next older size otherwise
+                let older = if attr.size > 0 && attr.size < size {
+                    Some(attr.size)
+                } else {
+                    PERF_ATTR_SIZES.iter().rev().find(|&&s| s < size).copied()
+                };
+                match older {
+                    Some(older) => {
+                        let unsupported = attr._fields_beyond(older);
+                        if !unsupported.is_empty() {
+                            return Err(Error::InvalidSetting {
+                                setting: unsupported[0],
+                                reason: format!(
+                                    "{:?} unsupported by the kernel's {} byte perf_event_attr",
+                                    unsupported, older
+                                ),
+                            });
+                        }
+                        debug!(
+                            "Retrying perf_event_open with attr size {} instead of {}",
+                            older, size
+                        );
+                        attr._truncate(older);
+                    }
+                    None => return Err(Error::System(nix::Error::Sys(Errno::E2BIG))),
+                }
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Invoke the `perf_event_open` system call without retrying.
+///
+/// The kernel overwrites the `size` of `attr` with the size it supports if it fails with `E2BIG`.
+pub(crate) fn _perf_event_open(
+    attr: &mut perf_event_attr,
+    pid: libc::pid_t,
+    cpu: libc::c_int,
+    group_fd: libc::c_int,
+    flags: libc::c_ulong,
 ) -> Result<std::os::unix::io::RawFd> {
     let fd = unsafe {
         libc::syscall(
             libc::SYS_perf_event_open,
-            attr as *const _,
+            attr as *mut _,
             pid,
             cpu,
             group_fd,
@@ -55,6 +139,32 @@ pub fn perf_event_open(
 
 // Extend perf_event_attr
 impl perf_event_attr {
+    /// Set the `size` of the attributes to `size`, clearing all fields beyond it.
+    fn _truncate(&mut self, size: u32) {
+        let len = std::mem::size_of::<Self>();
+        let start = std::cmp::min(size as usize, len);
+        let bytes = unsafe { std::slice::from_raw_parts_mut(self as *mut Self as *mut u8, len) };
+        for b in bytes[start..].iter_mut() {
+            *b = 0;
+        }
+        self.size = size;
+    }
+
+    /// Get the names of the fields set beyond the first `size` bytes of the attributes.
+    fn _fields_beyond(&self, size: u32) -> Vec<&'static str> {
+        let len = std::mem::size_of::<Self>();
+        let bytes = unsafe { std::slice::from_raw_parts(self as *const Self as *const u8, len) };
+        PERF_ATTR_LATER_FIELDS
+            .iter()
+            .filter(|&&(_, offset, width)| {
+                offset + width > size as usize
+                    && offset + width <= len
+                    && bytes[offset..offset + width].iter().any(|&b| b != 0)
+            })
+            .map(|&(name, _, _)| name)
+            .collect()
+    }
+
     /// Get the PMU string from the `type_` field of a `perf_event_attr`.
     fn _get_pmu(&self) -> Result<String> {
         let pmu = glob::glob("/sys/devices/*/type")?
@@ -173,4 +283,40 @@ mod tests {
         assert!(perf_str.is_ok());
         assert_eq!(perf_str.unwrap(), "software/config=0x1/uIGH");
     }
+
+    #[test]
+    fn test_perf_event_attr_truncate() {
+        let mut attr = perf_event_attr::default();
+        attr.type_ = perf_type_id::PERF_TYPE_SOFTWARE as _;
+        attr.config = perf_sw_ids::PERF_COUNT_SW_TASK_CLOCK as _;
+        attr.size = std::mem::size_of::<perf_event_attr>() as _;
+        attr.set_disabled(1);
+        attr.set_exclude_kernel(1);
+        attr.sample_regs_intr = 1;
+        attr.aux_watermark = 4096;
+        attr._truncate(PERF_ATTR_SIZES[4]);
+        assert_eq!(attr.size, 104);
+        assert_eq!(attr.sample_regs_intr, 1);
+        assert_eq!(attr.aux_watermark, 0);
+        assert_eq!(attr.config, perf_sw_ids::PERF_COUNT_SW_TASK_CLOCK as u64);
+        assert_eq!(attr.disabled(), 1);
+
+        let fd = perf_event_open(&attr, 0, -1, -1, PERF_FLAG_FD_CLOEXEC as _).unwrap();
+        assert!(nix::unistd::close(fd).is_ok());
+    }
+
+    #[test]
+    fn test_perf_event_attr_fields_beyond() {
+        let mut attr = perf_event_attr::default();
+        attr.config = perf_sw_ids::PERF_COUNT_SW_TASK_CLOCK as _;
+        assert!(attr._fields_beyond(PERF_ATTR_SIZES[0]).is_empty());
+        attr.sample_stack_user = 8192;
+        attr.aux_watermark = 4096;
+        assert_eq!(
+            attr._fields_beyond(PERF_ATTR_SIZES[0]),
+            vec!["sample_stack_user", "aux_watermark"]
+        );
+        assert_eq!(attr._fields_beyond(PERF_ATTR_SIZES[4]), vec!["aux_watermark"]);
+        assert!(attr._fields_beyond(PERF_ATTR_SIZES[5]).is_empty());
+    }
 }
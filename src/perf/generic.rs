@@ -0,0 +1,214 @@
+//! Generic hardware and software events supported by the kernel on all platforms.
+
+use crate::perf::ffi;
+
+/// Generic hardware events (`PERF_TYPE_HARDWARE`).
+///
+/// The kernel maps these to the corresponding events of the underlying microarchitecture. Not all
+/// events are supported on all microarchitectures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwId {
+    /// Total cycles.
+    CpuCycles,
+    /// Retired instructions.
+    Instructions,
+    /// Cache accesses, usually to the last level cache.
+    CacheReferences,
+    /// Cache misses, usually in the last level cache.
+    CacheMisses,
+    /// Retired branch instructions.
+    BranchInstructions,
+    /// Mispredicted branch instructions.
+    BranchMisses,
+    /// Bus cycles.
+    BusCycles,
+    /// Stalled cycles during issue.
+    StalledCyclesFrontend,
+    /// Stalled cycles during retirement.
+    StalledCyclesBackend,
+    /// Total cycles not affected by CPU frequency scaling.
+    RefCpuCycles,
+}
+
+impl HwId {
+    /// Get the name used by the `perf` tool for this event.
+    pub fn name(self) -> &'static str {
+        match self {
+            HwId::CpuCycles => "cpu-cycles",
+            HwId::Instructions => "instructions",
+            HwId::CacheReferences => "cache-references",
+            HwId::CacheMisses => "cache-misses",
+            HwId::BranchInstructions => "branch-instructions",
+            HwId::BranchMisses => "branch-misses",
+            HwId::BusCycles => "bus-cycles",
+            HwId::StalledCyclesFrontend => "stalled-cycles-frontend",
+            HwId::StalledCyclesBackend => "stalled-cycles-backend",
+            HwId::RefCpuCycles => "ref-cycles",
+        }
+    }
+
+    /// Get the event with the name `name` used by the `perf` tool, including its aliases.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cpu-cycles" | "cycles" => Some(HwId::CpuCycles),
+            "instructions" => Some(HwId::Instructions),
+            "cache-references" => Some(HwId::CacheReferences),
+            "cache-misses" => Some(HwId::CacheMisses),
+            "branch-instructions" | "branches" => Some(HwId::BranchInstructions),
+            "branch-misses" => Some(HwId::BranchMisses),
+            "bus-cycles" => Some(HwId::BusCycles),
+            "stalled-cycles-frontend" | "idle-cycles-frontend" => Some(HwId::StalledCyclesFrontend),
+            "stalled-cycles-backend" | "idle-cycles-backend" => Some(HwId::StalledCyclesBackend),
+            "ref-cycles" => Some(HwId::RefCpuCycles),
+            _ => None,
+        }
+    }
+
+    /// Get the corresponding kernel id.
+    fn _id(self) -> ffi::perf_hw_id {
+        use ffi::perf_hw_id::*;
+        match self {
+            HwId::CpuCycles => PERF_COUNT_HW_CPU_CYCLES,
+            HwId::Instructions => PERF_COUNT_HW_INSTRUCTIONS,
+            HwId::CacheReferences => PERF_COUNT_HW_CACHE_REFERENCES,
+            HwId::CacheMisses => PERF_COUNT_HW_CACHE_MISSES,
+            HwId::BranchInstructions => PERF_COUNT_HW_BRANCH_INSTRUCTIONS,
+            HwId::BranchMisses => PERF_COUNT_HW_BRANCH_MISSES,
+            HwId::BusCycles => PERF_COUNT_HW_BUS_CYCLES,
+            HwId::StalledCyclesFrontend => PERF_COUNT_HW_STALLED_CYCLES_FRONTEND,
+            HwId::StalledCyclesBackend => PERF_COUNT_HW_STALLED_CYCLES_BACKEND,
+            HwId::RefCpuCycles => PERF_COUNT_HW_REF_CPU_CYCLES,
+        }
+    }
+}
+
+impl From<HwId> for ffi::perf_event_attr {
+    fn from(id: HwId) -> Self {
+        ffi::perf_event_attr {
+            type_: ffi::perf_type_id::PERF_TYPE_HARDWARE as _,
+            config: id._id() as _,
+            ..Default::default()
+        }
+    }
+}
+
+/// Generic software events (`PERF_TYPE_SOFTWARE`) counted by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwId {
+    /// High resolution per-CPU timer.
+    CpuClock,
+    /// Clock count specific to the running task.
+    TaskClock,
+    /// Page faults.
+    PageFaults,
+    /// Context switches.
+    ContextSwitches,
+    /// Migrations of the process to a new CPU.
+    CpuMigrations,
+    /// Minor page faults, i.e., faults that did not require disk I/O.
+    PageFaultsMin,
+    /// Major page faults, i.e., faults that required disk I/O.
+    PageFaultsMaj,
+    /// Unaligned memory accesses fixed up by the kernel.
+    AlignmentFaults,
+    /// Unimplemented instructions emulated by the kernel.
+    EmulationFaults,
+    /// Placeholder event that counts nothing, e.g., to collect side-band records.
+    Dummy,
+}
+
+impl SwId {
+    /// Get the name used by the `perf` tool for this event.
+    pub fn name(self) -> &'static str {
+        match self {
+            SwId::CpuClock => "cpu-clock",
+            SwId::TaskClock => "task-clock",
+            SwId::PageFaults => "page-faults",
+            SwId::ContextSwitches => "context-switches",
+            SwId::CpuMigrations => "cpu-migrations",
+            SwId::PageFaultsMin => "minor-faults",
+            SwId::PageFaultsMaj => "major-faults",
+            SwId::AlignmentFaults => "alignment-faults",
+            SwId::EmulationFaults => "emulation-faults",
+            SwId::Dummy => "dummy",
+        }
+    }
+
+    /// Get the event with the name `name` used by the `perf` tool, including its aliases.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cpu-clock" => Some(SwId::CpuClock),
+            "task-clock" => Some(SwId::TaskClock),
+            "page-faults" | "faults" => Some(SwId::PageFaults),
+            "context-switches" | "cs" => Some(SwId::ContextSwitches),
+            "cpu-migrations" | "migrations" => Some(SwId::CpuMigrations),
+            "minor-faults" => Some(SwId::PageFaultsMin),
+            "major-faults" => Some(SwId::PageFaultsMaj),
+            "alignment-faults" => Some(SwId::AlignmentFaults),
+            "emulation-faults" => Some(SwId::EmulationFaults),
+            "dummy" => Some(SwId::Dummy),
+            _ => None,
+        }
+    }
+
+    /// Get the corresponding kernel id.
+    fn _id(self) -> ffi::perf_sw_ids {
+        use ffi::perf_sw_ids::*;
+        match self {
+            SwId::CpuClock => PERF_COUNT_SW_CPU_CLOCK,
+            SwId::TaskClock => PERF_COUNT_SW_TASK_CLOCK,
+            SwId::PageFaults => PERF_COUNT_SW_PAGE_FAULTS,
+            SwId::ContextSwitches => PERF_COUNT_SW_CONTEXT_SWITCHES,
+            SwId::CpuMigrations => PERF_COUNT_SW_CPU_MIGRATIONS,
+            SwId::PageFaultsMin => PERF_COUNT_SW_PAGE_FAULTS_MIN,
+            SwId::PageFaultsMaj => PERF_COUNT_SW_PAGE_FAULTS_MAJ,
+            SwId::AlignmentFaults => PERF_COUNT_SW_ALIGNMENT_FAULTS,
+            SwId::EmulationFaults => PERF_COUNT_SW_EMULATION_FAULTS,
+            SwId::Dummy => PERF_COUNT_SW_DUMMY,
+        }
+    }
+}
+
+impl From<SwId> for ffi::perf_event_attr {
+    fn from(id: SwId) -> Self {
+        ffi::perf_event_attr {
+            type_: ffi::perf_type_id::PERF_TYPE_SOFTWARE as _,
+            config: id._id() as _,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generic_attr() {
+        let attr: ffi::perf_event_attr = HwId::Instructions.into();
+        assert_eq!(attr.type_, ffi::perf_type_id::PERF_TYPE_HARDWARE as u32);
+        assert_eq!(
+            attr.config,
+            ffi::perf_hw_id::PERF_COUNT_HW_INSTRUCTIONS as u64
+        );
+        let attr: ffi::perf_event_attr = SwId::PageFaults.into();
+        assert_eq!(attr.type_, ffi::perf_type_id::PERF_TYPE_SOFTWARE as u32);
+        assert_eq!(
+            attr.config,
+            ffi::perf_sw_ids::PERF_COUNT_SW_PAGE_FAULTS as u64
+        );
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(HwId::from_name("cycles"), Some(HwId::CpuCycles));
+        assert_eq!(SwId::from_name("cs"), Some(SwId::ContextSwitches));
+        assert_eq!(HwId::from_name("task-clock"), None);
+        for id in [HwId::Instructions, HwId::RefCpuCycles].iter() {
+            assert_eq!(HwId::from_name(id.name()), Some(*id));
+        }
+        for id in [SwId::PageFaultsMaj, SwId::Dummy].iter() {
+            assert_eq!(SwId::from_name(id.name()), Some(*id));
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Parsing the samples of AMD Instruction Based Sampling (IBS).
+//!
+//! See "Processor Programming Reference (PPR) for AMD Family 17h", Section 2.1.15 "Instruction
+//! Based Sampling (IBS)" for the layout of the registers.
+
+use crate::perf::sysfs;
+use byteorder::{NativeEndian, ReadBytesExt};
+
+/// Name of the PMU sampling instruction fetches.
+pub const IBS_FETCH_PMU: &str = "ibs_fetch";
+/// Name of the PMU sampling micro-ops.
+pub const IBS_OP_PMU: &str = "ibs_op";
+/// Default sampling period of the IBS PMUs.
+pub const IBS_DEFAULT_PERIOD: u64 = 0x10000;
+
+/// Capability bit of the raw data indicating `IbsBrTarget` follows the op registers.
+const CAPS_BRNTRGT: u32 = 1 << 5;
+/// Capability bit of the raw data indicating `IbsOpData4` follows the op registers.
+const CAPS_OPDATA4: u32 = 1 << 10;
+/// Capability bit of the raw data indicating `IcIbsExtdCtl` follows the fetch registers.
+const CAPS_FETCHCTLEXTD: u32 = 1 << 9;
+
+/// Read the IBS capabilities and the registers from the `raw` data of a sample.
+///
+/// Fails if the data holds fewer than `min` registers.
+fn _read_registers(raw: &[u8], min: usize) -> crate::Result<(u32, Vec<u64>)> {
+    let mut ptr = std::io::Cursor::new(raw);
+    let caps = ptr.read_u32::<NativeEndian>()?;
+    let regs = (0..(raw.len() - 4) / 8)
+        .map(|_| ptr.read_u64::<NativeEndian>())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if regs.len() < min {
+        return Err(crate::Error::InvalidEvent(format!(
+            "IBS sample with {} registers instead of {}",
+            regs.len(),
+            min
+        )));
+    }
+    Ok((caps, regs))
+}
+
+/// Get bits `lo` to `hi` (inclusive) of `value`.
+fn _bits(value: u64, lo: u32, hi: u32) -> u64 {
+    (value >> lo) & (u64::MAX >> (63 - (hi - lo)))
+}
+
+/// Sample of an instruction fetch taken by the `ibs_fetch` PMU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IbsFetchSample {
+    /// IBS capabilities of the CPU.
+    pub caps: u32,
+    /// Value of `IbsFetchCtl`.
+    pub fetch_ctl: u64,
+    /// Linear address of the fetch (`IbsFetchLinAd`).
+    pub lin_addr: u64,
+    /// Value of `IbsFetchPhysAd`, valid if `phys_addr` returns it.
+    pub phys_addr_raw: u64,
+    /// Value of `IcIbsExtdCtl` if supported.
+    pub extd_ctl: Option<u64>,
+}
+
+impl IbsFetchSample {
+    /// Check if the `ibs_fetch` PMU is available on the local machine.
+    pub fn is_supported() -> bool {
+        sysfs::pmu_type(IBS_FETCH_PMU).is_ok()
+    }
+
+    /// Parse the `raw` data of a sample, i.e., `SampleRecord::raw`.
+    pub fn from_raw(raw: &[u8]) -> crate::Result<Self> {
+        let (caps, regs) = _read_registers(raw, 3)?;
+        Ok(IbsFetchSample {
+            caps,
+            fetch_ctl: regs[0],
+            lin_addr: regs[1],
+            phys_addr_raw: regs[2],
+            extd_ctl: if caps & CAPS_FETCHCTLEXTD != 0 {
+                regs.get(3).copied()
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Get the number of cycles from the start of the fetch to its completion or abort.
+    pub fn latency(&self) -> u64 {
+        _bits(self.fetch_ctl, 32, 47)
+    }
+
+    /// Check if the fetch completed, i.e., the instruction bytes were delivered to the decoder.
+    pub fn completed(&self) -> bool {
+        _bits(self.fetch_ctl, 50, 50) != 0
+    }
+
+    /// Check if the fetch missed the instruction cache.
+    pub fn ic_miss(&self) -> bool {
+        _bits(self.fetch_ctl, 51, 51) != 0
+    }
+
+    /// Check if the translation of the fetch address missed the L1 TLB.
+    pub fn l1_tlb_miss(&self) -> bool {
+        _bits(self.fetch_ctl, 55, 55) != 0
+    }
+
+    /// Check if the translation of the fetch address missed the L2 TLB.
+    pub fn l2_tlb_miss(&self) -> bool {
+        _bits(self.fetch_ctl, 56, 56) != 0
+    }
+
+    /// Check if the fetch missed the L2 cache. Only valid if the fetch completed.
+    pub fn l2_miss(&self) -> bool {
+        _bits(self.fetch_ctl, 58, 58) != 0
+    }
+
+    /// Get the size of the page of the fetch address in bytes, if the address was translated.
+    pub fn page_size(&self) -> Option<u64> {
+        self.phys_addr()?;
+        match _bits(self.fetch_ctl, 53, 54) {
+            0 => Some(4 << 10),
+            1 => Some(2 << 20),
+            2 => Some(1 << 30),
+            _ => None,
+        }
+    }
+
+    /// Get the physical address of the fetch, if it was translated.
+    pub fn phys_addr(&self) -> Option<u64> {
+        if _bits(self.fetch_ctl, 52, 52) != 0 {
+            Some(self.phys_addr_raw)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sample of a micro-op taken by the `ibs_op` PMU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IbsOpSample {
+    /// IBS capabilities of the CPU.
+    pub caps: u32,
+    /// Value of `IbsOpCtl`.
+    pub op_ctl: u64,
+    /// Linear address of the instruction of the micro-op (`IbsOpRip`).
+    pub rip: u64,
+    /// Value of `IbsOpData`.
+    pub op_data: u64,
+    /// Value of `IbsOpData2`.
+    pub op_data2: u64,
+    /// Value of `IbsOpData3`.
+    pub op_data3: u64,
+    /// Value of `IbsDcLinAd`, valid if `dc_lin_addr` returns it.
+    pub dc_lin_addr_raw: u64,
+    /// Value of `IbsDcPhysAd`, valid if `dc_phys_addr` returns it.
+    pub dc_phys_addr_raw: u64,
+    /// Target of the branch if supported and the micro-op is a branch (`IbsBrTarget`).
+    pub br_target: Option<u64>,
+}
+
+impl IbsOpSample {
+    /// Check if the `ibs_op` PMU is available on the local machine.
+    pub fn is_supported() -> bool {
+        sysfs::pmu_type(IBS_OP_PMU).is_ok()
+    }
+
+    /// Parse the `raw` data of a sample, i.e., `SampleRecord::raw`.
+    pub fn from_raw(raw: &[u8]) -> crate::Result<Self> {
+        let (caps, regs) = _read_registers(raw, 7)?;
+        Ok(IbsOpSample {
+            caps,
+            op_ctl: regs[0],
+            rip: regs[1],
+            op_data: regs[2],
+            op_data2: regs[3],
+            op_data3: regs[4],
+            dc_lin_addr_raw: regs[5],
+            dc_phys_addr_raw: regs[6],
+            br_target: if caps & CAPS_BRNTRGT != 0 {
+                regs.get(7).copied()
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Get the number of cycles from the completion to the retirement of the micro-op.
+    pub fn completion_to_retire(&self) -> u64 {
+        _bits(self.op_data, 0, 15)
+    }
+
+    /// Get the number of cycles from the tagging to the retirement of the micro-op.
+    pub fn tag_to_retire(&self) -> u64 {
+        _bits(self.op_data, 16, 31)
+    }
+
+    /// Check if the micro-op is a return.
+    pub fn is_return(&self) -> bool {
+        _bits(self.op_data, 34, 34) != 0
+    }
+
+    /// Check if the micro-op is a taken branch.
+    pub fn branch_taken(&self) -> bool {
+        _bits(self.op_data, 35, 35) != 0
+    }
+
+    /// Check if the micro-op is a mispredicted branch.
+    pub fn branch_mispredicted(&self) -> bool {
+        _bits(self.op_data, 36, 36) != 0
+    }
+
+    /// Check if the micro-op is a retired branch.
+    pub fn is_branch(&self) -> bool {
+        _bits(self.op_data, 37, 37) != 0
+    }
+
+    /// Check if `rip` is invalid.
+    pub fn rip_invalid(&self) -> bool {
+        _bits(self.op_data, 38, 38) != 0
+    }
+
+    /// Check if the micro-op is a load.
+    pub fn is_load(&self) -> bool {
+        _bits(self.op_data3, 0, 0) != 0
+    }
+
+    /// Check if the micro-op is a store.
+    pub fn is_store(&self) -> bool {
+        _bits(self.op_data3, 1, 1) != 0
+    }
+
+    /// Check if the memory access of the micro-op missed the L1 data TLB.
+    pub fn dc_l1_tlb_miss(&self) -> bool {
+        _bits(self.op_data3, 2, 2) != 0
+    }
+
+    /// Check if the memory access of the micro-op missed the L2 data TLB.
+    pub fn dc_l2_tlb_miss(&self) -> bool {
+        _bits(self.op_data3, 3, 3) != 0
+    }
+
+    /// Check if the memory access of the micro-op missed the data cache.
+    pub fn dc_miss(&self) -> bool {
+        _bits(self.op_data3, 7, 7) != 0
+    }
+
+    /// Check if the memory access of the micro-op missed the L2 cache.
+    pub fn l2_miss(&self) -> bool {
+        _bits(self.op_data3, 20, 20) != 0
+    }
+
+    /// Get the number of cycles from the data cache miss to the fill of the data.
+    pub fn dc_miss_latency(&self) -> u64 {
+        _bits(self.op_data3, 32, 47)
+    }
+
+    /// Get the size of the memory access in bytes, if reported.
+    pub fn mem_width(&self) -> Option<u64> {
+        match _bits(self.op_data3, 22, 25) {
+            0 => None,
+            w => Some(1 << (w - 1)),
+        }
+    }
+
+    /// Get the linear address of the memory access, if valid.
+    pub fn dc_lin_addr(&self) -> Option<u64> {
+        if _bits(self.op_data3, 17, 17) != 0 {
+            Some(self.dc_lin_addr_raw)
+        } else {
+            None
+        }
+    }
+
+    /// Get the physical address of the memory access, if valid.
+    pub fn dc_phys_addr(&self) -> Option<u64> {
+        if _bits(self.op_data3, 18, 18) != 0 {
+            Some(self.dc_phys_addr_raw)
+        } else {
+            None
+        }
+    }
+
+    /// Check if `IbsOpData4` was captured as well.
+    pub fn has_op_data4(&self) -> bool {
+        self.caps & CAPS_OPDATA4 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::{Capabilities, Operation, PerfEvent};
+
+    /// Build the raw data of a sample from `caps` and `regs`.
+    fn raw(caps: u32, regs: &[u64]) -> Vec<u8> {
+        let mut raw = caps.to_ne_bytes().to_vec();
+        for r in regs {
+            raw.extend_from_slice(&r.to_ne_bytes());
+        }
+        raw
+    }
+
+    #[test]
+    fn test_ibs_samples() {
+        let ctl = (1 << 58) | (1 << 53) | (1 << 52) | (1 << 51) | (1 << 50) | (0x42 << 32);
+        let fetch = IbsFetchSample::from_raw(&raw(0x1f, &[ctl, 0x40_1000, 0x1234_5000])).unwrap();
+        assert_eq!(fetch.latency(), 0x42);
+        assert!(fetch.completed() && fetch.ic_miss() && fetch.l2_miss());
+        assert!(!fetch.l1_tlb_miss() && !fetch.l2_tlb_miss());
+        assert_eq!(fetch.phys_addr(), Some(0x1234_5000));
+        assert_eq!(fetch.page_size(), Some(2 << 20));
+        assert_eq!(fetch.extd_ctl, None);
+        let fetch = IbsFetchSample::from_raw(&raw(CAPS_FETCHCTLEXTD, &[0, 0, 0, 7])).unwrap();
+        assert_eq!((fetch.phys_addr(), fetch.extd_ctl), (None, Some(7)));
+        assert!(IbsFetchSample::from_raw(&raw(0, &[0, 0])).is_err());
+        assert!(IbsFetchSample::from_raw(&[0, 0]).is_err());
+
+        let data = (1 << 37) | (1 << 36) | (1 << 35) | (0x20 << 16) | 0x3;
+        let data3 = (0x80 << 32) | (4 << 22) | (1 << 17) | (1 << 7) | 0x1;
+        let regs = [0, 0x40_2000, data, 0, data3, 0x7fff_0000, 0, 0x40_3000];
+        let op = IbsOpSample::from_raw(&raw(CAPS_BRNTRGT, &regs)).unwrap();
+        assert_eq!(op.rip, 0x40_2000);
+        assert_eq!((op.completion_to_retire(), op.tag_to_retire()), (3, 0x20));
+        assert!(op.is_branch() && op.branch_taken() && op.branch_mispredicted());
+        assert!(!op.is_return() && !op.rip_invalid());
+        assert!(op.is_load() && !op.is_store() && op.dc_miss() && !op.l2_miss());
+        assert_eq!(op.dc_miss_latency(), 0x80);
+        assert_eq!(op.mem_width(), Some(8));
+        assert_eq!(op.dc_lin_addr(), Some(0x7fff_0000));
+        assert_eq!(op.dc_phys_addr(), None);
+        assert_eq!(op.br_target, Some(0x40_3000));
+        assert!(!op.has_op_data4());
+        let op = IbsOpSample::from_raw(&raw(0, &regs)).unwrap();
+        assert_eq!(op.br_target, None);
+
+        // Not an AMD processor
+        if !IbsOpSample::is_supported() {
+            assert!(PerfEvent::build().ibs_op(false).is_err());
+            assert!(PerfEvent::build().ibs_fetch(true).is_err());
+            return;
+        }
+        // IBS samples the kernel as well
+        let caps = Capabilities::detect().unwrap();
+        if !caps.allows(Operation::KernelSampling) {
+            return;
+        }
+        let evt = PerfEvent::build().ibs_op(true).unwrap().open(None).unwrap();
+        assert!(
+            evt.attr.sample_type
+                & crate::perf::ffi::perf_event_sample_format::PERF_SAMPLE_RAW as u64
+                != 0
+        );
+    }
+}
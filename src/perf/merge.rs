@@ -0,0 +1,137 @@
+//! Merging of records from several ring buffers into a single timeline.
+
+use crate::perf::{ParsedRecord, RingBufferReader};
+use log::debug;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+
+/// Merges the records of several ring buffers, e.g., one per CPU, in global timestamp order.
+///
+/// Each ring buffer holds records in the order they were written on its CPU, but records of
+/// different buffers interleave arbitrarily. Records read from the buffers are queued and only
+/// released once no earlier record can show up in any other buffer. As in `perf`, records read in
+/// one round are released in the next round, once all buffers have been read past their
+/// timestamps. Records without a timestamp are ordered before all others.
+///
+/// The events writing to the buffers should record timestamps, i.e., set `PERF_SAMPLE_TIME` and
+/// `sample_id_all`, which is the default for `PerfEventBuilder`.
+#[derive(Debug)]
+pub struct RecordMerger {
+    /// Buffers being merged.
+    readers: Vec<RingBufferReader>,
+    /// Records read from each buffer and their timestamps that have not been released yet.
+    queues: Vec<VecDeque<(u64, ParsedRecord)>>,
+    /// Largest timestamp read in the last round.
+    last_round: u64,
+}
+
+impl RecordMerger {
+    /// Create a new merger for the buffers of `readers`.
+    pub fn new(readers: Vec<RingBufferReader>) -> Self {
+        let queues = readers.iter().map(|_| VecDeque::new()).collect();
+        RecordMerger {
+            readers,
+            queues,
+            last_round: 0,
+        }
+    }
+
+    /// Get the buffers being merged.
+    pub fn readers(&self) -> &[RingBufferReader] {
+        &self.readers
+    }
+
+    /// Read all buffers and get the records that are guaranteed to be in global timestamp order.
+    ///
+    /// Records read by this call with timestamps later than those of the previous call are held
+    /// back until the next call to `poll` or `flush`.
+    pub fn poll(&mut self) -> Vec<ParsedRecord> {
+        let mut round = self.last_round;
+        for (reader, queue) in self.readers.iter_mut().zip(self.queues.iter_mut()) {
+            for record in reader.drain() {
+                match record {
+                    Ok(r) => {
+                        let time = r.time().unwrap_or(0);
+                        round = std::cmp::max(round, time);
+                        queue.push_back((time, r));
+                    }
+                    Err(e) => debug!("Dropping unparseable record: {}", e),
+                }
+            }
+        }
+        let limit = self.last_round;
+        self.last_round = round;
+        self._release(Some(limit))
+    }
+
+    /// Read all buffers and get all remaining records in timestamp order.
+    ///
+    /// This should be called once the events writing to the buffers have been disabled.
+    pub fn flush(&mut self) -> Vec<ParsedRecord> {
+        let mut records = self.poll();
+        records.extend(self._release(None));
+        records
+    }
+
+    /// Remove queued records with timestamps up to `limit` in timestamp order.
+    fn _release(&mut self, limit: Option<u64>) -> Vec<ParsedRecord> {
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = self
+            .queues
+            .iter()
+            .enumerate()
+            .filter_map(|(i, q)| q.front().map(|(t, _)| Reverse((*t, i))))
+            .collect();
+        let mut records = Vec::new();
+        while let Some(Reverse((time, i))) = heap.pop() {
+            if matches!(limit, Some(l) if time > l) {
+                break;
+            }
+            records.push(self.queues[i].pop_front().unwrap().1);
+            if let Some((t, _)) = self.queues[i].front() {
+                heap.push(Reverse((*t, i)));
+            }
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::{PerfEvent, SwId};
+    use crate::Counter;
+
+    #[test]
+    fn test_record_merger() {
+        let mut evts: Vec<PerfEvent> = (0..2)
+            .map(|_| {
+                PerfEvent::software(SwId::TaskClock)
+                    .start_disabled()
+                    .set_period(100_000)
+                    .enable_sampling()
+                    .open(None)
+                    .unwrap()
+            })
+            .collect();
+        let readers = evts
+            .iter_mut()
+            .map(|e| e.take_reader().unwrap().unwrap())
+            .collect();
+        let mut merger = RecordMerger::new(readers);
+
+        for e in evts.iter() {
+            assert!(e.enable().is_ok());
+        }
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(10) {}
+        for e in evts.iter() {
+            assert!(e.disable().is_ok());
+        }
+
+        let mut records = merger.poll();
+        records.extend(merger.flush());
+        let times: Vec<u64> = records.iter().filter_map(|r| r.time()).collect();
+        assert!(!times.is_empty());
+        assert!(times.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
@@ -8,7 +8,10 @@ use lazy_static::lazy_static;
 use log::debug;
 use nix::libc;
 use nix::sys::mman;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 
 lazy_static! {
     /// Size of a single memory page on the machine.
@@ -36,6 +39,21 @@ fn _write_data_tail(header: *mut ffi::perf_event_mmap_page, value: u64) {
     volatile!(header.data_tail, value);
 }
 
+/// Internal implementation of the `read_aux_head` function.
+fn _read_aux_head(header: *const ffi::perf_event_mmap_page) -> u64 {
+    let header = unsafe { &*header };
+    let head = volatile!(header.aux_head);
+    std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire);
+    head
+}
+
+/// Internal implementation of the `write_aux_tail` function.
+fn _write_aux_tail(header: *mut ffi::perf_event_mmap_page, value: u64) {
+    let header = unsafe { &mut *header };
+    std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+    volatile!(header.aux_tail, value);
+}
+
 /// Userspace wrapper for the sampled/mmaped perf events.
 ///
 /// # Memory layout
@@ -50,8 +68,17 @@ fn _write_data_tail(header: *mut ffi::perf_event_mmap_page, value: u64) {
 /// │                  │ size
 /// │      Events      │  │
 /// │                  │  │
-/// └──────────────────┘  ▼
+/// ├───── aux.base ───┤  ▼ ▲
+/// │                  │    │
+/// │     AUX data     │ aux.size
+/// │    (optional)    │    │
+/// └──────────────────┘    ▼
 /// ```
+///
+/// # Huge pages
+/// The buffer is always backed by native pages. The kernel rejects `MAP_HUGETLB` mappings of
+/// perf event file descriptors with `EINVAL`, as `perf_mmap` allocates the pages of the buffer
+/// itself, so huge page backed buffers cannot be requested.
 #[derive(Debug)]
 pub struct RingBuffer {
     /// Metadata of the ring buffer.
@@ -64,6 +91,46 @@ pub struct RingBuffer {
     ///
     /// This is used to set `data_tail`.
     total_bytes_read: u64,
+    /// Optional AUX area used by PMUs that produce bulk data, e.g., Intel PT.
+    aux: Option<AuxArea>,
+    /// Names of the events writing to this buffer indexed by their kernel ids.
+    event_names: HashMap<u64, String>,
+    /// The kernel overwrites the oldest records instead of waiting for them to be read.
+    overwrite: bool,
+    /// Statistics about the consumed records.
+    stats: RingBufferStats,
+}
+
+/// Statistics about the records consumed from a `RingBuffer`.
+///
+/// These can be used to monitor the health of a collection session and tune the size of the
+/// buffer, e.g., a growing `lost` count suggests the buffer is too small or read too rarely.
+#[derive(Debug, Clone, Default)]
+pub struct RingBufferStats {
+    /// Total number of bytes consumed from the buffer.
+    pub bytes_consumed: u64,
+    /// Number of times consumption wrapped around the end of the buffer.
+    pub wraps: u64,
+    /// Number of records parsed by `RingBuffer::drain` indexed by their `perf_event_type`.
+    pub records: HashMap<u32, u64>,
+    /// Number of records the kernel reported as lost in `PERF_RECORD_LOST` records.
+    pub lost: u64,
+}
+
+impl RingBufferStats {
+    /// Get the number of parsed records of type `type_`.
+    pub fn records_of_type(&self, type_: ffi::perf_event_type) -> u64 {
+        self.records.get(&(type_ as u32)).cloned().unwrap_or(0)
+    }
+}
+
+/// AUX area of a `RingBuffer`.
+#[derive(Debug)]
+struct AuxArea {
+    /// Pointer to the beginning of the AUX data.
+    base: *mut u8,
+    /// Size in bytes of the AUX data.
+    size: usize,
 }
 
 impl RingBuffer {
@@ -72,12 +139,39 @@ impl RingBuffer {
     /// # Panics
     /// `npages` must be a power of 2. The call will panic otherwise.
     pub fn new(fd: libc::c_int, npages: usize) -> Result<Self> {
+        RingBuffer::_map(fd, npages, false)
+    }
+
+    /// Create a new mmaped buffer in overwrite mode from perf event file descriptor `fd` and ring
+    /// buffer size `npages`.
+    ///
+    /// The kernel never waits for records to be read from an overwrite buffer. Instead, the oldest
+    /// records are overwritten once the buffer is full, so that it always holds the latest records
+    /// (i.e., a flight recorder). The buffer is mapped read-only, so `advance` has no effect and the
+    /// records have to be read with `snapshot`. The event must be opened with `write_backward`
+    /// set.
+    ///
+    /// # Panics
+    /// `npages` must be a power of 2. The call will panic otherwise.
+    pub fn new_overwrite(fd: libc::c_int, npages: usize) -> Result<Self> {
+        RingBuffer::_map(fd, npages, true)
+    }
+
+    /// Internal implementation of `new` and `new_overwrite`.
+    fn _map(fd: libc::c_int, npages: usize, overwrite: bool) -> Result<Self> {
         assert_eq!(npages & (npages - 1), 0); // Check to see if npages is a power of 2
+
+        // A read-only mapping tells the kernel not to wait for data_tail
+        let prot = if overwrite {
+            mman::ProtFlags::PROT_READ
+        } else {
+            mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE
+        };
         let header = unsafe {
             mman::mmap(
                 std::ptr::null_mut(),
                 *PAGE_SIZE * (npages + 1),
-                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                prot,
                 mman::MapFlags::MAP_SHARED,
                 fd,
                 0,
@@ -88,10 +182,164 @@ impl RingBuffer {
             base: unsafe { (header as *mut u8).add(*PAGE_SIZE) },
             size: *PAGE_SIZE * npages,
             total_bytes_read: 0,
+            aux: None,
+            event_names: HashMap::new(),
+            overwrite,
+            stats: RingBufferStats::default(),
         };
         Ok(rb)
     }
 
+    /// Get statistics about the records consumed from this buffer.
+    pub fn stats(&self) -> RingBufferStats {
+        RingBufferStats {
+            bytes_consumed: self.total_bytes_read,
+            wraps: self.total_bytes_read / self.size as u64,
+            ..self.stats.clone()
+        }
+    }
+
+    /// Check if the buffer is in overwrite mode, see `new_overwrite`.
+    pub fn is_overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    /// Copy and parse all records currently held in an overwrite buffer, newest first.
+    ///
+    /// `attr` must be the attributes of the event writing to the buffer. The kernel keeps writing
+    /// while the records are copied, so the output of the event should be paused for a consistent
+    /// snapshot, see `PerfEvent::snapshot`.
+    pub fn snapshot(&self, attr: &ffi::perf_event_attr) -> Result<Vec<ParsedRecord>> {
+        let data = unsafe { std::slice::from_raw_parts(self.base as *const u8, self.size) };
+        let header_size = std::mem::size_of::<ffi::perf_event_header>();
+        // The kernel writes backwards from 0, so the number of bytes written is -head
+        let head = _read_data_head(self.header);
+        let limit = std::cmp::min(0u64.wrapping_sub(head), self.size as u64) as usize;
+        let mut records = Vec::new();
+        let mut pos = head as usize % self.size;
+        let mut total = 0usize;
+        let mut buf: Vec<u64> = Vec::new();
+        while total + header_size <= limit {
+            // Read the header, which may itself wrap around the end of the buffer
+            let mut hdr = [0u64; 1];
+            _copy_wrapped(data, pos, _as_bytes_mut(&mut hdr));
+            let size = unsafe { (*(hdr.as_ptr() as *const ffi::perf_event_header)).size } as usize;
+            if size < header_size || total + size > limit {
+                break;
+            }
+            buf.clear();
+            buf.resize(size / 8 + 1, 0);
+            _copy_wrapped(data, pos, &mut _as_bytes_mut(&mut buf)[..size]);
+            let record = unsafe { &*(buf.as_ptr() as *const RawRecord) };
+            records.push(record.parse(attr)?);
+            pos = (pos + size) % self.size;
+            total += size;
+        }
+        Ok(records)
+    }
+
+    /// Map the AUX area of perf event file descriptor `fd` with size `npages`.
+    ///
+    /// The AUX area is placed directly after the data section of the buffer.
+    ///
+    /// # Panics
+    /// `npages` must be a power of 2. The call will panic otherwise.
+    pub fn map_aux(&mut self, fd: libc::c_int, npages: usize) -> Result<()> {
+        assert_eq!(npages & (npages - 1), 0); // Check to see if npages is a power of 2
+        let offset = self.size + *PAGE_SIZE;
+        let size = *PAGE_SIZE * npages;
+        {
+            let header = unsafe { &mut *self.header };
+            header.aux_offset = offset as u64;
+            header.aux_size = size as u64;
+        }
+        let base = unsafe {
+            mman::mmap(
+                std::ptr::null_mut(),
+                size,
+                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                mman::MapFlags::MAP_SHARED,
+                fd,
+                offset as libc::off_t,
+            )? as *mut u8
+        };
+        self.aux = Some(AuxArea { base, size });
+        Ok(())
+    }
+
+    /// Get an iterator over the chunks of data that have been added to the AUX area by the kernel.
+    ///
+    /// The data between the tail and head of the AUX area is returned as at most two contiguous
+    /// chunks as it may wrap around the end of the area. The iterator is empty if no AUX area is
+    /// mapped.
+    ///
+    /// The iterator will not advance the tail of the AUX area. Doing so will require explicit
+    /// calls to `advance_aux`.
+    pub fn aux_chunks(&self) -> AuxChunks<'_> {
+        match self.aux {
+            Some(ref aux) => {
+                let head = _read_aux_head(self.header);
+                let tail = unsafe { &*self.header }.aux_tail;
+                AuxChunks {
+                    data: unsafe { std::slice::from_raw_parts(aux.base, aux.size) },
+                    next: tail,
+                    end: head,
+                }
+            }
+            None => AuxChunks {
+                data: &[],
+                next: 0,
+                end: 0,
+            },
+        }
+    }
+
+    /// Copy the data announced by the `PERF_RECORD_AUX` record `record` out of the AUX area and
+    /// notify the kernel that it has been read.
+    ///
+    /// Records must be passed in the order they were written, as the tail of the AUX area is moved
+    /// to the end of the data of `record`. Returns an empty buffer if no AUX area is mapped.
+    pub fn read_aux(&mut self, record: &AuxRecord) -> Vec<u8> {
+        let size = match self.aux {
+            Some(ref aux) => aux.size,
+            None => return Vec::new(),
+        };
+        let len = std::cmp::min(record.aux_size, size as u64) as usize;
+        let mut data = vec![0u8; len];
+        if let Some(ref aux) = self.aux {
+            let area = unsafe { std::slice::from_raw_parts(aux.base, aux.size) };
+            _copy_wrapped(area, (record.aux_offset % size as u64) as usize, &mut data);
+        }
+        _write_aux_tail(self.header, record.aux_offset + record.aux_size);
+        data
+    }
+
+    /// Notify the kernel that `num_bytes` bytes of data have been read from the AUX area.
+    pub fn advance_aux(&mut self, num_bytes: u64) {
+        if self.aux.is_some() {
+            let tail = unsafe { &*self.header }.aux_tail;
+            _write_aux_tail(self.header, tail + num_bytes);
+        }
+    }
+
+    /// Record that records with kernel id `id` are written to this buffer by the event `name`.
+    pub fn register_event(&mut self, id: u64, name: &str) {
+        self.event_names.insert(id, name.into());
+    }
+
+    /// Get the name of the event with kernel id `id` writing to this buffer.
+    pub fn event_name(&self, id: u64) -> Option<&str> {
+        self.event_names.get(&id).map(String::as_str)
+    }
+
+    /// Get the name of the event that generated `record`.
+    ///
+    /// Requires the kernel id to be part of the record, i.e., `PERF_SAMPLE_ID` or
+    /// `PERF_SAMPLE_IDENTIFIER` to be set in the `sample_type` of the event.
+    pub fn record_event_name(&self, record: &ParsedRecord) -> Option<&str> {
+        record.event_id().and_then(|id| self.event_name(id))
+    }
+
     /// Get an iterator over the events that have been added to the buffer from the kernel.
     ///
     /// The iterator will not update as new events are added, it only contains elements present
@@ -107,20 +355,83 @@ impl RingBuffer {
     ///
     /// The call will clear the buffer if `None` is passed to the `num` field.
     pub fn advance(&mut self, num: Option<usize>) {
+        // The tail of an overwrite buffer is not used by the kernel, and the mapping is read-only
+        if self.overwrite {
+            return;
+        }
         // Get the position of the buffer to advance data_tail
-        let header = self.header;
-        let mut iter = self.events();
-        let bytes_read = if let Some(n) = num {
-            let _ = iter.nth(n);
-            iter.bytes_read
+        if let Some(n) = num {
+            let bytes_read = {
+                let mut iter = self.events();
+                let _ = iter.by_ref().take(n).count();
+                iter.bytes_read
+            };
+            self.total_bytes_read += bytes_read;
         } else {
-            let _ = iter.try_fold(0, |_, _| Some(0)); // goto last entry
-            iter.bytes_read
-        };
-        self.total_bytes_read += bytes_read;
+            // Skip all records without iterating over them
+            self.total_bytes_read = _read_data_head(self.header);
+        }
 
         // Write value to data_tail
-        _write_data_tail(header, self.total_bytes_read);
+        _write_data_tail(self.header, self.total_bytes_read);
+    }
+
+    /// Get an iterator that parses and consumes the records in the buffer.
+    ///
+    /// `attr` must be the attributes of the event writing to the buffer. Unlike `events`, the tail
+    /// of the buffer is advanced past each record as it is yielded, so no call to `advance` is
+    /// required. Records that are not yielded, e.g., because the iterator is dropped early, remain
+    /// in the buffer. The iterator does not yield records added after its creation.
+    ///
+    /// An overwrite buffer cannot be drained, use `snapshot` instead.
+    pub fn drain(&mut self, attr: &ffi::perf_event_attr) -> RingBufferDrain<'_> {
+        let head = if self.overwrite {
+            self.total_bytes_read
+        } else {
+            _read_data_head(self.header)
+        };
+        RingBufferDrain {
+            buf: self,
+            attr: *attr,
+            head,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Wait for records to be available by spinning on the head of the buffer.
+    ///
+    /// Unlike waiting with `poll`, no system call is made and the thread is not descheduled, which
+    /// minimizes the latency of noticing new records in latency-critical loops. The pause between
+    /// checks grows exponentially up to a bound, after which the thread yields between checks.
+    ///
+    /// The call will spin for at most `timeout` milliseconds, or indefinitely if `timeout` is
+    /// `-1`. Returns `true` if there are records to be read.
+    pub fn spin_wait(&self, timeout: libc::c_int) -> bool {
+        /// Maximum number of spin iterations between checks before yielding.
+        const MAX_SPINS: u32 = 1 << 10;
+        let start = std::time::Instant::now();
+        let timeout = if timeout < 0 {
+            None
+        } else {
+            Some(std::time::Duration::from_millis(timeout as u64))
+        };
+        let mut spins = 1;
+        loop {
+            if self.events_pending() {
+                return true;
+            }
+            if matches!(timeout, Some(t) if start.elapsed() >= t) {
+                return false;
+            }
+            if spins <= MAX_SPINS {
+                for _ in 0..spins {
+                    std::hint::spin_loop();
+                }
+                spins *= 2;
+            } else {
+                std::thread::yield_now();
+            }
+        }
     }
 
     /// Checks whether there are pending events.
@@ -140,6 +451,10 @@ impl Drop for RingBuffer {
     fn drop(&mut self) {
         // Consume all entries (Not sure if the kernel requires this... Probably not)
         self.advance(None);
+        // Unmap AUX area
+        if let Some(ref aux) = self.aux {
+            let _ = unsafe { mman::munmap(aux.base as *mut std::ffi::c_void, aux.size) };
+        }
         // Unmap buffer
         let _ =
             unsafe { mman::munmap(self.header as *mut std::ffi::c_void, self.size + *PAGE_SIZE) };
@@ -148,9 +463,199 @@ impl Drop for RingBuffer {
 
 unsafe impl Send for RingBuffer {}
 
-/// Iterator over records in a `RingBuffer`.
+/// Handle to consume the `RingBuffer` of a `PerfEvent` from another thread.
+///
+/// The reader owns the buffer and a duplicate of the event's file descriptor, so it can be moved
+/// to a consumer thread while the event is controlled (e.g., enabled or disabled) from another.
+/// Only the reader updates the tail of the buffer, which is ordered with respect to the kernel by
+/// memory fences. Created with `PerfEvent::take_reader`.
+#[derive(Debug)]
+pub struct RingBufferReader {
+    /// Buffer being consumed.
+    buf: RingBuffer,
+    /// Attributes of the event writing to the buffer.
+    attr: ffi::perf_event_attr,
+    /// Duplicate of the file descriptor of the event used to wait for data.
+    file: std::fs::File,
+}
+
+impl RingBufferReader {
+    /// Create a new reader for the buffer `buf` of the event with attributes `attr` and file
+    /// descriptor `fd`.
+    pub(crate) fn new(buf: RingBuffer, attr: ffi::perf_event_attr, fd: RawFd) -> Result<Self> {
+        let fd = nix::unistd::dup(fd)?;
+        Ok(RingBufferReader {
+            buf,
+            attr,
+            file: unsafe { std::fs::File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Get the underlying `RingBuffer`.
+    pub fn ring_buffer(&self) -> &RingBuffer {
+        &self.buf
+    }
+
+    /// Get an iterator that parses and consumes the records in the buffer, see
+    /// `RingBuffer::drain`.
+    pub fn drain(&mut self) -> RingBufferDrain<'_> {
+        let attr = self.attr;
+        self.buf.drain(&attr)
+    }
+
+    /// Wait for records to be available in the buffer.
+    ///
+    /// The call will block for at most `timeout` milliseconds, or indefinitely if `timeout` is
+    /// `-1`. Returns `true` if there are records to be read.
+    pub fn wait(&self, timeout: libc::c_int) -> Result<bool> {
+        if self.buf.events_pending() {
+            return Ok(true);
+        }
+        let mut pollfd = [nix::poll::PollFd::new(
+            self.file.as_raw_fd(),
+            nix::poll::PollFlags::POLLIN,
+        )];
+        nix::poll::poll(&mut pollfd, timeout)?;
+        Ok(self.buf.events_pending())
+    }
+
+    /// Wait for records to be available in the buffer without making system calls, see
+    /// `RingBuffer::spin_wait`.
+    pub fn spin_wait(&self, timeout: libc::c_int) -> bool {
+        self.buf.spin_wait(timeout)
+    }
+
+    /// Check if the process monitored by the event has exited.
+    pub fn is_closed(&self) -> Result<bool> {
+        let mut pollfd = [nix::poll::PollFd::new(
+            self.file.as_raw_fd(),
+            nix::poll::PollFlags::POLLHUP,
+        )];
+        nix::poll::poll(&mut pollfd, 0)?;
+        let revents = pollfd[0]
+            .revents()
+            .unwrap_or_else(nix::poll::PollFlags::empty);
+        Ok(revents.intersects(nix::poll::PollFlags::POLLHUP))
+    }
+}
+
+impl AsRawFd for RingBufferReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// View an 8-byte aligned buffer as bytes.
+fn _as_bytes_mut(buf: &mut [u64]) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 8) }
+}
+
+/// Fill `dst` with the bytes of the circular buffer `data` starting at index `start`.
+fn _copy_wrapped(data: &[u8], start: usize, dst: &mut [u8]) {
+    let first = std::cmp::min(dst.len(), data.len() - start);
+    dst[..first].copy_from_slice(&data[start..start + first]);
+    let rest = dst.len() - first;
+    dst[first..].copy_from_slice(&data[..rest]);
+}
+
+/// Iterator over contiguous chunks of data in the AUX area of a `RingBuffer`.
+///
+/// `'m` corresponds to the lifetime of the containing `RingBuffer`.
+#[derive(Debug)]
+pub struct AuxChunks<'m> {
+    /// AUX area of the `RingBuffer`.
+    data: &'m [u8],
+    /// Byte index of the next chunk (unwrapped).
+    next: u64,
+    /// Byte index of the last byte written by the kernel (unwrapped).
+    end: u64,
+}
+
+impl<'m> Iterator for AuxChunks<'m> {
+    type Item = &'m [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end || self.data.is_empty() {
+            return None;
+        }
+        let size = self.data.len() as u64;
+        let start = self.next % size;
+        let len = std::cmp::min(self.end - self.next, size - start);
+        self.next += len;
+        Some(&self.data[start as usize..(start + len) as usize])
+    }
+}
+
+/// Draining iterator over the records in a `RingBuffer`, see `RingBuffer::drain`.
 ///
 /// `'m` corresponds to the lifetime of the containing `RingBuffer`.
+#[derive(Debug)]
+pub struct RingBufferDrain<'m> {
+    /// Buffer being drained.
+    buf: &'m mut RingBuffer,
+    /// Attributes of the event writing to the buffer.
+    attr: ffi::perf_event_attr,
+    /// Byte index of the last byte written by the kernel (unwrapped).
+    head: u64,
+    /// Memory to store a record that has been wrapped around the end of the buffer.
+    scratch: Vec<u64>,
+}
+
+impl Iterator for RingBufferDrain<'_> {
+    type Item = Result<ParsedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_size = std::mem::size_of::<ffi::perf_event_header>();
+        let tail = self.buf.total_bytes_read;
+        if tail + header_size as u64 > self.head {
+            return None;
+        }
+        let data = unsafe { std::slice::from_raw_parts(self.buf.base as *const u8, self.buf.size) };
+        let pos = (tail % self.buf.size as u64) as usize;
+
+        // The header itself may wrap around the end of the buffer
+        let mut hdr = [0u64; 1];
+        _copy_wrapped(data, pos, _as_bytes_mut(&mut hdr));
+        let size = unsafe { (*(hdr.as_ptr() as *const ffi::perf_event_header)).size } as usize;
+        if size < header_size {
+            return None;
+        }
+
+        // Only copy records that wrap around the end of the buffer
+        let record = if pos + size <= data.len() {
+            #[allow(clippy::cast_ptr_alignment)]
+            unsafe {
+                &*(data[pos..].as_ptr() as *const RawRecord)
+            }
+        } else {
+            self.scratch.clear();
+            self.scratch.resize(size / 8 + 1, 0);
+            _copy_wrapped(data, pos, &mut _as_bytes_mut(&mut self.scratch)[..size]);
+            unsafe { &*(self.scratch.as_ptr() as *const RawRecord) }
+        };
+        let parsed = record.parse(&self.attr);
+        *self
+            .buf
+            .stats
+            .records
+            .entry(record.header.type_)
+            .or_insert(0) += 1;
+        if let Ok(ParsedRecord::Lost(ref lost)) = parsed {
+            self.buf.stats.lost += lost.num;
+        }
+
+        // Hand the memory of the record back to the kernel
+        self.buf.total_bytes_read += size as u64;
+        _write_data_tail(self.buf.header, self.buf.total_bytes_read);
+        Some(parsed)
+    }
+}
+
+/// Iterator over records in a `RingBuffer`.
+///
+/// `'m` corresponds to the lifetime of the mutable borrow of the containing `RingBuffer`. The
+/// yielded `RecordView`s share this lifetime, so the buffer cannot be advanced while they are in
+/// use.
 ///
 /// The `RingBuffer` also allows directly accessing the bytes of the mapped buffer using the the
 /// `index` and `index_mut` functions.
@@ -179,8 +684,6 @@ pub struct RingBufferIter<'m> {
     end_idx: u64,
     /// Total bytes read by the iterator.
     bytes_read: u64,
-    /// Extra memory to store an event that has been wrapped around the end of the `RingBuffer`.
-    extra: [u8; 256],
 }
 
 impl<'m> RingBufferIter<'m> {
@@ -193,28 +696,20 @@ impl<'m> RingBufferIter<'m> {
             next_idx: data_tail % buf.size as u64,
             end_idx: data_head % buf.size as u64,
             bytes_read: 0,
-            extra: [0u8; 256],
         }
     }
 
-    /// Get the `RawRecord` at position `self.next`.
-    #[allow(clippy::cast_ptr_alignment)]
-    #[inline(always)]
-    fn _get_next_record(&self) -> &'m RawRecord {
-        let ptr = &self.data[self.next_idx as usize] as *const u8 as *const RawRecord;
-        unsafe { &*ptr }
-    }
-
-    /// Get the `RawRecord` stored in `self.extra`.
+    /// Get the size of the record at position `self.next`.
     #[allow(clippy::cast_ptr_alignment)]
     #[inline(always)]
-    fn _get_record_at_extra(&self) -> &'m RawRecord {
-        unsafe { &*(&self.extra as *const u8 as *const RawRecord) }
+    fn _get_next_record_size(&self) -> usize {
+        let ptr = &self.data[self.next_idx as usize] as *const u8 as *const ffi::perf_event_header;
+        unsafe { (*ptr).size as usize }
     }
 }
 
 impl<'m> Iterator for RingBufferIter<'m> {
-    type Item = &'m RawRecord;
+    type Item = RecordView<'m>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // Check if available
@@ -223,39 +718,31 @@ impl<'m> Iterator for RingBufferIter<'m> {
         }
 
         // Get new record
-        let mut evt = self._get_next_record();
+        let size = self._get_next_record_size();
+        let start = self.next_idx as usize;
 
         // Update next
-        let next = self.next_idx + evt.header.size as u64;
+        let next = self.next_idx + size as u64;
         let limit = self.data.len() as u64;
-        self.next_idx = if next > limit {
-            // Copy data from end of data to extra
-            let num_at_end = limit - self.next_idx;
-            let src_range = (self.next_idx as usize)..(limit as usize);
-            let dst_range = 0..(num_at_end as usize);
-            self.extra[dst_range].copy_from_slice(&self.data[src_range]);
-
-            // Copy data from begining of data to extra
-            let num_at_beg = evt.header.size as u64 - num_at_end;
-            let src_range = 0..(num_at_beg as usize);
-            let dst_range = (num_at_end as usize)..((num_at_end + num_at_beg) as usize);
-            self.extra[dst_range].copy_from_slice(&self.data[src_range]);
-
-            // Set evt to point to the extra buffer
-            evt = self._get_record_at_extra();
-
-            // Done
-            num_at_beg
-        } else if next == limit {
-            0
+        let (evt, next_idx) = if next > limit {
+            // Copy data from end and begining of data to a new buffer
+            let mut bytes = vec![0u64; size / 8 + 1];
+            _copy_wrapped(self.data, start, &mut _as_bytes_mut(&mut bytes)[..size]);
+            (Cow::Owned(bytes), next - limit)
         } else {
-            next
+            // Records are 8-byte aligned and their size is a multiple of 8
+            #[allow(clippy::cast_ptr_alignment)]
+            let bytes = unsafe {
+                std::slice::from_raw_parts(self.data[start..].as_ptr() as *const u64, size / 8)
+            };
+            (Cow::Borrowed(bytes), next % limit)
         };
+        self.next_idx = next_idx;
 
         // Maintain total bytes read
-        self.bytes_read += evt.header.size as u64;
+        self.bytes_read += size as u64;
 
-        Some(evt)
+        Some(RecordView { bytes: evt })
     }
 }
 
@@ -268,13 +755,49 @@ impl std::fmt::Debug for RingBufferIter<'_> {
             .entry(&"bytes_read", &self.bytes_read)
             .entry(&"data.ptr", &self.data.as_ptr())
             .entry(&"data.len", &self.data.len())
-            .entry(&"extra.ptr", &self.extra.as_ptr())
             .finish()
     }
 }
 
 unsafe impl Send for RingBufferIter<'_> {}
 
+/// View of a record in a `RingBuffer` yielded by `RingBufferIter`.
+///
+/// Records stored contiguously in the buffer are borrowed from the mapped memory without copying,
+/// while records wrapped around the end of the buffer are copied. The view dereferences to the
+/// `RawRecord` it holds.
+///
+/// `'m` corresponds to the lifetime of the mutable borrow of the containing `RingBuffer`.
+#[derive(Debug, Clone)]
+pub struct RecordView<'m> {
+    /// 8-byte aligned memory holding the record.
+    bytes: Cow<'m, [u64]>,
+}
+
+impl RecordView<'_> {
+    /// Check if the record is borrowed from the `RingBuffer` rather than copied.
+    pub fn is_borrowed(&self) -> bool {
+        match self.bytes {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        }
+    }
+
+    /// Get the bytes of the record, including its header.
+    pub fn as_bytes(&self) -> &[u8] {
+        let size = self.header.size as usize;
+        unsafe { std::slice::from_raw_parts(self.bytes.as_ptr() as *const u8, size) }
+    }
+}
+
+impl std::ops::Deref for RecordView<'_> {
+    type Target = RawRecord;
+
+    fn deref(&self) -> &RawRecord {
+        unsafe { &*(self.bytes.as_ptr() as *const RawRecord) }
+    }
+}
+
 /// Individual record in a `RingBuffer`.
 #[repr(C)]
 #[derive(Debug)]
@@ -296,10 +819,12 @@ impl RawRecord {
     /// Only call this on the events of interest as this function will allocate new memory and
     /// memcopy each event.
     ///
+    /// `attr` must be the attributes of the event that generated this record.
+    ///
     /// # Note
-    /// The implementation of this function is closely tied to that of the `PerfEventBuilder` with
-    /// only configurations supported there being implemented here.
-    pub fn parse(&self) -> Result<ParsedRecord> {
+    /// Samples are parsed based on the `sample_type` and `read_format` of `attr`. Sample flags
+    /// newer than `PERF_SAMPLE_CGROUP` and the `PERF_FORMAT_LOST` read format are not supported.
+    pub fn parse(&self, attr: &ffi::perf_event_attr) -> Result<ParsedRecord> {
         let raw_data = unsafe {
             std::slice::from_raw_parts(
                 self.data.as_ptr(),
@@ -313,20 +838,24 @@ impl RawRecord {
             crate::util::hexdump(raw_data)
         );
 
+        let sample_id = || SampleId::from_trailer(raw_data, attr);
         let mut ptr = std::io::Cursor::new(raw_data);
         let res = match self.header.type_.into() {
             ffi::perf_event_type::PERF_RECORD_SWITCH => {
                 let is_out = (self.header.misc & ffi::PERF_RECORD_MISC_SWITCH_OUT as u16) != 0;
                 let is_preempt =
                     (self.header.misc & ffi::PERF_RECORD_MISC_SWITCH_OUT_PREEMPT as u16) != 0;
-                ParsedRecord::ContextSwitch(if is_out {
-                    if is_preempt {
-                        ContextSwitchRecord::SwitchOutRunning
+                ParsedRecord::ContextSwitch(ContextSwitchRecord {
+                    kind: if is_out {
+                        if is_preempt {
+                            ContextSwitchKind::SwitchOutRunning
+                        } else {
+                            ContextSwitchKind::SwitchOutIdle
+                        }
                     } else {
-                        ContextSwitchRecord::SwitchOutIdle
-                    }
-                } else {
-                    ContextSwitchRecord::SwitchIn
+                        ContextSwitchKind::SwitchIn
+                    },
+                    sample_id: sample_id()?,
                 })
             }
 
@@ -336,6 +865,7 @@ impl RawRecord {
                 tid: ptr.read_u32::<NativeEndian>()?,
                 ptid: ptr.read_u32::<NativeEndian>()?,
                 time: ptr.read_u64::<NativeEndian>()?,
+                sample_id: sample_id()?,
             }),
 
             ffi::perf_event_type::PERF_RECORD_FORK => ParsedRecord::Fork(ProcessRecord {
@@ -344,12 +874,14 @@ impl RawRecord {
                 tid: ptr.read_u32::<NativeEndian>()?,
                 ptid: ptr.read_u32::<NativeEndian>()?,
                 time: ptr.read_u64::<NativeEndian>()?,
+                sample_id: sample_id()?,
             }),
 
             ffi::perf_event_type::PERF_RECORD_THROTTLE => ParsedRecord::Throttle(ThrottleRecord {
                 time: ptr.read_u64::<NativeEndian>()?,
                 id: ptr.read_u64::<NativeEndian>()?,
                 stream_id: ptr.read_u64::<NativeEndian>()?,
+                sample_id: sample_id()?,
             }),
 
             ffi::perf_event_type::PERF_RECORD_UNTHROTTLE => {
@@ -357,12 +889,14 @@ impl RawRecord {
                     time: ptr.read_u64::<NativeEndian>()?,
                     id: ptr.read_u64::<NativeEndian>()?,
                     stream_id: ptr.read_u64::<NativeEndian>()?,
+                    sample_id: sample_id()?,
                 })
             }
 
             ffi::perf_event_type::PERF_RECORD_LOST => ParsedRecord::Lost(LostRecord {
                 id: ptr.read_u64::<NativeEndian>()?,
                 num: ptr.read_u64::<NativeEndian>()?,
+                sample_id: sample_id()?,
             }),
 
             ffi::perf_event_type::PERF_RECORD_COMM => ParsedRecord::Comm(CommRecord {
@@ -373,9 +907,27 @@ impl RawRecord {
                     let filter_comm = &raw_comm[0..raw_comm
                         .iter()
                         .position(|&byte| byte == 0)
-                        .unwrap_or_else(|| raw_comm.len())];
+                        .unwrap_or(raw_comm.len())];
                     std::str::from_utf8(filter_comm)?.into()
                 },
+                sample_id: sample_id()?,
+            }),
+
+            ffi::perf_event_type::PERF_RECORD_MMAP => ParsedRecord::Mmap(MmapRecord {
+                pid: ptr.read_u32::<NativeEndian>()?,
+                tid: ptr.read_u32::<NativeEndian>()?,
+                address: ptr.read_u64::<NativeEndian>()?,
+                length: ptr.read_u64::<NativeEndian>()?,
+                page_offset: ptr.read_u64::<NativeEndian>()?,
+                filename: {
+                    let raw_name = &raw_data[ptr.position() as usize..];
+                    let filtered_name = &raw_name[0..raw_name
+                        .iter()
+                        .position(|&byte| byte == 0)
+                        .unwrap_or(raw_name.len())];
+                    std::str::from_utf8(filtered_name)?.into()
+                },
+                sample_id: sample_id()?,
             }),
 
             ffi::perf_event_type::PERF_RECORD_MMAP2 => ParsedRecord::Mmap2(Mmap2Record {
@@ -395,24 +947,72 @@ impl RawRecord {
                     let filtered_name = &raw_name[0..raw_name
                         .iter()
                         .position(|&byte| byte == 0)
-                        .unwrap_or_else(|| raw_name.len())];
+                        .unwrap_or(raw_name.len())];
                     std::str::from_utf8(filtered_name)?.into()
                 },
+                sample_id: sample_id()?,
             }),
 
-            ffi::perf_event_type::PERF_RECORD_SAMPLE => ParsedRecord::Sample(SampleRecord {
-                ip: ptr.read_u64::<NativeEndian>()?,
-                pid: ptr.read_u32::<NativeEndian>()?,
-                tid: ptr.read_u32::<NativeEndian>()?,
-                time: ptr.read_u64::<NativeEndian>()?,
-                cpu: ptr.read_u32::<NativeEndian>()?,
-                period: {
-                    let _ = ptr.read_u32::<NativeEndian>()?; // Reserved field res
-                    ptr.read_u64::<NativeEndian>()?
+            ffi::perf_event_type::PERF_RECORD_CGROUP => ParsedRecord::Cgroup(CgroupRecord {
+                id: ptr.read_u64::<NativeEndian>()?,
+                path: {
+                    let raw_path = &raw_data[ptr.position() as usize..];
+                    let filtered_path = &raw_path[0..raw_path
+                        .iter()
+                        .position(|&byte| byte == 0)
+                        .unwrap_or(raw_path.len())];
+                    std::str::from_utf8(filtered_path)?.into()
+                },
+                sample_id: sample_id()?,
+            }),
+
+            ffi::perf_event_type::PERF_RECORD_KSYMBOL => ParsedRecord::Ksymbol(KsymbolRecord {
+                address: ptr.read_u64::<NativeEndian>()?,
+                length: ptr.read_u32::<NativeEndian>()?,
+                ksym_type: ptr.read_u16::<NativeEndian>()?,
+                flags: ptr.read_u16::<NativeEndian>()?,
+                name: {
+                    let raw_name = &raw_data[ptr.position() as usize..];
+                    let filtered_name = &raw_name[0..raw_name
+                        .iter()
+                        .position(|&byte| byte == 0)
+                        .unwrap_or(raw_name.len())];
+                    std::str::from_utf8(filtered_name)?.into()
+                },
+                sample_id: sample_id()?,
+            }),
+
+            ffi::perf_event_type::PERF_RECORD_BPF_EVENT => ParsedRecord::BpfEvent(BpfEventRecord {
+                event_type: ptr.read_u16::<NativeEndian>()?,
+                flags: ptr.read_u16::<NativeEndian>()?,
+                id: ptr.read_u32::<NativeEndian>()?,
+                tag: {
+                    let mut tag = [0u8; 8];
+                    std::io::Read::read_exact(&mut ptr, &mut tag)?;
+                    tag
                 },
-                value: PerfEventValue::from_cursor(&mut ptr)?,
+                sample_id: sample_id()?,
             }),
 
+            ffi::perf_event_type::PERF_RECORD_AUX => ParsedRecord::Aux(AuxRecord {
+                aux_offset: ptr.read_u64::<NativeEndian>()?,
+                aux_size: ptr.read_u64::<NativeEndian>()?,
+                flags: ptr.read_u64::<NativeEndian>()?,
+                sample_id: sample_id()?,
+            }),
+
+            ffi::perf_event_type::PERF_RECORD_ITRACE_START => {
+                ParsedRecord::ItraceStart(ItraceStartRecord {
+                    pid: ptr.read_u32::<NativeEndian>()?,
+                    tid: ptr.read_u32::<NativeEndian>()?,
+                    sample_id: sample_id()?,
+                })
+            }
+
+            ffi::perf_event_type::PERF_RECORD_SAMPLE => {
+                ParsedRecord::Sample(SampleRecord::from_cursor(&mut ptr, attr)?)
+            }
+
             _ => ParsedRecord::UnknownEvent,
         };
         Ok(res)
@@ -421,7 +1021,16 @@ impl RawRecord {
 
 /// Ring buffer records corresponding to context switches.
 #[derive(Debug)]
-pub enum ContextSwitchRecord {
+pub struct ContextSwitchRecord {
+    /// Direction of the context switch.
+    pub kind: ContextSwitchKind,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+/// Kinds of context switches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextSwitchKind {
     /// Process switched in.
     SwitchIn,
     /// Process switched out when idle.
@@ -430,6 +1039,73 @@ pub enum ContextSwitchRecord {
     SwitchOutRunning,
 }
 
+/// Trailer of non-sample records identifying their origin.
+///
+/// This is appended to all records other than samples if `sample_id_all` is set in the attributes
+/// of the event. The fields present are selected by the event's `sample_type`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub struct SampleId {
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub time: Option<u64>,
+    /// Id of the event that generated the record.
+    pub id: Option<u64>,
+    pub stream_id: Option<u64>,
+    pub cpu: Option<u32>,
+}
+
+impl SampleId {
+    /// Parse the trailer at the end of `raw_data` based on the attributes `attr` of the event.
+    ///
+    /// Returns `None` if `sample_id_all` is not set.
+    fn from_trailer(raw_data: &[u8], attr: &ffi::perf_event_attr) -> Result<Option<Self>> {
+        use ffi::perf_event_sample_format::*;
+        if attr.sample_id_all() == 0 {
+            return Ok(None);
+        }
+        let has = |flag: ffi::perf_event_sample_format| attr.sample_type & flag as u64 != 0;
+        let size = 8 * [
+            PERF_SAMPLE_TID,
+            PERF_SAMPLE_TIME,
+            PERF_SAMPLE_ID,
+            PERF_SAMPLE_STREAM_ID,
+            PERF_SAMPLE_CPU,
+            PERF_SAMPLE_IDENTIFIER,
+        ]
+        .iter()
+        .filter(|&&f| has(f))
+        .count();
+        if size > raw_data.len() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let mut ptr = std::io::Cursor::new(&raw_data[raw_data.len() - size..]);
+        let mut sample_id = SampleId::default();
+        if has(PERF_SAMPLE_TID) {
+            sample_id.pid = Some(ptr.read_u32::<NativeEndian>()?);
+            sample_id.tid = Some(ptr.read_u32::<NativeEndian>()?);
+        }
+        if has(PERF_SAMPLE_TIME) {
+            sample_id.time = Some(ptr.read_u64::<NativeEndian>()?);
+        }
+        if has(PERF_SAMPLE_ID) {
+            sample_id.id = Some(ptr.read_u64::<NativeEndian>()?);
+        }
+        if has(PERF_SAMPLE_STREAM_ID) {
+            sample_id.stream_id = Some(ptr.read_u64::<NativeEndian>()?);
+        }
+        if has(PERF_SAMPLE_CPU) {
+            sample_id.cpu = Some(ptr.read_u32::<NativeEndian>()?);
+            let _ = ptr.read_u32::<NativeEndian>()?; // Reserved field res
+        }
+        if has(PERF_SAMPLE_IDENTIFIER) {
+            sample_id.id = Some(ptr.read_u64::<NativeEndian>()?);
+        }
+        Ok(Some(sample_id))
+    }
+}
+
 /// Ring buffer records corresponding to process forks and exits.
 #[derive(Debug)]
 #[allow(missing_docs)]
@@ -439,6 +1115,8 @@ pub struct ProcessRecord {
     pub tid: u32,
     pub ptid: u32,
     pub time: u64,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
 }
 
 /// Ring buffer records corresponding to throttle and unthrottle events.
@@ -448,6 +1126,53 @@ pub struct ThrottleRecord {
     pub time: u64,
     pub id: u64,
     pub stream_id: u64,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+/// Ring buffer records announcing new data in the AUX area, e.g., Intel PT trace data.
+#[derive(Debug, Clone)]
+pub struct AuxRecord {
+    /// Byte index of the data in the AUX area (unwrapped).
+    pub aux_offset: u64,
+    /// Size of the data in bytes.
+    pub aux_size: u64,
+    /// `PERF_AUX_FLAG_*` flags describing the data.
+    pub flags: u64,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+impl AuxRecord {
+    /// Check if the data was truncated because the AUX area was full.
+    pub fn is_truncated(&self) -> bool {
+        self.flags & u64::from(ffi::PERF_AUX_FLAG_TRUNCATED) != 0
+    }
+
+    /// Check if the data was written in overwrite mode, i.e., as a snapshot.
+    pub fn is_overwrite(&self) -> bool {
+        self.flags & u64::from(ffi::PERF_AUX_FLAG_OVERWRITE) != 0
+    }
+
+    /// Check if the data is incomplete, e.g., because of a PMU interrupt.
+    pub fn is_partial(&self) -> bool {
+        self.flags & u64::from(ffi::PERF_AUX_FLAG_PARTIAL) != 0
+    }
+
+    /// Check if the data is corrupted because samples collided in the AUX area.
+    pub fn has_collision(&self) -> bool {
+        self.flags & u64::from(ffi::PERF_AUX_FLAG_COLLISION) != 0
+    }
+}
+
+/// Ring buffer records marking the start of instruction tracing of a thread.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct ItraceStartRecord {
+    pub pid: u32,
+    pub tid: u32,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
 }
 
 /// Ring buffer records corresponding to lost samples.
@@ -456,6 +1181,8 @@ pub struct ThrottleRecord {
 pub struct LostRecord {
     pub id: u64,
     pub num: u64,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
 }
 
 /// Ring buffer records corresponding to changes in process names.
@@ -465,6 +1192,24 @@ pub struct CommRecord {
     pub pid: u32,
     pub tid: u32,
     pub comm: String,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+/// Ring buffer records with information about `mmap` calls without the inode details.
+///
+/// These are produced instead of `Mmap2Record`s if `mmap2` is not set in the event's attributes.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct MmapRecord {
+    pub pid: u32,
+    pub tid: u32,
+    pub address: u64,
+    pub length: u64,
+    pub page_offset: u64,
+    pub filename: String,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
 }
 
 /// Ring buffer records with information about `mmap` calls.
@@ -483,23 +1228,397 @@ pub struct Mmap2Record {
     pub protection: u32,
     pub flags: u32,
     pub filename: String,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+/// Ring buffer records mapping cgroup ids to their paths.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct CgroupRecord {
+    pub id: u64,
+    pub path: String,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+/// Ring buffer records corresponding to the registration of kernel symbols, e.g., BPF programs.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct KsymbolRecord {
+    pub address: u64,
+    pub length: u32,
+    /// Type of the symbol (see `ffi::perf_record_ksymbol_type`).
+    pub ksym_type: u16,
+    pub flags: u16,
+    pub name: String,
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+impl KsymbolRecord {
+    /// Check if the symbol is being unregistered.
+    pub fn is_unregister(&self) -> bool {
+        (self.flags as u32 & ffi::PERF_RECORD_KSYMBOL_FLAGS_UNREGISTER) != 0
+    }
+}
+
+/// Ring buffer records corresponding to loading and unloading of BPF programs.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct BpfEventRecord {
+    /// Type of the event (see `ffi::perf_bpf_event_type`).
+    pub event_type: u16,
+    pub flags: u16,
+    /// Id of the BPF program.
+    pub id: u32,
+    /// Tag of the BPF program, i.e., a hash of its instructions.
+    pub tag: [u8; 8],
+    /// Trailer identifying the origin of the record if `sample_id_all` is set.
+    pub sample_id: Option<SampleId>,
+}
+
+/// Register values captured with a sampled perf event.
+#[derive(Debug, Clone)]
+pub struct SampleRegs {
+    /// ABI of the sampled registers (see `ffi::perf_sample_regs_abi`).
+    pub abi: u64,
+    /// Mask of the registers that were requested.
+    pub mask: u64,
+    /// Values of the captured registers in increasing order of register index.
+    ///
+    /// This is empty if the ABI is `PERF_SAMPLE_REGS_ABI_NONE`, e.g., for kernel threads.
+    pub values: Vec<u64>,
+}
+
+impl SampleRegs {
+    /// Parse the register dump of a sample for registers selected by `mask`.
+    fn from_cursor(ptr: &mut std::io::Cursor<&[u8]>, mask: u64) -> Result<Self> {
+        let abi = ptr.read_u64::<NativeEndian>()?;
+        let mut values = vec![];
+        if abi != ffi::perf_sample_regs_abi::PERF_SAMPLE_REGS_ABI_NONE as u64 {
+            for _ in 0..mask.count_ones() {
+                values.push(ptr.read_u64::<NativeEndian>()?);
+            }
+        }
+        Ok(SampleRegs { abi, mask, values })
+    }
+
+    /// Get the value of the register with index `idx`.
+    ///
+    /// Returns `None` if the register was not captured.
+    pub fn get(&self, idx: u32) -> Option<u64> {
+        if idx >= 64 || self.mask & (1 << idx) == 0 {
+            return None;
+        }
+        let pos = (self.mask & ((1 << idx) - 1)).count_ones();
+        self.values.get(pos as usize).copied()
+    }
+}
+
+/// User-space stack captured with a sampled perf event.
+#[derive(Debug, Clone)]
+pub struct SampleStack {
+    /// Raw bytes of the stack starting at the sampled stack pointer.
+    ///
+    /// Only the first `dyn_size` bytes contain valid data.
+    pub data: Vec<u8>,
+    /// Number of bytes of the stack that were actually dumped by the kernel.
+    pub dyn_size: u64,
+}
+
+impl SampleStack {
+    /// Parse the stack dump of a sample.
+    fn from_cursor(ptr: &mut std::io::Cursor<&[u8]>) -> Result<Self> {
+        use std::io::Read;
+        let size = ptr.read_u64::<NativeEndian>()?;
+        let mut data = vec![0u8; size as usize];
+        ptr.read_exact(&mut data)?;
+        let dyn_size = if size != 0 {
+            ptr.read_u64::<NativeEndian>()?
+        } else {
+            0
+        };
+        Ok(SampleStack { data, dyn_size })
+    }
+
+    /// Get the valid part of the captured stack.
+    pub fn valid_data(&self) -> &[u8] {
+        let len = std::cmp::min(self.dyn_size as usize, self.data.len());
+        &self.data[..len]
+    }
+}
+
+/// Entry of the branch stack captured with a sampled perf event.
+#[derive(Debug, Clone, Copy)]
+pub struct BranchEntry {
+    /// Address of the branch instruction.
+    pub from: u64,
+    /// Target address of the branch.
+    pub to: u64,
+    /// Bitfield with details about the branch.
+    pub flags: u64,
+}
+
+impl BranchEntry {
+    /// Check if the branch was mispredicted.
+    pub fn mispredicted(&self) -> bool {
+        self.flags & 0x1 != 0
+    }
+
+    /// Check if the branch was predicted correctly.
+    pub fn predicted(&self) -> bool {
+        self.flags & 0x2 != 0
+    }
+
+    /// Check if the branch was in a transaction.
+    pub fn in_tx(&self) -> bool {
+        self.flags & 0x4 != 0
+    }
+
+    /// Check if the branch was a transaction abort.
+    pub fn abort(&self) -> bool {
+        self.flags & 0x8 != 0
+    }
+
+    /// Get the number of cycles since the previous branch (`0` if not supported).
+    pub fn cycles(&self) -> u16 {
+        ((self.flags >> 4) & 0xFFFF) as u16
+    }
+
+    /// Get the type of the branch (see `ffi::PERF_BR_*`).
+    pub fn branch_type(&self) -> u8 {
+        ((self.flags >> 20) & 0xF) as u8
+    }
 }
 
 /// Ring buffer records corresponding to a sampled perf event.
+///
+/// Fields are only present if the corresponding flag is set in the `sample_type` of the event.
 #[derive(Debug)]
 #[allow(missing_docs)]
 pub struct SampleRecord {
-    pub ip: u64,
-    pub pid: u32,
-    pub tid: u32,
-    pub time: u64,
-    pub cpu: u32,
-    pub period: u64,
-    pub value: crate::perf::PerfEventValue,
+    pub ip: Option<u64>,
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub time: Option<u64>,
+    pub addr: Option<u64>,
+    /// Id of the event that generated the sample (`PERF_SAMPLE_ID` or `PERF_SAMPLE_IDENTIFIER`).
+    pub id: Option<u64>,
+    pub stream_id: Option<u64>,
+    pub cpu: Option<u32>,
+    pub period: Option<u64>,
+    /// Value of the event if `PERF_SAMPLE_READ` is set without `PERF_FORMAT_GROUP`.
+    pub value: Option<crate::perf::PerfEventValue>,
+    /// Values of all events in the group if `PERF_SAMPLE_READ` is set with `PERF_FORMAT_GROUP`.
+    pub group_values: Option<Vec<crate::perf::PerfEventValue>>,
+    pub callchain: Option<Vec<u64>>,
+    pub raw: Option<Vec<u8>>,
+    /// Index of the hardware branch buffer if `PERF_SAMPLE_BRANCH_HW_INDEX` is set.
+    pub branch_hw_idx: Option<u64>,
+    pub branch_stack: Option<Vec<BranchEntry>>,
+    pub regs_user: Option<SampleRegs>,
+    pub stack_user: Option<SampleStack>,
+    pub weight: Option<u64>,
+    pub data_src: Option<u64>,
+    pub transaction: Option<u64>,
+    pub regs_intr: Option<SampleRegs>,
+    pub phys_addr: Option<u64>,
+    pub aux: Option<Vec<u8>>,
+    pub cgroup: Option<u64>,
+}
+
+impl SampleRecord {
+    /// Parse a sample based on the `sample_type` and `read_format` of attributes `attr`.
+    fn from_cursor(ptr: &mut std::io::Cursor<&[u8]>, attr: &ffi::perf_event_attr) -> Result<Self> {
+        use ffi::perf_event_sample_format::*;
+        use std::io::Read;
+        if attr.sample_type >= (PERF_SAMPLE_CGROUP as u64) << 1 {
+            return Err(crate::Error::NotImplemented);
+        }
+        let has = |flag: ffi::perf_event_sample_format| attr.sample_type & flag as u64 != 0;
+        let read_u64 = |ptr: &mut std::io::Cursor<&[u8]>, flag| -> Result<Option<u64>> {
+            if has(flag) {
+                Ok(Some(ptr.read_u64::<NativeEndian>()?))
+            } else {
+                Ok(None)
+            }
+        };
+        let read_bytes = |ptr: &mut std::io::Cursor<&[u8]>, size: usize| -> Result<Vec<u8>> {
+            let mut data = vec![0u8; size];
+            ptr.read_exact(&mut data)?;
+            Ok(data)
+        };
+
+        let identifier = read_u64(ptr, PERF_SAMPLE_IDENTIFIER)?;
+        let ip = read_u64(ptr, PERF_SAMPLE_IP)?;
+        let (pid, tid) = if has(PERF_SAMPLE_TID) {
+            (
+                Some(ptr.read_u32::<NativeEndian>()?),
+                Some(ptr.read_u32::<NativeEndian>()?),
+            )
+        } else {
+            (None, None)
+        };
+        let time = read_u64(ptr, PERF_SAMPLE_TIME)?;
+        let addr = read_u64(ptr, PERF_SAMPLE_ADDR)?;
+        let id = read_u64(ptr, PERF_SAMPLE_ID)?.or(identifier);
+        let stream_id = read_u64(ptr, PERF_SAMPLE_STREAM_ID)?;
+        let cpu = if has(PERF_SAMPLE_CPU) {
+            let cpu = ptr.read_u32::<NativeEndian>()?;
+            let _ = ptr.read_u32::<NativeEndian>()?; // Reserved field res
+            Some(cpu)
+        } else {
+            None
+        };
+        let period = read_u64(ptr, PERF_SAMPLE_PERIOD)?;
+        let (value, group_values) = if has(PERF_SAMPLE_READ) {
+            SampleRecord::_read_values(ptr, attr.read_format)?
+        } else {
+            (None, None)
+        };
+        let callchain = if has(PERF_SAMPLE_CALLCHAIN) {
+            let nr = ptr.read_u64::<NativeEndian>()?;
+            Some(
+                (0..nr)
+                    .map(|_| ptr.read_u64::<NativeEndian>())
+                    .collect::<std::io::Result<Vec<u64>>>()?,
+            )
+        } else {
+            None
+        };
+        let raw = if has(PERF_SAMPLE_RAW) {
+            let size = ptr.read_u32::<NativeEndian>()?;
+            Some(read_bytes(ptr, size as usize)?)
+        } else {
+            None
+        };
+        let (branch_hw_idx, branch_stack) = if has(PERF_SAMPLE_BRANCH_STACK) {
+            let nr = ptr.read_u64::<NativeEndian>()?;
+            let hw_idx = if attr.branch_sample_type
+                & ffi::perf_branch_sample_type::PERF_SAMPLE_BRANCH_HW_INDEX as u64
+                != 0
+            {
+                Some(ptr.read_u64::<NativeEndian>()?)
+            } else {
+                None
+            };
+            let mut entries = Vec::with_capacity(nr as usize);
+            for _ in 0..nr {
+                entries.push(BranchEntry {
+                    from: ptr.read_u64::<NativeEndian>()?,
+                    to: ptr.read_u64::<NativeEndian>()?,
+                    flags: ptr.read_u64::<NativeEndian>()?,
+                });
+            }
+            (hw_idx, Some(entries))
+        } else {
+            (None, None)
+        };
+        let regs_user = if has(PERF_SAMPLE_REGS_USER) {
+            Some(SampleRegs::from_cursor(ptr, attr.sample_regs_user)?)
+        } else {
+            None
+        };
+        let stack_user = if has(PERF_SAMPLE_STACK_USER) {
+            Some(SampleStack::from_cursor(ptr)?)
+        } else {
+            None
+        };
+        let weight = read_u64(ptr, PERF_SAMPLE_WEIGHT)?;
+        let data_src = read_u64(ptr, PERF_SAMPLE_DATA_SRC)?;
+        let transaction = read_u64(ptr, PERF_SAMPLE_TRANSACTION)?;
+        let regs_intr = if has(PERF_SAMPLE_REGS_INTR) {
+            Some(SampleRegs::from_cursor(ptr, attr.sample_regs_intr)?)
+        } else {
+            None
+        };
+        let phys_addr = read_u64(ptr, PERF_SAMPLE_PHYS_ADDR)?;
+        let cgroup = read_u64(ptr, PERF_SAMPLE_CGROUP)?;
+        let aux = if has(PERF_SAMPLE_AUX) {
+            let size = ptr.read_u64::<NativeEndian>()?;
+            Some(read_bytes(ptr, size as usize)?)
+        } else {
+            None
+        };
+
+        Ok(SampleRecord {
+            ip,
+            pid,
+            tid,
+            time,
+            addr,
+            id,
+            stream_id,
+            cpu,
+            period,
+            value,
+            group_values,
+            callchain,
+            raw,
+            branch_hw_idx,
+            branch_stack,
+            regs_user,
+            stack_user,
+            weight,
+            data_src,
+            transaction,
+            regs_intr,
+            phys_addr,
+            aux,
+            cgroup,
+        })
+    }
+
+    /// Parse the values of a sample with `PERF_SAMPLE_READ` based on `read_format`.
+    #[allow(clippy::type_complexity)]
+    fn _read_values(
+        ptr: &mut std::io::Cursor<&[u8]>,
+        read_format: u64,
+    ) -> Result<(Option<PerfEventValue>, Option<Vec<PerfEventValue>>)> {
+        use ffi::perf_event_read_format::*;
+        if read_format >= PERF_FORMAT_GROUP as u64 * 2 {
+            return Err(crate::Error::NotImplemented);
+        }
+        let has = |flag: ffi::perf_event_read_format| read_format & flag as u64 != 0;
+        let read_opt = |ptr: &mut std::io::Cursor<&[u8]>, flag| -> Result<u64> {
+            if has(flag) {
+                Ok(ptr.read_u64::<NativeEndian>()?)
+            } else {
+                Ok(0)
+            }
+        };
+        if has(PERF_FORMAT_GROUP) {
+            let nr = ptr.read_u64::<NativeEndian>()?;
+            let time_enabled = read_opt(ptr, PERF_FORMAT_TOTAL_TIME_ENABLED)?;
+            let time_running = read_opt(ptr, PERF_FORMAT_TOTAL_TIME_RUNNING)?;
+            let mut values = Vec::with_capacity(nr as usize);
+            for _ in 0..nr {
+                values.push(PerfEventValue {
+                    value: ptr.read_u64::<NativeEndian>()?,
+                    time_enabled,
+                    time_running,
+                    id: read_opt(ptr, PERF_FORMAT_ID)?,
+                });
+            }
+            Ok((None, Some(values)))
+        } else {
+            Ok((
+                Some(PerfEventValue {
+                    value: ptr.read_u64::<NativeEndian>()?,
+                    time_enabled: read_opt(ptr, PERF_FORMAT_TOTAL_TIME_ENABLED)?,
+                    time_running: read_opt(ptr, PERF_FORMAT_TOTAL_TIME_RUNNING)?,
+                    id: read_opt(ptr, PERF_FORMAT_ID)?,
+                }),
+                None,
+            ))
+        }
+    }
 }
 
 /// Ring buffer records with parsed fields.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ParsedRecord {
     /// Record corresponding to `PERF_RECORD_SWITCH`.
     ContextSwitch(ContextSwitchRecord),
@@ -515,10 +1634,465 @@ pub enum ParsedRecord {
     Lost(LostRecord),
     /// Record corresponding to `PERF_RECORD_COMM`.
     Comm(CommRecord),
+    /// Record corresponding to `PERF_RECORD_MMAP`.
+    Mmap(MmapRecord),
     /// Record corresponding to `PERF_RECORD_MMAP2`.
     Mmap2(Mmap2Record),
+    /// Record corresponding to `PERF_RECORD_CGROUP`.
+    Cgroup(CgroupRecord),
+    /// Record corresponding to `PERF_RECORD_KSYMBOL`.
+    Ksymbol(KsymbolRecord),
+    /// Record corresponding to `PERF_RECORD_BPF_EVENT`.
+    BpfEvent(BpfEventRecord),
+    /// Record corresponding to `PERF_RECORD_AUX`.
+    Aux(AuxRecord),
+    /// Record corresponding to `PERF_RECORD_ITRACE_START`.
+    ItraceStart(ItraceStartRecord),
     /// Record corresponding to `PERF_RECORD_SAMPLE`.
     Sample(SampleRecord),
     /// Record corresponding to all unimplemented `PERF_RECORD_*` types.
     UnknownEvent,
 }
+
+impl ParsedRecord {
+    /// Get the `sample_id` trailer of records other than samples.
+    fn _sample_id(&self) -> Option<&SampleId> {
+        let sample_id = match self {
+            ParsedRecord::ContextSwitch(r) => &r.sample_id,
+            ParsedRecord::Exit(r) | ParsedRecord::Fork(r) => &r.sample_id,
+            ParsedRecord::Throttle(r) | ParsedRecord::UnThrottle(r) => &r.sample_id,
+            ParsedRecord::Lost(r) => &r.sample_id,
+            ParsedRecord::Comm(r) => &r.sample_id,
+            ParsedRecord::Mmap(r) => &r.sample_id,
+            ParsedRecord::Mmap2(r) => &r.sample_id,
+            ParsedRecord::Cgroup(r) => &r.sample_id,
+            ParsedRecord::Ksymbol(r) => &r.sample_id,
+            ParsedRecord::BpfEvent(r) => &r.sample_id,
+            ParsedRecord::Aux(r) => &r.sample_id,
+            ParsedRecord::ItraceStart(r) => &r.sample_id,
+            ParsedRecord::Sample(_) | ParsedRecord::UnknownEvent => return None,
+        };
+        sample_id.as_ref()
+    }
+
+    /// Get the kernel id of the event that generated this record, if it was recorded.
+    pub fn event_id(&self) -> Option<u64> {
+        match self {
+            ParsedRecord::Sample(s) => s.id.or_else(|| s.value.as_ref().map(|v| v.id)),
+            _ => self._sample_id().and_then(|s| s.id),
+        }
+    }
+
+    /// Get the timestamp of this record, if it was recorded.
+    ///
+    /// Requires `PERF_SAMPLE_TIME` in the `sample_type` of the event, and `sample_id_all` for
+    /// records other than samples.
+    pub fn time(&self) -> Option<u64> {
+        match self {
+            ParsedRecord::Sample(s) => s.time,
+            _ => self._sample_id().and_then(|s| s.time),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an 8-byte aligned buffer holding a record with `type_` and `data`.
+    fn make_record(type_: ffi::perf_event_type, data: &[u8]) -> Vec<u64> {
+        let size = std::mem::size_of::<ffi::perf_event_header>() + data.len();
+        let mut buf = vec![0u64; size.div_ceil(8)];
+        let bytes =
+            unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 8) };
+        bytes[0..4].copy_from_slice(&(type_ as u32).to_ne_bytes());
+        bytes[6..8].copy_from_slice(&(size as u16).to_ne_bytes());
+        bytes[8..size].copy_from_slice(data);
+        buf
+    }
+
+    /// Parse a record of `type_` holding `data` with the attributes `attr`.
+    fn parse(
+        type_: ffi::perf_event_type,
+        data: &[u8],
+        attr: &ffi::perf_event_attr,
+    ) -> ParsedRecord {
+        let buf = make_record(type_, data);
+        let rec = unsafe { &*(buf.as_ptr() as *const RawRecord) };
+        rec.parse(attr).unwrap()
+    }
+
+    /// Parse a record as in `parse` and unwrap the `$variant` of `ParsedRecord` it must be.
+    macro_rules! parse_as {
+        ($variant:ident, $type_:ident, $data:expr, $attr:expr) => {
+            match parse(ffi::perf_event_type::$type_, $data, $attr) {
+                ParsedRecord::$variant(r) => r,
+                r => panic!("Expected a {} record, got {:?}", stringify!($variant), r),
+            }
+        };
+    }
+
+    /// Create attributes for a sample with the layout configured by `PerfEventBuilder`.
+    fn sampled_attr(extra_sample_type: u64) -> ffi::perf_event_attr {
+        use ffi::perf_event_read_format::*;
+        use ffi::perf_event_sample_format::*;
+        ffi::perf_event_attr {
+            read_format: PERF_FORMAT_ID as u64
+                | PERF_FORMAT_TOTAL_TIME_RUNNING as u64
+                | PERF_FORMAT_TOTAL_TIME_ENABLED as u64,
+            sample_type: PERF_SAMPLE_IP as u64
+                | PERF_SAMPLE_TID as u64
+                | PERF_SAMPLE_TIME as u64
+                | PERF_SAMPLE_CPU as u64
+                | PERF_SAMPLE_PERIOD as u64
+                | PERF_SAMPLE_READ as u64
+                | extra_sample_type,
+            ..Default::default()
+        }
+    }
+
+    /// Serialize a list of `u64`s.
+    fn to_bytes(vals: &[u64]) -> Vec<u8> {
+        vals.iter().flat_map(|x| x.to_ne_bytes().to_vec()).collect()
+    }
+
+    /// Create a one page `RingBuffer` holding `records` starting at byte index `start`.
+    fn make_ring_buffer(records: &[Vec<u64>], start: u64) -> RingBuffer {
+        let npages = 1;
+        let header = unsafe {
+            mman::mmap(
+                std::ptr::null_mut(),
+                *PAGE_SIZE * (npages + 1),
+                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                mman::MapFlags::MAP_SHARED | mman::MapFlags::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+            .unwrap() as *mut ffi::perf_event_mmap_page
+        };
+        let rb = RingBuffer {
+            header,
+            base: unsafe { (header as *mut u8).add(*PAGE_SIZE) },
+            size: *PAGE_SIZE * npages,
+            total_bytes_read: start,
+            aux: None,
+            event_names: HashMap::new(),
+            overwrite: false,
+            stats: RingBufferStats::default(),
+        };
+        let bytes: Vec<u8> = records
+            .iter()
+            .flat_map(|r| r.iter().flat_map(|x| x.to_ne_bytes().to_vec()))
+            .collect();
+        let data = unsafe { std::slice::from_raw_parts_mut(rb.base, rb.size) };
+        for (i, b) in bytes.iter().enumerate() {
+            data[(start as usize + i) % rb.size] = *b;
+        }
+        unsafe {
+            (*header).data_tail = start;
+            (*header).data_head = start + bytes.len() as u64;
+        }
+        rb
+    }
+
+    #[test]
+    fn test_iter_large_wrap() {
+        let name = "x".repeat(500);
+        let mut data = to_bytes(&[(7 << 32) | 7]);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&[0u8; 4]);
+        let rec = make_record(ffi::perf_event_type::PERF_RECORD_COMM, &data);
+        let mut rb = make_ring_buffer(&[rec], (*PAGE_SIZE - 64) as u64);
+        let attr = ffi::perf_event_attr::default();
+        let records: Vec<ParsedRecord> = rb.events().map(|r| r.parse(&attr).unwrap()).collect();
+        assert_eq!(records.len(), 1);
+        match records[0] {
+            ParsedRecord::Comm(ref c) => {
+                assert_eq!(c.pid, 7);
+                assert_eq!(c.comm, name);
+            }
+            _ => panic!("Expected a comm record"),
+        }
+    }
+
+    #[test]
+    fn test_record_view() {
+        let records: Vec<Vec<u64>> = (1..=2u64)
+            .map(|i| make_record(ffi::perf_event_type::PERF_RECORD_LOST, &to_bytes(&[i, i])))
+            .collect();
+        let mut rb = make_ring_buffer(&records, (*PAGE_SIZE - 32) as u64);
+        let views: Vec<RecordView> = rb.events().collect();
+        assert_eq!(views.len(), 2);
+        assert!(views[0].is_borrowed());
+        assert!(!views[1].is_borrowed());
+        assert_eq!(views[1].as_bytes(), to_bytes(&records[1]).as_slice());
+        assert_eq!(
+            views[1].header.type_,
+            ffi::perf_event_type::PERF_RECORD_LOST as u32
+        );
+    }
+
+    #[test]
+    fn test_drain_wrap() {
+        // Write two lost records with the second one wrapping around the end of the buffer
+        let records: Vec<Vec<u64>> = (1..=2u64)
+            .map(|i| {
+                make_record(
+                    ffi::perf_event_type::PERF_RECORD_LOST,
+                    &to_bytes(&[i, 10 * i]),
+                )
+            })
+            .collect();
+        let start = (*PAGE_SIZE - 32) as u64;
+        let mut rb = make_ring_buffer(&records, start);
+        let end = start + records.iter().map(|r| r.len() as u64 * 8).sum::<u64>();
+
+        let attr = ffi::perf_event_attr::default();
+        let lost: Vec<(u64, u64)> = rb
+            .drain(&attr)
+            .map(|r| match r.unwrap() {
+                ParsedRecord::Lost(l) => (l.id, l.num),
+                _ => panic!("Expected a lost record"),
+            })
+            .collect();
+        assert_eq!(lost, vec![(1, 10), (2, 20)]);
+        assert_eq!(unsafe { (*rb.header).data_tail }, end);
+        let stats = rb.stats();
+        assert_eq!(stats.bytes_consumed, end);
+        assert_eq!(stats.wraps, 1);
+        assert_eq!(
+            stats.records_of_type(ffi::perf_event_type::PERF_RECORD_LOST),
+            2
+        );
+        assert_eq!(stats.lost, 30);
+        assert!(rb.drain(&attr).next().is_none());
+    }
+
+    #[test]
+    fn test_aux_chunks_wrap() {
+        let data: Vec<u8> = (0u8..8).collect();
+        let chunks: Vec<&[u8]> = AuxChunks {
+            data: &data,
+            next: 14,
+            end: 19,
+        }
+        .collect();
+        assert_eq!(chunks, vec![&[6u8, 7][..], &[0u8, 1, 2][..]]);
+    }
+
+    #[test]
+    fn test_read_aux() {
+        let attr = ffi::perf_event_attr::default();
+        let record = parse_as!(Aux, PERF_RECORD_AUX, &to_bytes(&[6, 5, 0x1]), &attr);
+        assert!(record.is_truncated() && !record.is_partial());
+
+        let mut rb = make_ring_buffer(&[], 0);
+        assert!(rb.read_aux(&record).is_empty());
+        let mut data: Vec<u8> = (0u8..8).collect();
+        rb.aux = Some(AuxArea {
+            base: data.as_mut_ptr(),
+            size: data.len(),
+        });
+        assert_eq!(rb.read_aux(&record), vec![6, 7, 0, 1, 2]);
+        assert_eq!(unsafe { &*rb.header }.aux_tail, 11);
+        // The AUX area is not mapped
+        rb.aux = None;
+    }
+
+    #[test]
+    fn test_parse_ksymbol() {
+        let attr = ffi::perf_event_attr::default();
+        let mut data = to_bytes(&[0xffff_0000, (1 << 48) | (1 << 32) | 64]);
+        data.extend_from_slice(b"bpf_prog_1\0\0\0\0\0\0");
+        let k = parse_as!(Ksymbol, PERF_RECORD_KSYMBOL, &data, &attr);
+        assert_eq!(k.address, 0xffff_0000);
+        assert_eq!(k.length, 64);
+        assert_eq!(
+            k.ksym_type,
+            ffi::perf_record_ksymbol_type::PERF_RECORD_KSYMBOL_TYPE_BPF as u16
+        );
+        assert!(k.is_unregister());
+        assert_eq!(k.name, "bpf_prog_1");
+    }
+
+    #[test]
+    fn test_parse_bpf_event() {
+        let attr = ffi::perf_event_attr::default();
+        let mut data = to_bytes(&[(42 << 32) | 1]);
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let b = parse_as!(BpfEvent, PERF_RECORD_BPF_EVENT, &data, &attr);
+        assert_eq!(
+            b.event_type,
+            ffi::perf_bpf_event_type::PERF_BPF_EVENT_PROG_LOAD as u16
+        );
+        assert_eq!(b.id, 42);
+        assert_eq!(b.tag, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_parse_mmap() {
+        let attr = ffi::perf_event_attr::default();
+        let mut data = to_bytes(&[(2 << 32) | 1, 0x40_0000, 0x1000, 0]);
+        data.extend_from_slice(b"[kernel.kallsyms]_text\0\0");
+        let m = parse_as!(Mmap, PERF_RECORD_MMAP, &data, &attr);
+        assert_eq!(m.pid, 1);
+        assert_eq!(m.tid, 2);
+        assert_eq!(m.address, 0x40_0000);
+        assert_eq!(m.length, 0x1000);
+        assert_eq!(m.filename, "[kernel.kallsyms]_text");
+    }
+
+    #[test]
+    fn test_parse_sample_id_trailer() {
+        use ffi::perf_event_sample_format::*;
+        let mut attr = ffi::perf_event_attr {
+            sample_type: PERF_SAMPLE_TID as u64 | PERF_SAMPLE_TIME as u64 | PERF_SAMPLE_CPU as u64,
+            ..Default::default()
+        };
+        attr.set_sample_id_all(1);
+        let mut data = to_bytes(&[(5 << 32) | 5]);
+        data.extend_from_slice(b"bash\0\0\0\0");
+        data.extend_from_slice(&to_bytes(&[(6 << 32) | 5, 1234, 3]));
+        let c = parse_as!(Comm, PERF_RECORD_COMM, &data, &attr);
+        assert_eq!(c.comm, "bash");
+        let sample_id = c.sample_id.unwrap();
+        assert_eq!(sample_id.pid, Some(5));
+        assert_eq!(sample_id.tid, Some(6));
+        assert_eq!(sample_id.time, Some(1234));
+        assert_eq!(sample_id.cpu, Some(3));
+        assert_eq!(sample_id.id, None);
+    }
+
+    #[test]
+    fn test_parse_sample_custom_layout() {
+        use ffi::perf_event_read_format::*;
+        use ffi::perf_event_sample_format::*;
+        let attr = ffi::perf_event_attr {
+            sample_type: PERF_SAMPLE_IDENTIFIER as u64
+                | PERF_SAMPLE_IP as u64
+                | PERF_SAMPLE_READ as u64
+                | PERF_SAMPLE_CALLCHAIN as u64
+                | PERF_SAMPLE_BRANCH_STACK as u64,
+            read_format: PERF_FORMAT_GROUP as u64 | PERF_FORMAT_ID as u64,
+            ..Default::default()
+        };
+        let data = to_bytes(&[
+            77,     // identifier
+            0x1000, // ip
+            2,      // nr
+            10,     // value
+            1,      // id
+            20,     // value
+            2,      // id
+            2,      // nr
+            0xa,    // ips
+            0xb,    // ips
+            1,      // nr
+            0x10,   // from
+            0x20,   // to
+            0x1,    // flags
+        ]);
+        let s = parse_as!(Sample, PERF_RECORD_SAMPLE, &data, &attr);
+        assert_eq!(s.id, Some(77));
+        assert_eq!(s.ip, Some(0x1000));
+        assert!(s.pid.is_none());
+        assert!(s.value.is_none());
+        let values = s.group_values.unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[1].value, 20);
+        assert_eq!(values[1].id, 2);
+        assert_eq!(s.callchain.unwrap(), vec![0xa, 0xb]);
+        let branches = s.branch_stack.unwrap();
+        assert_eq!(branches[0].to, 0x20);
+        assert!(branches[0].mispredicted());
+    }
+
+    #[test]
+    fn test_parse_sample_regs_user() {
+        let attr = ffi::perf_event_attr {
+            sample_regs_user: 0b1011,
+            ..sampled_attr(ffi::perf_event_sample_format::PERF_SAMPLE_REGS_USER as u64)
+        };
+        let data = to_bytes(&[
+            0x1000,        // ip
+            (7 << 32) | 5, // pid, tid
+            42,            // time
+            3,             // cpu, res
+            100,           // period
+            1,             // value
+            2,             // time_enabled
+            2,             // time_running
+            9,             // id
+            ffi::perf_sample_regs_abi::PERF_SAMPLE_REGS_ABI_64 as u64,
+            10,
+            11,
+            13,
+        ]);
+        let s = parse_as!(Sample, PERF_RECORD_SAMPLE, &data, &attr);
+        assert_eq!(s.ip, Some(0x1000));
+        assert_eq!(s.pid, Some(5));
+        assert_eq!(s.tid, Some(7));
+        assert_eq!(s.period, Some(100));
+        assert_eq!(s.value.unwrap().id, 9);
+        let regs = s.regs_user.unwrap();
+        assert_eq!(regs.get(0), Some(10));
+        assert_eq!(regs.get(1), Some(11));
+        assert_eq!(regs.get(2), None);
+        assert_eq!(regs.get(3), Some(13));
+    }
+
+    #[test]
+    fn test_parse_sample_stack_user() {
+        let attr = sampled_attr(ffi::perf_event_sample_format::PERF_SAMPLE_STACK_USER as u64);
+        let data = to_bytes(&[
+            0x1000,        // ip
+            (7 << 32) | 5, // pid, tid
+            42,            // time
+            3,             // cpu, res
+            100,           // period
+            1,             // value
+            2,             // time_enabled
+            2,             // time_running
+            9,             // id
+            16,            // size
+            0xdead,        // data
+            0xbeef,        // data
+            8,             // dyn_size
+        ]);
+        let s = parse_as!(Sample, PERF_RECORD_SAMPLE, &data, &attr);
+        assert!(s.regs_user.is_none());
+        let stack = s.stack_user.unwrap();
+        assert_eq!(stack.data.len(), 16);
+        assert_eq!(stack.dyn_size, 8);
+        assert_eq!(stack.valid_data(), &0xdeadu64.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_parse_sample_aux_cgroup() {
+        use ffi::perf_event_sample_format::*;
+        let attr = ffi::perf_event_attr {
+            sample_type: PERF_SAMPLE_IP as u64 | PERF_SAMPLE_AUX as u64 | PERF_SAMPLE_CGROUP as u64,
+            ..Default::default()
+        };
+        let mut data = to_bytes(&[
+            0x1000, // ip
+            17,     // cgroup
+            3,      // size
+        ]);
+        data.extend_from_slice(&[1, 2, 3, 0, 0, 0, 0, 0]);
+        let s = parse_as!(Sample, PERF_RECORD_SAMPLE, &data, &attr);
+        assert_eq!(s.ip, Some(0x1000));
+        assert_eq!(s.cgroup, Some(17));
+        assert_eq!(s.aux.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_parse_cgroup() {
+        let attr = ffi::perf_event_attr::default();
+        let mut data = to_bytes(&[17]);
+        data.extend_from_slice(b"/system.slice\0\0\0");
+        let c = parse_as!(Cgroup, PERF_RECORD_CGROUP, &data, &attr);
+        assert_eq!(c.id, 17);
+        assert_eq!(c.path, "/system.slice");
+    }
+}
@@ -5,16 +5,72 @@ pub mod ffi;
 mod version;
 pub use version::PerfVersion;
 
+mod exec;
+pub use exec::{CommandResult, PerfCommand};
+
+mod generic;
+pub use generic::{HwId, SwId};
+
+mod spec;
+pub use spec::parse_event;
+
 mod event;
-pub use event::{PerfEvent, PerfEventBuilder, PerfEventValue};
+pub use event::{
+    AttachedProcess, CountingGuard, PerCpuEvent, PerfEvent, PerfEventBuilder, PerfEventGroup,
+    PerfEventValue, UncoreEvent,
+};
+
+mod overflow;
+pub use overflow::OverflowHandler;
+
+mod capabilities;
+pub use capabilities::{Capabilities, Operation};
+
+mod probe;
+pub use probe::{max_precise_ip, probe_event, EventSupport, KernelFeatures};
+
+mod poller;
+pub use poller::{PollEvent, Poller};
+
+mod merge;
+pub use merge::RecordMerger;
+
+mod multiplex;
+pub use multiplex::MultiplexScheduler;
+
+mod stat;
+pub use stat::{stat, StatCount, StatReport, Target};
+
+mod timeseries;
+pub use timeseries::{TimeSeriesCollector, TimeSeriesHandle, TimeSeriesSample};
+
+pub mod sysfs;
+pub mod tracefs;
+
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "async")]
+pub use stream::RecordStream;
 
 mod mmap;
 pub(crate) use mmap::PAGE_SIZE;
 pub use mmap::{
-    CommRecord, ContextSwitchRecord, LostRecord, Mmap2Record, ParsedRecord, ProcessRecord,
-    RawRecord, RingBuffer, RingBufferIter, SampleRecord, ThrottleRecord,
+    AuxChunks, AuxRecord, BpfEventRecord, BranchEntry, CgroupRecord, CommRecord, ContextSwitchKind,
+    ContextSwitchRecord, ItraceStartRecord, KsymbolRecord, LostRecord, Mmap2Record, MmapRecord,
+    ParsedRecord, ProcessRecord, RawRecord, RecordView, RingBuffer, RingBufferDrain,
+    RingBufferIter, RingBufferReader, RingBufferStats, SampleId, SampleRecord, SampleRegs,
+    SampleStack, ThrottleRecord,
 };
 
+mod pt;
+pub use pt::{PtChunk, PtTrace, INTEL_PT_PMU};
+
+mod pt_decoder;
+pub use pt_decoder::{PtDecoder, PtFlow, PtFlowEvent, PtPacket};
+
+mod ibs;
+pub use ibs::{IbsFetchSample, IbsOpSample, IBS_DEFAULT_PERIOD, IBS_FETCH_PMU, IBS_OP_PMU};
+
 /// Allow conversion of an event to a Linux perf event string.
 pub trait ToPerfString<V, C>
 where
@@ -0,0 +1,239 @@
+//! Userspace multiplexing of more events than there are hardware counters, similar to
+//! `perf stat`.
+
+use crate::api::Counter;
+use crate::perf::{PerfEventGroup, PerfEventValue};
+use crate::{Error, Result};
+use log::debug;
+use std::time::{Duration, Instant};
+
+/// Rotates groups of events through a limited number of hardware counters.
+///
+/// The kernel multiplexes events that do not fit onto the PMU itself, but it rotates them on its
+/// own timer and gives no control over the events counted together. Instead, the scheduler enables
+/// the groups in turns for a fixed time slice, with at most `num_counters` events enabled at a
+/// time. The values returned by `read` are scaled to the whole measurement by
+/// `ScaledValue::scaled_value`.
+#[derive(Debug)]
+pub struct MultiplexScheduler {
+    /// Groups of events being scheduled.
+    groups: Vec<PerfEventGroup>,
+    /// Number of hardware counters available to the groups.
+    num_counters: usize,
+    /// Time for which a set of groups is enabled before rotating to the next one.
+    slice: Duration,
+    /// Indices of the groups currently enabled.
+    active: Vec<usize>,
+    /// Index of the group enabled first on the next rotation.
+    next: usize,
+    /// Time at which the scheduler was started, if it is running.
+    started: Option<Instant>,
+    /// Time spent running before the scheduler was last started.
+    elapsed: Duration,
+}
+
+impl MultiplexScheduler {
+    /// Create a new scheduler for `num_counters` hardware counters rotating every `slice`.
+    pub fn new(num_counters: usize, slice: Duration) -> Self {
+        MultiplexScheduler {
+            groups: Vec::new(),
+            num_counters,
+            slice,
+            active: Vec::new(),
+            next: 0,
+            started: None,
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    /// Add a group of events to be scheduled.
+    ///
+    /// Fails if the group has more events than there are counters, or the scheduler is running.
+    pub fn add_group(&mut self, group: PerfEventGroup) -> Result<()> {
+        if group.len() > self.num_counters || self.is_running() {
+            return Err(Error::InvalidEvent(format!(
+                "Cannot schedule group {} with {} events on {} counters",
+                group.name(),
+                group.len(),
+                self.num_counters
+            )));
+        }
+        group.disable()?;
+        self.groups.push(group);
+        Ok(())
+    }
+
+    /// Get the groups being scheduled.
+    pub fn groups(&self) -> &[PerfEventGroup] {
+        &self.groups
+    }
+
+    /// Check if the scheduler is running.
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Get the total time the scheduler has been running.
+    pub fn elapsed(&self) -> Duration {
+        match self.started {
+            Some(start) => self.elapsed + start.elapsed(),
+            None => self.elapsed,
+        }
+    }
+
+    /// Start enabling the groups in turns.
+    ///
+    /// `rotate` has to be called once every time slice, or use `run_for` instead.
+    pub fn start(&mut self) -> Result<()> {
+        if !self.is_running() {
+            self.started = Some(Instant::now());
+            self._schedule()?;
+        }
+        Ok(())
+    }
+
+    /// Disable the current groups and enable the next ones.
+    pub fn rotate(&mut self) -> Result<()> {
+        if self.is_running() {
+            self._disable_active()?;
+            self._schedule()?;
+        }
+        Ok(())
+    }
+
+    /// Stop the scheduler and disable all groups.
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(start) = self.started.take() {
+            self.elapsed += start.elapsed();
+            self._disable_active()?;
+        }
+        Ok(())
+    }
+
+    /// Run the scheduler for `duration`, rotating the groups every time slice.
+    ///
+    /// Blocks the calling thread until done.
+    pub fn run_for(&mut self, duration: Duration) -> Result<()> {
+        let end = Instant::now() + duration;
+        self.start()?;
+        loop {
+            let now = Instant::now();
+            if now >= end {
+                break;
+            }
+            std::thread::sleep(std::cmp::min(self.slice, end - now));
+            if Instant::now() < end {
+                self.rotate()?;
+            }
+        }
+        self.stop()
+    }
+
+    /// Reset the values of all groups and the time the scheduler has been running.
+    pub fn reset(&mut self) -> Result<()> {
+        self.groups.iter().try_for_each(|g| g.reset())?;
+        self.elapsed = Duration::from_secs(0);
+        if self.started.is_some() {
+            self.started = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Read the values of the events of each group.
+    ///
+    /// The `time_enabled` of each value is the total time the scheduler has been running, so that
+    /// the scaled value estimates the count over the whole measurement.
+    pub fn read(&self) -> Result<Vec<Vec<PerfEventValue>>> {
+        let time_enabled = self.elapsed().as_nanos() as u64;
+        self.groups
+            .iter()
+            .map(|g| {
+                let mut values = g.read_sync()?;
+                for v in values.iter_mut() {
+                    v.time_enabled = time_enabled;
+                }
+                Ok(values)
+            })
+            .collect()
+    }
+
+    /// Disable the groups that are currently enabled.
+    fn _disable_active(&mut self) -> Result<()> {
+        for i in self.active.drain(..) {
+            self.groups[i].disable()?;
+        }
+        Ok(())
+    }
+
+    /// Enable as many groups as fit onto the counters, starting with the next one in turn.
+    fn _schedule(&mut self) -> Result<()> {
+        let mut used = 0;
+        for i in 0..self.groups.len() {
+            let idx = (self.next + i) % self.groups.len();
+            used += self.groups[idx].len();
+            if used > self.num_counters {
+                break;
+            }
+            self.active.push(idx);
+        }
+        if !self.groups.is_empty() {
+            self.next = (self.next + self.active.len()) % self.groups.len();
+        }
+        for &i in self.active.iter() {
+            self.groups[i].enable()?;
+        }
+        debug!("Scheduled event groups {:?}", self.active);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::{PerfEvent, SwId};
+    use crate::ScaledValue;
+
+    fn busy_loop() {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(5) {}
+    }
+
+    #[test]
+    fn test_multiplex_scheduler() {
+        let mut sched = MultiplexScheduler::new(2, Duration::from_millis(5));
+        let attrs = vec![
+            vec![SwId::TaskClock.into(), SwId::PageFaults.into()],
+            vec![SwId::TaskClock.into()],
+            vec![SwId::TaskClock.into()],
+        ];
+        for group in attrs {
+            let group = PerfEvent::build().open_group(group).unwrap();
+            assert!(sched.add_group(group).is_ok());
+        }
+        let group = PerfEvent::build()
+            .open_group(vec![SwId::TaskClock.into(); 3])
+            .unwrap();
+        assert!(sched.add_group(group).is_err());
+
+        assert!(sched.start().is_ok());
+        assert_eq!(sched.active, vec![0]);
+        busy_loop();
+        assert!(sched.rotate().is_ok());
+        assert_eq!(sched.active, vec![1, 2]);
+        busy_loop();
+        assert!(sched.rotate().is_ok());
+        assert_eq!(sched.active, vec![0]);
+        busy_loop();
+        assert!(sched.stop().is_ok());
+        assert!(sched.active.is_empty());
+
+        let elapsed = sched.elapsed().as_nanos() as u64;
+        let values = sched.read().unwrap();
+        assert_eq!(values.len(), 3);
+        for v in values.iter().map(|g| &g[0]) {
+            assert_eq!(v.time_enabled, elapsed);
+            assert!(v.time_running > 0 && v.time_running < elapsed);
+            assert!(v.scaled_value() > v.raw_value());
+        }
+    }
+}
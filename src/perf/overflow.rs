@@ -0,0 +1,187 @@
+//! Asynchronous notifications of counter overflows using `SIGIO`.
+//!
+//! The kernel sends a `SIGIO` signal to the process when a perf event configured for asynchronous
+//! IO overflows. The signal handler forwards the file descriptor of the event through a pipe to a
+//! dispatcher thread, which runs the callback registered for the event outside of signal context.
+
+use crate::Result;
+use lazy_static::lazy_static;
+use log::debug;
+use nix::libc;
+use nix::sys::signal;
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Callback invoked on overflow of a perf event.
+pub type OverflowHandler = Box<dyn FnMut() + Send>;
+
+lazy_static! {
+    /// Callbacks registered for overflow notifications indexed by the fd of the perf event.
+    static ref HANDLERS: Mutex<HashMap<RawFd, Arc<Mutex<OverflowHandler>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `fcntl` command to set the signal sent on IO readiness (not exported by `libc`).
+const F_SETSIG: libc::c_int = 10;
+
+/// Write end of the pipe used to forward notifications from the signal handler.
+static PIPE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Prefix of `siginfo_t` for `SIGPOLL`/`SIGIO` signals (`libc` does not expose `si_fd`).
+#[repr(C)]
+struct _SigPollInfo {
+    si_signo: libc::c_int,
+    si_errno: libc::c_int,
+    si_code: libc::c_int,
+    si_band: libc::c_long,
+    si_fd: libc::c_int,
+}
+
+/// Create the pipe used to forward notifications, with a non-blocking write end.
+fn _pipe() -> Result<(RawFd, RawFd)> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+    let (rd, wr) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+    fcntl(wr, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    Ok((rd, wr))
+}
+
+/// Forward a notification for the perf event `fd` through the write end of the pipe `wr`.
+///
+/// Async-signal-safe and never blocks. If the pipe is full (`EAGAIN`) the dispatcher is already
+/// behind and the notification is dropped. `errno` is preserved for the interrupted code.
+fn _notify(wr: RawFd, fd: RawFd) {
+    let bytes = fd.to_ne_bytes();
+    unsafe {
+        let errno = *libc::__errno_location();
+        libc::write(wr, bytes.as_ptr() as *const libc::c_void, bytes.len());
+        *libc::__errno_location() = errno;
+    }
+}
+
+/// Signal handler for `SIGIO`.
+///
+/// Only async-signal-safe functions may be used here.
+extern "C" fn _sigio_handler(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let fd = unsafe { (*(info as *const _SigPollInfo)).si_fd };
+    let pipe = PIPE_FD.load(Ordering::SeqCst);
+    if pipe != -1 {
+        _notify(pipe, fd);
+    }
+}
+
+/// Run callbacks for the file descriptors read from the pipe `rd`.
+fn _dispatch(rd: RawFd) {
+    let mut buf = [0u8; std::mem::size_of::<RawFd>()];
+    loop {
+        match nix::unistd::read(rd, &mut buf) {
+            Ok(n) if n == buf.len() => {
+                let fd = RawFd::from_ne_bytes(buf);
+                // Release the lock before running the callback, so that it can (un)register
+                // handlers itself
+                let handler = HANDLERS.lock().unwrap().get(&fd).cloned();
+                if let Some(handler) = handler {
+                    (handler.lock().unwrap())();
+                }
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            _ => break,
+        }
+    }
+}
+
+/// Install the signal handler and start the dispatcher thread if not already done.
+///
+/// Must be called with the lock on `HANDLERS` held.
+fn _start_dispatcher() -> Result<()> {
+    if PIPE_FD.load(Ordering::SeqCst) != -1 {
+        return Ok(());
+    }
+    let (rd, wr) = _pipe()?;
+    PIPE_FD.store(wr, Ordering::SeqCst);
+    let action = signal::SigAction::new(
+        signal::SigHandler::SigAction(_sigio_handler),
+        signal::SaFlags::SA_SIGINFO | signal::SaFlags::SA_RESTART,
+        signal::SigSet::empty(),
+    );
+    unsafe {
+        signal::sigaction(signal::Signal::SIGIO, &action)?;
+    }
+    std::thread::Builder::new()
+        .name("perf-overflow".into())
+        .spawn(move || _dispatch(rd))?;
+    debug!("Started overflow dispatcher thread");
+    Ok(())
+}
+
+/// Deliver overflow notifications of the perf event `fd` to `handler`.
+///
+/// Any previously registered handler for `fd` is replaced.
+pub(crate) fn register(fd: RawFd, handler: OverflowHandler) -> Result<()> {
+    let mut handlers = HANDLERS.lock().unwrap();
+    _start_dispatcher()?;
+    unsafe {
+        if libc::fcntl(fd, libc::F_SETOWN, libc::getpid()) == -1
+            || libc::fcntl(fd, F_SETSIG, libc::SIGIO) == -1
+        {
+            return Err(crate::Error::from_errno());
+        }
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_ASYNC) == -1 {
+            return Err(crate::Error::from_errno());
+        }
+    }
+    handlers.insert(fd, Arc::new(Mutex::new(handler)));
+    Ok(())
+}
+
+/// Stop delivering overflow notifications of the perf event `fd`.
+pub(crate) fn unregister(fd: RawFd) {
+    HANDLERS.lock().unwrap().remove(&fd);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_dispatch_unregister_in_handler() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let (rd, wr) = _pipe().unwrap();
+        // Not a perf event, so register it directly without the `fcntl` setup
+        let fd = wr;
+        let handler: OverflowHandler = Box::new(move || {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            unregister(fd);
+        });
+        HANDLERS
+            .lock()
+            .unwrap()
+            .insert(fd, Arc::new(Mutex::new(handler)));
+        for _ in 0..2 {
+            _notify(wr, fd);
+        }
+        nix::unistd::close(wr).unwrap();
+        _dispatch(rd);
+        nix::unistd::close(rd).unwrap();
+        // The second notification is dropped as the handler unregistered itself
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_notify_full_pipe() {
+        let (rd, wr) = _pipe().unwrap();
+        // Fill the pipe, further notifications must neither block nor clobber errno
+        let capacity = unsafe { libc::fcntl(wr, libc::F_GETPIPE_SZ) };
+        assert!(capacity > 0);
+        for _ in 0..capacity as usize / std::mem::size_of::<RawFd>() + 1 {
+            unsafe { *libc::__errno_location() = libc::EINTR };
+            _notify(wr, 0);
+            assert_eq!(nix::errno::errno(), libc::EINTR);
+        }
+        nix::unistd::close(wr).unwrap();
+        nix::unistd::close(rd).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+//! Waiting for data on many perf events at once using `epoll`.
+
+use crate::Result;
+use log::debug;
+use nix::libc;
+use nix::sys::epoll;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Readiness of an event registered with a `Poller`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollEvent {
+    /// Token the event was registered with.
+    pub token: u64,
+    /// The ring buffer of the event has data to be read.
+    pub readable: bool,
+    /// The process monitored by the event has exited.
+    pub closed: bool,
+}
+
+/// Waits for data on a set of perf events with a single `epoll` instance.
+///
+/// Unlike polling each event separately, the cost of a wait does not grow with the number of
+/// registered events, which makes it suitable for per-CPU and system-wide sessions. Events are
+/// identified by a user-provided token, e.g., their index in a collection.
+#[derive(Debug)]
+pub struct Poller {
+    /// File descriptor of the `epoll` instance.
+    epfd: RawFd,
+    /// Buffer receiving ready events from the kernel.
+    ready: Vec<epoll::EpollEvent>,
+}
+
+impl Poller {
+    /// Create a new poller without any registered events.
+    pub fn new() -> Result<Self> {
+        let epfd = epoll::epoll_create1(epoll::EpollCreateFlags::EPOLL_CLOEXEC)?;
+        Ok(Poller {
+            epfd,
+            ready: Vec::new(),
+        })
+    }
+
+    /// Register `fd` to be reported with `token` when it is ready.
+    ///
+    /// Any perf event can be registered, e.g., a `PerfEvent` or the per CPU events of a
+    /// `PerCpuEvent`. Registered events must be removed before they are closed.
+    pub fn add<F: AsRawFd>(&mut self, fd: &F, token: u64) -> Result<()> {
+        let mut evt = epoll::EpollEvent::new(
+            epoll::EpollFlags::EPOLLIN | epoll::EpollFlags::EPOLLHUP,
+            token,
+        );
+        epoll::epoll_ctl(
+            self.epfd,
+            epoll::EpollOp::EpollCtlAdd,
+            fd.as_raw_fd(),
+            &mut evt,
+        )?;
+        self.ready.push(epoll::EpollEvent::empty());
+        debug!("Registered fd {} with poller {}", fd.as_raw_fd(), self.epfd);
+        Ok(())
+    }
+
+    /// Stop reporting readiness of `fd`.
+    pub fn remove<F: AsRawFd>(&mut self, fd: &F) -> Result<()> {
+        epoll::epoll_ctl(self.epfd, epoll::EpollOp::EpollCtlDel, fd.as_raw_fd(), None)?;
+        self.ready.pop();
+        Ok(())
+    }
+
+    /// Wait for registered events to become ready.
+    ///
+    /// The call will block for at most `timeout` milliseconds, or indefinitely if `timeout` is
+    /// `-1`. Returns the ready events, which is empty if the timeout expired.
+    pub fn wait(&mut self, timeout: libc::c_int) -> Result<Vec<PollEvent>> {
+        if self.ready.is_empty() {
+            self.ready.push(epoll::EpollEvent::empty());
+        }
+        let n = loop {
+            match epoll::epoll_wait(self.epfd, &mut self.ready, timeout as isize) {
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                res => break res?,
+            }
+        };
+        Ok(self.ready[..n]
+            .iter()
+            .map(|e| PollEvent {
+                token: e.data(),
+                readable: e.events().contains(epoll::EpollFlags::EPOLLIN),
+                closed: e.events().contains(epoll::EpollFlags::EPOLLHUP),
+            })
+            .collect())
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.epfd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::{PerfEvent, SwId};
+    use crate::Counter;
+
+    #[test]
+    fn test_poller() {
+        let evts: Vec<PerfEvent> = (0..2)
+            .map(|_| {
+                PerfEvent::software(SwId::TaskClock)
+                    .start_disabled()
+                    .set_period(100_000)
+                    .set_wakeup_events(1)
+                    .enable_sampling()
+                    .open(None)
+                    .unwrap()
+            })
+            .collect();
+        let mut poller = Poller::new().unwrap();
+        for (i, e) in evts.iter().enumerate() {
+            assert!(poller.add(e, i as u64).is_ok());
+        }
+        assert!(poller.wait(0).unwrap().is_empty());
+
+        assert!(evts[1].enable().is_ok());
+        let start = std::time::Instant::now();
+        while start.elapsed() < std::time::Duration::from_millis(10) {}
+        assert!(evts[1].disable().is_ok());
+        let ready = poller.wait(1000).unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].token, 1);
+        assert!(ready[0].readable);
+
+        for e in evts.iter() {
+            assert!(poller.remove(e).is_ok());
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Probing whether events can be counted on the local machine.
+
+use crate::perf::{ffi, sysfs, SwId, PAGE_SIZE};
+use crate::Error;
+use log::debug;
+use nix::errno::Errno;
+use nix::libc;
+
+/// Outcome of probing an event with `probe_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSupport {
+    /// The event can be opened.
+    Supported,
+    /// The event is not supported by the kernel or the hardware, e.g., the PMU does not exist.
+    NotSupported,
+    /// Opening the event is not permitted, e.g., because of `perf_event_paranoid`.
+    PermissionDenied,
+    /// The configuration of the event is invalid, e.g., it sets reserved bits.
+    InvalidConfig,
+    /// The counters required by the event are in use by another user.
+    Busy,
+    /// Opening the event failed with another error.
+    Other(Errno),
+}
+
+impl EventSupport {
+    /// Check if the event can be opened.
+    pub fn is_supported(self) -> bool {
+        self == EventSupport::Supported
+    }
+
+    /// Classify the result of a call to `perf_event_open`.
+    fn _from_result(res: crate::Result<std::os::unix::io::RawFd>) -> Self {
+        match res {
+            Ok(fd) => {
+                let _ = nix::unistd::close(fd);
+                EventSupport::Supported
+            }
+            Err(Error::System(nix::Error::Sys(errno))) => match errno {
+                Errno::ENOENT | Errno::EOPNOTSUPP | Errno::ENODEV | Errno::ENOSYS => {
+                    EventSupport::NotSupported
+                }
+                Errno::EACCES | Errno::EPERM => EventSupport::PermissionDenied,
+                Errno::EINVAL | Errno::E2BIG => EventSupport::InvalidConfig,
+                Errno::EBUSY => EventSupport::Busy,
+                e => EventSupport::Other(e),
+            },
+            Err(_) => EventSupport::Other(Errno::UnknownErrno),
+        }
+    }
+}
+
+/// Open the event described by `attr` for a moment to check whether it can be counted.
+///
+/// The event is first opened disabled for the current process. If this is rejected as invalid,
+/// e.g., for uncore events that cannot count per process, it is opened for the whole first online
+/// CPU instead.
+pub fn probe_event(attr: &ffi::perf_event_attr) -> EventSupport {
+    let mut attr = *attr;
+    attr.size = std::mem::size_of::<ffi::perf_event_attr>() as _;
+    attr.set_disabled(1);
+    let flags = ffi::PERF_FLAG_FD_CLOEXEC as libc::c_ulong;
+    let res = EventSupport::_from_result(ffi::perf_event_open(&attr, 0, -1, -1, flags));
+    if res != EventSupport::InvalidConfig {
+        return res;
+    }
+    let cpu = match crate::util::online_cpus() {
+        Ok(cpus) if !cpus.is_empty() => cpus[0],
+        _ => return res,
+    };
+    let res = EventSupport::_from_result(ffi::perf_event_open(&attr, -1, cpu, -1, flags));
+    debug!(
+        "Probed event with attributes {:?} on CPU {}: {:?}",
+        attr, cpu, res
+    );
+    res
+}
+
+/// Find the highest precision of the instruction pointer, i.e., `precise_ip`, with which the event
+/// described by `attr` can be opened.
+///
+/// Like the `perf` tool, the levels are tried from `3` downwards. Returns `0` if the event does
+/// not support precise sampling, e.g., because PEBS is not available in a virtual machine.
+pub fn max_precise_ip(attr: &ffi::perf_event_attr) -> u8 {
+    let mut attr = *attr;
+    for level in (1..=3).rev() {
+        attr.set_precise_ip(level.into());
+        if probe_event(&attr).is_supported() {
+            debug!("Event supports precise_ip {}", level);
+            return level;
+        }
+    }
+    0
+}
+
+/// Features of the perf interface of the running kernel, detected by test-opening events instead
+/// of asking the `perf` tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelFeatures {
+    /// Largest size of `perf_event_attr` understood by both the kernel and this crate.
+    pub attr_size: u32,
+    /// The core PMU is registered in sysfs, so events can be named by terms such as
+    /// `cpu/event=0x3c,name=CYCLES/` instead of raw codes.
+    pub name_term: bool,
+    /// Core events can program the offcore response MSRs through the `offcore_rsp` term.
+    pub offcore_rsp: bool,
+    /// Core events can program the load latency threshold through the `ldlat` term.
+    pub ldlat: bool,
+}
+
+impl KernelFeatures {
+    /// Detect the features of the running kernel.
+    pub fn probe() -> Self {
+        let core = _core_pmu();
+        let formats = core
+            .as_ref()
+            .and_then(|pmu| sysfs::pmu_formats(pmu).ok())
+            .unwrap_or_default();
+        let features = KernelFeatures {
+            attr_size: _probe_attr_size(),
+            name_term: !formats.is_empty(),
+            offcore_rsp: formats.contains_key("offcore_rsp")
+                && _probe_terms(
+                    core.as_deref(),
+                    "event=0xb7,umask=0x1,offcore_rsp=0x10001",
+                    0,
+                ),
+            ldlat: formats.contains_key("ldlat")
+                && _probe_terms(core.as_deref(), "event=0xcd,umask=0x1,ldlat=0x3", 2),
+        };
+        debug!("Probed kernel features {:?}", features);
+        features
+    }
+}
+
+/// Get the name of the PMU of the cores, i.e., `cpu`, or that of the first core type on hybrid
+/// systems.
+fn _core_pmu() -> Option<String> {
+    if sysfs::pmu_type("cpu").is_ok() {
+        return Some("cpu".into());
+    }
+    sysfs::hybrid_pmus().ok()?.into_iter().next()
+}
+
+/// Find the largest size of `perf_event_attr` understood by both the kernel and this crate.
+///
+/// Attributes larger than the kernel's are only rejected if any of the extra bytes are set, but
+/// the kernel rejects any `size` larger than a page with `E2BIG`. It then writes the size of its
+/// own `perf_event_attr` into `size`, before checking whether the event may be opened at all.
+/// Falls back to the size of the first version of the structure if no size is reported.
+fn _probe_attr_size() -> u32 {
+    let size = std::mem::size_of::<ffi::perf_event_attr>() as u32;
+    let mut attr: ffi::perf_event_attr = SwId::TaskClock.into();
+    attr.set_disabled(1);
+    attr.set_exclude_kernel(1);
+    attr.set_exclude_hv(1);
+    attr.size = *PAGE_SIZE as u32 + 8;
+    let flags = ffi::PERF_FLAG_FD_CLOEXEC as libc::c_ulong;
+    match ffi::_perf_event_open(&mut attr, 0, -1, -1, flags) {
+        Err(Error::System(nix::Error::Sys(Errno::E2BIG))) if attr.size as usize <= *PAGE_SIZE => {
+            debug!("Kernel perf_event_attr has {} bytes", attr.size);
+            std::cmp::min(attr.size, size)
+        }
+        Ok(fd) => {
+            let _ = nix::unistd::close(fd);
+            ffi::PERF_ATTR_SIZES[0]
+        }
+        Err(_) => ffi::PERF_ATTR_SIZES[0],
+    }
+}
+
+/// Check if the kernel accepts an event of `pmu` encoded from `terms`, sampled with
+/// `precise_ip`.
+///
+/// Events that may not be opened because of missing permissions are considered accepted.
+fn _probe_terms(pmu: Option<&str>, terms: &str, precise_ip: u64) -> bool {
+    let mut attr = match pmu.map(|p| sysfs::encode_terms(p, terms)) {
+        Some(Ok(attr)) => attr,
+        _ => return false,
+    };
+    if precise_ip > 0 {
+        attr.set_precise_ip(precise_ip);
+        attr.__bindgen_anon_1.sample_period = 10_007;
+    }
+    !matches!(
+        probe_event(&attr),
+        EventSupport::NotSupported | EventSupport::InvalidConfig
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::SwId;
+
+    #[test]
+    fn test_probe_event() {
+        assert_eq!(
+            probe_event(&SwId::TaskClock.into()),
+            EventSupport::Supported
+        );
+        let attr = ffi::perf_event_attr {
+            type_: ffi::perf_type_id::PERF_TYPE_SOFTWARE as _,
+            config: 1 << 40,
+            ..Default::default()
+        };
+        assert!(!probe_event(&attr).is_supported());
+        let attr = ffi::perf_event_attr {
+            type_: 1 << 30,
+            ..Default::default()
+        };
+        assert_eq!(probe_event(&attr), EventSupport::NotSupported);
+    }
+
+    #[test]
+    fn test_max_precise_ip() {
+        let mut attr: ffi::perf_event_attr = SwId::TaskClock.into();
+        attr.__bindgen_anon_1.sample_period = 10_000;
+        let level = max_precise_ip(&attr);
+        assert!(level <= 3);
+        attr.set_precise_ip(level.into());
+        assert!(probe_event(&attr).is_supported());
+        attr.type_ = 1 << 30;
+        assert_eq!(max_precise_ip(&attr), 0);
+    }
+
+    #[test]
+    fn test_kernel_features() {
+        let features = KernelFeatures::probe();
+        assert!(ffi::PERF_ATTR_SIZES.contains(&features.attr_size));
+        assert!(features.attr_size as usize <= std::mem::size_of::<ffi::perf_event_attr>());
+        // Kernels since 3.7 understand at least version 3 of the structure
+        assert!(features.attr_size >= ffi::PERF_ATTR_SIZES[3]);
+        assert!(features.name_term || !features.offcore_rsp);
+        assert_eq!(features, KernelFeatures::probe());
+    }
+}
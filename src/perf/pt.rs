@@ -0,0 +1,169 @@
+//! Capturing Intel Processor Trace (PT) data together with the records needed to decode it.
+
+use crate::perf::{AuxRecord, ParsedRecord, PerfEvent};
+use crate::{Error, Result};
+use std::io::Write;
+
+/// Name of the PMU recording Intel PT.
+pub const INTEL_PT_PMU: &str = "intel_pt";
+
+/// Chunk of trace data copied out of the AUX area of an event.
+#[derive(Debug, Clone)]
+pub struct PtChunk {
+    /// Record announcing the data, with its position in the trace and its flags.
+    pub record: AuxRecord,
+    /// Trace data.
+    pub data: Vec<u8>,
+}
+
+/// Intel PT data captured with an event opened by `PerfEventBuilder::intel_pt`.
+///
+/// Decoding the trace requires the sideband records describing the traced processes alongside the
+/// trace data, i.e., the memory mappings of the binaries and the process names. These are kept in
+/// the order they were written, so they can be matched to the trace by their timestamps.
+#[derive(Debug, Default)]
+pub struct PtTrace {
+    /// Chunks of trace data in the order they were written.
+    pub chunks: Vec<PtChunk>,
+    /// Sideband records, e.g., `Mmap2`, `Comm` and `ItraceStart`, in the order they were written.
+    pub sideband: Vec<ParsedRecord>,
+}
+
+impl PtTrace {
+    /// Create an empty trace.
+    pub fn new() -> Self {
+        PtTrace::default()
+    }
+
+    /// Check if the intel_pt PMU is available on the local machine.
+    pub fn is_supported() -> bool {
+        crate::perf::sysfs::pmu_type(INTEL_PT_PMU).is_ok()
+    }
+
+    /// Move the records and trace data written by `evt` since the last call into this trace.
+    ///
+    /// Returns the number of added chunks of trace data. Fails if `evt` has no ring buffer.
+    pub fn collect(&mut self, evt: &mut PerfEvent) -> Result<usize> {
+        let attr = evt.attr;
+        let rb = evt.ring_buffer.as_mut().ok_or(Error::NoneError)?;
+        let records = rb.drain(&attr).collect::<Result<Vec<_>>>()?;
+        let mut added = 0;
+        for record in records {
+            if self._add(record, |aux| rb.read_aux(aux)) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Add `record`, reading the trace data it announces with `read_aux`.
+    ///
+    /// Returns `true` if a chunk of trace data was added. Records that are not needed to decode
+    /// the trace, e.g., samples, are dropped.
+    fn _add<F: FnOnce(&AuxRecord) -> Vec<u8>>(
+        &mut self,
+        record: ParsedRecord,
+        read_aux: F,
+    ) -> bool {
+        match record {
+            ParsedRecord::Aux(aux) => {
+                let data = read_aux(&aux);
+                self.chunks.push(PtChunk { record: aux, data });
+                return true;
+            }
+            ParsedRecord::Mmap(_)
+            | ParsedRecord::Mmap2(_)
+            | ParsedRecord::Comm(_)
+            | ParsedRecord::Fork(_)
+            | ParsedRecord::Exit(_)
+            | ParsedRecord::ContextSwitch(_)
+            | ParsedRecord::ItraceStart(_)
+            | ParsedRecord::Ksymbol(_)
+            | ParsedRecord::Lost(_) => self.sideband.push(record),
+            _ => {}
+        }
+        false
+    }
+
+    /// Get the total size of the trace data in bytes.
+    pub fn data_len(&self) -> usize {
+        self.chunks.iter().map(|c| c.data.len()).sum()
+    }
+
+    /// Check if any chunk of trace data is incomplete, i.e., it was truncated or corrupted.
+    pub fn has_gaps(&self) -> bool {
+        self.chunks
+            .iter()
+            .any(|c| c.record.is_truncated() || c.record.has_collision())
+    }
+
+    /// Write the trace data of all chunks to `out`, e.g., to decode it with `libipt`.
+    pub fn write_data<W: Write>(&self, mut out: W) -> Result<()> {
+        for chunk in self.chunks.iter() {
+            out.write_all(&chunk.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Counter;
+    use crate::perf::{CommRecord, LostRecord};
+
+    #[test]
+    fn test_pt_trace() {
+        let mut trace = PtTrace::new();
+        let comm = ParsedRecord::Comm(CommRecord {
+            pid: 1,
+            tid: 1,
+            comm: "test".into(),
+            sample_id: None,
+        });
+        assert!(!trace._add(comm, |_| unreachable!()));
+        let aux = |offset, size, flags| {
+            ParsedRecord::Aux(AuxRecord {
+                aux_offset: offset,
+                aux_size: size,
+                flags,
+                sample_id: None,
+            })
+        };
+        assert!(trace._add(aux(0, 3, 0), |a| vec![1; a.aux_size as usize]));
+        assert!(!trace.has_gaps());
+        assert!(trace._add(aux(3, 2, 0x1), |a| vec![2; a.aux_size as usize]));
+        assert!(!trace._add(ParsedRecord::UnknownEvent, |_| unreachable!()));
+        let lost = ParsedRecord::Lost(LostRecord {
+            id: 0,
+            num: 1,
+            sample_id: None,
+        });
+        assert!(!trace._add(lost, |_| unreachable!()));
+
+        assert_eq!(trace.chunks.len(), 2);
+        assert_eq!(trace.sideband.len(), 2);
+        assert_eq!(trace.data_len(), 5);
+        assert!(trace.has_gaps());
+        let mut out = Vec::new();
+        trace.write_data(&mut out).unwrap();
+        assert_eq!(out, vec![1, 1, 1, 2, 2]);
+
+        if !PtTrace::is_supported() {
+            assert!(PerfEvent::build().intel_pt("").is_err());
+            return;
+        }
+        let mut evt = PerfEvent::build()
+            .intel_pt("")
+            .unwrap()
+            .start_disabled()
+            .open(None)
+            .unwrap();
+        evt.enable().unwrap();
+        let _ = (0..1000).sum::<u64>();
+        evt.disable().unwrap();
+        let mut trace = PtTrace::new();
+        trace.collect(&mut evt).unwrap();
+        assert!(trace.data_len() > 0);
+    }
+}
@@ -0,0 +1,583 @@
+//! Decoding Intel Processor Trace (PT) data into packets and branches.
+//!
+//! See "Intel 64 and IA-32 Architectures Software Developers Manual Volume 3C", Section 33.4
+//! "Trace Packets and Data Types" for the format of the packets.
+
+use crate::{Error, Result};
+use std::collections::VecDeque;
+
+/// Packet Stream Boundary, at which decoding can start.
+const PSB: [u8; 16] = [
+    0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82,
+];
+
+/// Packet of Intel PT trace data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PtPacket {
+    /// Padding.
+    Pad,
+    /// Outcomes of conditional branches from the oldest to the most recent one, `true` if taken.
+    Tnt(Vec<bool>),
+    /// Target of an indirect branch, far branch or exception, `None` if suppressed.
+    Tip(Option<u64>),
+    /// Tracing was enabled, with the IP of the first traced instruction.
+    TipPge(Option<u64>),
+    /// Tracing was disabled, with the target IP of the last traced branch.
+    TipPgd(Option<u64>),
+    /// Source IP of an asynchronous event, e.g., an interrupt, or the current IP after a `Psb`.
+    Fup(Option<u64>),
+    /// Packet Stream Boundary, after which the IP compression is reset.
+    Psb,
+    /// End of the status packets following a `Psb`.
+    PsbEnd,
+    /// Execution mode, i.e., `CS.L` for 64-bit mode and `CS.D` for a default operand size of 32.
+    ModeExec {
+        /// `CS.L` is set.
+        mode64: bool,
+        /// `CS.D` is set.
+        default32: bool,
+    },
+    /// Transactional state of TSX.
+    ModeTsx {
+        /// Executing in a transaction.
+        in_tx: bool,
+        /// The transaction was aborted.
+        abort: bool,
+    },
+    /// Write of CR3, i.e., a change of the address space.
+    Pip {
+        /// New value of CR3.
+        cr3: u64,
+        /// Executing in VMX non-root operation, i.e., in a guest.
+        non_root: bool,
+    },
+    /// Value of the timestamp counter.
+    Tsc(u64),
+    /// Bits of the crystal clock, emitted periodically.
+    Mtc(u8),
+    /// Relation between the timestamp counter and the crystal clock.
+    Tma {
+        /// Low bits of the crystal clock.
+        ctc: u16,
+        /// Number of cycles of the fast counter since the crystal clock ticked.
+        fast_counter: u16,
+    },
+    /// Number of core clock cycles since the last `Cyc`.
+    Cyc(u64),
+    /// Ratio of the core clock to the bus clock.
+    Cbr(u8),
+    /// The internal buffers overflowed and packets were lost.
+    Ovf,
+    /// Tracing was stopped by an address filter or the end of the output region.
+    TraceStop,
+    /// Base address of the VMCS of the current virtual machine.
+    Vmcs(u64),
+    /// Maintenance packet with a model specific payload.
+    Mnt(u64),
+    /// Value written with `PTWRITE`, followed by a `Fup` with its IP if `ip` is set.
+    Ptw {
+        /// Written value.
+        payload: u64,
+        /// A `Fup` with the IP of the instruction follows.
+        ip: bool,
+    },
+    /// Power event packet, i.e., `MWAIT`, `PWRE`, `PWRX` or `EXSTOP`, identified by its extended
+    /// opcode with its raw payload.
+    Power {
+        /// Second byte of the packet.
+        opcode: u8,
+        /// Payload following the opcode.
+        payload: u64,
+    },
+}
+
+/// Decoder of the packets of Intel PT trace data, e.g., of `PtChunk::data`.
+///
+/// Decoding starts at the first Packet Stream Boundary, as the preceding packets cannot be
+/// decoded without knowing the compressed IPs. Each packet is returned with its offset in the
+/// data. After a malformed packet, decoding continues at the next Packet Stream Boundary.
+#[derive(Debug, Clone)]
+pub struct PtDecoder<'a> {
+    /// Trace data.
+    data: &'a [u8],
+    /// Offset of the next packet.
+    pos: usize,
+    /// Last IP used to decompress the IPs of `Tip`, `Fup` and related packets.
+    last_ip: u64,
+    /// A Packet Stream Boundary was found.
+    synced: bool,
+}
+
+impl<'a> PtDecoder<'a> {
+    /// Create a decoder of the trace `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        PtDecoder {
+            data,
+            pos: 0,
+            last_ip: 0,
+            synced: false,
+        }
+    }
+
+    /// Get the offset of the next packet.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Move to the next Packet Stream Boundary at or after the current position.
+    ///
+    /// The last IP is reset, as the IPs following a PSB are not compressed against earlier ones.
+    /// Returns `false` if there is none.
+    pub fn sync(&mut self) -> bool {
+        let found = self.data[self.pos.min(self.data.len())..]
+            .windows(PSB.len())
+            .position(|w| w == PSB);
+        match found {
+            Some(off) => {
+                self.pos += off;
+                self.last_ip = 0;
+                self.synced = true;
+            }
+            None => self.pos = self.data.len(),
+        }
+        self.synced
+    }
+}
+
+impl<'a> Iterator for PtDecoder<'a> {
+    type Item = Result<(usize, PtPacket)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.synced && !self.sync() {
+            return None;
+        }
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let offset = self.pos;
+        match _decode_packet(&self.data[offset..], &mut self.last_ip) {
+            Ok((packet, len)) => {
+                if packet == PtPacket::Psb {
+                    self.last_ip = 0;
+                }
+                self.pos += len;
+                Some(Ok((offset, packet)))
+            }
+            Err(reason) => {
+                self.pos += 1;
+                self.synced = false;
+                Some(Err(Error::PtDecode { offset, reason }))
+            }
+        }
+    }
+}
+
+/// Decode the packet at the start of `data`, updating the IP compression state `last_ip`.
+///
+/// Returns the packet with its length, or the reason it is malformed.
+fn _decode_packet(
+    data: &[u8],
+    last_ip: &mut u64,
+) -> std::result::Result<(PtPacket, usize), String> {
+    let payload = |start: usize, len: usize| -> std::result::Result<u64, String> {
+        let bytes = data
+            .get(start..start + len)
+            .ok_or_else(|| "truncated packet".to_string())?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, &b| (acc << 8) | u64::from(b)))
+    };
+    let byte = data[0];
+    let packet = match byte {
+        0x00 => (PtPacket::Pad, 1),
+        0x02 => return _decode_extended(data, payload),
+        0x19 => (PtPacket::Tsc(payload(1, 7)?), 8),
+        0x59 => (PtPacket::Mtc(payload(1, 1)? as u8), 2),
+        0x99 => {
+            let mode = payload(1, 1)?;
+            let bit = |b: u32| mode & (1 << b) != 0;
+            let packet = match mode >> 5 {
+                0 => PtPacket::ModeExec {
+                    mode64: bit(0),
+                    default32: bit(1),
+                },
+                1 => PtPacket::ModeTsx {
+                    in_tx: bit(0),
+                    abort: bit(1),
+                },
+                leaf => return Err(format!("unknown MODE leaf {}", leaf)),
+            };
+            (packet, 2)
+        }
+        b if b & 0x1 == 0 => {
+            // Short TNT with the outcomes below the highest set (stop) bit
+            (PtPacket::Tnt(_tnt_bits(u64::from(b >> 1))), 1)
+        }
+        b if b & 0x3 == 0x3 => _decode_cyc(data)?,
+        b => {
+            let len = match b >> 5 {
+                0 => 0,
+                1 => 2,
+                2 => 4,
+                3 | 4 => 6,
+                6 => 8,
+                ip_bytes => return Err(format!("reserved IPBytes {}", ip_bytes)),
+            };
+            let ip = if len == 0 {
+                None
+            } else {
+                let ip = _decompress_ip(b >> 5, payload(1, len)?, *last_ip);
+                *last_ip = ip;
+                Some(ip)
+            };
+            let packet = match b & 0x1f {
+                0x0d => PtPacket::Tip(ip),
+                0x11 => PtPacket::TipPge(ip),
+                0x01 => PtPacket::TipPgd(ip),
+                0x1d => PtPacket::Fup(ip),
+                _ => return Err(format!("unknown opcode {:#x}", b)),
+            };
+            (packet, 1 + len)
+        }
+    };
+    Ok(packet)
+}
+
+/// Decode the packet with an extended opcode at the start of `data`, reading its fields with
+/// `payload`.
+fn _decode_extended<F>(data: &[u8], payload: F) -> std::result::Result<(PtPacket, usize), String>
+where
+    F: Fn(usize, usize) -> std::result::Result<u64, String>,
+{
+    let opcode = payload(1, 1)? as u8;
+    let packet = match opcode {
+        0xa3 => {
+            let bits = payload(2, 6)?;
+            if bits == 0 {
+                return Err("TNT without stop bit".into());
+            }
+            (PtPacket::Tnt(_tnt_bits(bits)), 8)
+        }
+        0x43 => {
+            let bits = payload(2, 6)?;
+            let packet = PtPacket::Pip {
+                cr3: (bits >> 1) << 5,
+                non_root: bits & 0x1 != 0,
+            };
+            (packet, 8)
+        }
+        0x82 => {
+            if data.get(..PSB.len()) != Some(&PSB[..]) {
+                return Err("incomplete PSB".into());
+            }
+            (PtPacket::Psb, PSB.len())
+        }
+        0x23 => (PtPacket::PsbEnd, 2),
+        0x03 => (PtPacket::Cbr(payload(2, 1)? as u8), 4),
+        0x73 => {
+            let packet = PtPacket::Tma {
+                ctc: payload(2, 2)? as u16,
+                fast_counter: (payload(5, 2)? & 0x1ff) as u16,
+            };
+            (packet, 7)
+        }
+        0xf3 => (PtPacket::Ovf, 2),
+        0x83 => (PtPacket::TraceStop, 2),
+        0xc8 => (PtPacket::Vmcs(payload(2, 5)? << 12), 7),
+        0xc3 => {
+            if payload(2, 1)? != 0x88 {
+                return Err("malformed MNT".into());
+            }
+            (PtPacket::Mnt(payload(3, 8)?), 11)
+        }
+        op if op & 0x1f == 0x12 => {
+            let len = match (op >> 5) & 0x3 {
+                0 => 4,
+                1 => 8,
+                size => return Err(format!("reserved PTW payload size {}", size)),
+            };
+            let packet = PtPacket::Ptw {
+                payload: payload(2, len)?,
+                ip: op & 0x80 != 0,
+            };
+            (packet, 2 + len)
+        }
+        0xc2 | 0x22 | 0xa2 | 0x62 | 0xe2 => {
+            let len = match opcode {
+                0xc2 => 8,
+                0x22 => 2,
+                0xa2 => 5,
+                _ => 0,
+            };
+            let packet = PtPacket::Power {
+                opcode,
+                payload: payload(2, len)?,
+            };
+            (packet, 2 + len)
+        }
+        op => return Err(format!("unknown extended opcode {:#x}", op)),
+    };
+    Ok(packet)
+}
+
+/// Decode the CYC packet at the start of `data`, whose bytes hold 5 and then 7 bits of the
+/// cycle count until one without the `Exp` bit.
+fn _decode_cyc(data: &[u8]) -> std::result::Result<(PtPacket, usize), String> {
+    let mut cycles = u64::from(data[0] >> 3);
+    let (mut shift, mut len, mut exp) = (5, 1, data[0] & 0x4 != 0);
+    while exp {
+        let b = *data.get(len).ok_or("truncated packet")?;
+        if shift > 63 {
+            return Err("CYC overflows 64 bits".into());
+        }
+        cycles |= u64::from(b >> 1) << shift;
+        shift += 7;
+        len += 1;
+        exp = b & 0x1 != 0;
+    }
+    Ok((PtPacket::Cyc(cycles), len))
+}
+
+/// Get the outcomes in `bits` below its highest set (stop) bit, starting with the oldest one.
+fn _tnt_bits(bits: u64) -> Vec<bool> {
+    let stop = 63 - bits.leading_zeros();
+    (0..stop).rev().map(|b| bits & (1 << b) != 0).collect()
+}
+
+/// Get the IP from the compressed `ip` of a packet with `ip_bytes` and the last IP `last_ip`.
+fn _decompress_ip(ip_bytes: u8, ip: u64, last_ip: u64) -> u64 {
+    match ip_bytes {
+        1 => (last_ip & !0xffff) | ip,
+        2 => (last_ip & !0xffff_ffff) | ip,
+        3 => (((ip << 16) as i64) >> 16) as u64,
+        4 => (last_ip & !0xffff_ffff_ffff) | ip,
+        _ => ip,
+    }
+}
+
+/// Change of the control flow or of the tracing state reconstructed from Intel PT packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtFlowEvent {
+    /// Tracing was enabled at the IP.
+    Enabled(u64),
+    /// Tracing was disabled, after the instruction at the IP if known.
+    Disabled(Option<u64>),
+    /// A conditional branch was taken (`true`) or not.
+    Conditional(bool),
+    /// An indirect or far branch jumped to the IP.
+    Indirect(u64),
+    /// An asynchronous event, e.g., an interrupt, moved the control flow from the IP `from` to
+    /// `to`, if known.
+    Async {
+        /// IP of the first instruction not completed.
+        from: u64,
+        /// Target IP, e.g., of the interrupt handler.
+        to: Option<u64>,
+    },
+    /// The address space was switched to the one with the CR3 value.
+    AddressSpace(u64),
+    /// Packets were lost.
+    Overflow,
+    /// Value of the timestamp counter.
+    Timestamp(u64),
+}
+
+/// Reconstruction of the branches taken by the traced code from the packets of a `PtDecoder`.
+///
+/// Conditional branches are only reported with their outcome, as locating them requires
+/// disassembling the traced binaries.
+#[derive(Debug, Clone)]
+pub struct PtFlow<'a> {
+    /// Decoder of the packets.
+    decoder: PtDecoder<'a>,
+    /// Events reconstructed from the last packet and not yet returned.
+    pending: VecDeque<PtFlowEvent>,
+    /// Source IP of an asynchronous event, waiting for its target.
+    fup: Option<u64>,
+    /// The current packet is a status packet between a `Psb` and a `PsbEnd`.
+    in_psb: bool,
+    /// The next `Fup` holds the IP of a `Ptw` or power event instead of a branch source.
+    skip_fup: bool,
+}
+
+impl<'a> PtFlow<'a> {
+    /// Reconstruct the branches of the trace `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        PtFlow {
+            decoder: PtDecoder::new(data),
+            pending: VecDeque::new(),
+            fup: None,
+            in_psb: false,
+            skip_fup: false,
+        }
+    }
+
+    /// Update the state with `packet`, queuing the events it causes.
+    fn _add(&mut self, packet: PtPacket) {
+        let event = match packet {
+            PtPacket::Psb => {
+                self.in_psb = true;
+                self.fup = None;
+                None
+            }
+            PtPacket::PsbEnd => {
+                self.in_psb = false;
+                None
+            }
+            PtPacket::Fup(ip) if !self.in_psb && !self.skip_fup => {
+                self.fup = ip;
+                None
+            }
+            PtPacket::Fup(_) => {
+                self.skip_fup = false;
+                None
+            }
+            PtPacket::Ptw { ip, .. } => {
+                self.skip_fup = ip;
+                None
+            }
+            PtPacket::Power { opcode: 0xe2, .. } => {
+                self.skip_fup = true;
+                None
+            }
+            PtPacket::Tnt(bits) => {
+                self.fup = None;
+                self.pending
+                    .extend(bits.into_iter().map(PtFlowEvent::Conditional));
+                None
+            }
+            PtPacket::Tip(ip) => match (self.fup.take(), ip) {
+                (Some(from), to) => Some(PtFlowEvent::Async { from, to }),
+                (None, Some(ip)) => Some(PtFlowEvent::Indirect(ip)),
+                (None, None) => None,
+            },
+            PtPacket::TipPge(ip) => {
+                self.fup = None;
+                ip.map(PtFlowEvent::Enabled)
+            }
+            PtPacket::TipPgd(ip) => Some(PtFlowEvent::Disabled(self.fup.take().or(ip))),
+            PtPacket::Pip { cr3, .. } => Some(PtFlowEvent::AddressSpace(cr3)),
+            PtPacket::Ovf => {
+                self.fup = None;
+                Some(PtFlowEvent::Overflow)
+            }
+            PtPacket::Tsc(tsc) => Some(PtFlowEvent::Timestamp(tsc)),
+            _ => None,
+        };
+        self.pending.extend(event);
+    }
+}
+
+impl<'a> Iterator for PtFlow<'a> {
+    type Item = Result<PtFlowEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            match self.decoder.next()? {
+                Ok((_, packet)) => self._add(packet),
+                Err(e) => {
+                    self.fup = None;
+                    self.in_psb = false;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pt_decoder() {
+        let mut data = vec![0xff, 0xff];
+        data.extend_from_slice(&PSB);
+        data.extend_from_slice(&[0x99, 0x01]);
+        data.extend_from_slice(&[0xdd, 0x00, 0x10, 0x40, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0x02, 0x23]);
+        data.extend_from_slice(&[0x19, 0x34, 0x12, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0x31, 0x00, 0x20]);
+        data.push(0x0c);
+        data.extend_from_slice(&[0x02, 0xa3, 0x0b, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0x2f, 0x06]);
+        data.extend_from_slice(&[0x6d, 0x10, 0, 0, 0, 0, 0x80]);
+        data.extend_from_slice(&[0x5d, 0x20, 0, 0, 0]);
+        data.extend_from_slice(&[0x01, 0x00]);
+        data.extend_from_slice(&[0x02, 0x43, 0x01, 0x01, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0x02, 0x03, 0x20, 0x00]);
+        data.extend_from_slice(&[0x02, 0xf3]);
+        data.extend_from_slice(&[0x19, 0x00]);
+
+        let packets: Vec<_> = PtDecoder::new(&data).collect();
+        let expected = vec![
+            PtPacket::Psb,
+            PtPacket::ModeExec {
+                mode64: true,
+                default32: false,
+            },
+            PtPacket::Fup(Some(0x40_1000)),
+            PtPacket::PsbEnd,
+            PtPacket::Tsc(0x1234),
+            PtPacket::TipPge(Some(0x40_2000)),
+            PtPacket::Tnt(vec![true, false]),
+            PtPacket::Tnt(vec![false, true, true]),
+            PtPacket::Cyc(101),
+            PtPacket::Tip(Some(0xffff_8000_0000_0010)),
+            PtPacket::Fup(Some(0xffff_8000_0000_0020)),
+            PtPacket::TipPgd(None),
+            PtPacket::Pad,
+            PtPacket::Pip {
+                cr3: 0x1000,
+                non_root: true,
+            },
+            PtPacket::Cbr(0x20),
+            PtPacket::Ovf,
+        ];
+        assert_eq!(packets.len(), expected.len() + 1);
+        for (p, e) in packets.iter().zip(expected.iter()) {
+            assert_eq!(&p.as_ref().unwrap().1, e);
+        }
+        assert_eq!(packets[0].as_ref().unwrap().0, 2);
+        match packets.last().unwrap() {
+            Err(Error::PtDecode { offset, .. }) => assert_eq!(*offset, data.len() - 2),
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        let events: Vec<_> = PtFlow::new(&data).collect();
+        let expected = vec![
+            PtFlowEvent::Timestamp(0x1234),
+            PtFlowEvent::Enabled(0x40_2000),
+            PtFlowEvent::Conditional(true),
+            PtFlowEvent::Conditional(false),
+            PtFlowEvent::Conditional(false),
+            PtFlowEvent::Conditional(true),
+            PtFlowEvent::Conditional(true),
+            PtFlowEvent::Indirect(0xffff_8000_0000_0010),
+            PtFlowEvent::Disabled(Some(0xffff_8000_0000_0020)),
+            PtFlowEvent::AddressSpace(0x1000),
+            PtFlowEvent::Overflow,
+        ];
+        assert_eq!(events.len(), expected.len() + 1);
+        for (ev, e) in events.iter().zip(expected.iter()) {
+            assert_eq!(ev.as_ref().unwrap(), e);
+        }
+        assert!(events.last().unwrap().is_err());
+        assert!(PtDecoder::new(&[0x19, 0x00]).next().is_none());
+
+        // The last IP is reset by a PSB
+        let mut data = PSB.to_vec();
+        data.extend_from_slice(&[0xcd, 0x00, 0x10, 0, 0, 0, 0x80, 0xff, 0xff]);
+        data.extend_from_slice(&PSB);
+        data.extend_from_slice(&[0x4d, 0x00, 0x20, 0x40, 0x00]);
+        let ips: Vec<_> = PtDecoder::new(&data)
+            .filter_map(|p| match p.unwrap().1 {
+                PtPacket::Tip(ip) => ip,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ips, vec![0xffff_8000_0000_1000, 0x40_2000]);
+    }
+}
@@ -0,0 +1,123 @@
+//! Parsing of symbolic event names as accepted by the `perf` tool, e.g., `cycles:u`.
+
+use crate::perf::ffi;
+use crate::perf::{HwId, SwId};
+use crate::{Error, Result};
+
+/// Parse an event name in the syntax of the `perf` tool into a `perf_event_attr`.
+///
+/// The name is either a generic hardware or software event (e.g., `instructions` or
+/// `task-clock`), or a raw event code in hexadecimal (e.g., `r01c4`). It may be followed by a
+/// colon and the modifiers:
+/// - `u`, `k`, `h`: count in user space, the kernel or the hypervisor (only the given levels are
+///   counted),
+/// - `G`, `H`: count in guest virtual machines or the host,
+/// - `I`: do not count when the CPU is idle,
+/// - `D`: pin the event to the PMU,
+/// - `p`: increase the requested precision of the sampled instruction pointer (up to 3),
+/// - `P`: request the maximum precision.
+pub fn parse_event(spec: &str) -> Result<ffi::perf_event_attr> {
+    let mut parts = spec.splitn(2, ':');
+    let name = parts.next().unwrap_or_default();
+    let mut attr = _parse_name(name).ok_or_else(|| Error::InvalidEvent(spec.into()))?;
+    if let Some(modifiers) = parts.next() {
+        _apply_modifiers(&mut attr, modifiers).ok_or_else(|| Error::InvalidEvent(spec.into()))?;
+    }
+    Ok(attr)
+}
+
+/// Get the attributes of the event `name` without modifiers.
+fn _parse_name(name: &str) -> Option<ffi::perf_event_attr> {
+    if let Some(id) = HwId::from_name(name) {
+        return Some(id.into());
+    }
+    if let Some(id) = SwId::from_name(name) {
+        return Some(id.into());
+    }
+    let code = name.strip_prefix('r')?;
+    if code.is_empty() {
+        return None;
+    }
+    Some(ffi::perf_event_attr {
+        type_: ffi::perf_type_id::PERF_TYPE_RAW as _,
+        config: u64::from_str_radix(code, 16).ok()?,
+        ..Default::default()
+    })
+}
+
+/// Apply the `modifiers` of an event name to `attr`.
+///
+/// Returns `None` if a modifier is unknown.
+fn _apply_modifiers(attr: &mut ffi::perf_event_attr, modifiers: &str) -> Option<()> {
+    let (mut user, mut kernel, mut hv, mut guest, mut host) = (false, false, false, false, false);
+    let mut precise = 0;
+    for m in modifiers.chars() {
+        match m {
+            'u' => user = true,
+            'k' => kernel = true,
+            'h' => hv = true,
+            'G' => guest = true,
+            'H' => host = true,
+            'I' => attr.set_exclude_idle(1),
+            'D' => attr.set_pinned(1),
+            'p' => precise += 1,
+            'P' => precise = 3,
+            _ => return None,
+        }
+    }
+    if precise > 3 {
+        return None;
+    }
+    if user || kernel || hv {
+        attr.set_exclude_user(!user as _);
+        attr.set_exclude_kernel(!kernel as _);
+        attr.set_exclude_hv(!hv as _);
+    }
+    if guest || host {
+        attr.set_exclude_guest(!guest as _);
+        attr.set_exclude_host(!host as _);
+    }
+    attr.set_precise_ip(precise);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        let attr = parse_event("cycles").unwrap();
+        assert_eq!(attr.type_, ffi::perf_type_id::PERF_TYPE_HARDWARE as u32);
+        assert_eq!(
+            attr.config,
+            ffi::perf_hw_id::PERF_COUNT_HW_CPU_CYCLES as u64
+        );
+        assert_eq!(attr.exclude_user(), 0);
+        assert_eq!(attr.exclude_kernel(), 0);
+
+        let attr = parse_event("instructions:kpp").unwrap();
+        assert_eq!(attr.exclude_user(), 1);
+        assert_eq!(attr.exclude_kernel(), 0);
+        assert_eq!(attr.exclude_hv(), 1);
+        assert_eq!(attr.precise_ip(), 2);
+
+        let attr = parse_event("r01c4:u").unwrap();
+        assert_eq!(attr.type_, ffi::perf_type_id::PERF_TYPE_RAW as u32);
+        assert_eq!(attr.config, 0x01c4);
+        assert_eq!(attr.exclude_user(), 0);
+        assert_eq!(attr.exclude_kernel(), 1);
+
+        let attr = parse_event("task-clock:GP").unwrap();
+        assert_eq!(attr.type_, ffi::perf_type_id::PERF_TYPE_SOFTWARE as u32);
+        assert_eq!(attr.exclude_guest(), 0);
+        assert_eq!(attr.exclude_host(), 1);
+        assert_eq!(attr.precise_ip(), 3);
+
+        assert!(parse_event("cycles:x").is_err());
+        assert!(parse_event("cycles:pppp").is_err());
+        assert!(parse_event("r").is_err());
+        assert!(parse_event("rxyz").is_err());
+        assert!(parse_event("unknown").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! One-shot counting of events, similar to `perf stat`.
+
+use crate::api::Counter;
+use crate::perf::ffi::perf_event_attr;
+use crate::perf::{PerfCommand, PerfEvent, PerfEventValue};
+use crate::registry::PmuEvent;
+use crate::{Error, Result, ScaledValue};
+use nix::libc;
+use nix::sys::wait::WaitStatus;
+use std::time::{Duration, Instant};
+
+/// What is measured by `stat` and for how long.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Measure all threads of the running process with the given ID for a fixed duration.
+    Process(libc::pid_t, Duration),
+    /// Measure all processes on all CPUs for a fixed duration.
+    System(Duration),
+    /// Run the command, given as program followed by its arguments, until it exits.
+    Command(Vec<String>),
+}
+
+/// Count of a single event measured by `stat`.
+#[derive(Debug, Clone)]
+pub struct StatCount {
+    /// Name of the event.
+    pub name: String,
+    /// Value read from the counters.
+    pub raw: u64,
+    /// Value scaled to the whole measurement, in case the event was multiplexed.
+    pub scaled: u64,
+    /// Percentage of the measurement during which the event was actually counting.
+    ///
+    /// This is below 100 if the event was multiplexed with other events.
+    pub running_pct: f64,
+}
+
+impl From<(String, PerfEventValue)> for StatCount {
+    fn from((name, val): (String, PerfEventValue)) -> Self {
+        let running_pct = if val.time_enabled == 0 {
+            0.0
+        } else {
+            100.0 * val.time_running as f64 / val.time_enabled as f64
+        };
+        StatCount {
+            name,
+            raw: val.raw_value(),
+            scaled: val.scaled_value(),
+            running_pct,
+        }
+    }
+}
+
+/// Outcome of `stat`.
+#[derive(Debug, Clone)]
+pub struct StatReport {
+    /// Counts of the events in the order they were passed to `stat`.
+    pub counts: Vec<StatCount>,
+    /// Wall clock time of the measurement.
+    pub elapsed: Duration,
+    /// Exit status of the command, if the target was a command.
+    pub status: Option<WaitStatus>,
+}
+
+/// Count `events` on `target` and report their totals.
+///
+/// Every event is opened as an individual counter, so the kernel multiplexes them if there are
+/// more events than counters. Events with multiple `perf_event_attrs`, e.g., uncore events with
+/// one PMU per box, report the sum over all of them. Metric events are not supported.
+pub fn stat(events: &[PmuEvent], target: Target) -> Result<StatReport> {
+    let attrs = events
+        .iter()
+        .map(|e| {
+            if e.is_metric {
+                return Err(Error::InvalidEvent(format!("{} is a metric", e.name)));
+            }
+            Ok((e.name.clone(), e.to_perf_event_attr(None)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    _stat(attrs, target)
+}
+
+/// Count the events `attrs`, each given by its name and the `perf_event_attrs` to be summed.
+fn _stat(attrs: Vec<(String, Vec<perf_event_attr>)>, target: Target) -> Result<StatReport> {
+    let start = Instant::now();
+    let mut status = None;
+    // Values of the individual counters in the same order as `attrs`
+    let values: Vec<PerfEventValue> = match target {
+        Target::Process(pid, duration) => {
+            let procs = attrs
+                .iter()
+                .flat_map(|(name, a)| a.iter().map(move |a| (name, a)))
+                .map(|(name, a)| PerfEvent::build().name(name.clone()).attach(pid, Some(*a)))
+                .collect::<Result<Vec<_>>>()?;
+            std::thread::sleep(duration);
+            procs
+                .into_iter()
+                .map(|p| Ok(p.detach()?.remove(0)))
+                .collect::<Result<_>>()?
+        }
+        Target::System(duration) => {
+            let evts = attrs
+                .iter()
+                .flat_map(|(name, a)| a.iter().map(move |a| (name, a)))
+                .map(|(name, a)| PerfEvent::build().name(name.clone()).open_per_cpu(Some(*a)))
+                .collect::<Result<Vec<_>>>()?;
+            std::thread::sleep(duration);
+            evts.iter()
+                .map(|e| {
+                    e.disable()?;
+                    e.sum()
+                })
+                .collect::<Result<_>>()?
+        }
+        Target::Command(argv) => {
+            let mut argv = argv.into_iter();
+            let program = argv
+                .next()
+                .ok_or_else(|| Error::InvalidEvent("Empty command".into()))?;
+            let mut cmd = PerfCommand::new(program).args(argv);
+            for (name, a) in attrs.iter() {
+                for a in a.iter() {
+                    cmd = cmd.event(PerfEvent::build().name(name.clone()), Some(*a));
+                }
+            }
+            let res = cmd.run()?;
+            status = Some(res.status);
+            res.values()?
+        }
+    };
+    let elapsed = start.elapsed();
+
+    let mut values = values.into_iter();
+    let counts = attrs
+        .into_iter()
+        .map(|(name, a)| {
+            let total = PerfEventValue::_sum(values.by_ref().take(a.len()));
+            StatCount::from((name, total))
+        })
+        .collect();
+    Ok(StatReport {
+        counts,
+        elapsed,
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::SwId;
+
+    fn sw_attrs() -> Vec<(String, Vec<perf_event_attr>)> {
+        vec![
+            ("task-clock".into(), vec![SwId::TaskClock.into()]),
+            (
+                "faults".into(),
+                vec![SwId::PageFaults.into(), SwId::PageFaults.into()],
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_stat_command() {
+        let cmd = vec!["sh".into(), "-c".into(), "exit 3".into()];
+        let report = _stat(sw_attrs(), Target::Command(cmd)).unwrap();
+        match report.status {
+            Some(WaitStatus::Exited(_, code)) => assert_eq!(code, 3),
+            _ => panic!("Command did not exit normally"),
+        }
+        assert_eq!(report.counts.len(), 2);
+        assert_eq!(report.counts[0].name, "task-clock");
+        assert!(report.counts[0].raw > 0);
+        assert!(report.counts[1].raw > 0);
+        assert!((report.counts[0].running_pct - 100.0).abs() < 1e-6);
+        assert!(_stat(sw_attrs(), Target::Command(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_stat_process() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("1")
+            .spawn()
+            .unwrap();
+        let target = Target::Process(child.id() as _, Duration::from_millis(10));
+        let report = _stat(sw_attrs(), target).unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(report.status.is_none());
+        assert!(report.elapsed >= Duration::from_millis(10));
+        assert_eq!(report.counts.len(), 2);
+    }
+}
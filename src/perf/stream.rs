@@ -0,0 +1,79 @@
+//! Asynchronous consumption of ring buffer records using `tokio`.
+//!
+//! Requires the `async` feature.
+
+use crate::perf::{ParsedRecord, PerfEvent};
+use crate::{Error, Result};
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+
+/// Stream of the records written to the ring buffer of a sampled `PerfEvent`.
+///
+/// The stream is registered with the `tokio` reactor and is woken up when the kernel signals that
+/// new records are available, so no thread has to block in `poll`. Each record is marked as read
+/// as soon as it is yielded. The stream ends once the monitored process exits and all remaining
+/// records have been read.
+///
+/// Must be created from within a `tokio` runtime.
+#[derive(Debug)]
+pub struct RecordStream {
+    /// Event whose ring buffer is being consumed.
+    inner: AsyncFd<PerfEvent>,
+}
+
+impl RecordStream {
+    /// Create a new stream consuming the ring buffer of `event`.
+    ///
+    /// Fails if `event` is not sampled, i.e., does not have a ring buffer.
+    pub fn new(event: PerfEvent) -> Result<Self> {
+        if event.ring_buffer.is_none() {
+            return Err(Error::InvalidEvent(format!(
+                "{} is not sampled",
+                crate::Counter::name(&event)
+            )));
+        }
+        Ok(RecordStream {
+            inner: AsyncFd::new(event)?,
+        })
+    }
+
+    /// Get the event whose ring buffer is being consumed.
+    pub fn event(&self) -> &PerfEvent {
+        self.inner.get_ref()
+    }
+
+    /// Stop consuming the ring buffer and return the underlying event.
+    pub fn into_inner(self) -> PerfEvent {
+        self.inner.into_inner()
+    }
+
+    /// Parse and consume the oldest record in the ring buffer, if any.
+    fn _next_record(&mut self) -> Option<Result<ParsedRecord>> {
+        let event = self.inner.get_mut();
+        let attr = event.attr;
+        event.ring_buffer.as_mut()?.drain(&attr).next()
+    }
+}
+
+impl Stream for RecordStream {
+    type Item = Result<ParsedRecord>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(record) = this._next_record() {
+                return Poll::Ready(Some(record));
+            }
+            if crate::Counter::is_closed(this.inner.get_ref()).unwrap_or(true) {
+                return Poll::Ready(None);
+            }
+            match this.inner.poll_read_ready(cx) {
+                Poll::Ready(Ok(mut guard)) => guard.clear_ready(),
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Utilities to query dynamic PMUs registered with the kernel through sysfs.
+
+use crate::perf::ffi;
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Directory in which the kernel lists all registered PMUs.
+const EVENT_SOURCE_DEVICES: &str = "/sys/bus/event_source/devices";
+
+/// Get the sysfs directory of the PMU `pmu`.
+fn _pmu_dir(pmu: &str) -> PathBuf {
+    PathBuf::from(EVENT_SOURCE_DEVICES).join(pmu)
+}
+
+/// Get the value to be used as the `type_` field of a `perf_event_attr` for PMU `pmu`.
+pub fn pmu_type(pmu: &str) -> Result<u32> {
+    let path = _pmu_dir(pmu).join("type");
+    if !path.exists() {
+        return Err(Error::KernelCapabilityError);
+    }
+    Ok(std::fs::read_to_string(path)?.trim().parse()?)
+}
+
+/// Get the CPUs on which events of PMU `pmu` should be opened.
+///
+/// Uncore PMUs list one CPU per package or die in their `cpumask`, and on hybrid systems each core
+/// PMU lists the CPUs of its type. Otherwise all online CPUs are returned.
+pub fn pmu_cpus(pmu: &str) -> Result<Vec<i32>> {
+    for name in &["cpumask", "cpus"] {
+        let path = _pmu_dir(pmu).join(name);
+        if path.exists() {
+            return crate::util::parse_cpu_list(&std::fs::read_to_string(path)?);
+        }
+    }
+    crate::util::online_cpus()
+}
+
+/// Check if the PMU `pmu` only counts system-wide, i.e., on the CPUs listed in its `cpumask`.
+///
+/// This holds for uncore PMUs and for PMUs such as `power` or `cstate_core`, which count all
+/// processes and cannot exclude user or kernel space.
+pub fn pmu_is_system_wide(pmu: &str) -> bool {
+    _pmu_dir(pmu).join("cpumask").exists()
+}
+
+/// Get the raw format specification of the field `name` of PMU `pmu`, e.g., `config:0-7`.
+pub fn pmu_format(pmu: &str, name: &str) -> Result<String> {
+    let path = _pmu_dir(pmu).join("format").join(name);
+    Ok(std::fs::read_to_string(path)?.trim().into())
+}
+
+/// Parse a format specification for a single bit in `config`, e.g., `config:0`.
+fn _parse_config_bit(spec: &str) -> Result<u32> {
+    match spec.split(':').collect::<Vec<_>>().as_slice() {
+        ["config", bit] => Ok(bit.parse()?),
+        _ => Err(Error::InvalidEvent(spec.into())),
+    }
+}
+
+/// Get the bit in `config` corresponding to the single bit flag `name` of PMU `pmu`.
+pub fn pmu_config_bit(pmu: &str, name: &str) -> Result<u32> {
+    _parse_config_bit(&pmu_format(pmu, name)?)
+}
+
+/// Location of a field in the configuration of a `perf_event_attr`, parsed from the format
+/// files of a PMU, e.g., `config:0-7,32-35`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PmuFormat {
+    /// Index of the configuration word, i.e., `0` for `config`, `1` for `config1` and `2` for
+    /// `config2`.
+    pub config: usize,
+    /// Bit ranges holding the field, starting with the least significant bits of its value.
+    pub bits: Vec<RangeInclusive<u32>>,
+}
+
+impl PmuFormat {
+    /// Parse a format specification.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || Error::InvalidEvent(spec.into());
+        let mut parts = spec.trim().splitn(2, ':');
+        let config = match parts.next() {
+            Some("config") => 0,
+            Some("config1") => 1,
+            Some("config2") => 2,
+            _ => return Err(invalid()),
+        };
+        let mut bits = Vec::new();
+        for range in parts.next().ok_or_else(invalid)?.split(',') {
+            let mut bounds = range.splitn(2, '-');
+            let start: u32 = bounds.next().unwrap().parse()?;
+            let end: u32 = match bounds.next() {
+                Some(end) => end.parse()?,
+                None => start,
+            };
+            if start > end || end > 63 {
+                return Err(invalid());
+            }
+            bits.push(start..=end);
+        }
+        Ok(PmuFormat { config, bits })
+    }
+
+    /// Get the number of bits of the field.
+    pub fn width(&self) -> u32 {
+        self.bits.iter().map(|r| r.end() - r.start() + 1).sum()
+    }
+
+    /// Place `value` at the bits of this field in the configuration words `config`.
+    ///
+    /// Fails if `value` does not fit into the field.
+    pub fn apply(&self, value: u64, config: &mut [u64; 3]) -> Result<()> {
+        if self.width() < 64 && value >> self.width() != 0 {
+            return Err(Error::InvalidEvent(format!(
+                "{:#x} does not fit into {} bits",
+                value,
+                self.width()
+            )));
+        }
+        let mut value = value;
+        for range in self.bits.iter() {
+            let width = range.end() - range.start() + 1;
+            let mask = if width == 64 {
+                u64::MAX
+            } else {
+                (1 << width) - 1
+            };
+            config[self.config] &= !(mask << range.start());
+            config[self.config] |= (value & mask) << range.start();
+            value = value.checked_shr(width).unwrap_or(0);
+        }
+        Ok(())
+    }
+}
+
+/// Get the names of all PMUs registered with the kernel.
+pub fn pmus() -> Result<Vec<String>> {
+    let mut pmus: Vec<String> = std::fs::read_dir(EVENT_SOURCE_DEVICES)?
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .collect();
+    pmus.sort();
+    Ok(pmus)
+}
+
+/// Get the names of the core PMUs of a hybrid CPU, e.g., `cpu_core` and `cpu_atom`.
+///
+/// Returns an empty list if the CPU has a single core PMU named `cpu`.
+pub fn hybrid_pmus() -> Result<Vec<String>> {
+    Ok(pmus()?
+        .into_iter()
+        .filter(|p| p.starts_with("cpu_"))
+        .collect())
+}
+
+/// Check if the PMU `name` is a box of the box-replicated PMU `pmu`, e.g., `uncore_cbox_1` of
+/// `uncore_cbox`.
+fn _is_box_of(name: &str, pmu: &str) -> bool {
+    match name.strip_prefix(pmu) {
+        Some("") => true,
+        Some(idx) => match idx.strip_prefix('_') {
+            Some(idx) => !idx.is_empty() && idx.chars().all(|c| c.is_ascii_digit()),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// Get all boxes of the uncore PMU `pmu` sorted by their index, e.g., `uncore_cbox_0`,
+/// `uncore_cbox_1`, ... for `uncore_cbox`.
+///
+/// A PMU that is not replicated is its own single box.
+pub fn pmu_boxes(pmu: &str) -> Result<Vec<String>> {
+    let mut boxes: Vec<String> = pmus()?.into_iter().filter(|p| _is_box_of(p, pmu)).collect();
+    boxes.sort_by_key(|b| (b.len(), b.clone()));
+    Ok(boxes)
+}
+
+/// Get all fields of the configuration of PMU `pmu` listed in its format files.
+pub fn pmu_formats(pmu: &str) -> Result<HashMap<String, PmuFormat>> {
+    let dir = _pmu_dir(pmu).join("format");
+    if !dir.exists() {
+        return Ok(HashMap::new());
+    }
+    let mut formats = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let spec = std::fs::read_to_string(entry.path())?;
+        formats.insert(name, PmuFormat::parse(&spec)?);
+    }
+    Ok(formats)
+}
+
+/// Get the event aliases exported by PMU `pmu` together with their terms, e.g.,
+/// `("tsc", "event=0x00")`.
+///
+/// Files describing the aliases, e.g., their unit or scale, are skipped.
+pub fn pmu_events(pmu: &str) -> Result<Vec<(String, String)>> {
+    let dir = _pmu_dir(pmu).join("events");
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut events = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.contains('.') {
+            continue;
+        }
+        events.push((name, std::fs::read_to_string(entry.path())?.trim().into()));
+    }
+    events.sort();
+    Ok(events)
+}
+
+/// Get the scale and unit of the event alias `event` of PMU `pmu`, e.g., `(2.3e-10, "Joules")`.
+///
+/// Returns `None` if the alias is reported as a plain count.
+pub fn pmu_event_scale(pmu: &str, event: &str) -> Result<Option<(f64, String)>> {
+    let dir = _pmu_dir(pmu).join("events");
+    let unit = dir.join(format!("{}.unit", event));
+    if !unit.exists() {
+        return Ok(None);
+    }
+    let unit = std::fs::read_to_string(unit)?.trim().to_string();
+    let scale = match std::fs::read_to_string(dir.join(format!("{}.scale", event))) {
+        Ok(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidEvent(format!("Invalid scale {}", s.trim())))?,
+        Err(_) => 1.0,
+    };
+    Ok(Some((scale, unit)))
+}
+
+/// Build the configuration holding the values of `fields` placed at the bits described by
+/// `formats`.
+///
+/// Fields named `config`, `config1` or `config2` set the whole configuration word. Fails if a field
+/// is not described by `formats` or if its value does not fit.
+pub fn encode_fields(
+    formats: &HashMap<String, PmuFormat>,
+    fields: &[(&str, u64)],
+) -> Result<ffi::perf_event_attr> {
+    let mut config = [0u64; 3];
+    for &(name, value) in fields.iter() {
+        match name {
+            "config" => config[0] = value,
+            "config1" => config[1] = value,
+            "config2" => config[2] = value,
+            _ => formats
+                .get(name)
+                .ok_or_else(|| Error::InvalidEvent(format!("Unknown field {}", name)))?
+                .apply(value, &mut config)?,
+        }
+    }
+    let mut attr = ffi::perf_event_attr {
+        config: config[0],
+        ..Default::default()
+    };
+    attr.__bindgen_anon_3.config1 = config[1];
+    attr.__bindgen_anon_4.config2 = config[2];
+    Ok(attr)
+}
+
+/// Build the configuration described by `terms` using the fields `formats`.
+fn _encode_terms(
+    formats: &HashMap<String, PmuFormat>,
+    terms: &str,
+) -> Result<ffi::perf_event_attr> {
+    let mut fields = Vec::new();
+    for term in terms.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let mut parts = term.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let value = match parts.next() {
+            Some(v) if v.starts_with("0x") => u64::from_str_radix(&v[2..], 16)?,
+            Some(v) => v.parse()?,
+            None => 1,
+        };
+        fields.push((name, value));
+    }
+    encode_fields(formats, &fields)
+}
+
+/// Build a `perf_event_attr` for PMU `pmu` from `terms` such as `event=0x3c,umask=0x1,edge`.
+///
+/// The terms are placed at the bits advertised in the format files of the PMU. Terms without a
+/// value are set to `1`.
+pub fn encode_terms(pmu: &str, terms: &str) -> Result<ffi::perf_event_attr> {
+    let mut attr = _encode_terms(&pmu_formats(pmu)?, terms)?;
+    attr.type_ = pmu_type(pmu)?;
+    Ok(attr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_bit() {
+        assert_eq!(_parse_config_bit("config:0").unwrap(), 0);
+        assert_eq!(_parse_config_bit("config:63").unwrap(), 63);
+        assert!(_parse_config_bit("config1:0").is_err());
+        assert!(_parse_config_bit("config:0-7").is_err());
+    }
+
+    #[test]
+    fn test_pmu_format() {
+        let fmt = PmuFormat::parse("config:0-7,32-35\n").unwrap();
+        assert_eq!(fmt.config, 0);
+        assert_eq!(fmt.bits, vec![0..=7, 32..=35]);
+        assert_eq!(fmt.width(), 12);
+        let mut config = [0u64; 3];
+        assert!(fmt.apply(0xabc, &mut config).is_ok());
+        assert_eq!(config[0], 0xa_0000_00bc);
+        assert!(fmt.apply(0x1000, &mut config).is_err());
+
+        let fmt = PmuFormat::parse("config1:0-63").unwrap();
+        assert!(fmt.apply(u64::MAX, &mut config).is_ok());
+        assert_eq!(config[1], u64::MAX);
+        assert!(PmuFormat::parse("config3:0").is_err());
+        assert!(PmuFormat::parse("config:7-0").is_err());
+        assert!(PmuFormat::parse("config:64").is_err());
+    }
+
+    #[test]
+    fn test_encode_terms() {
+        let mut formats = HashMap::new();
+        formats.insert("event".into(), PmuFormat::parse("config:0-7").unwrap());
+        formats.insert("umask".into(), PmuFormat::parse("config:8-15").unwrap());
+        formats.insert("edge".into(), PmuFormat::parse("config:18").unwrap());
+        formats.insert("ldlat".into(), PmuFormat::parse("config1:0-15").unwrap());
+        let attr = _encode_terms(&formats, "event=0x3c,umask=0x1,edge,ldlat=3").unwrap();
+        assert_eq!(attr.config, 0x4013c);
+        assert_eq!(unsafe { attr.__bindgen_anon_3.config1 }, 3);
+        assert!(_encode_terms(&formats, "event=0x3c,unknown=1").is_err());
+        assert!(_encode_terms(&formats, "event=0x100").is_err());
+    }
+
+    #[test]
+    fn test_pmu_boxes() {
+        assert!(_is_box_of("uncore_cbox_0", "uncore_cbox"));
+        assert!(_is_box_of("uncore_cbox_12", "uncore_cbox"));
+        assert!(_is_box_of("uncore_imc", "uncore_imc"));
+        assert!(!_is_box_of("uncore_imc_free_running_0", "uncore_imc"));
+        assert!(!_is_box_of("uncore_cbox_", "uncore_cbox"));
+        assert!(!_is_box_of("uncore_cbox0", "uncore_cbox"));
+        assert_eq!(pmu_boxes("software").unwrap(), vec!["software"]);
+    }
+
+    #[test]
+    fn test_sysfs_pmus() {
+        let pmus = pmus().unwrap();
+        assert!(hybrid_pmus().unwrap().iter().all(|p| pmus.contains(p)));
+        assert!(pmus.iter().any(|p| p == "software"));
+        assert!(pmu_formats("software").unwrap().is_empty());
+        assert!(pmu_events("software").unwrap().is_empty());
+        assert!(pmu_event_scale("software", "cpu-clock").unwrap().is_none());
+        for pmu in pmus.iter() {
+            for (name, terms) in pmu_events(pmu).unwrap() {
+                assert!(!name.contains('.'));
+                if !terms.contains('?') {
+                    assert!(encode_terms(pmu, &terms).is_ok());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pmu_cpus() {
+        // Software PMU neither has a cpumask nor a list of CPUs
+        assert_eq!(
+            pmu_cpus("software").unwrap(),
+            crate::util::online_cpus().unwrap()
+        );
+    }
+}
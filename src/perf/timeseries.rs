@@ -0,0 +1,210 @@
+//! Periodic collection of counter values on a background thread, similar to `perf stat -I`.
+
+use crate::api::Counter;
+use crate::perf::PerfEventValue;
+use crate::Result;
+use log::debug;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Counter that can be read by a `TimeSeriesCollector`.
+type BoxedCounter = Box<dyn Counter<PerfEventValue> + Send>;
+
+/// Change of the counters of a `TimeSeriesCollector` during one interval.
+#[derive(Debug, Clone)]
+pub struct TimeSeriesSample {
+    /// Time at which the counters were read.
+    pub timestamp: SystemTime,
+    /// Time elapsed since the counters were read previously.
+    pub interval: Duration,
+    /// Change of the value of each counter, in the order the counters were added.
+    pub deltas: Vec<PerfEventValue>,
+}
+
+/// Reads a set of counters at a fixed interval on a background thread.
+///
+/// The collector enables the counters when started and sends the change of their values during
+/// every interval over a channel, e.g., to feed a dashboard while a long-running workload is being
+/// monitored.
+pub struct TimeSeriesCollector {
+    /// Time between consecutive reads of the counters.
+    interval: Duration,
+    /// Counters to be read.
+    counters: Vec<BoxedCounter>,
+}
+
+impl std::fmt::Debug for TimeSeriesCollector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&String> = self.counters.iter().map(|c| c.name()).collect();
+        f.debug_struct("TimeSeriesCollector")
+            .field("interval", &self.interval)
+            .field("counters", &names)
+            .finish()
+    }
+}
+
+/// Handle to a running `TimeSeriesCollector`.
+///
+/// Dropping the handle stops the collection.
+#[derive(Debug)]
+pub struct TimeSeriesHandle {
+    /// Receiving end of the samples taken by the background thread.
+    samples: mpsc::Receiver<Result<TimeSeriesSample>>,
+    /// Channel used to signal the background thread to stop.
+    stop: Option<mpsc::Sender<()>>,
+    /// Background thread, which returns the counters once stopped.
+    thread: Option<std::thread::JoinHandle<Vec<BoxedCounter>>>,
+}
+
+impl TimeSeriesCollector {
+    /// Create a new collector reading its counters every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        TimeSeriesCollector {
+            interval,
+            counters: Vec::new(),
+        }
+    }
+
+    /// Add a counter to be read, e.g., a `PerfEvent` or a `PerCpuEvent`.
+    pub fn counter<C>(mut self, counter: C) -> Self
+    where
+        C: Counter<PerfEventValue> + Send + 'static,
+    {
+        self.counters.push(Box::new(counter));
+        self
+    }
+
+    /// Enable the counters and start reading them on a background thread.
+    pub fn start(self) -> Result<TimeSeriesHandle> {
+        let (sample_tx, sample_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("perf-timeseries".into())
+            .spawn(move || self._run(sample_tx, stop_rx))?;
+        Ok(TimeSeriesHandle {
+            samples: sample_rx,
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Read all counters.
+    fn _read(&self) -> Result<Vec<PerfEventValue>> {
+        self.counters.iter().map(|c| c.read_sync()).collect()
+    }
+
+    /// Body of the background thread.
+    ///
+    /// Runs until a stop signal is received, the receiver of the samples is dropped, or the
+    /// counters cannot be read anymore.
+    fn _run(
+        self,
+        samples: mpsc::Sender<Result<TimeSeriesSample>>,
+        stop: mpsc::Receiver<()>,
+    ) -> Vec<BoxedCounter> {
+        if let Err(e) = self._collect(&samples, &stop) {
+            let _ = samples.send(Err(e));
+        }
+        let _ = self.counters.iter().try_for_each(|c| c.disable());
+        debug!("Stopped time series collection");
+        self.counters
+    }
+
+    /// Read the counters every interval and send the changes of their values to `samples`.
+    fn _collect(
+        &self,
+        samples: &mpsc::Sender<Result<TimeSeriesSample>>,
+        stop: &mpsc::Receiver<()>,
+    ) -> Result<()> {
+        self.counters.iter().try_for_each(|c| c.enable())?;
+        let mut prev = self._read()?;
+        let mut last = Instant::now();
+        let mut deadline = last + self.interval;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if !matches!(
+                stop.recv_timeout(timeout),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            ) {
+                return Ok(());
+            }
+            let values = self._read()?;
+            let now = Instant::now();
+            let sample = TimeSeriesSample {
+                timestamp: SystemTime::now(),
+                interval: now - last,
+                deltas: values
+                    .iter()
+                    .zip(prev.iter())
+                    .map(|(v, p)| v.delta(p))
+                    .collect(),
+            };
+            if samples.send(Ok(sample)).is_err() {
+                return Ok(());
+            }
+            prev = values;
+            last = now;
+            // Do not try to catch up if reading the counters took longer than the interval
+            deadline = std::cmp::max(deadline + self.interval, now);
+        }
+    }
+}
+
+impl TimeSeriesHandle {
+    /// Get the receiving end of the samples.
+    ///
+    /// The channel is closed once the collection stops, e.g., because a counter could not be
+    /// read. In that case the last item is the error that stopped the collection.
+    pub fn samples(&self) -> &mpsc::Receiver<Result<TimeSeriesSample>> {
+        &self.samples
+    }
+
+    /// Stop the collection and get back the counters, which are disabled.
+    pub fn stop(mut self) -> Vec<Box<dyn Counter<PerfEventValue> + Send>> {
+        self._stop()
+    }
+
+    /// Signal the background thread to stop and wait for it.
+    fn _stop(&mut self) -> Vec<BoxedCounter> {
+        drop(self.stop.take());
+        match self.thread.take().map(|t| t.join()) {
+            Some(Ok(counters)) => counters,
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Drop for TimeSeriesHandle {
+    fn drop(&mut self) {
+        self._stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::{PerfEvent, SwId};
+    use crate::ScaledValue;
+
+    #[test]
+    fn test_timeseries_collector() {
+        let evt = PerfEvent::software(SwId::CpuClock)
+            .name("cpu-clock".into())
+            .start_disabled()
+            .open_per_cpu(None)
+            .unwrap();
+        let handle = TimeSeriesCollector::new(Duration::from_millis(10))
+            .counter(evt)
+            .start()
+            .unwrap();
+        for _ in 0..3 {
+            let sample = handle.samples().recv().unwrap().unwrap();
+            assert_eq!(sample.deltas.len(), 1);
+            assert!(sample.interval > Duration::from_millis(0));
+            assert!(sample.deltas[0].raw_value() > 0);
+        }
+        let counters = handle.stop();
+        assert_eq!(counters.len(), 1);
+        assert_eq!(counters[0].name(), "cpu-clock");
+    }
+}
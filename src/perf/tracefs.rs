@@ -0,0 +1,397 @@
+//! Utilities to query static tracepoints from the kernel's tracing filesystem.
+
+use crate::perf::ffi;
+use crate::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// Locations at which the tracing filesystem is usually mounted.
+const TRACEFS_MOUNTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Get the mount point of the tracing filesystem.
+pub fn tracefs_root() -> Result<PathBuf> {
+    TRACEFS_MOUNTS
+        .iter()
+        .map(Path::new)
+        .find(|p| p.join("events").is_dir())
+        .map(Path::to_path_buf)
+        .ok_or(Error::KernelCapabilityError)
+}
+
+/// Split a tracepoint name of the form `subsystem:event` into its components.
+fn _split_tracepoint(name: &str) -> Result<(&str, &str)> {
+    let mut parts = name.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(subsys), Some(event))
+            if !subsys.is_empty() && !event.is_empty() && !event.contains(':') =>
+        {
+            Ok((subsys, event))
+        }
+        _ => Err(Error::InvalidEvent(name.into())),
+    }
+}
+
+/// Get the id of the tracepoint `name` of the form `subsystem:event`, e.g., `sched:sched_switch`.
+///
+/// This id is to be used as the `config` of a `PERF_TYPE_TRACEPOINT` event.
+pub fn tracepoint_id(name: &str) -> Result<u64> {
+    let (subsys, event) = _split_tracepoint(name)?;
+    let path = tracefs_root()?
+        .join("events")
+        .join(subsys)
+        .join(event)
+        .join("id");
+    Ok(std::fs::read_to_string(path)?.trim().parse()?)
+}
+
+/// Get the names of all tracepoints below the `events` directory of the tracing filesystem mounted
+/// at `root`.
+fn _list_tracepoints(root: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for subsys in std::fs::read_dir(root.join("events"))? {
+        let subsys = subsys?;
+        if !subsys.file_type()?.is_dir() {
+            continue;
+        }
+        for event in std::fs::read_dir(subsys.path())? {
+            let event = event?;
+            if event.path().join("id").is_file() {
+                names.push(format!(
+                    "{}:{}",
+                    subsys.file_name().to_string_lossy(),
+                    event.file_name().to_string_lossy()
+                ));
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Get the names of all tracepoints of the form `subsystem:event`, e.g., `sched:sched_switch`.
+pub fn tracepoints() -> Result<Vec<String>> {
+    _list_tracepoints(&tracefs_root()?)
+}
+
+/// Kind of data stored in a field of a tracepoint record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Integer of the size of the field.
+    Integer,
+    /// Pointer into the address space of the kernel.
+    Pointer,
+    /// Fixed size array with the given number of elements, e.g., `char comm[16]`.
+    Array(usize),
+    /// Variable length data stored after the fixed fields, e.g., `__data_loc char[] name`.
+    ///
+    /// The field holds the offset of the data in its lower and the length in its upper 16 bits.
+    /// The offset of `__rel_loc` fields is relative to the end of the field.
+    DynamicArray {
+        /// The offset is relative to the end of the field.
+        relative: bool,
+    },
+}
+
+/// Description of a field of the records of a tracepoint, parsed from its `format` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracepointField {
+    /// Name of the field.
+    pub name: String,
+    /// C type of the field without the array length, e.g., `unsigned short` or `char`.
+    pub type_name: String,
+    /// Kind of data stored in the field.
+    pub kind: FieldKind,
+    /// Offset of the field in bytes from the start of the record.
+    pub offset: usize,
+    /// Size of the field in bytes.
+    pub size: usize,
+    /// The field holds a signed value.
+    pub signed: bool,
+    /// The field is common to all tracepoints, e.g., `common_pid`.
+    pub common: bool,
+}
+
+impl TracepointField {
+    /// Parse a field description of the form
+    /// `field:char prev_comm[16]; offset:8; size:16; signed:0;`.
+    fn _parse(line: &str, common: bool) -> Result<Self> {
+        let err = || Error::InvalidEvent(format!("Invalid tracepoint field {}", line.trim()));
+        let mut decl = None;
+        let (mut offset, mut size, mut signed) = (None, None, false);
+        for part in line.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once(':').ok_or_else(err)?;
+            match key {
+                "field" => decl = Some(value.trim()),
+                "offset" => offset = Some(value.parse()?),
+                "size" => size = Some(value.parse()?),
+                "signed" => signed = value.parse::<i32>()? != 0,
+                _ => {}
+            }
+        }
+        let decl = decl.ok_or_else(err)?;
+        let (type_name, name) = decl.rsplit_once(' ').ok_or_else(err)?;
+        let (name, len) = match name.split_once('[') {
+            Some((n, l)) => (n, Some(l.trim_end_matches(']').parse()?)),
+            None => (name, None),
+        };
+        let type_name = type_name.trim();
+        let (kind, type_name) = if let Some(t) = type_name.strip_prefix("__data_loc") {
+            let kind = FieldKind::DynamicArray { relative: false };
+            (kind, t.trim().trim_end_matches("[]"))
+        } else if let Some(t) = type_name.strip_prefix("__rel_loc") {
+            let kind = FieldKind::DynamicArray { relative: true };
+            (kind, t.trim().trim_end_matches("[]"))
+        } else if let Some(len) = len {
+            (FieldKind::Array(len), type_name)
+        } else if type_name.ends_with('*') {
+            (FieldKind::Pointer, type_name)
+        } else {
+            (FieldKind::Integer, type_name)
+        };
+        Ok(TracepointField {
+            name: name.into(),
+            type_name: type_name.into(),
+            kind,
+            offset: offset.ok_or_else(err)?,
+            size: size.ok_or_else(err)?,
+            signed,
+            common,
+        })
+    }
+}
+
+/// Description of a tracepoint and the layout of its records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracepoint {
+    /// Name of the tracepoint of the form `subsystem:event`.
+    pub name: String,
+    /// Id of the tracepoint, used as the `config` of `PERF_TYPE_TRACEPOINT` events.
+    pub id: u64,
+    /// Fields of the records of the tracepoint, starting with the common fields.
+    pub fields: Vec<TracepointField>,
+    /// Format string used by the kernel to print records.
+    pub print_fmt: String,
+}
+
+impl Tracepoint {
+    /// Parse the `format` file of the tracepoint of subsystem `subsys`.
+    pub fn parse_format(subsys: &str, format: &str) -> Result<Self> {
+        let mut name = None;
+        let mut id = None;
+        let mut fields = Vec::new();
+        let mut print_fmt = String::new();
+        // Common fields are separated from the specific ones by an empty line
+        let mut common = true;
+        let mut in_format = false;
+        for line in format.lines() {
+            let trimmed = line.trim();
+            if let Some(n) = trimmed.strip_prefix("name:") {
+                name = Some(n.trim());
+            } else if let Some(i) = trimmed.strip_prefix("ID:") {
+                id = Some(i.trim().parse()?);
+            } else if trimmed == "format:" {
+                in_format = true;
+            } else if let Some(p) = trimmed.strip_prefix("print fmt:") {
+                print_fmt = p.trim().into();
+                in_format = false;
+            } else if in_format && trimmed.is_empty() {
+                common = false;
+            } else if in_format && trimmed.starts_with("field:") {
+                fields.push(TracepointField::_parse(trimmed, common)?);
+            }
+        }
+        match (name, id) {
+            (Some(name), Some(id)) => Ok(Tracepoint {
+                name: format!("{}:{}", subsys, name),
+                id,
+                fields,
+                print_fmt,
+            }),
+            _ => Err(Error::InvalidEvent(format!(
+                "Invalid format of tracepoint in {}",
+                subsys
+            ))),
+        }
+    }
+
+    /// Get the field named `name`.
+    pub fn field(&self, name: &str) -> Option<&TracepointField> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Get a `perf_event_attr` counting this tracepoint.
+    ///
+    /// Enable sampling of `PERF_SAMPLE_RAW` in the builder to receive the records described by
+    /// `fields`.
+    pub fn to_perf_event_attr(&self) -> ffi::perf_event_attr {
+        ffi::perf_event_attr {
+            type_: ffi::perf_type_id::PERF_TYPE_TRACEPOINT as _,
+            size: std::mem::size_of::<ffi::perf_event_attr>() as _,
+            config: self.id,
+            ..Default::default()
+        }
+    }
+}
+
+/// Registry of the static tracepoints of the kernel listed in the tracing filesystem.
+#[derive(Debug, Clone)]
+pub struct TracepointRegistry {
+    /// Mount point of the tracing filesystem.
+    root: PathBuf,
+}
+
+impl TracepointRegistry {
+    /// Create a registry of the tracepoints of the tracing filesystem at its usual mount point.
+    pub fn new() -> Result<Self> {
+        Ok(TracepointRegistry::with_root(tracefs_root()?))
+    }
+
+    /// Create a registry of the tracepoints of the tracing filesystem mounted at `root`.
+    pub fn with_root<P: Into<PathBuf>>(root: P) -> Self {
+        TracepointRegistry { root: root.into() }
+    }
+
+    /// Get the names of all subsystems, e.g., `sched`.
+    pub fn subsystems(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(self.root.join("events"))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Get the names of all events of subsystem `subsys`, e.g., `sched_switch`.
+    pub fn events(&self, subsys: &str) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(self.root.join("events").join(subsys))? {
+            let entry = entry?;
+            if entry.path().join("id").is_file() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Get the names of all tracepoints of the form `subsystem:event`.
+    pub fn tracepoints(&self) -> Result<Vec<String>> {
+        _list_tracepoints(&self.root)
+    }
+
+    /// Get the description of the tracepoint `name` of the form `subsystem:event`.
+    pub fn get(&self, name: &str) -> Result<Tracepoint> {
+        let (subsys, event) = _split_tracepoint(name)?;
+        let path = self
+            .root
+            .join("events")
+            .join(subsys)
+            .join(event)
+            .join("format");
+        Tracepoint::parse_format(subsys, &std::fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_tracepoint() {
+        assert_eq!(
+            _split_tracepoint("sched:sched_switch").unwrap(),
+            ("sched", "sched_switch")
+        );
+        assert!(_split_tracepoint("sched_switch").is_err());
+        assert!(_split_tracepoint("sched:").is_err());
+        assert!(_split_tracepoint("a:b:c").is_err());
+    }
+
+    #[test]
+    fn test_list_tracepoints() {
+        let root = std::env::temp_dir().join(format!("perf-utils-tracefs-{}", std::process::id()));
+        for (subsys, event) in &[("sched", "sched_switch"), ("irq", "irq_handler_entry")] {
+            let dir = root.join("events").join(subsys).join(event);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("id"), "42\n").unwrap();
+        }
+        std::fs::write(root.join("events").join("sched").join("enable"), "0\n").unwrap();
+        std::fs::write(root.join("events").join("header_page"), "").unwrap();
+        let names = _list_tracepoints(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            names.unwrap(),
+            vec!["irq:irq_handler_entry", "sched:sched_switch"]
+        );
+    }
+
+    const SCHED_SWITCH: &str = "name: sched_switch
+ID: 316
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:char prev_comm[16];\toffset:8;\tsize:16;\tsigned:0;
+\tfield:pid_t prev_pid;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:__data_loc char[] name;\toffset:28;\tsize:4;\tsigned:0;
+\tfield:void * ptr;\toffset:32;\tsize:8;\tsigned:0;
+
+print fmt: \"prev_comm=%s prev_pid=%d\", REC->prev_comm, REC->prev_pid
+";
+
+    #[test]
+    fn test_parse_tracepoint_format() {
+        let tp = Tracepoint::parse_format("sched", SCHED_SWITCH).unwrap();
+        assert_eq!(tp.name, "sched:sched_switch");
+        assert_eq!(tp.id, 316);
+        assert_eq!(tp.fields.len(), 8);
+        assert_eq!(tp.fields.iter().filter(|f| f.common).count(), 4);
+        assert_eq!(
+            tp.field("common_pid").unwrap(),
+            &TracepointField {
+                name: "common_pid".into(),
+                type_name: "int".into(),
+                kind: FieldKind::Integer,
+                offset: 4,
+                size: 4,
+                signed: true,
+                common: true,
+            }
+        );
+        let comm = tp.field("prev_comm").unwrap();
+        assert_eq!(comm.kind, FieldKind::Array(16));
+        assert_eq!(comm.type_name, "char");
+        assert!(!comm.common);
+        let name = tp.field("name").unwrap();
+        assert_eq!(name.kind, FieldKind::DynamicArray { relative: false });
+        assert_eq!(name.type_name, "char");
+        assert_eq!(tp.field("ptr").unwrap().kind, FieldKind::Pointer);
+        assert!(tp.print_fmt.starts_with("\"prev_comm=%s"));
+
+        let attr = tp.to_perf_event_attr();
+        assert_eq!(attr.type_, ffi::perf_type_id::PERF_TYPE_TRACEPOINT as u32);
+        assert_eq!(attr.config, 316);
+        assert!(Tracepoint::parse_format("sched", "format:\n").is_err());
+    }
+
+    #[test]
+    fn test_tracepoint_registry() {
+        let root = std::env::temp_dir().join(format!("perf-utils-tpreg-{}", std::process::id()));
+        let dir = root.join("events").join("sched").join("sched_switch");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("id"), "316\n").unwrap();
+        std::fs::write(dir.join("format"), SCHED_SWITCH).unwrap();
+        let reg = TracepointRegistry::with_root(&root);
+        let subsystems = reg.subsystems();
+        let events = reg.events("sched");
+        let tp = reg.get("sched:sched_switch");
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(subsystems.unwrap(), vec!["sched"]);
+        assert_eq!(events.unwrap(), vec!["sched_switch"]);
+        assert_eq!(tp.unwrap().id, 316);
+    }
+}
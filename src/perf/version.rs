@@ -1,20 +1,77 @@
+use crate::perf::KernelFeatures;
+use lazy_static::lazy_static;
+use log::debug;
 use regex::Regex;
 use std::path::Path;
 use std::process::Command;
 
-#[derive(Debug)]
+lazy_static! {
+    /// Version detected on first use of `PerfVersion::cached`.
+    static ref DETECTED: PerfVersion = PerfVersion::detect();
+}
+
+#[derive(Debug, Clone)]
 /// Details of the userspace `perf` tool version.
+///
+/// Created with `new`, or from the features of the running kernel with `from_features`.
 pub struct PerfVersion {
     /// Major version.
     pub major: i32,
     /// Minor version.
     pub minor: i32,
+    /// Features of the kernel, if the version was derived from them.
+    features: Option<KernelFeatures>,
 }
 
 impl PerfVersion {
     /// Create a new PerfVersion structure directly
     pub fn new(major: i32, minor: i32) -> Self {
-        PerfVersion { major, minor }
+        PerfVersion {
+            major,
+            minor,
+            features: None,
+        }
+    }
+
+    /// Create a `perf` version structure matching the features of the kernel.
+    ///
+    /// Kernels registering the core PMU in sysfs are treated like a recent `perf` tool. Otherwise,
+    /// events have to be given as raw codes.
+    pub fn from_features(features: KernelFeatures) -> Self {
+        let (major, minor) = if features.name_term {
+            (5, 1 << 10)
+        } else {
+            (3, 0)
+        };
+        PerfVersion {
+            major,
+            minor,
+            features: Some(features),
+        }
+    }
+
+    /// Create a `perf` version structure by probing the kernel, without the `perf` tool.
+    pub fn probe() -> Self {
+        PerfVersion::from_features(KernelFeatures::probe())
+    }
+
+    /// Create a `perf` version structure from the `perf` tool if it is installed, or by probing
+    /// the kernel otherwise.
+    pub fn detect() -> Self {
+        PerfVersion::get_details_from_tool().unwrap_or_else(|e| {
+            debug!("Probing kernel as perf tool is unavailable -- {:?}", e);
+            PerfVersion::probe()
+        })
+    }
+
+    /// Get the version detected as in `detect`, running the detection only once per process.
+    pub fn cached() -> &'static PerfVersion {
+        &DETECTED
+    }
+
+    /// Get the features of the kernel the version was derived from, if any.
+    pub fn features(&self) -> Option<&KernelFeatures> {
+        self.features.as_ref()
     }
 
     /// Create `perf` version structure by parsing the output of the `perf` command.
@@ -23,7 +80,7 @@ impl PerfVersion {
         let ver_re = Regex::new(r"perf version (\d+)\.(\d+)")?;
         let matches = ver_re
             .captures(std::str::from_utf8(perf_output_buf.as_slice())?)
-            .unwrap();
+            .ok_or(crate::Error::NoneError)?;
         let major = matches.get(1).unwrap().as_str().parse::<i32>()?;
         let minor = if major > 4 {
             1 << 10 // infinity (hopefully perf versions never reach this high)
@@ -31,7 +88,7 @@ impl PerfVersion {
             matches.get(2).unwrap().as_str().parse::<i32>()?
         };
 
-        Ok(PerfVersion { major, minor })
+        Ok(PerfVersion::new(major, minor))
     }
 
     /// Allows for direct access.
@@ -46,12 +103,18 @@ impl PerfVersion {
 
     /// Allows setting offcore response.
     pub fn offcore(&self) -> bool {
-        !self.direct() && Path::new("/sys/devices/cpu/format/offcore_rsp").exists()
+        match self.features {
+            Some(c) => c.offcore_rsp,
+            None => !self.direct() && Path::new("/sys/devices/cpu/format/offcore_rsp").exists(),
+        }
     }
 
     /// Allows setting load latency.
     pub fn ldlat(&self) -> bool {
-        !self.direct() && Path::new("/sys/devices/cpu/format/ldlat").exists()
+        match self.features {
+            Some(c) => c.ldlat,
+            None => !self.direct() && Path::new("/sys/devices/cpu/format/ldlat").exists(),
+        }
     }
 }
 
@@ -64,4 +127,35 @@ mod tests {
         let pv = PerfVersion::get_details_from_tool();
         assert!(pv.is_ok());
     }
+
+    #[test]
+    fn test_perf_version_probe() {
+        let features = KernelFeatures {
+            attr_size: 64,
+            name_term: false,
+            offcore_rsp: false,
+            ldlat: false,
+        };
+        let pv = PerfVersion::from_features(features);
+        assert!(pv.direct() && !pv.has_name() && !pv.offcore());
+        let pv = PerfVersion::from_features(KernelFeatures {
+            name_term: true,
+            ldlat: true,
+            ..features
+        });
+        assert!(!pv.direct() && pv.has_name() && pv.ldlat() && !pv.offcore());
+        assert_eq!(
+            pv.features(),
+            Some(&KernelFeatures {
+                name_term: true,
+                ldlat: true,
+                ..features
+            })
+        );
+
+        let pv = PerfVersion::detect();
+        assert!(pv.features().is_some() || PerfVersion::get_details_from_tool().is_ok());
+        assert!(std::ptr::eq(PerfVersion::cached(), PerfVersion::cached()));
+        assert_eq!(PerfVersion::cached().major, pv.major);
+    }
 }
@@ -0,0 +1,219 @@
+//! Persistent cache of parsed PMU event databases.
+//!
+//! Loading a `Pmu` parses hundreds of JSON files and runs the `perf` tool, or probes the kernel, to
+//! detect its version. The cache stores the JSON descriptions of the events of a CPU together with
+//! the `perf` version in a single file, which is invalidated whenever one of the files the events
+//! were read from is modified.
+
+use crate::perf::PerfVersion;
+use crate::registry::{_mapfile_entries, EventSource, Pmu, RawEvent};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Modification times of the files and directories of a pmu-events tree, indexed by their path
+/// relative to the root of the tree.
+type Fingerprint = BTreeMap<String, u64>;
+
+/// Get the modification time of `path` in nanoseconds since the epoch.
+fn _mtime(path: &str) -> crate::Result<u64> {
+    let mtime = std::fs::metadata(path)?.modified()?;
+    let mtime = mtime.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(mtime.as_secs() * 1_000_000_000 + u64::from(mtime.subsec_nanos()))
+}
+
+/// Get the modification times of the files of the tree `path` the events of `cpu` are read from.
+///
+/// These are the root of the tree, `mapfile.csv`, the architecture standard events and the files
+/// and directories mapped to `cpu`. Directories are included so that adding or removing event
+/// files invalidates the cache as well.
+fn _fingerprint(cpu: &str, path: &str) -> crate::Result<Fingerprint> {
+    let source = EventSource::Dir(path);
+    let mut files = vec![String::new(), String::from("mapfile.csv")];
+    files.extend(source.json_files(""));
+    for entry in _mapfile_entries(&source.read("mapfile.csv")?, cpu) {
+        files.extend(entry.json_files(source));
+        files.push(entry.file);
+    }
+    files
+        .into_iter()
+        .map(|f| {
+            let mtime = _mtime(&format!("{}/{}", path, f))?;
+            Ok((f, mtime))
+        })
+        .collect()
+}
+
+/// Get the location of the cache file for `cpu` in `cache_dir`.
+fn _cache_file(cache_dir: &Path, cpu: &str) -> PathBuf {
+    let name: String = cpu
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    cache_dir.join(format!("pmu-{}.json", name))
+}
+
+impl Pmu {
+    /// Load PMU event information for local CPU from the specified path, using a cache in
+    /// `cache_dir`.
+    ///
+    /// See `Pmu::from_cpu_str_cached`.
+    pub fn from_local_cpu_cached<P: AsRef<Path>>(
+        path: String,
+        cache_dir: P,
+    ) -> crate::Result<Self> {
+        let cpu_str = crate::arch::get_cpu_string();
+        Pmu::from_cpu_str_cached(cpu_str, path, cache_dir)
+    }
+
+    /// Load CPU-specific PMU information from the specified path, using a cache in `cache_dir`.
+    ///
+    /// The cache is keyed on `cpu` and the modification times of the files in `path` the events
+    /// are read from. On a miss the events are parsed as in `Pmu::from_cpu_str` and the cache is
+    /// updated; failing to write the cache is not an error.
+    pub fn from_cpu_str_cached<P: AsRef<Path>>(
+        cpu: String,
+        path: String,
+        cache_dir: P,
+    ) -> crate::Result<Self> {
+        let file = _cache_file(cache_dir.as_ref(), &cpu);
+        let files = _fingerprint(&cpu, &path)?;
+        if let Some(pmu) = Pmu::_read_cache(&file, &cpu, &path, &files) {
+            debug!("Loaded events of {} from cache {:?}", cpu, file);
+            return Ok(pmu);
+        }
+        let raw_events = Pmu::_load_raw_events(&cpu, &path)?;
+        let version = PerfVersion::cached();
+        let pmu = Pmu::_from_raw_events(cpu, raw_events, version);
+        if let Err(e) = pmu._write_cache(&file, &path, &files, version) {
+            warn!("Could not write cache {:?} -- {:?}", file, e);
+        }
+        Ok(pmu)
+    }
+
+    /// Load a `Pmu` from the cache `file` if it is valid for `cpu`, `path` and the modification
+    /// times `files`.
+    ///
+    /// The cache is only valid if it was written with the same `perf` version as
+    /// `PerfVersion::cached`, which is then used to rebuild the events so that they see the
+    /// features of the running kernel.
+    fn _read_cache(file: &Path, cpu: &str, path: &str, files: &Fingerprint) -> Option<Self> {
+        let s = std::fs::read_to_string(file).ok()?;
+        let v: Value = serde_json::from_str(&s).ok()?;
+        if v["cpu_str"] != cpu || v["path"] != path || v["files"] != json!(files) {
+            return None;
+        }
+        let version = PerfVersion::cached();
+        if v["perf_version"] != json!([version.major, version.minor]) {
+            return None;
+        }
+        let raw_events: Vec<RawEvent> = serde_json::from_value(v["events"].clone()).ok()?;
+        Some(Pmu::_from_raw_events(cpu.into(), raw_events, version))
+    }
+
+    /// Store the events of this `Pmu` parsed from `path` in the cache `file`.
+    fn _write_cache(
+        &self,
+        file: &Path,
+        path: &str,
+        files: &Fingerprint,
+        version: &PerfVersion,
+    ) -> crate::Result<()> {
+        let v = json!({
+            "cpu_str": self.cpu_str,
+            "path": path,
+            "files": files,
+            "perf_version": [version.major, version.minor],
+            "events": self.raw_events,
+        });
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so concurrent readers never see a partial cache
+        let tmp = file.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_string(&v)?)?;
+        std::fs::rename(&tmp, file)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::time::{TimeVal, TimeValLike};
+
+    #[test]
+    fn test_pmu_cache() {
+        let dir = std::env::temp_dir().join(format!("perf-utils-cache-{}", std::process::id()));
+        let file = _cache_file(&dir, "GenuineIntel-6-55-4");
+        assert_eq!(file.file_name().unwrap(), "pmu-GenuineIntel_6_55_4.json");
+
+        let raw: RawEvent = [
+            ("EventName", "INST_RETIRED.ANY"),
+            ("EventCode", "0xC0"),
+            ("UMask", "0x00"),
+            ("Topic", "pipeline"),
+        ]
+        .iter()
+        .map(|&(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let version = PerfVersion::cached();
+        let pmu = Pmu::_from_raw_events("GenuineIntel-6-55-4".into(), vec![raw], version);
+        assert_eq!(pmu.events.len(), 1);
+        let files: Fingerprint = vec![(String::from("mapfile.csv"), 42)]
+            .into_iter()
+            .collect();
+        assert!(pmu._write_cache(&file, "/events", &files, version).is_ok());
+
+        let cached = Pmu::_read_cache(&file, "GenuineIntel-6-55-4", "/events", &files).unwrap();
+        assert_eq!(cached.cpu_str, pmu.cpu_str);
+        assert_eq!(cached.events.len(), 1);
+        assert_eq!(cached.events[0].name, "INST_RETIRED.ANY");
+        let mut modified = files.clone();
+        modified.insert("skylakex/cache.json".into(), 43);
+        assert!(Pmu::_read_cache(&file, "GenuineIntel-6-55-4", "/events", &modified).is_none());
+        assert!(Pmu::_read_cache(&file, "GenuineIntel-6-55-4", "/other", &files).is_none());
+        assert!(Pmu::_read_cache(&file, "GenuineIntel-6-3F-2", "/events", &files).is_none());
+        // Events are rebuilt with the features of the running kernel
+        assert_eq!(
+            cached.events[0].to_perf_string(version, None),
+            pmu.events[0].to_perf_string(version, None)
+        );
+        // A cache written with another perf version is stale
+        let other = PerfVersion::new(version.major + 1, version.minor);
+        assert!(pmu._write_cache(&file, "/events", &files, &other).is_ok());
+        assert!(Pmu::_read_cache(&file, "GenuineIntel-6-55-4", "/events", &files).is_none());
+
+        // Modifying an event file of the CPU changes the fingerprint, other files do not
+        let tree = dir.join("events");
+        std::fs::create_dir_all(tree.join("skylakex")).unwrap();
+        std::fs::create_dir_all(tree.join("haswell")).unwrap();
+        std::fs::write(
+            tree.join("mapfile.csv"),
+            "GenuineIntel-6-55,v1,skylakex,core\nGenuineIntel-6-3F,v1,haswell,core\n",
+        )
+        .unwrap();
+        for f in &["skylakex/cache.json", "haswell/cache.json"] {
+            std::fs::write(tree.join(f), "[]").unwrap();
+        }
+        let path = tree.to_str().unwrap();
+        let files = _fingerprint("GenuineIntel-6-55-4", path).unwrap();
+        let names: Vec<&str> = files.keys().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            vec!["", "mapfile.csv", "skylakex", "skylakex/cache.json"]
+        );
+        let touch = |f: &str, secs| {
+            let time = TimeVal::seconds(secs);
+            nix::sys::stat::utimes(&tree.join(f), &time, &time).unwrap();
+        };
+        touch("haswell/cache.json", 1000);
+        assert_eq!(_fingerprint("GenuineIntel-6-55-4", path).unwrap(), files);
+        touch("skylakex/cache.json", 1000);
+        assert_ne!(_fingerprint("GenuineIntel-6-55-4", path).unwrap(), files);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,481 @@
+//! Validation of the counter constraints of events before opening them.
+
+use crate::perf::ffi::perf_event_attr;
+use crate::perf::{sysfs, PerfEventBuilder, PerfEventGroup};
+use crate::registry::{CoreType, PmuEvent};
+use crate::{Error, Result};
+use log::debug;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+
+/// Number of counters assumed for each box of an uncore PMU by default, see
+/// `GroupPlanner::uncore_counters`.
+const DEFAULT_UNCORE_COUNTERS: usize = 4;
+
+/// Number of performance counters of the core PMU of a logical processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CounterLimits {
+    /// Number of general purpose counters.
+    pub general: usize,
+    /// Number of fixed function counters.
+    pub fixed: usize,
+    /// Mask of architectural events the CPU reports as unavailable, in the order of
+    /// `arch::ArchEvent::ALL` on x86_64.
+    pub unavailable_events: u32,
+}
+
+impl CounterLimits {
+    /// Create new limits with `general` general purpose and `fixed` fixed function counters.
+    pub fn new(general: usize, fixed: usize) -> Self {
+        CounterLimits {
+            general,
+            fixed,
+            unavailable_events: 0,
+        }
+    }
+
+    /// Set the mask of architectural events that cannot be counted.
+    pub fn unavailable_events(mut self, mask: u32) -> Self {
+        self.unavailable_events = mask;
+        self
+    }
+
+    /// Get the limits of the local CPU.
+    ///
+    /// Fails if the number of counters cannot be determined on this CPU.
+    pub fn from_local_cpu() -> Result<Self> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            match crate::arch::ArchPerfmon::detect() {
+                Some(p) => Ok(CounterLimits::new(p.general as _, p.fixed as _)
+                    .unavailable_events(p.unavailable_events)),
+                None => Err(Error::NotImplemented),
+            }
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            Err(Error::NotImplemented)
+        }
+    }
+
+    /// Get the limits of the PMU of the cores of type `ty` of the local hybrid CPU.
+    ///
+    /// The counters are enumerated from a helper thread pinned to a CPU of this type, so the
+    /// affinity of the calling thread is not changed. Fails if the CPU has no such cores.
+    pub fn for_core_type(ty: CoreType) -> Result<Self> {
+        sysfs::pmu_type(ty.pmu_name())?;
+        let cpu = *sysfs::pmu_cpus(ty.pmu_name())?
+            .first()
+            .ok_or(Error::NoneError)?;
+        std::thread::spawn(move || -> Result<Self> {
+            let mut set = CpuSet::new();
+            set.set(cpu as usize)?;
+            sched_setaffinity(Pid::from_raw(0), &set)?;
+            CounterLimits::from_local_cpu()
+        })
+        .join()
+        .map_err(|_| Error::NoneError)?
+    }
+
+    /// Check if the event with `code` and `umask` is an architectural event that the CPU reports
+    /// as unavailable, e.g., because it is broken or disabled by a hypervisor.
+    pub(crate) fn _is_unavailable(&self, code: u64, umask: u64) -> bool {
+        #[cfg(target_arch = "x86_64")]
+        {
+            matches!(crate::arch::ArchEvent::from_code(code, umask),
+                Some(e) if self.unavailable_events & (1 << e as u32) != 0)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            let _ = (code, umask);
+            false
+        }
+    }
+
+    /// Check that `events` can be counted at the same time, i.e., scheduled as a group.
+    ///
+    /// Every event is assigned a distinct counter among those listed for it in the JSON event
+    /// files. Architectural events reported as unavailable cannot be assigned any counter. Uncore
+    /// and metric events are not checked. Fails with `Error::CounterConflict` listing an event
+    /// that could not be assigned a counter, followed by the events occupying the counters it
+    /// could have used.
+    pub fn check_group(&self, events: &[&PmuEvent]) -> Result<()> {
+        let allowed: Vec<(&PmuEvent, Vec<usize>)> = events
+            .iter()
+            .filter_map(|e| Some((*e, e._allowed_counters(self)?)))
+            .collect();
+        // Event assigned to each counter
+        let mut assigned: Vec<Option<usize>> = vec![None; self.general + self.fixed];
+        for (i, (evt, counters)) in allowed.iter().enumerate() {
+            let mut visited = vec![false; assigned.len()];
+            if !CounterLimits::_assign(i, &allowed, &mut assigned, &mut visited) {
+                let mut conflicts = vec![evt.name.clone()];
+                conflicts.extend(
+                    counters
+                        .iter()
+                        .filter_map(|&c| assigned[c])
+                        .map(|e| allowed[e].0.name.clone()),
+                );
+                return Err(Error::CounterConflict(conflicts));
+            }
+        }
+        Ok(())
+    }
+
+    /// Try to assign a counter to event `evt`, moving previously assigned events to other
+    /// counters if required (augmenting path of a bipartite matching).
+    fn _assign(
+        evt: usize,
+        allowed: &[(&PmuEvent, Vec<usize>)],
+        assigned: &mut Vec<Option<usize>>,
+        visited: &mut Vec<bool>,
+    ) -> bool {
+        for &c in allowed[evt].1.iter() {
+            if visited[c] {
+                continue;
+            }
+            visited[c] = true;
+            let free = match assigned[c] {
+                None => true,
+                Some(other) => CounterLimits::_assign(other, allowed, assigned, visited),
+            };
+            if free {
+                assigned[c] = Some(evt);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Events that can be counted at the same time, as one group on each instance of their PMU.
+#[derive(Debug, Clone)]
+pub struct PlannedGroup<'a> {
+    /// Name of the PMU counting the events, e.g., `cpu_atom` for events of the efficiency cores
+    /// of a hybrid CPU. `None` for the core PMU, or for events counted by the PMUs of all types
+    /// of cores of a hybrid CPU.
+    pub pmu: Option<String>,
+    /// Events of the group with the leader first.
+    pub events: Vec<&'a PmuEvent>,
+}
+
+impl PlannedGroup<'_> {
+    /// Get the `perf_event_attrs` of the group for each instance of its PMU, e.g., for each box of
+    /// an uncore PMU or each type of cores of a hybrid CPU.
+    pub fn to_perf_event_attrs(&self) -> Result<Vec<Vec<perf_event_attr>>> {
+        let attrs = self
+            .events
+            .iter()
+            .map(|e| e.to_perf_event_attr(None))
+            .collect::<Result<Vec<_>>>()?;
+        let instances = attrs.first().map_or(0, Vec::len);
+        if attrs.iter().any(|a| a.len() != instances) {
+            return Err(Error::InvalidEvent(format!(
+                "Events of group {:?} are counted on different PMUs",
+                self.pmu
+            )));
+        }
+        Ok((0..instances)
+            .map(|i| attrs.iter().map(|a| a[i]).collect())
+            .collect())
+    }
+
+    /// Open the group configured by `builder` on each instance of its PMU.
+    ///
+    /// Groups of uncore events are opened on every box through the CPUs listed in its
+    /// `cpumask`, ignoring the `pid` and `cpuid` set in `builder`. Other groups are opened as
+    /// configured by `builder`.
+    pub fn open(&self, builder: PerfEventBuilder) -> Result<Vec<PerfEventGroup>> {
+        let instances = self.to_perf_event_attrs()?;
+        if self.events.iter().all(|e| e._is_uncore()) {
+            let boxes = self.events[0]._box_attrs()?;
+            let mut groups = Vec::new();
+            for ((b, _), attrs) in boxes.iter().zip(instances) {
+                let cpus = sysfs::pmu_cpus(b)?;
+                groups.extend(
+                    builder
+                        .clone()
+                        .open_group_on_cpus(&cpus, attrs)?
+                        .into_iter()
+                        .map(|(_, g)| g),
+                );
+            }
+            Ok(groups)
+        } else {
+            instances
+                .into_iter()
+                .map(|attrs| builder.clone().open_group(attrs))
+                .collect()
+        }
+    }
+}
+
+/// Partitions events into groups that can each be scheduled on the PMU at once.
+///
+/// Events of the core PMU are grouped such that every event is assigned one of the counters
+/// listed for it in the JSON event files, see `CounterLimits::check_group`. On a hybrid CPU, the
+/// events of each type of cores are grouped separately and checked against the counters of that
+/// type. Events of other PMUs are grouped by PMU with at most `uncore_counters` events per
+/// group. Opening the resulting groups does not fail due to too many or conflicting events, and
+/// all events of a group count at the same time.
+#[derive(Debug, Clone)]
+pub struct GroupPlanner {
+    /// Counters of the core PMU.
+    limits: CounterLimits,
+    /// Counters of the PMU of each type of cores of a hybrid CPU.
+    core_limits: HashMap<CoreType, CounterLimits>,
+    /// Counters of each box of uncore PMUs.
+    uncore_counters: usize,
+}
+
+impl GroupPlanner {
+    /// Create a new planner for a core PMU with the counters `limits`.
+    pub fn new(limits: CounterLimits) -> Self {
+        GroupPlanner {
+            limits,
+            core_limits: HashMap::new(),
+            uncore_counters: DEFAULT_UNCORE_COUNTERS,
+        }
+    }
+
+    /// Create a new planner for the core PMUs of the local CPU.
+    ///
+    /// On a hybrid CPU, the counters of each type of cores are enumerated separately, see
+    /// `CounterLimits::for_core_type`.
+    pub fn from_local_cpu() -> Result<Self> {
+        let mut planner = GroupPlanner::new(CounterLimits::from_local_cpu()?);
+        for pmu in sysfs::hybrid_pmus().unwrap_or_default() {
+            if let Some(ty) = CoreType::from_pmu_name(&pmu) {
+                planner = planner.core_type_limits(ty, CounterLimits::for_core_type(ty)?);
+            }
+        }
+        Ok(planner)
+    }
+
+    /// Set the counters of the PMU of the cores of type `ty` of a hybrid CPU (defaults to the
+    /// counters of the core PMU).
+    pub fn core_type_limits(mut self, ty: CoreType, limits: CounterLimits) -> Self {
+        self.core_limits.insert(ty, limits);
+        self
+    }
+
+    /// Set the number of counters of each box of uncore PMUs (default `4`).
+    pub fn uncore_counters(mut self, counters: usize) -> Self {
+        self.uncore_counters = counters;
+        self
+    }
+
+    /// Partition `events` into groups, keeping the order of the events within each group.
+    ///
+    /// Each event is added to the first group of its PMU it fits in. Fails for metrics, which
+    /// should be resolved into their events first, e.g., with `MetricDependencies`, and for events
+    /// that cannot be counted on this CPU at all.
+    pub fn plan<'a>(&self, events: &[&'a PmuEvent]) -> Result<Vec<PlannedGroup<'a>>> {
+        let mut groups: Vec<PlannedGroup<'a>> = Vec::new();
+        for &evt in events {
+            if evt.is_metric {
+                return Err(Error::InvalidEvent(format!("{} is a metric", evt.name)));
+            }
+            let pmu = evt.pmu().map(String::from);
+            let core_type = evt.core_type();
+            let is_core = pmu.is_none() || core_type.is_some();
+            if is_core {
+                self._check_group(core_type, &[evt])?;
+            }
+            let fits = |g: &PlannedGroup| {
+                if g.pmu != pmu {
+                    false
+                } else if is_core {
+                    let mut evts = g.events.clone();
+                    evts.push(evt);
+                    self._check_group(core_type, &evts).is_ok()
+                } else {
+                    g.events.len() < self.uncore_counters
+                }
+            };
+            match groups.iter_mut().find(|g| fits(g)) {
+                Some(g) => g.events.push(evt),
+                None => groups.push(PlannedGroup {
+                    pmu,
+                    events: vec![evt],
+                }),
+            }
+        }
+        debug!(
+            "Planned {} groups for {} events",
+            groups.len(),
+            events.len()
+        );
+        Ok(groups)
+    }
+
+    /// Check that `events` of the cores of type `core_type` can be counted as a group.
+    ///
+    /// Events that are not specific to a type of cores are counted by the PMUs of all types of
+    /// cores of a hybrid CPU, so they are checked against the counters of each of them.
+    fn _check_group(&self, core_type: Option<CoreType>, events: &[&PmuEvent]) -> Result<()> {
+        match core_type {
+            Some(ty) => self
+                .core_limits
+                .get(&ty)
+                .unwrap_or(&self.limits)
+                .check_group(events),
+            None => {
+                self.limits.check_group(events)?;
+                self.core_limits
+                    .values()
+                    .try_for_each(|l| l.check_group(events))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::PerfVersion;
+    use crate::registry::RawEvent;
+
+    fn make_event(name: &str, counter: &str) -> PmuEvent {
+        let mut raw = RawEvent::new();
+        raw.insert("EventName".into(), name.into());
+        raw.insert("EventCode".into(), "0xC0".into());
+        raw.insert("Topic".into(), "pipeline".into());
+        raw.insert("Counter".into(), counter.into());
+        PmuEvent::from_raw_event(&raw, &PerfVersion::new(4, 20)).unwrap()
+    }
+
+    #[test]
+    fn test_check_group() {
+        let limits = CounterLimits::new(4, 3);
+        let fixed = make_event("INST_RETIRED.ANY", "Fixed counter 0");
+        let any = make_event("BR_INST_RETIRED.ALL_BRANCHES", "0,1,2,3");
+        let low = make_event("L1D_PEND_MISS.PENDING", "2");
+        let prec = make_event("INST_RETIRED.PREC_DIST", "1");
+        let wide = make_event("MEM_LOAD_RETIRED.L3_MISS", "0-3");
+
+        assert!(limits.check_group(&[&fixed, &any, &low, &prec]).is_ok());
+        assert!(limits
+            .check_group(&[&fixed, &any, &wide, &any, &low, &prec])
+            .is_err());
+        // Requires moving `high` away from counter 2
+        let high = make_event("CYCLE_ACTIVITY.STALLS_L2_MISS", "2,3");
+        assert!(limits.check_group(&[&high, &low]).is_ok());
+        assert!(limits.check_group(&[&high, &low, &low]).is_err());
+        match limits.check_group(&[&low, &low]) {
+            Err(Error::CounterConflict(names)) => {
+                assert_eq!(names, vec![low.name.clone(), low.name.clone()])
+            }
+            _ => panic!("Expected a conflict"),
+        }
+        match limits.check_group(&[&fixed, &fixed]) {
+            Err(Error::CounterConflict(names)) => assert_eq!(names.len(), 2),
+            _ => panic!("Expected a conflict"),
+        }
+        // Not available on this CPU
+        let extra = make_event("CYCLE_ACTIVITY.STALLS_L1D_MISS", "4");
+        assert!(limits.check_group(&[&extra]).is_err());
+        assert!(CounterLimits::new(8, 3).check_group(&[&extra]).is_ok());
+        // Counters that cannot be parsed do not constrain the event
+        let unknown = make_event("UOPS_ISSUED.ANY", "Any");
+        assert!(limits.check_group(&[&unknown, &any, &low, &prec]).is_ok());
+        // Architectural event reported as unavailable
+        let branches = PmuEvent::builder("BR_INST_RETIRED.ALL_BRANCHES", 0xc4).build();
+        assert!(limits.check_group(&[&branches]).is_ok());
+        #[cfg(target_arch = "x86_64")]
+        match limits.unavailable_events(1 << 5).check_group(&[&branches]) {
+            Err(Error::CounterConflict(names)) => assert_eq!(names, vec![branches.name.clone()]),
+            _ => panic!("Expected a conflict"),
+        }
+    }
+
+    #[test]
+    fn test_group_planner() {
+        let planner = GroupPlanner::new(CounterLimits::new(4, 3)).uncore_counters(2);
+        let fixed = make_event("INST_RETIRED.ANY", "Fixed counter 0");
+        let any = make_event("BR_INST_RETIRED.ALL_BRANCHES", "0,1,2,3");
+        let low = make_event("L1D_PEND_MISS.PENDING", "2");
+        let prec = make_event("INST_RETIRED.PREC_DIST", "1");
+        let imc = PmuEvent::builder("UNC_M_CAS_COUNT.RD", 0x04)
+            .pmu("uncore_imc")
+            .build();
+        let cha = PmuEvent::builder("UNC_CHA_CLOCKTICKS", 0x00)
+            .pmu("uncore_cha")
+            .build();
+
+        let names = |groups: &[PlannedGroup]| -> Vec<Vec<String>> {
+            groups
+                .iter()
+                .map(|g| g.events.iter().map(|e| e.name.clone()).collect())
+                .collect()
+        };
+        let groups = planner
+            .plan(&[
+                &low, &imc, &fixed, &low, &cha, &any, &imc, &imc, &prec, &fixed,
+            ])
+            .unwrap();
+        assert_eq!(
+            names(&groups),
+            vec![
+                vec![
+                    "L1D_PEND_MISS.PENDING",
+                    "INST_RETIRED.ANY",
+                    "BR_INST_RETIRED.ALL_BRANCHES",
+                    "INST_RETIRED.PREC_DIST",
+                ],
+                vec!["UNC_M_CAS_COUNT.RD", "UNC_M_CAS_COUNT.RD"],
+                vec!["L1D_PEND_MISS.PENDING", "INST_RETIRED.ANY"],
+                vec!["UNC_CHA_CLOCKTICKS"],
+                vec!["UNC_M_CAS_COUNT.RD"],
+            ]
+        );
+        assert_eq!(groups[0].pmu, None);
+        assert_eq!(groups[1].pmu, Some("uncore_imc".to_string()));
+        for g in groups.iter().filter(|g| g.pmu.is_none()) {
+            assert!(planner.limits.check_group(&g.events).is_ok());
+        }
+
+        let extra = make_event("CYCLE_ACTIVITY.STALLS_L1D_MISS", "4");
+        assert!(planner.plan(&[&extra]).is_err());
+        let mut raw = RawEvent::new();
+        raw.insert("MetricName".into(), "IPC".into());
+        raw.insert("MetricExpr".into(), "INST_RETIRED.ANY / CYCLES".into());
+        raw.insert("Topic".into(), "metrics".into());
+        let ipc = PmuEvent::from_raw_event(&raw, &PerfVersion::new(4, 20)).unwrap();
+        assert!(planner.plan(&[&ipc]).is_err());
+        assert!(planner.plan(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_group_planner_hybrid() {
+        let planner = GroupPlanner::new(CounterLimits::new(4, 3))
+            .core_type_limits(CoreType::Efficiency, CounterLimits::new(2, 0));
+        let any = make_event("BR_INST_RETIRED.ALL_BRANCHES", "0,1,2,3");
+        let fixed = make_event("INST_RETIRED.ANY", "Fixed counter 0");
+        let core = any.for_core_type(CoreType::Performance).unwrap();
+        let atom = any.for_core_type(CoreType::Efficiency).unwrap();
+
+        let groups = planner
+            .plan(&[&atom, &core, &atom, &core, &core, &atom])
+            .unwrap();
+        let pmus: Vec<_> = groups
+            .iter()
+            .map(|g| (g.pmu.as_deref(), g.events.len()))
+            .collect();
+        assert_eq!(
+            pmus,
+            vec![
+                (Some("cpu_atom"), 2),
+                (Some("cpu_core"), 3),
+                (Some("cpu_atom"), 1),
+            ]
+        );
+        // The efficiency cores have no fixed counters
+        let fixed_atom = fixed.for_core_type(CoreType::Efficiency).unwrap();
+        assert!(planner.plan(&[&fixed_atom]).is_err());
+        assert!(planner.plan(&[&fixed]).is_err());
+        let fixed_core = fixed.for_core_type(CoreType::Performance).unwrap();
+        assert_eq!(planner.plan(&[&fixed_core]).unwrap().len(), 1);
+    }
+}
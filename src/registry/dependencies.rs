@@ -0,0 +1,180 @@
+use crate::registry::events::{_count_events, _resolve_metric};
+use crate::registry::{Pmu, PmuEvent};
+use crate::Result;
+use nix::libc;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Events needed to calculate a set of metrics, with events shared by several metrics counted
+/// only once.
+///
+/// Metrics referring to other metrics are resolved down to the plain events they are derived
+/// from, so collecting N related metrics, e.g., all top-down metrics of one level, does not open
+/// the same counters N times.
+#[derive(Debug, Clone)]
+pub struct MetricDependencies<'a> {
+    /// Metrics the dependencies were resolved for.
+    metrics: Vec<&'a PmuEvent>,
+    /// Plain events needed by any of the metrics, without duplicates.
+    events: Vec<&'a PmuEvent>,
+    /// Metrics referred to by any of the metrics, each after all metrics it depends on.
+    nested: Vec<&'a PmuEvent>,
+    /// Indices into `events` of the events needed by each metric.
+    indices: Vec<Vec<usize>>,
+}
+
+impl<'a> MetricDependencies<'a> {
+    /// Resolve the events of `pmu` needed to calculate `metrics`.
+    ///
+    /// Plain events may be passed as well, which only depend on themselves. Fails if a metric
+    /// refers to an event that is not in `pmu`, or depends on itself.
+    pub fn resolve(pmu: &'a Pmu, metrics: &[&'a PmuEvent]) -> Result<Self> {
+        let mut deps = MetricDependencies {
+            metrics: metrics.to_vec(),
+            events: Vec::new(),
+            nested: Vec::new(),
+            indices: Vec::new(),
+        };
+        for &m in metrics {
+            let mut events = Vec::new();
+            let mut nested = Vec::new();
+            match m.metric_expr() {
+                Some(expr) => _resolve_metric(
+                    &m.name,
+                    expr,
+                    pmu,
+                    &mut vec![m.name.as_str()],
+                    &mut events,
+                    &mut nested,
+                )?,
+                None => events.push(m),
+            }
+            let indices = events.into_iter().map(|e| deps._add_event(e)).collect();
+            deps.indices.push(indices);
+            for n in nested {
+                if !deps.nested.iter().any(|x| x.name == n.name) {
+                    deps.nested.push(n);
+                }
+            }
+        }
+        Ok(deps)
+    }
+
+    /// Get the index of `evt` in `events`, adding it if it is not there yet.
+    fn _add_event(&mut self, evt: &'a PmuEvent) -> usize {
+        match self.events.iter().position(|e| e.name == evt.name) {
+            Some(i) => i,
+            None => {
+                self.events.push(evt);
+                self.events.len() - 1
+            }
+        }
+    }
+
+    /// Get the metrics the dependencies were resolved for.
+    pub fn metrics(&self) -> &[&'a PmuEvent] {
+        &self.metrics
+    }
+
+    /// Get the plain events needed by all metrics, each listed once.
+    ///
+    /// This is the order in which the events are opened as a group by `collect`.
+    pub fn events(&self) -> &[&'a PmuEvent] {
+        &self.events
+    }
+
+    /// Get the metrics that are referred to by any of the metrics, in the order in which they
+    /// have to be evaluated.
+    pub fn nested(&self) -> &[&'a PmuEvent] {
+        &self.nested
+    }
+
+    /// Get the indices into `events` of the events needed by the metric `name`.
+    pub fn event_indices(&self, name: &str) -> Option<&[usize]> {
+        self.metrics
+            .iter()
+            .position(|m| m.name == name)
+            .map(|i| self.indices[i].as_slice())
+    }
+
+    /// Calculate the value of each metric, in the order they were passed to `resolve`, given the
+    /// `values` of the events indexed by their names.
+    ///
+    /// The values of nested metrics are calculated first and are added to `values`.
+    pub fn evaluate(&self, values: &mut HashMap<String, f64>) -> Result<Vec<f64>> {
+        for n in self.nested.iter() {
+            let val = n.evaluate(values)?;
+            values.insert(n.name.clone(), val);
+        }
+        self.metrics.iter().map(|m| m.evaluate(values)).collect()
+    }
+
+    /// Count all events as a single group for `duration` and calculate the value of each metric.
+    ///
+    /// The events are counted as in `MetricEvent::collect`, i.e., on the process `target`, or on
+    /// all CPUs if `target` is `-1`.
+    pub fn collect(&self, target: libc::pid_t, duration: Duration) -> Result<Vec<f64>> {
+        let name = self
+            .metrics
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut values = _count_events(&name, &self.events, target, duration)?;
+        self.evaluate(&mut values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::PerfVersion;
+    use crate::registry::RawEvent;
+
+    fn metric(name: &str, expr: &str) -> PmuEvent {
+        let mut raw = RawEvent::new();
+        raw.insert("MetricName".into(), name.into());
+        raw.insert("MetricExpr".into(), expr.into());
+        raw.insert("Topic".into(), "metrics".into());
+        PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap()
+    }
+
+    #[test]
+    fn test_metric_dependencies() {
+        let mut pmu = Pmu::default();
+        for (name, code) in [("CYCLES", 0x3c), ("INST", 0xc0), ("UOPS", 0xc2)].iter() {
+            pmu.events.push(PmuEvent::builder(*name, *code).build());
+        }
+        pmu.events.push(metric("SLOTS", "4 * CYCLES"));
+        pmu.events.push(metric("IPC", "INST / CYCLES"));
+        pmu.events.push(metric("RETIRING", "UOPS / SLOTS"));
+        pmu.events.push(metric("UPI", "UOPS / INST"));
+        pmu.events.push(metric("LOOP", "LOOP + 1"));
+        pmu.events.push(metric("BROKEN", "MISSING * 2"));
+
+        let find = |n: &str| pmu.events.iter().find(|e| e.name == n).unwrap();
+        let metrics = vec![find("IPC"), find("RETIRING"), find("UPI"), find("CYCLES")];
+        let deps = MetricDependencies::resolve(&pmu, &metrics).unwrap();
+        let names: Vec<&str> = deps.events().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["INST", "CYCLES", "UOPS"]);
+        assert_eq!(deps.nested().len(), 1);
+        assert_eq!(deps.event_indices("IPC").unwrap(), &[0, 1]);
+        assert_eq!(deps.event_indices("RETIRING").unwrap(), &[2, 1]);
+        assert_eq!(deps.event_indices("UPI").unwrap(), &[2, 0]);
+        assert_eq!(deps.event_indices("CYCLES").unwrap(), &[1]);
+        assert!(deps.event_indices("SLOTS").is_none());
+
+        let mut values = HashMap::new();
+        values.insert("CYCLES".to_string(), 100.0);
+        values.insert("INST".to_string(), 200.0);
+        values.insert("UOPS".to_string(), 300.0);
+        assert_eq!(
+            deps.evaluate(&mut values).unwrap(),
+            vec![2.0, 0.75, 1.5, 100.0]
+        );
+        assert_eq!(values["SLOTS"], 400.0);
+
+        assert!(MetricDependencies::resolve(&pmu, &[find("LOOP")]).is_err());
+        assert!(MetricDependencies::resolve(&pmu, &[find("BROKEN")]).is_err());
+    }
+}
@@ -1,10 +1,195 @@
 use crate::perf::ffi::{perf_event_attr, perf_type_id};
-use crate::perf::PerfVersion;
-use crate::registry::MetricExpr;
-use crate::{BaseEvent, Counter, Event};
+use crate::perf::sysfs::{self, PmuFormat};
+use crate::perf::{
+    AttachedProcess, PerfEvent, PerfEventBuilder, PerfEventGroup, PerfEventValue, PerfVersion,
+    UncoreEvent,
+};
+use crate::registry::{CounterLimits, MetricExpr, Pmu};
+use crate::{BaseEvent, Counter, Event, ScaledValue};
 use crate::{Error, Result};
 use derive_more::From;
-use log::{error, warn};
+use log::{debug, warn};
+use nix::libc;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Layout of the configuration of core events used if the kernel does not export the format of
+/// the core PMU.
+fn _default_core_formats() -> HashMap<String, PmuFormat> {
+    let formats: &[(&str, &str)] = if cfg!(target_arch = "x86_64") {
+        &[
+            ("event", "config:0-7"),
+            ("umask", "config:8-15"),
+            ("edge", "config:18"),
+            ("any", "config:21"),
+            ("inv", "config:23"),
+            ("cmask", "config:24-31"),
+            ("ldlat", "config1:0-15"),
+            ("offcore_rsp", "config1:0-63"),
+        ]
+    } else {
+        &[("event", "config:0-63")]
+    };
+    formats
+        .iter()
+        .map(|&(n, f)| (n.into(), PmuFormat::parse(f).unwrap()))
+        .collect()
+}
+
+/// Get the layout of the configuration of the core PMU `pmu`.
+fn _core_formats(pmu: &str) -> HashMap<String, PmuFormat> {
+    match sysfs::pmu_formats(pmu) {
+        Ok(f) if !f.is_empty() => f,
+        _ => _default_core_formats(),
+    }
+}
+
+/// Parse a number such as an `EventCode`, which is hexadecimal if prefixed by `0x` and decimal
+/// otherwise, e.g., in the event files of s390x.
+fn _parse_num(num: &str) -> Result<u64> {
+    let num = num.trim();
+    match num.strip_prefix("0x").or_else(|| num.strip_prefix("0X")) {
+        Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+        None => Ok(num.parse()?),
+    }
+}
+
+/// Parse the `Filter` of an uncore event into fields of the configuration of its PMU.
+///
+/// Filters are either terms naming fields of the format files of the PMU, e.g.,
+/// `filter_opc=0x182,filter_tid=0x3e`, or values of bits of the filter registers, e.g.,
+/// `CBoFilter0[23:17]=0x01`. The filter registers are programmed through `config1`, the first in
+/// its lower and the second in its upper 32 bits.
+fn _parse_filter(filter: &str) -> Result<Vec<(String, u64)>> {
+    let invalid = || Error::InvalidEvent(format!("Invalid filter {}", filter));
+    // Split at commas outside of bit ranges such as `[24:21,17]`
+    let mut terms = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in filter.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                terms.push(&filter[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    terms.push(&filter[start..]);
+
+    let mut fields = Vec::new();
+    let mut config = [0u64; 3];
+    let mut registers = false;
+    for term in terms.into_iter().map(str::trim) {
+        if term.is_empty() || ["null", "na", "n/a"].contains(&&*term.to_lowercase()) {
+            continue;
+        }
+        let (name, value) = match term.split_once('=') {
+            Some((n, v)) => (n.trim(), _parse_num(v)?),
+            None => (term, 1),
+        };
+        match name.split_once('[') {
+            Some((reg, bits)) => {
+                // Index of the filter register, e.g., `1` for `CBoFilter1`
+                let idx = match reg.chars().last().and_then(|c| c.to_digit(10)) {
+                    Some(i) if i < 2 && reg.contains("Filter") => i,
+                    _ => return Err(invalid()),
+                };
+                let mut ranges = Vec::new();
+                for range in bits.strip_suffix(']').ok_or_else(invalid)?.split(',') {
+                    let (hi, lo) = range.split_once(':').unwrap_or((range, range));
+                    let (hi, lo): (u32, u32) = (hi.trim().parse()?, lo.trim().parse()?);
+                    if lo > hi || hi > 31 {
+                        return Err(invalid());
+                    }
+                    ranges.push(lo + 32 * idx..=hi + 32 * idx);
+                }
+                // Ranges are listed starting with the most significant bits
+                ranges.reverse();
+                PmuFormat {
+                    config: 1,
+                    bits: ranges,
+                }
+                .apply(value, &mut config)?;
+                registers = true;
+            }
+            None => fields.push((name.to_string(), value)),
+        }
+    }
+    if registers {
+        fields.push(("config1".into(), config[1]));
+    }
+    Ok(fields)
+}
+
+/// Parse the `Counter` of an event into its fixed counter, e.g., `Fixed counter 0`, or the list
+/// of general purpose counters it can be scheduled on, e.g., `0,1,2,3`.
+fn _parse_counter(counter: &str) -> Result<(Option<u32>, Option<Vec<u32>>)> {
+    let counter = counter.trim();
+    match counter.strip_prefix("Fixed counter") {
+        _ if counter.is_empty() => Ok((None, None)),
+        Some(f) => Ok((Some(f.trim().parse()?), None)),
+        None => {
+            let counters = crate::util::parse_cpu_list(counter)?;
+            Ok((None, Some(counters.into_iter().map(|x| x as u32).collect())))
+        }
+    }
+}
+
+/// Parse a list of errata such as `SKL091, SKL044`.
+fn _parse_errata(errata: &str) -> Vec<String> {
+    errata
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty() && !["null", "n/a", "none", "0"].contains(&&*e.to_lowercase()))
+        .map(String::from)
+        .collect()
+}
+
+/// Split a `ScaleUnit` such as `6.4e-05MiB` into its scale and unit.
+fn _parse_scale_unit(su: &str) -> Result<(f64, String)> {
+    let su = su.trim();
+    // The scale is the longest prefix that is a valid number
+    (1..=su.len())
+        .rev()
+        .filter(|&i| su.is_char_boundary(i))
+        .find_map(|i| {
+            su[..i]
+                .parse::<f64>()
+                .ok()
+                .map(|s| (s, su[i..].trim().into()))
+        })
+        .ok_or_else(|| Error::InvalidEvent(format!("Invalid scale unit {}", su)))
+}
+
+/// Type of the cores of a hybrid CPU, e.g., Alder Lake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoreType {
+    /// Performance cores, counted by the `cpu_core` PMU.
+    Performance,
+    /// Efficiency cores, counted by the `cpu_atom` PMU.
+    Efficiency,
+}
+
+impl CoreType {
+    /// Get the name of the core PMU of this type of cores.
+    pub fn pmu_name(self) -> &'static str {
+        match self {
+            CoreType::Performance => "cpu_core",
+            CoreType::Efficiency => "cpu_atom",
+        }
+    }
+
+    /// Get the type of cores counted by the PMU `pmu`.
+    pub fn from_pmu_name(pmu: &str) -> Option<Self> {
+        match pmu {
+            "cpu_core" => Some(CoreType::Performance),
+            "cpu_atom" => Some(CoreType::Efficiency),
+            _ => None,
+        }
+    }
+}
 
 /// Raw event format represented in the JSON event files.
 pub type RawEvent = std::collections::HashMap<String, String>;
@@ -85,6 +270,8 @@ impl HPCEvent {
             "iMPH-U" => Some("uncore_arb"),
             "CPU-M-CF" => Some("cpum_cf"),
             "CPU-M-SF" => Some("cpum_sf"),
+            "PAI-CRYPTO" => Some("pai_crypto"),
+            "PAI-EXT" => Some("pai_ext"),
             "UPI LL" => Some("uncore_upi"),
             "hisi_sccl,ddrc" => Some("hisi_sccl,ddrc"),
             "hisi_sccl,hha" => Some("hisi_sccl,hha"),
@@ -109,14 +296,14 @@ impl HPCEvent {
         let mut evt_code = 0;
         if let Some(c) = revt.get("EventCode") {
             let splits: Vec<&str> = c.split(',').collect();
-            evt_code |= u64::from_str_radix(&splits[0][2..], 16)?;
+            evt_code |= _parse_num(splits[0])?;
         }
         if let Some(c) = revt.get("ExtSel") {
             evt_code |= u64::from_str_radix(&c.as_str()[2..], 16)? << 21;
         }
         evt.event_code = Some(evt_code);
         if let Some(u) = revt.get("UMask") {
-            evt.umask = Some(u64::from_str_radix(&u[2..], 16)?);
+            evt.umask = Some(_parse_num(u)?);
         }
         if let Some(c) = revt.get("CounterMask") {
             evt.cmask = Some(c.parse()?);
@@ -214,6 +401,8 @@ pub struct MetricEvent {
     expr: (MetricExpr, String),
     /// Metadata for grouping metrics.
     metric_group: Option<String>,
+    /// Condition under which the value of the metric should be highlighted.
+    threshold: Option<MetricExpr>,
 }
 
 impl MetricEvent {
@@ -234,9 +423,210 @@ impl MetricEvent {
         if let Some(mg) = revt.get("MetricGroup") {
             evt.metric_group = Some(mg.clone());
         }
+        evt.threshold = _parse_threshold(&evt.name, revt);
 
         Ok(evt)
     }
+
+    /// Calculate the value of the metric given the `values` of the events it is derived from.
+    pub fn evaluate(&self, values: &HashMap<String, f64>) -> Result<f64> {
+        self.expr.0.evaluate(values)
+    }
+
+    /// Check if the value of the metric exceeds its threshold, see `PmuEvent::exceeds_threshold`.
+    pub fn exceeds_threshold(&self, values: &HashMap<String, f64>) -> Result<Option<bool>> {
+        _exceeds_threshold(&self.threshold, &self.name, values, || {
+            self.evaluate(values)
+        })
+    }
+
+    /// Count the events this metric is derived from for `duration` and calculate its value.
+    ///
+    /// The events are looked up by name in `pmu`, resolving metrics that refer to other metrics,
+    /// and are opened as a group on all threads of the process `target` (`0` selects the current
+    /// process), or on all processes of every online CPU if `target` is `-1`. On a hybrid CPU,
+    /// one group is opened for each type of cores and the counts of each event are summed. The
+    /// counts are scaled for multiplexing and by the scale of each event before evaluating the
+    /// expression. `duration_time` refers to the measured wall clock time in seconds.
+    pub fn collect(&self, pmu: &Pmu, target: libc::pid_t, duration: Duration) -> Result<f64> {
+        let mut events = Vec::new();
+        let mut metrics = Vec::new();
+        _resolve_metric(
+            &self.name,
+            &self.expr.0,
+            pmu,
+            &mut vec![self.name.as_str()],
+            &mut events,
+            &mut metrics,
+        )?;
+        let mut values = _count_events(&self.name, &events, target, duration)?;
+        for m in metrics {
+            let val = m.evaluate(&values)?;
+            values.insert(m.name.clone(), val);
+        }
+        self.evaluate(&values)
+    }
+}
+
+/// Parse the `MetricThreshold` of the metric `name` described by `raw`, if any.
+///
+/// A threshold that cannot be parsed is ignored, so that the metric itself can still be used.
+fn _parse_threshold(name: &str, raw: &RawEvent) -> Option<MetricExpr> {
+    let t = raw.get("MetricThreshold")?;
+    match MetricExpr::parse_str(t) {
+        Ok(expr) => Some(expr),
+        Err(e) => {
+            warn!("Ignoring threshold {:?} of metric {}: {}", t, name, e);
+            None
+        }
+    }
+}
+
+/// Evaluate `threshold` of the metric `name` given the `values` of other metrics and events.
+///
+/// The value of the metric itself is computed with `evaluate` if it is not in `values`.
+fn _exceeds_threshold<F>(
+    threshold: &Option<MetricExpr>,
+    name: &str,
+    values: &HashMap<String, f64>,
+    evaluate: F,
+) -> Result<Option<bool>>
+where
+    F: FnOnce() -> Result<f64>,
+{
+    let threshold = match threshold {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let res = if values.contains_key(name) {
+        threshold.evaluate(values)?
+    } else {
+        let mut values = values.clone();
+        values.insert(name.into(), evaluate()?);
+        threshold.evaluate(&values)?
+    };
+    Ok(Some(res != 0.0))
+}
+
+/// Count `events` as a group for `duration` and get their values indexed by name.
+///
+/// The group is opened on all threads of the process `target`, or on all processes of every
+/// online CPU if `target` is `-1`, see `MetricEvent::collect`. On a hybrid CPU, one group is
+/// opened for each type of cores, see `_split_by_core_pmu`. The values are scaled for
+/// multiplexing and by the scale of each event. Besides the events, the values contain the
+/// `source_count` of each event and the measured `duration_time` in seconds.
+pub(crate) fn _count_events(
+    name: &str,
+    events: &[&PmuEvent],
+    target: libc::pid_t,
+    duration: Duration,
+) -> Result<HashMap<String, f64>> {
+    let attrs = events
+        .iter()
+        .map(|e| e.to_perf_event_attr(None))
+        .collect::<Result<Vec<_>>>()?;
+    let indexed: IndexedAttrs = attrs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| a.iter().map(move |&a| (i, a)))
+        .collect();
+    if indexed.is_empty() {
+        return Err(Error::InvalidEvent(format!(
+            "{} does not use any events",
+            name
+        )));
+    }
+    debug!("Collecting {} from {} events", name, indexed.len());
+
+    let builder = PerfEvent::build().name(name.to_string());
+    let start = Instant::now();
+    let counts = if target == -1 {
+        let mut groups = Vec::new();
+        for (pmu, group) in _split_by_core_pmu(indexed) {
+            let cpus = match pmu {
+                Some(ref p) => sysfs::pmu_cpus(p)?,
+                None => crate::util::online_cpus()?,
+            };
+            let (index, attrs): (Vec<_>, Vec<_>) = group.into_iter().unzip();
+            for (_, g) in builder.clone().open_group_on_cpus(&cpus, attrs)? {
+                groups.push((index.clone(), g));
+            }
+        }
+        _measure_groups(name, groups, events.len(), duration)?
+    } else {
+        let mut groups = Vec::new();
+        for (_, group) in _split_by_core_pmu(indexed) {
+            let (index, attrs): (Vec<_>, Vec<_>) = group.into_iter().unzip();
+            groups.push((index, builder.clone().attach_group(target, attrs)?));
+        }
+        _measure_groups(name, groups, events.len(), duration)?
+    };
+    let elapsed = start.elapsed();
+
+    let mut values = HashMap::new();
+    values.insert("duration_time".to_string(), elapsed.as_secs_f64());
+    for ((evt, a), total) in events.iter().zip(attrs.iter()).zip(counts) {
+        values.insert(evt.name.clone(), evt.scaled(&total).0);
+        values.insert(format!("source_count({})", evt.name), a.len() as f64);
+    }
+    Ok(values)
+}
+
+/// Let the counting `groups` of `len` events run for `duration` and read the value of each event
+/// summed over all groups, see `HybridGroup`.
+fn _measure_groups<C: Counter<Vec<PerfEventValue>>>(
+    name: &str,
+    groups: Vec<(Vec<usize>, C)>,
+    len: usize,
+    duration: Duration,
+) -> Result<Vec<PerfEventValue>> {
+    let group = HybridGroup {
+        name: name.to_string(),
+        groups,
+        len,
+    };
+    std::thread::sleep(duration);
+    group.disable()?;
+    group.read_sync()
+}
+
+/// Find the events in `pmu` needed to evaluate `expr` of the metric `name`.
+///
+/// Plain events are added to `events`. Metrics referred to by `expr` are resolved recursively and
+/// added to `metrics` after all metrics they depend on. `chain` holds the metrics currently being
+/// resolved to detect cycles.
+pub(crate) fn _resolve_metric<'a>(
+    name: &str,
+    expr: &MetricExpr,
+    pmu: &'a Pmu,
+    chain: &mut Vec<&'a str>,
+    events: &mut Vec<&'a PmuEvent>,
+    metrics: &mut Vec<&'a PmuEvent>,
+) -> Result<()> {
+    for var in expr.get_counters() {
+        if var == "duration_time" || events.iter().chain(metrics.iter()).any(|e| e.name == *var) {
+            continue;
+        }
+        let evt = pmu.events.iter().find(|e| e.name == *var).ok_or_else(|| {
+            Error::InvalidEvent(format!("Cannot resolve {} in metric {}", var, name))
+        })?;
+        match evt.parsed_metric_expr {
+            Some(ref inner) if evt.is_metric => {
+                if chain.contains(&evt.name.as_str()) {
+                    return Err(Error::InvalidEvent(format!(
+                        "Metric {} depends on itself",
+                        evt.name
+                    )));
+                }
+                chain.push(&evt.name);
+                _resolve_metric(&evt.name, inner, pmu, chain, events, metrics)?;
+                chain.pop();
+                metrics.push(evt);
+            }
+            _ => events.push(evt),
+        }
+    }
+    Ok(())
 }
 
 //impl crate::EventGroup<u64> for MetricEvent {
@@ -275,6 +665,113 @@ impl PartialEq for MetricEvent {
 
 impl Eq for MetricEvent {}
 
+/// `perf_event_attrs` of a group of events, each with the index of the event it belongs to.
+pub(crate) type IndexedAttrs = Vec<(usize, perf_event_attr)>;
+
+/// Split the `perf_event_attrs` of a group into groups that can be opened, one for each core PMU
+/// of a hybrid CPU.
+///
+/// Events counted by `cpu_core` and `cpu_atom` cannot be scheduled in the same group, so each of
+/// these PMUs gets a group with its own leader. The remaining events, e.g., all events of a CPU
+/// with a single core PMU, form one group. Returns the groups together with the name of their
+/// hybrid PMU.
+pub(crate) fn _split_by_core_pmu(attrs: IndexedAttrs) -> Vec<(Option<String>, IndexedAttrs)> {
+    let core_pmus: Vec<(String, u32)> = sysfs::hybrid_pmus()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|p| {
+            let ty = sysfs::pmu_type(&p).ok()?;
+            Some((p, ty))
+        })
+        .collect();
+    _split_by_type(attrs, &core_pmus)
+}
+
+/// Implementation of `_split_by_core_pmu` given the name and type of each hybrid PMU.
+fn _split_by_type(
+    attrs: IndexedAttrs,
+    core_pmus: &[(String, u32)],
+) -> Vec<(Option<String>, IndexedAttrs)> {
+    let mut groups: Vec<(Option<String>, IndexedAttrs)> = Vec::new();
+    for (i, attr) in attrs {
+        let pmu = core_pmus
+            .iter()
+            .find(|(_, ty)| *ty == attr.type_)
+            .map(|(p, _)| p.clone());
+        match groups.iter_mut().find(|(p, _)| *p == pmu) {
+            Some((_, group)) => group.push((i, attr)),
+            None => groups.push((pmu, vec![(i, attr)])),
+        }
+    }
+    groups
+}
+
+/// Groups of events opened by `PmuEvent::open` and `PmuEvent::attach`, one for each core PMU of a
+/// hybrid CPU.
+///
+/// Events counted by `cpu_core` and `cpu_atom` cannot be scheduled in the same group, so each PMU
+/// gets a group with its own leader, see `_split_by_core_pmu`. The value of each event is the sum
+/// of its counts on all PMUs. On CPUs with a single core PMU, this holds a single group.
+#[derive(Debug)]
+pub struct HybridGroup<C> {
+    /// Name of the event.
+    name: String,
+    /// Groups together with the index of the event of each of their members.
+    groups: Vec<(Vec<usize>, C)>,
+    /// Number of events, i.e., `1` for an event or the number of events of a metric.
+    len: usize,
+}
+
+impl<C: Counter<Vec<PerfEventValue>>> Counter<Vec<PerfEventValue>> for HybridGroup<C> {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn enable(&self) -> Result<()> {
+        self.groups.iter().try_for_each(|(_, g)| g.enable())
+    }
+
+    fn disable(&self) -> Result<()> {
+        self.groups.iter().try_for_each(|(_, g)| g.disable())
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.groups.iter().try_for_each(|(_, g)| g.reset())
+    }
+
+    fn is_closed(&self) -> Result<bool> {
+        for (_, g) in self.groups.iter() {
+            if g.is_closed()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Read the value of each event, summed over the groups of all PMUs counting it.
+    fn read_sync(&self) -> Result<Vec<PerfEventValue>> {
+        let mut values = vec![Vec::new(); self.len];
+        for (index, g) in self.groups.iter() {
+            for (&i, v) in index.iter().zip(g.read_sync()?) {
+                values[i].push(v);
+            }
+        }
+        Ok(values.into_iter().map(PerfEventValue::_sum).collect())
+    }
+}
+
+impl<C> HybridGroup<C> {
+    /// Get the groups, one for each core PMU of a hybrid CPU.
+    pub fn groups(&self) -> impl Iterator<Item = &C> {
+        self.groups.iter().map(|(_, g)| g)
+    }
+
+    /// Get mutable access to the groups, e.g., to refresh the threads of an `AttachedProcess`.
+    pub fn groups_mut(&mut self) -> impl Iterator<Item = &mut C> {
+        self.groups.iter_mut().map(|(_, g)| g)
+    }
+}
+
 /// Abstraction for a performance counter event.
 #[derive(Debug, Default, Clone)]
 pub struct PmuEvent {
@@ -297,18 +794,30 @@ pub struct PmuEvent {
     cmask: Option<u8>,
     edge: bool,
     inv: bool,
+    any_thread: bool,
     msr: Option<u64>,
     msr_val: Option<u64>,
+    fc_mask: Option<u64>,
+    port_mask: Option<u64>,
+    filter: Vec<(String, u64)>,
     pmu: Option<String>,
     unit: Option<String>,
     offcore_rsp: bool,
     ldlat: bool,
     frontend: bool,
+    counters: Option<Vec<u32>>,
+    fixed_counter: Option<u32>,
+    sysfs_terms: Option<String>,
+    scale: Option<f64>,
+    scale_unit: Option<String>,
+    per_pkg: bool,
+    errata: Vec<String>,
 
     // Fields dealing with derived events
     metric_group: Option<String>,
     metric_expr: Option<String>,
     parsed_metric_expr: Option<MetricExpr>,
+    metric_threshold: Option<MetricExpr>,
 }
 
 impl PmuEvent {
@@ -322,11 +831,15 @@ impl PmuEvent {
             "iMPH-U" => Some("uncore_arb"),
             "CPU-M-CF" => Some("cpum_cf"),
             "CPU-M-SF" => Some("cpum_sf"),
+            "PAI-CRYPTO" => Some("pai_crypto"),
+            "PAI-EXT" => Some("pai_ext"),
             "UPI LL" => Some("uncore_upi"),
             "hisi_sccl,ddrc" => Some("hisi_sccl,ddrc"),
             "hisi_sccl,hha" => Some("hisi_sccl,hha"),
             "hisi_sccl,l3c" => Some("hisi_sccl,l3c"),
             "L3PMC" => Some("amd_l3"),
+            "cpu_core" => Some("cpu_core"),
+            "cpu_atom" => Some("cpu_atom"),
             _ => None,
         }
     }
@@ -342,14 +855,14 @@ impl PmuEvent {
             let mut evt_code = 0;
             if let Some(c) = raw_event.get("EventCode") {
                 let splits: Vec<&str> = c.split(',').collect();
-                evt_code |= u64::from_str_radix(&splits[0][2..], 16)?;
+                evt_code |= _parse_num(splits[0])?;
             }
             if let Some(c) = raw_event.get("ExtSel") {
                 evt_code |= u64::from_str_radix(&c.as_str()[2..], 16)? << 21;
             }
             evt.event_code = Some(evt_code);
             if let Some(u) = raw_event.get("UMask") {
-                evt.umask = Some(u64::from_str_radix(&u[2..], 16)?);
+                evt.umask = Some(_parse_num(u)?);
             }
             if let Some(c) = raw_event.get("CounterMask") {
                 evt.cmask = Some(c.parse()?);
@@ -360,6 +873,22 @@ impl PmuEvent {
             if let Some(i) = raw_event.get("Invert") {
                 evt.inv = (i.parse::<i32>()?) != 0;
             }
+            if let Some(a) = raw_event.get("AnyThread") {
+                evt.any_thread = (a.parse::<i32>()?) != 0;
+            }
+            if let Some(f) = raw_event.get("FCMask") {
+                evt.fc_mask = Some(_parse_num(f)?).filter(|&f| f != 0);
+            }
+            if let Some(p) = raw_event.get("PortMask") {
+                evt.port_mask = Some(_parse_num(p)?).filter(|&p| p != 0);
+            }
+            if let Some(f) = raw_event.get("Filter") {
+                // A filter that cannot be parsed is ignored, so that the event can still be used
+                evt.filter = _parse_filter(f).unwrap_or_else(|e| {
+                    warn!("Ignoring filter {:?} of event {}: {}", f, evt.name, e);
+                    Vec::new()
+                });
+            }
             if let Some(msr) = raw_event.get("MSRIndex") {
                 let split: Vec<&str> = msr.split(',').collect();
                 evt.msr = if split[0].len() == 1 {
@@ -383,6 +912,15 @@ impl PmuEvent {
                     evt.frontend = true;
                 }
             }
+            if let Some(c) = raw_event.get("Counter") {
+                match _parse_counter(c) {
+                    Ok((fixed, counters)) => {
+                        evt.fixed_counter = fixed;
+                        evt.counters = counters;
+                    }
+                    Err(e) => warn!("Ignoring counters {:?} of event {}: {}", c, evt.name, e),
+                }
+            }
             if let Some(u) = raw_event.get("Unit") {
                 if u == "NCU" {
                     evt.umask = Some(0);
@@ -392,7 +930,8 @@ impl PmuEvent {
                 evt.pmu = if let Some(pmu) = PmuEvent::_pmu_from_json(u.as_str()) {
                     Some(String::from(pmu))
                 } else {
-                    Some(format!("uncore_{}", u))
+                    // Names of uncore PMUs are lower case, e.g., `uncore_iio` for `IIO`
+                    Some(format!("uncore_{}", u.to_lowercase()))
                 };
             }
         } else if let Some(n) = raw_event.get("MetricName") {
@@ -405,11 +944,23 @@ impl PmuEvent {
             }
             evt.parsed_metric_expr = Some(MetricExpr::parse_str(expr.as_str())?);
             evt.metric_expr = Some(expr);
+            evt.metric_threshold = _parse_threshold(n, raw_event);
         } else {
             return Err(crate::Error::ParseEvent(raw_event.clone()));
         }
 
         evt.topic = raw_event.get("Topic").expect("Topic").clone();
+        if let Some(e) = raw_event.get("Errata") {
+            evt.errata = _parse_errata(e);
+        }
+        if let Some(p) = raw_event.get("PerPkg") {
+            evt.per_pkg = (p.parse::<i32>()?) != 0;
+        }
+        if let Some(su) = raw_event.get("ScaleUnit") {
+            let (scale, unit) = _parse_scale_unit(su)?;
+            evt.scale = Some(scale);
+            evt.scale_unit = Some(unit);
+        }
         if let Some(d) = raw_event.get("BriefDescription") {
             evt.desc = d.clone();
         }
@@ -421,21 +972,231 @@ impl PmuEvent {
         Ok(evt)
     }
 
+    /// Create a new `PmuEvent` for the tracepoint `name` of the form `subsystem:event` with the
+    /// id `id`, e.g., `sched:sched_switch`.
+    pub fn from_tracepoint(name: &str, id: u64) -> Self {
+        let subsys = name.split(':').next().unwrap_or_default();
+        PmuEvent {
+            name: name.into(),
+            topic: "tracepoint".into(),
+            desc: format!("Tracepoint {} of subsystem {}", name, subsys),
+            pmu: Some("tracepoint".into()),
+            sysfs_terms: Some(format!("config={}", id)),
+            ..Default::default()
+        }
+    }
+
+    /// Start building an event named `name` with event code `event_code` from code, see
+    /// `PmuEventBuilder`.
+    pub fn builder<S: Into<String>>(name: S, event_code: u64) -> PmuEventBuilder {
+        PmuEventBuilder {
+            event: PmuEvent {
+                name: name.into(),
+                topic: "custom".into(),
+                event_code: Some(event_code),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Create a new `PmuEvent` from the event alias `name` of PMU `pmu` exported through sysfs.
+    ///
+    /// `terms` are the contents of the file describing the alias, e.g., `event=0x3c,umask=0x00`.
+    /// The event is named as in the `perf` tool, e.g., `msr/tsc/`.
+    pub fn from_sysfs(pmu: &str, name: &str, terms: &str) -> Self {
+        let (scale, scale_unit) = match sysfs::pmu_event_scale(pmu, name) {
+            Ok(Some((s, u))) => (Some(s), Some(u)),
+            _ => (None, None),
+        };
+        PmuEvent {
+            scale,
+            scale_unit,
+            name: format!("{}/{}/", pmu, name),
+            topic: pmu.into(),
+            desc: format!("{} event of PMU {}", name, pmu),
+            pmu: Some(pmu.into()),
+            sysfs_terms: Some(terms.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Get the general purpose counters this event can be counted on as a bitmask, e.g., `0b0100`
+    /// for `"Counter": "2"`.
+    ///
+    /// Returns `None` if the event is not restricted to some counters, including events whose
+    /// `Counter` cannot be parsed, or is only counted by a fixed function counter, see
+    /// `fixed_counter`.
+    pub fn counter_mask(&self) -> Option<u64> {
+        if self.fixed_counter.is_some() {
+            return None;
+        }
+        self.counters
+            .as_ref()
+            .map(|c| c.iter().filter(|&&x| x < 64).fold(0, |m, &x| m | (1 << x)))
+    }
+
+    /// Get the fixed function counter counting this event, e.g., `0` for
+    /// `"Counter": "Fixed counter 0"`.
+    pub fn fixed_counter(&self) -> Option<u32> {
+        self.fixed_counter
+    }
+
+    /// Get the name of the PMU counting this event, e.g., `uncore_imc` or `cpu_core`.
+    ///
+    /// Returns `None` for events of the core PMU that are not bound to a type of cores.
+    pub fn pmu(&self) -> Option<&str> {
+        self.pmu.as_deref()
+    }
+
+    /// Get the parsed expression of this metric, or `None` if this is not a metric.
+    pub fn metric_expr(&self) -> Option<&MetricExpr> {
+        self.parsed_metric_expr.as_ref().filter(|_| self.is_metric)
+    }
+
+    /// Get the metric groups this metric belongs to, e.g., `TopdownL1`.
+    ///
+    /// Events that are not metrics do not belong to any group.
+    pub fn metric_groups(&self) -> Vec<&str> {
+        match self.metric_group {
+            Some(ref mg) => mg
+                .split(&[';', ','][..])
+                .map(str::trim)
+                .filter(|g| !g.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the identifiers of the known silicon errata affecting this event, e.g., `SKL091`.
+    ///
+    /// Counts of affected events may be unreliable, see the specification update of the CPU.
+    pub fn errata(&self) -> &[String] {
+        &self.errata
+    }
+
+    /// Warn about errata affecting this event, or the events needed to compute this metric.
+    fn _warn_errata(&self, events: Option<&Vec<PmuEvent>>) {
+        let evts = if self.is_metric {
+            self._get_metric_events(events)
+        } else {
+            vec![self]
+        };
+        for evt in evts.iter().filter(|e| !e.errata.is_empty()) {
+            warn!(
+                "Counts of {} may be unreliable due to errata {}",
+                evt.name,
+                evt.errata.join(", ")
+            );
+        }
+    }
+
+    /// Check if this event counts occurrences of a whole package (socket), i.e., it should be
+    /// counted on a single CPU of each package, see `open_per_package`.
+    pub fn is_per_pkg(&self) -> bool {
+        self.per_pkg
+    }
+
+    /// Calculate the value of this metric given the `values` of the events it is derived from,
+    /// indexed by their names.
+    ///
+    /// The value of a plain event is looked up in `values` directly. See `MetricExpr::evaluate`.
+    pub fn evaluate(&self, values: &HashMap<String, f64>) -> Result<f64> {
+        match self.parsed_metric_expr {
+            Some(ref expr) => expr.evaluate(values),
+            None => values
+                .get(&self.name)
+                .copied()
+                .ok_or_else(|| Error::InvalidEvent(format!("No value for event {}", self.name))),
+        }
+    }
+
+    /// Check if the value of this metric exceeds its `MetricThreshold`, i.e., if `perf` would
+    /// highlight it, e.g., `tma_frontend_bound > 0.15`.
+    ///
+    /// Thresholds often refer to other metrics, e.g., the parent of a top-down metric, so
+    /// `values` should hold the values of all metrics involved, indexed by their names. The value
+    /// of this metric is calculated from `values` if it is missing. Returns `None` for events
+    /// without a threshold.
+    pub fn exceeds_threshold(&self, values: &HashMap<String, f64>) -> Result<Option<bool>> {
+        _exceeds_threshold(&self.metric_threshold, &self.name, values, || {
+            self.evaluate(values)
+        })
+    }
+
+    /// Convert a count of this event into the unit it is reported in, e.g., `MiB` for bandwidth
+    /// events or `Joules` for energy events.
+    ///
+    /// Returns the value scaled for multiplexing and by the scale of the event, together with the
+    /// unit. Events without a unit are reported as plain counts with an empty unit.
+    pub fn scaled(&self, value: &PerfEventValue) -> (f64, &str) {
+        (
+            value.scaled_value() as f64 * self.scale.unwrap_or(1.0),
+            self.scale_unit.as_deref().unwrap_or(""),
+        )
+    }
+
+    /// Get the type of cores counting this event on a hybrid CPU.
+    ///
+    /// Returns `None` for events that are not specific to one type of cores.
+    pub fn core_type(&self) -> Option<CoreType> {
+        self.pmu.as_ref().and_then(|p| CoreType::from_pmu_name(p))
+    }
+
+    /// Restrict this event to the cores of type `ty` of a hybrid CPU.
+    ///
+    /// Core events that are not specific to a type of cores are bound to the PMU of `ty`. Returns
+    /// `None` if this event can only be counted on the other type of cores. Uncore events and
+    /// metrics are returned unchanged.
+    pub fn for_core_type(&self, ty: CoreType) -> Option<Self> {
+        if self.is_metric || self.sysfs_terms.is_some() {
+            return Some(self.clone());
+        }
+        match (self.core_type(), &self.pmu) {
+            (Some(t), _) if t != ty => None,
+            (None, None) => {
+                let mut evt = self.clone();
+                evt.pmu = Some(ty.pmu_name().into());
+                evt.unit = Some(ty.pmu_name().into());
+                Some(evt)
+            }
+            _ => Some(self.clone()),
+        }
+    }
+
+    /// Check if this event is counted by the CPU measurement facilities of s390x, e.g., `cpum_cf`.
+    ///
+    /// Unlike uncore PMUs, these count on every CPU like core PMUs.
+    fn _is_cpu_measurement(&self) -> bool {
+        matches!(self.pmu, Some(ref p) if p.starts_with("cpum_") || p.starts_with("pai_"))
+    }
+
+    /// Check if this event is counted on each box of an uncore PMU, see `open_uncore`.
+    ///
+    /// Event aliases of sysfs are counted like uncore events if their PMU only counts
+    /// system-wide, e.g., `power/energy-pkg/` or `cstate_core/c6-residency/`.
+    pub(crate) fn _is_uncore(&self) -> bool {
+        match (&self.pmu, &self.sysfs_terms) {
+            _ if self.is_metric || self.core_type().is_some() || self._is_cpu_measurement() => {
+                false
+            }
+            (Some(pmu), Some(_)) => sysfs::pmu_is_system_wide(pmu),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
     /// Perf strings for core events.
     fn _get_core_event_string(&self, is_direct: bool, put_name: bool) -> String {
         assert!(self.event_code.is_some());
         if is_direct {
-            let umask = if let Some(u) = self.umask {
-                format!("{:X}", u & 0xFF)
-            } else {
-                String::default()
-            };
-            let event_code = if cfg!(target_arch = "x86_64") {
-                self.event_code.unwrap() & 0xFF
+            let umask = self.umask.map_or(0, |u| u & 0xFF);
+            let config = if cfg!(target_arch = "x86_64") {
+                let any = if self.any_thread { 1 << 21 } else { 0 };
+                any | umask << 8 | (self.event_code.unwrap() & 0xFF)
             } else {
-                self.event_code.unwrap()
+                umask << 8 | self.event_code.unwrap()
             };
-            format!("r{}{:X}", umask, event_code)
+            format!("r{:X}", config)
         } else {
             let umask = if let Some(u) = self.umask {
                 format!(",umask={:#X}", u)
@@ -457,6 +1218,11 @@ impl PmuEvent {
             } else {
                 String::default()
             };
+            let any = if self.any_thread {
+                String::from(",any=1")
+            } else {
+                String::default()
+            };
             let name = if put_name {
                 format!(
                     ",name={}",
@@ -469,12 +1235,13 @@ impl PmuEvent {
                 String::default()
             };
             format!(
-                "cpu/event={:#X}{}{}{}{}{}/",
+                "cpu/event={:#X}{}{}{}{}{}{}/",
                 self.event_code.unwrap(),
                 umask,
                 cmask,
                 edge,
                 inv,
+                any,
                 name
             )
         }
@@ -502,13 +1269,19 @@ impl PmuEvent {
         } else {
             String::default()
         };
-        let name = if put_name {
+        let masks = self
+            ._mask_fields()
+            .iter()
+            .fold(String::new(), |s, (n, v)| format!("{},{}={:#X}", s, n, v));
+        let name = if put_name && self._is_cpu_measurement() {
+            format!(",name={}", self.name.replace(".", "_"))
+        } else if put_name {
             format!(",name={}_NUM", self.name.replace(".", "_"))
         } else {
             String::default()
         };
         format!(
-            "{}/event={:#X}{}{}{}{}{}/",
+            "{}/event={:#X}{}{}{}{}{}{}/",
             match self.pmu {
                 Some(ref p) => p,
                 _ => unreachable!(),
@@ -518,6 +1291,7 @@ impl PmuEvent {
             cmask,
             edge,
             inv,
+            masks,
             name
         )
     }
@@ -561,7 +1335,9 @@ impl PmuEvent {
     /// correct `PmuEvent` corresponding to the metric. If one is sure that `self` is not a metric
     /// event,
     pub fn to_perf_string(&self, pv: &PerfVersion, events: Option<&Vec<PmuEvent>>) -> String {
-        if !self.is_metric {
+        if self.sysfs_terms.is_some() {
+            self.name.clone()
+        } else if !self.is_metric {
             if self.unit.is_none() {
                 self._get_core_event_string(pv.direct(), pv.has_name())
             } else {
@@ -584,51 +1360,31 @@ impl PmuEvent {
         &self,
         events: Option<&Vec<PmuEvent>>,
     ) -> Result<Vec<perf_event_attr>> {
-        let evts = if !self.is_metric {
-            let mut attr = perf_event_attr::default();
-            attr.type_ = perf_type_id::PERF_TYPE_RAW as _;
+        let evts = if let (Some(terms), Some(pmu)) = (&self.sysfs_terms, &self.pmu) {
+            let mut attr = crate::perf::sysfs::encode_terms(pmu, terms)?;
             attr.size = std::mem::size_of::<perf_event_attr>() as _;
-            if let Some(e) = self.event_code {
-                if cfg!(target_arch = "x86_64") {
-                    attr.config |= e & 0xFF;
+            vec![attr]
+        } else if !self.is_metric {
+            if self.pmu.is_some() {
+                self._box_attrs()?.into_iter().map(|(_, a)| a).collect()
+            } else {
+                let hybrid = sysfs::hybrid_pmus().unwrap_or_default();
+                if hybrid.is_empty() {
+                    let mut attr = self._encode_config(&_core_formats("cpu"))?;
+                    attr.type_ = perf_type_id::PERF_TYPE_RAW as _;
+                    vec![attr]
                 } else {
-                    attr.config |= e;
+                    // Count the event on all types of cores of a hybrid CPU
+                    hybrid
+                        .iter()
+                        .map(|pmu| {
+                            let mut attr = self._encode_config(&_core_formats(pmu))?;
+                            attr.type_ = sysfs::pmu_type(pmu)?;
+                            Ok(attr)
+                        })
+                        .collect::<Result<_>>()?
                 }
             }
-            if let Some(u) = self.umask {
-                attr.config |= (u as u64 & 0xFF) << 8;
-            }
-            if let Some(c) = self.cmask {
-                attr.config |= (c as u64 & 0xF) << 24;
-            }
-            if self.inv {
-                attr.config |= 1u64 << 23;
-            }
-            if self.edge {
-                attr.config |= 1u64 << 18;
-            }
-            if self.offcore_rsp {
-                unsafe { attr.__bindgen_anon_3.config1 |= self.msr_val.unwrap() }
-            } else if self.ldlat {
-                unsafe { attr.__bindgen_anon_3.config1 |= self.msr_val.unwrap() & 0xFFFF }
-            }
-            if let Some(ref pmu) = self.pmu {
-                glob::glob(&format!("/sys/devices/{}*/type", pmu))?
-                    .filter_map(std::result::Result::ok)
-                    .map(|path| {
-                        let mut a = attr;
-                        a.type_ = std::fs::read_to_string(&path)
-                            .map(|s| s.trim().parse::<u32>().unwrap())
-                            .unwrap_or_else(|_| {
-                                error!("Could not read {:?} for event {}", path, self.name);
-                                0
-                            });
-                        a
-                    })
-                    .collect()
-            } else {
-                vec![attr]
-            }
         } else {
             self._get_metric_events(events)
                 .iter()
@@ -637,6 +1393,417 @@ impl PmuEvent {
         };
         Ok(evts)
     }
+
+    /// Get the `perf_event_attr` of this event for each box of its uncore PMU, e.g., for each
+    /// `uncore_cbox_N`, together with the name of the box.
+    pub(crate) fn _box_attrs(&self) -> Result<Vec<(String, perf_event_attr)>> {
+        let pmu = self.pmu.as_ref().ok_or(Error::NoneError)?;
+        sysfs::pmu_boxes(pmu)?
+            .into_iter()
+            .map(|b| {
+                let mut attr = match self.sysfs_terms {
+                    Some(ref terms) => sysfs::encode_terms(&b, terms)?,
+                    None => {
+                        let mut attr = self._encode_config(&sysfs::pmu_formats(&b)?)?;
+                        attr.type_ = sysfs::pmu_type(&b)?;
+                        attr
+                    }
+                };
+                attr.size = std::mem::size_of::<perf_event_attr>() as _;
+                Ok((b, attr))
+            })
+            .collect()
+    }
+
+    /// Get the function and port masks of uncore events, e.g., of the IIO PMUs, and their filters
+    /// by the names of the fields holding them in the format files of the PMU.
+    fn _mask_fields(&self) -> Vec<(&str, u64)> {
+        let mut fields = Vec::new();
+        if let Some(f) = self.fc_mask {
+            fields.push(("fc_mask", f));
+        }
+        if let Some(p) = self.port_mask {
+            fields.push(("ch_mask", p));
+        }
+        fields.extend(self.filter.iter().map(|(n, v)| (n.as_str(), *v)));
+        fields
+    }
+
+    /// Build the configuration of this event placing its fields at the bits described by
+    /// `formats`, i.e., the format files of the PMU counting the event.
+    fn _encode_config(&self, formats: &HashMap<String, PmuFormat>) -> Result<perf_event_attr> {
+        let mut fields = Vec::new();
+        if let Some(e) = self.event_code {
+            let width = formats.get("event").map_or(64, PmuFormat::width);
+            fields.push((
+                "event",
+                if width < 64 {
+                    e & ((1 << width) - 1)
+                } else {
+                    e
+                },
+            ));
+        }
+        if let Some(u) = self.umask {
+            fields.push(("umask", u));
+        }
+        if let Some(c) = self.cmask {
+            // Uncore PMUs call the counter mask threshold
+            let name = if formats.contains_key("cmask") {
+                "cmask"
+            } else {
+                "thresh"
+            };
+            fields.push((name, c as u64));
+        }
+        if self.inv {
+            fields.push(("inv", 1));
+        }
+        if self.any_thread {
+            fields.push(("any", 1));
+        }
+        if self.edge {
+            fields.push(("edge", 1));
+        }
+        fields.extend(self._mask_fields());
+        if self.offcore_rsp {
+            fields.push(("offcore_rsp", self.msr_val.unwrap()));
+        } else if self.ldlat {
+            fields.push(("ldlat", self.msr_val.unwrap() & 0xFFFF));
+        }
+        let mut attr = sysfs::encode_fields(formats, &fields)
+            .map_err(|e| Error::InvalidEvent(format!("{}: {}", self.name, e)))?;
+        attr.size = std::mem::size_of::<perf_event_attr>() as _;
+        Ok(attr)
+    }
+
+    /// Get the counters of the core PMU this event can be scheduled on given `limits`.
+    ///
+    /// Counters are numbered with the general purpose counters first, followed by the fixed
+    /// function counters. Returns `None` for uncore and metric events.
+    pub(crate) fn _allowed_counters(&self, limits: &CounterLimits) -> Option<Vec<usize>> {
+        if self.is_metric || (self.pmu.is_some() && self.core_type().is_none()) {
+            return None;
+        }
+        let code = (self.event_code.unwrap_or(0), self.umask.unwrap_or(0));
+        let allowed = match (self.fixed_counter, &self.counters) {
+            (None, _) if limits._is_unavailable(code.0, code.1) => vec![],
+            (Some(f), _) if (f as usize) < limits.fixed => vec![limits.general + f as usize],
+            (Some(_), _) => vec![],
+            (None, Some(c)) => c
+                .iter()
+                .map(|&x| x as usize)
+                .filter(|&x| x < limits.general)
+                .collect(),
+            (None, None) => (0..limits.general).collect(),
+        };
+        Some(allowed)
+    }
+
+    /// Check that this event, or all events needed to compute this metric, can be counted
+    /// together on the local CPU.
+    ///
+    /// Succeeds if the limits of the local CPU are unknown.
+    pub fn check_schedulable(&self, events: Option<&Vec<PmuEvent>>) -> Result<()> {
+        let evts = if self.is_metric {
+            self._get_metric_events(events)
+        } else {
+            vec![self]
+        };
+        match CounterLimits::from_local_cpu() {
+            Ok(limits) => limits.check_group(&evts),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Check if this event, or all events needed to compute this metric, can be counted on the
+    /// local machine.
+    ///
+    /// Each event is opened for a moment using `perf::probe_event`.
+    pub fn is_supported(&self, events: Option<&Vec<PmuEvent>>) -> bool {
+        match self.to_perf_event_attr(events) {
+            Ok(attrs) => {
+                !attrs.is_empty()
+                    && attrs
+                        .iter()
+                        .all(|a| crate::perf::probe_event(a).is_supported())
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Get the `perf_event_attrs` returned by `to_perf_event_attr` with the index of the event each
+    /// of them belongs to, i.e., `0` for this event or the index among the events of a metric.
+    ///
+    /// Also returns the number of events.
+    fn _indexed_attrs(&self, events: Option<&Vec<PmuEvent>>) -> Result<(usize, IndexedAttrs)> {
+        if !self.is_metric {
+            let attrs = self.to_perf_event_attr(None)?;
+            return Ok((1, attrs.into_iter().map(|a| (0, a)).collect()));
+        }
+        let evts = self._get_metric_events(events);
+        let attrs = evts
+            .iter()
+            .enumerate()
+            .flat_map(|(i, x)| {
+                x.to_perf_event_attr(None)
+                    .unwrap_or_else(|_| vec![])
+                    .into_iter()
+                    .map(move |a| (i, a))
+            })
+            .collect();
+        Ok((evts.len(), attrs))
+    }
+
+    /// Open the `perf_event_attrs` of this event as one group for each core PMU of a hybrid CPU.
+    ///
+    /// `open` is called with the builder, the name of the hybrid PMU and the attributes of each
+    /// group, see `_split_by_core_pmu`.
+    fn _open_groups<C, F>(
+        &self,
+        builder: PerfEventBuilder,
+        events: Option<&Vec<PmuEvent>>,
+        mut open: F,
+    ) -> Result<HybridGroup<C>>
+    where
+        F: FnMut(PerfEventBuilder, Option<&str>, Vec<perf_event_attr>) -> Result<C>,
+    {
+        self.check_schedulable(events)?;
+        self._warn_errata(events);
+        let (len, attrs) = self._indexed_attrs(events)?;
+        if attrs.is_empty() {
+            return Err(Error::InvalidEvent(format!(
+                "{} does not use any events",
+                self.name
+            )));
+        }
+        let builder = builder.name(self.name.clone());
+        let mut groups = Vec::new();
+        for (pmu, group) in _split_by_core_pmu(attrs) {
+            debug!("Opening {} on PMU {:?}", self.name, pmu);
+            let (index, attrs): (Vec<_>, Vec<_>) = group.into_iter().unzip();
+            groups.push((index, open(builder.clone(), pmu.as_deref(), attrs)?));
+        }
+        Ok(HybridGroup {
+            name: self.name.clone(),
+            groups,
+            len,
+        })
+    }
+
+    /// Open this event as a group configured by `builder`.
+    ///
+    /// The group contains all `perf_event_attrs` returned by `to_perf_event_attr`, e.g., all
+    /// events needed to compute a metric. On a hybrid CPU, one group is opened for each type of
+    /// cores, see `HybridGroup`. Fails with `Error::CounterConflict` if the events cannot be
+    /// counted together. Logs a warning if any of the events is affected by errata.
+    pub fn open(
+        &self,
+        builder: PerfEventBuilder,
+        events: Option<&Vec<PmuEvent>>,
+    ) -> Result<HybridGroup<PerfEventGroup>> {
+        self._open_groups(builder, events, |b, _, attrs| b.open_group(attrs))
+    }
+
+    /// Open this event as a group configured by `builder` once per package (socket), on the first
+    /// CPU of each package that can count it.
+    ///
+    /// This is how events for which `is_per_pkg` holds should be counted, as opening them on
+    /// every CPU counts each occurrence once per CPU of the package. Returns the groups together
+    /// with the ID of their package.
+    pub fn open_per_package(
+        &self,
+        builder: PerfEventBuilder,
+        events: Option<&Vec<PmuEvent>>,
+    ) -> Result<Vec<(i32, HybridGroup<PerfEventGroup>)>> {
+        let cpus = crate::util::cpu_packages()?;
+        crate::util::package_cpus()?
+            .into_iter()
+            .map(|(pkg, _)| {
+                let group = self._open_groups(builder.clone(), events, |b, pmu, attrs| {
+                    let allowed = match pmu {
+                        Some(p) => sysfs::pmu_cpus(p)?,
+                        None => crate::util::online_cpus()?,
+                    };
+                    let cpu = cpus
+                        .iter()
+                        .find(|&&(c, p)| p == pkg && allowed.contains(&c))
+                        .map(|&(c, _)| c)
+                        .ok_or(Error::NoneError)?;
+                    let mut groups = b.open_group_on_cpus(&[cpu], attrs)?;
+                    Ok(groups.remove(0).1)
+                })?;
+                Ok((pkg, group))
+            })
+            .collect()
+    }
+
+    /// Open this uncore event on every box of its PMU, e.g., on all `uncore_cbox_N`.
+    ///
+    /// Each box is monitored through the CPUs listed in its `cpumask`. This is also how energy
+    /// and C-state residency events of sysfs, e.g., `power/energy-pkg/`, are counted. Fails for
+    /// core events, events of the CPU measurement facilities of s390x, and metrics.
+    pub fn open_uncore(&self, builder: PerfEventBuilder) -> Result<UncoreEvent> {
+        if !self._is_uncore() {
+            return Err(Error::InvalidEvent(format!(
+                "{} is not an uncore event",
+                self.name
+            )));
+        }
+        self._warn_errata(None);
+        builder
+            .name(self.name.clone())
+            .open_uncore(self._box_attrs()?)
+    }
+
+    /// Attach this event to all threads of the running process `pid`.
+    ///
+    /// The events are opened as in `open` and the process is monitored as in
+    /// `PerfEventBuilder::attach_group`.
+    pub fn attach(
+        &self,
+        builder: PerfEventBuilder,
+        pid: nix::libc::pid_t,
+        events: Option<&Vec<PmuEvent>>,
+    ) -> Result<HybridGroup<AttachedProcess>> {
+        self._open_groups(builder, events, |b, _, attrs| b.attach_group(pid, attrs))
+    }
+}
+
+/// Helper struct to build a `PmuEvent` from code, e.g., for custom or undocumented events that are
+/// not listed in the JSON files of the CPU.
+///
+/// The event is counted on the core PMU unless `pmu` is set.
+#[derive(Debug, Clone)]
+pub struct PmuEventBuilder {
+    /// Event being built.
+    event: PmuEvent,
+}
+
+impl PmuEventBuilder {
+    /// Set the topic of the event.
+    ///
+    /// Defaults to `custom`.
+    pub fn topic<S: Into<String>>(mut self, topic: S) -> Self {
+        self.event.topic = topic.into();
+        self
+    }
+
+    /// Set the brief summary of the event.
+    pub fn desc<S: Into<String>>(mut self, desc: S) -> Self {
+        self.event.desc = desc.into();
+        self
+    }
+
+    /// Set the unit mask of the event.
+    pub fn umask(mut self, umask: u64) -> Self {
+        self.event.umask = Some(umask);
+        self
+    }
+
+    /// Set the counter mask of the event, called threshold by uncore PMUs.
+    pub fn cmask(mut self, cmask: u8) -> Self {
+        self.event.cmask = Some(cmask);
+        self
+    }
+
+    /// Count rising edges of the condition, i.e., the number of times it begins.
+    pub fn edge(mut self) -> Self {
+        self.event.edge = true;
+        self
+    }
+
+    /// Invert the comparison against the counter mask.
+    pub fn inv(mut self) -> Self {
+        self.event.inv = true;
+        self
+    }
+
+    /// Count the event on all hyper-threads of the core.
+    pub fn any_thread(mut self) -> Self {
+        self.event.any_thread = true;
+        self
+    }
+
+    /// Set the Linux name of the PMU counting the event, e.g., `uncore_imc` or `cpu_atom`.
+    ///
+    /// Box-replicated uncore PMUs are named without the index of the box.
+    pub fn pmu<S: Into<String>>(mut self, pmu: S) -> Self {
+        let pmu = pmu.into();
+        self.event.unit = Some(pmu.clone());
+        self.event.pmu = Some(pmu);
+        self
+    }
+
+    /// Set the value of the offcore response MSR selecting the transactions to be counted.
+    pub fn offcore_rsp(mut self, val: u64) -> Self {
+        self.event.msr = Some(0x1A6);
+        self.event.msr_val = Some(val);
+        self.event.offcore_rsp = true;
+        self.event.ldlat = false;
+        self
+    }
+
+    /// Set the minimum latency in cycles of the loads to be counted.
+    pub fn ldlat(mut self, latency: u16) -> Self {
+        self.event.msr = Some(0x3F6);
+        self.event.msr_val = Some(latency as u64);
+        self.event.ldlat = true;
+        self.event.offcore_rsp = false;
+        self
+    }
+
+    /// Mark the event as counting occurrences of a whole package, see `PmuEvent::is_per_pkg`.
+    pub fn per_pkg(mut self) -> Self {
+        self.event.per_pkg = true;
+        self
+    }
+
+    /// Set the factor and unit counts of the event are converted to, see `PmuEvent::scaled`.
+    pub fn scale_unit<S: Into<String>>(mut self, scale: f64, unit: S) -> Self {
+        self.event.scale = Some(scale);
+        self.event.scale_unit = Some(unit.into());
+        self
+    }
+
+    /// Set the mask of the functions of the ports selected by `port_mask` to be counted, e.g., by
+    /// IIO events.
+    pub fn fc_mask(mut self, mask: u64) -> Self {
+        self.event.fc_mask = Some(mask);
+        self
+    }
+
+    /// Set the mask of the ports to be counted, e.g., by IIO events.
+    pub fn port_mask(mut self, mask: u64) -> Self {
+        self.event.port_mask = Some(mask);
+        self
+    }
+
+    /// Set the filter field `name` of an uncore event, e.g., `filter_opc`, to `value`.
+    pub fn filter<S: Into<String>>(mut self, name: S, value: u64) -> Self {
+        self.event.filter.push((name.into(), value));
+        self
+    }
+
+    /// Restrict the event to the general purpose counters set in `mask`, see
+    /// `PmuEvent::counter_mask`.
+    pub fn counter_mask(mut self, mask: u64) -> Self {
+        self.event.counters = Some((0..64).filter(|c| mask & (1 << c) != 0).collect());
+        self.event.fixed_counter = None;
+        self
+    }
+
+    /// Restrict the event to the fixed function counter `counter`.
+    pub fn fixed_counter(mut self, counter: u32) -> Self {
+        self.event.fixed_counter = Some(counter);
+        self.event.counters = None;
+        self
+    }
+
+    /// Generate the `PmuEvent`.
+    pub fn build(self) -> PmuEvent {
+        self.event
+    }
 }
 
 #[cfg(test)]
@@ -646,6 +1813,489 @@ mod tests {
     use rayon::prelude::*;
     use std::process::{Command, Stdio};
 
+    #[test]
+    fn test_pmuevent_encode_config() {
+        let evt = PmuEvent {
+            name: "test".into(),
+            event_code: Some(0x3c),
+            umask: Some(0x1),
+            cmask: Some(0x2),
+            edge: true,
+            ..Default::default()
+        };
+        if cfg!(target_arch = "x86_64") {
+            let attr = evt._encode_config(&_default_core_formats()).unwrap();
+            assert_eq!(attr.config, 0x0204_013c);
+            let any = PmuEvent {
+                any_thread: true,
+                ..evt.clone()
+            };
+            let attr = any._encode_config(&_default_core_formats()).unwrap();
+            assert_eq!(attr.config, 0x0224_013c);
+            assert_eq!(any.to_perf_string(&PerfVersion::new(3, 0), None), "r20013C");
+            assert!(any
+                .to_perf_string(&PerfVersion::new(5, 15), None)
+                .contains(",any=1"));
+        }
+
+        let mut formats = HashMap::new();
+        formats.insert("event".into(), PmuFormat::parse("config:0-7").unwrap());
+        formats.insert("umask".into(), PmuFormat::parse("config:8-15").unwrap());
+        formats.insert("edge".into(), PmuFormat::parse("config:18").unwrap());
+        formats.insert("thresh".into(), PmuFormat::parse("config:24-28").unwrap());
+        let attr = evt._encode_config(&formats).unwrap();
+        assert_eq!(attr.config, 0x0204_013c);
+        formats.remove("edge");
+        assert!(evt._encode_config(&formats).is_err());
+    }
+
+    #[test]
+    fn test_pmuevent_masks() {
+        let mut raw = RawEvent::new();
+        raw.insert(
+            "EventName".into(),
+            "UNC_IIO_DATA_REQ_OF_CPU.MEM_READ.PART0".into(),
+        );
+        raw.insert("EventCode".into(), "0x83".into());
+        raw.insert("UMask".into(), "0x04".into());
+        raw.insert("FCMask".into(), "0x07".into());
+        raw.insert("PortMask".into(), "0x01".into());
+        raw.insert("Unit".into(), "IIO".into());
+        raw.insert("Topic".into(), "uncore-io".into());
+        let evt = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert_eq!(evt.pmu(), Some("uncore_iio"));
+        assert_eq!(
+            evt.to_perf_string(&PerfVersion::new(5, 15), None),
+            "uncore_iio/event=0x83,umask=0x4,fc_mask=0x7,ch_mask=0x1,\
+             name=UNC_IIO_DATA_REQ_OF_CPU_MEM_READ_PART0_NUM/"
+        );
+
+        let mut formats = HashMap::new();
+        formats.insert("event".into(), PmuFormat::parse("config:0-7").unwrap());
+        formats.insert("umask".into(), PmuFormat::parse("config:8-15").unwrap());
+        formats.insert("ch_mask".into(), PmuFormat::parse("config:36-47").unwrap());
+        formats.insert("fc_mask".into(), PmuFormat::parse("config:48-50").unwrap());
+        let attr = evt._encode_config(&formats).unwrap();
+        assert_eq!(attr.config, 0x0007_0010_0000_0483);
+
+        // Masks of zero are left out
+        raw.insert("FCMask".into(), "0x00".into());
+        raw.insert("PortMask".into(), "0x00".into());
+        let evt = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert!(!evt
+            .to_perf_string(&PerfVersion::new(5, 15), None)
+            .contains("_mask"));
+        formats.remove("fc_mask");
+        assert!(evt._encode_config(&formats).is_ok());
+
+        let evt = PmuEvent::builder("UNC_IIO_TEST", 0x83)
+            .pmu("uncore_iio")
+            .fc_mask(0x7)
+            .port_mask(0x1)
+            .build();
+        assert!(evt._encode_config(&formats).is_err());
+    }
+
+    #[test]
+    fn test_pmuevent_power() {
+        let sw = PmuEvent::from_sysfs("software", "TASK_CLOCK", "config=1");
+        assert!(!sw._is_uncore());
+        assert!(sw.open_uncore(PerfEvent::build()).is_err());
+
+        // Energy events are only available on machines exposing RAPL
+        let (name, terms) = match sysfs::pmu_events("power").unwrap_or_default().pop() {
+            Some(e) => e,
+            None => return,
+        };
+        let evt = PmuEvent::from_sysfs("power", &name, &terms);
+        assert!(evt._is_uncore());
+        assert_eq!(evt.scale_unit.as_deref(), Some("Joules"));
+        let boxes = evt._box_attrs().unwrap();
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].1.type_, sysfs::pmu_type("power").unwrap());
+
+        let uncore = evt.open_uncore(PerfEvent::build()).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        let (joules, unit) = evt.scaled(&uncore.sum().unwrap());
+        assert!(joules >= 0.0);
+        assert_eq!(unit, "Joules");
+    }
+
+    #[test]
+    fn test_pmuevent_filter() {
+        assert_eq!(
+            _parse_filter("filter_opc=0x182, filter_nc=1").unwrap(),
+            vec![("filter_opc".into(), 0x182), ("filter_nc".into(), 1)]
+        );
+        assert_eq!(
+            _parse_filter("CBoFilter0[23:17]=0x01,CBoFilter1[28:20]=0x19C").unwrap(),
+            vec![("config1".into(), (0x19c << 52) | (0x01 << 17))]
+        );
+        assert_eq!(
+            _parse_filter("CBoFilter1[24:21,17]=0x3").unwrap(),
+            vec![("config1".into(), (1 << 53) | (1 << 49))]
+        );
+        assert!(_parse_filter("null").unwrap().is_empty());
+        assert!(_parse_filter("CBoFilter0[17:23]=0x1").is_err());
+        assert!(_parse_filter("CBoFilter[23:17]=0x1").is_err());
+
+        let mut raw = RawEvent::new();
+        raw.insert("EventName".into(), "UNC_CHA_TOR_INSERTS.IA_MISS".into());
+        raw.insert("EventCode".into(), "0x35".into());
+        raw.insert("UMask".into(), "0x21".into());
+        raw.insert("Filter".into(), "filter_opc=0x202".into());
+        raw.insert("Unit".into(), "CHA".into());
+        raw.insert("Topic".into(), "uncore-cache".into());
+        let evt = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert_eq!(
+            evt.to_perf_string(&PerfVersion::new(5, 15), None),
+            "uncore_cha/event=0x35,umask=0x21,filter_opc=0x202,\
+             name=UNC_CHA_TOR_INSERTS_IA_MISS_NUM/"
+        );
+
+        raw.insert("Filter".into(), "CBoFilter0[17:23]=0x1".into());
+        let unfiltered = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert_eq!(
+            unfiltered.to_perf_string(&PerfVersion::new(5, 15), None),
+            "uncore_cha/event=0x35,umask=0x21,name=UNC_CHA_TOR_INSERTS_IA_MISS_NUM/"
+        );
+
+        let mut formats = HashMap::new();
+        formats.insert("event".into(), PmuFormat::parse("config:0-7").unwrap());
+        formats.insert("umask".into(), PmuFormat::parse("config:8-15").unwrap());
+        formats.insert(
+            "filter_opc".into(),
+            PmuFormat::parse("config1:9-18").unwrap(),
+        );
+        let attr = evt._encode_config(&formats).unwrap();
+        assert_eq!(attr.config, 0x2135);
+        assert_eq!(unsafe { attr.__bindgen_anon_3.config1 }, 0x202 << 9);
+
+        let evt = PmuEvent::builder("UNC_CHA_TEST", 0x35)
+            .pmu("uncore_cha")
+            .filter("filter_nc", 1)
+            .build();
+        assert!(evt._encode_config(&formats).is_err());
+    }
+
+    #[test]
+    fn test_pmuevent_builder() {
+        let evt = PmuEvent::builder("TEST.EVENT", 0x3c)
+            .umask(0x1)
+            .cmask(0x2)
+            .edge()
+            .desc("Test event")
+            .build();
+        assert_eq!(evt.name, "TEST.EVENT");
+        assert_eq!(evt.topic, "custom");
+        assert!(!evt.is_metric);
+        assert_eq!(
+            evt.to_perf_string(&PerfVersion::new(5, 15), None),
+            "cpu/event=0x3C,umask=0x1,cmask=0x2,edge=1,name=TEST_EVENT/"
+        );
+        if cfg!(target_arch = "x86_64") {
+            let attr = evt._encode_config(&_default_core_formats()).unwrap();
+            assert_eq!(attr.config, 0x0204_013c);
+            let attr = PmuEvent::builder("LOADS", 0xcd)
+                .umask(0x1)
+                .ldlat(128)
+                .build()
+                ._encode_config(&_default_core_formats())
+                .unwrap();
+            assert_eq!(unsafe { attr.__bindgen_anon_3.config1 }, 128);
+        }
+
+        let evt = PmuEvent::builder("UNC_M_CAS_COUNT.RD", 0x04)
+            .umask(0x3)
+            .pmu("uncore_imc")
+            .per_pkg()
+            .scale_unit(64.0, "Bytes")
+            .build();
+        assert!(evt.is_per_pkg());
+        assert!(evt.core_type().is_none());
+        assert_eq!(
+            evt.to_perf_string(&PerfVersion::new(5, 15), None),
+            "uncore_imc/event=0x4,umask=0x3,name=UNC_M_CAS_COUNT_RD_NUM/"
+        );
+        let evt = PmuEvent::builder("CYCLES", 0x3c).pmu("cpu_atom").build();
+        assert_eq!(evt.core_type(), Some(CoreType::Efficiency));
+    }
+
+    #[test]
+    fn test_pmuevent_tracepoint() {
+        let evt = PmuEvent::from_tracepoint("sched:sched_switch", 42);
+        assert_eq!(evt.topic, "tracepoint");
+        assert_eq!(
+            evt.to_perf_string(&PerfVersion::new(5, 15), None),
+            "sched:sched_switch"
+        );
+        let attrs = evt.to_perf_event_attr(None).unwrap();
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(attrs[0].type_, perf_type_id::PERF_TYPE_TRACEPOINT as u32);
+        assert_eq!(attrs[0].config, 42);
+    }
+
+    #[test]
+    fn test_pmuevent_s390() {
+        let mut raw = RawEvent::new();
+        raw.insert("Unit".into(), "CPU-M-CF".into());
+        raw.insert("EventCode".into(), "128".into());
+        raw.insert("EventName".into(), "L1D_DIR_WRITES".into());
+        raw.insert("Topic".into(), "basic".into());
+        let evt = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert_eq!(evt.pmu.as_deref(), Some("cpum_cf"));
+        assert_eq!(evt.event_code, Some(128));
+        assert!(evt._is_cpu_measurement());
+        assert_eq!(
+            evt.to_perf_string(&PerfVersion::new(5, 15), None),
+            "cpum_cf/event=0x80,name=L1D_DIR_WRITES/"
+        );
+        let mut formats = HashMap::new();
+        formats.insert("event".into(), PmuFormat::parse("config:0-63").unwrap());
+        assert_eq!(evt._encode_config(&formats).unwrap().config, 128);
+        assert!(evt.open_uncore(PerfEventBuilder::default()).is_err());
+
+        assert_eq!(_parse_num("0x3C").unwrap(), 0x3c);
+        assert_eq!(_parse_num("0").unwrap(), 0);
+        assert!(_parse_num("0xZZ").is_err());
+    }
+
+    #[test]
+    fn test_pmuevent_evaluate() {
+        let mut raw = RawEvent::new();
+        raw.insert("MetricName".into(), "IPC".into());
+        raw.insert(
+            "MetricExpr".into(),
+            "INST_RETIRED.ANY / CPU_CLK_UNHALTED.THREAD".into(),
+        );
+        raw.insert("Topic".into(), "metrics".into());
+        let metric = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        let legacy = MetricEvent::from_raw_event(&raw).unwrap();
+        let mut values = HashMap::new();
+        values.insert("INST_RETIRED.ANY".to_string(), 300.0);
+        assert!(metric.evaluate(&values).is_err());
+        values.insert("CPU_CLK_UNHALTED.THREAD".to_string(), 200.0);
+        assert_eq!(metric.evaluate(&values).unwrap(), 1.5);
+        assert_eq!(legacy.evaluate(&values).unwrap(), 1.5);
+
+        let evt = PmuEvent::builder("INST_RETIRED.ANY", 0xc0).build();
+        assert_eq!(evt.evaluate(&values).unwrap(), 300.0);
+    }
+
+    #[test]
+    fn test_metric_collect() {
+        let metric = |name: &str, expr: &str| {
+            let mut raw = RawEvent::new();
+            raw.insert("MetricName".into(), name.into());
+            raw.insert("MetricExpr".into(), expr.into());
+            raw.insert("Topic".into(), "metrics".into());
+            raw
+        };
+        let mut pmu = Pmu::default();
+        pmu.events.push(PmuEvent {
+            name: "TASK_CLOCK".into(),
+            pmu: Some("software".into()),
+            sysfs_terms: Some("config=1".into()),
+            ..Default::default()
+        });
+        let rate = metric("CLOCK_RATE", "TASK_CLOCK / duration_time");
+        pmu.events
+            .push(PmuEvent::from_raw_event(&rate, &PerfVersion::new(5, 15)).unwrap());
+
+        let unknown = MetricEvent::from_raw_event(&metric("X", "UNKNOWN / 2")).unwrap();
+        assert!(unknown.collect(&pmu, -1, Duration::from_millis(1)).is_err());
+
+        let ratio = MetricEvent::from_raw_event(&metric(
+            "RATIO",
+            "CLOCK_RATE * duration_time / TASK_CLOCK",
+        ))
+        .unwrap();
+        for &target in [0, -1].iter() {
+            let val = ratio
+                .collect(&pmu, target, Duration::from_millis(10))
+                .unwrap();
+            assert!((val - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_pmuevent_threshold() {
+        let mut raw = RawEvent::new();
+        raw.insert("MetricName".into(), "tma_fetch_latency".into());
+        raw.insert("MetricExpr".into(), "FETCH_STALLS / SLOTS".into());
+        raw.insert(
+            "MetricThreshold".into(),
+            "tma_fetch_latency > 0.1 & tma_frontend_bound > 0.15".into(),
+        );
+        raw.insert("Topic".into(), "metrics".into());
+        let metric = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        let legacy = MetricEvent::from_raw_event(&raw).unwrap();
+
+        let mut values = HashMap::new();
+        values.insert("FETCH_STALLS".to_string(), 20.0);
+        values.insert("SLOTS".to_string(), 100.0);
+        assert!(metric.exceeds_threshold(&values).is_err());
+        values.insert("tma_frontend_bound".to_string(), 0.3);
+        assert_eq!(metric.exceeds_threshold(&values).unwrap(), Some(true));
+        assert_eq!(legacy.exceeds_threshold(&values).unwrap(), Some(true));
+        values.insert("tma_fetch_latency".to_string(), 0.05);
+        assert_eq!(metric.exceeds_threshold(&values).unwrap(), Some(false));
+
+        raw.insert("MetricThreshold".into(), "> 0.1".into());
+        let metric = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert_eq!(metric.exceeds_threshold(&values).unwrap(), None);
+        let evt = PmuEvent::builder("INST_RETIRED.ANY", 0xc0).build();
+        assert_eq!(evt.exceeds_threshold(&values).unwrap(), None);
+    }
+
+    #[test]
+    fn test_pmuevent_counters() {
+        let parse = |counter: &str| {
+            let mut raw = RawEvent::new();
+            raw.insert("EventName".into(), "INST_RETIRED.ANY".into());
+            raw.insert("EventCode".into(), "0xC0".into());
+            raw.insert("Topic".into(), "pipeline".into());
+            raw.insert("Counter".into(), counter.into());
+            PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15))
+        };
+        let evt = parse("0,1,2,3").unwrap();
+        assert_eq!(evt.counter_mask(), Some(0xF));
+        assert_eq!(evt.fixed_counter(), None);
+        assert_eq!(parse("2").unwrap().counter_mask(), Some(0b100));
+        assert_eq!(parse("0-1,4-7").unwrap().counter_mask(), Some(0xF3));
+        let evt = parse("Fixed counter 1").unwrap();
+        assert_eq!(evt.counter_mask(), None);
+        assert_eq!(evt.fixed_counter(), Some(1));
+        // Events whose counters cannot be parsed are kept without a constraint
+        for counter in &["Any", "Fixed counter X", "0,1,x", ""] {
+            let evt = parse(counter).unwrap();
+            assert_eq!(evt.name, "INST_RETIRED.ANY");
+            assert_eq!(evt.counter_mask(), None);
+            assert_eq!(evt.fixed_counter(), None);
+        }
+
+        let evt = PmuEvent::builder("INST_RETIRED.ANY", 0xc0).build();
+        assert_eq!(evt.counter_mask(), None);
+        let evt = PmuEvent::builder("L1D_PEND_MISS.PENDING", 0x48)
+            .counter_mask(0b100)
+            .build();
+        assert_eq!(evt.counter_mask(), Some(0b100));
+        assert_eq!(
+            evt._allowed_counters(&CounterLimits::new(4, 3)),
+            Some(vec![2])
+        );
+        let evt = PmuEvent::builder("INST_RETIRED.ANY", 0xc0)
+            .fixed_counter(0)
+            .build();
+        assert_eq!(evt.fixed_counter(), Some(0));
+        assert_eq!(
+            evt._allowed_counters(&CounterLimits::new(4, 3)),
+            Some(vec![4])
+        );
+    }
+
+    #[test]
+    fn test_pmuevent_errata() {
+        assert_eq!(_parse_errata("SKL091, SKL044"), vec!["SKL091", "SKL044"]);
+        assert!(_parse_errata("null").is_empty());
+        assert!(_parse_errata("N/A").is_empty());
+
+        let mut raw = RawEvent::new();
+        raw.insert("EventName".into(), "BR_MISP_RETIRED.ALL_BRANCHES".into());
+        raw.insert("EventCode".into(), "0xC5".into());
+        raw.insert("Errata".into(), "BDM57".into());
+        raw.insert("Topic".into(), "pipeline".into());
+        let evt = PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap();
+        assert_eq!(evt.errata(), &["BDM57".to_string()]);
+    }
+
+    #[test]
+    fn test_pmuevent_scaled() {
+        assert_eq!(
+            _parse_scale_unit("6.4e-05MiB").unwrap(),
+            (6.4e-05, "MiB".to_string())
+        );
+        assert_eq!(_parse_scale_unit("100%").unwrap(), (100.0, "%".to_string()));
+        assert_eq!(
+            _parse_scale_unit("1per_instr").unwrap(),
+            (1.0, "per_instr".to_string())
+        );
+        assert!(_parse_scale_unit("MiB").is_err());
+
+        let value = PerfEventValue {
+            value: 1000,
+            time_enabled: 2,
+            time_running: 1,
+            id: 0,
+        };
+        let mut evt = PmuEvent::default();
+        assert_eq!(evt.scaled(&value), (2000.0, ""));
+        evt.scale = Some(6.4e-05);
+        evt.scale_unit = Some("MiB".into());
+        let (v, unit) = evt.scaled(&value);
+        assert!((v - 0.128).abs() < 1e-9);
+        assert_eq!(unit, "MiB");
+    }
+
+    #[test]
+    fn test_pmuevent_core_type() {
+        let pv = PerfVersion::new(5, 15);
+        let evt = PmuEvent {
+            name: "test".into(),
+            event_code: Some(0x3c),
+            umask: Some(0x1),
+            ..Default::default()
+        };
+        assert_eq!(evt.core_type(), None);
+        let atom = evt.for_core_type(CoreType::Efficiency).unwrap();
+        assert_eq!(atom.core_type(), Some(CoreType::Efficiency));
+        assert!(atom.for_core_type(CoreType::Performance).is_none());
+        assert!(atom.for_core_type(CoreType::Efficiency).is_some());
+        assert!(atom
+            .to_perf_string(&pv, None)
+            .starts_with("cpu_atom/event=0x3C,umask=0x1"));
+        assert_eq!(
+            CoreType::from_pmu_name("cpu_core"),
+            Some(CoreType::Performance)
+        );
+        assert_eq!(CoreType::from_pmu_name("cpu"), None);
+    }
+
+    #[test]
+    fn test_hybrid_group() {
+        let attr = |ty| perf_event_attr {
+            type_: ty,
+            ..Default::default()
+        };
+        let core_pmus = vec![("cpu_core".to_string(), 8), ("cpu_atom".to_string(), 10)];
+        let attrs = vec![(0, attr(8)), (0, attr(10)), (1, attr(1)), (2, attr(8))];
+        let groups = _split_by_type(attrs.clone(), &core_pmus);
+        let pmus: Vec<_> = groups.iter().map(|(p, _)| p.as_deref()).collect();
+        assert_eq!(pmus, vec![Some("cpu_core"), Some("cpu_atom"), None]);
+        let index: Vec<usize> = groups[0].1.iter().map(|(i, _)| *i).collect();
+        assert_eq!(index, vec![0, 2]);
+        assert_eq!(_split_by_type(attrs, &[]).len(), 1);
+
+        // Values of the same event are summed over all groups
+        let open = || {
+            PerfEvent::software(crate::perf::SwId::TaskClock)
+                .open_group(vec![crate::perf::SwId::TaskClock.into(); 2])
+                .unwrap()
+        };
+        let group = HybridGroup {
+            name: "test".into(),
+            groups: vec![(vec![0, 1], open()), (vec![0], open())],
+            len: 2,
+        };
+        group.enable().unwrap();
+        let _ = (0..10_000).sum::<u64>();
+        group.disable().unwrap();
+        let values = group.read_sync().unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(values[0].value > values[1].value);
+        assert_eq!(group.groups().count(), 2);
+    }
+
     #[test]
     fn test_pmuevent_to_perfstring() -> Result<()> {
         let pmu_events_path = std::env::var("PMU_EVENTS")?;
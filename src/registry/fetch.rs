@@ -0,0 +1,198 @@
+//! Downloading pmu-events definitions from the Linux kernel tree.
+//!
+//! Files are retrieved with the `curl` tool, which has to be installed.
+
+use crate::registry::{MapfileEntry, Pmu};
+use crate::{Error, Result};
+use log::debug;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Git repository hosting a pmu-events tree, e.g., the Linux kernel or a mirror of it.
+#[derive(Debug, Clone)]
+pub struct EventsRepo {
+    /// GitHub repository in the form `owner/name`.
+    pub repo: String,
+    /// Branch, tag or commit to fetch the files from.
+    pub git_ref: String,
+    /// Directory of the repository holding the trees of all architectures.
+    pub path: String,
+}
+
+impl Default for EventsRepo {
+    fn default() -> Self {
+        EventsRepo {
+            repo: "torvalds/linux".into(),
+            git_ref: "master".into(),
+            path: "tools/perf/pmu-events/arch".into(),
+        }
+    }
+}
+
+/// Get the name of the pmu-events tree of the local architecture.
+fn _arch_dir() -> Result<&'static str> {
+    if cfg!(target_arch = "x86_64") {
+        Ok("x86")
+    } else if cfg!(target_arch = "powerpc64") {
+        Ok("powerpc")
+    } else {
+        Err(Error::NotImplemented)
+    }
+}
+
+/// Retrieve the contents of `url`.
+// `io::Error::other` requires Rust 1.74
+#[allow(clippy::io_other_error)]
+fn _download(url: &str) -> Result<Vec<u8>> {
+    debug!("Downloading {}", url);
+    let output = Command::new("curl").args(["-fsSL", url]).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Could not download {} -- {}", url, output.status),
+        )
+        .into());
+    }
+    Ok(output.stdout)
+}
+
+/// Get the names of the JSON files in a directory listing of the GitHub contents API.
+fn _json_files(listing: &[u8]) -> Result<Vec<String>> {
+    let v: Value = serde_json::from_slice(listing)?;
+    let entries = v.as_array().ok_or(Error::NoneError)?;
+    Ok(entries
+        .iter()
+        .filter(|e| e["type"] == "file")
+        .filter_map(|e| e["name"].as_str())
+        .filter(|n| n.ends_with(".json"))
+        .map(String::from)
+        .collect())
+}
+
+impl EventsRepo {
+    /// Get the URL of the file `file` of the tree of architecture `arch`.
+    fn _file_url(&self, arch: &str, file: &str) -> String {
+        format!(
+            "https://raw.githubusercontent.com/{}/{}/{}/{}/{}",
+            self.repo, self.git_ref, self.path, arch, file
+        )
+    }
+
+    /// Get the URL listing the directory `dir` of the tree of architecture `arch`.
+    fn _listing_url(&self, arch: &str, dir: &str) -> String {
+        format!(
+            "https://api.github.com/repos/{}/contents/{}/{}/{}?ref={}",
+            self.repo, self.path, arch, dir, self.git_ref
+        )
+    }
+
+    /// Download `file` of the tree of `arch` to `dest`, unless it already exists.
+    fn _fetch_file(&self, arch: &str, file: &str, dest: &Path) -> Result<()> {
+        let path = dest.join(file);
+        if path.exists() {
+            return Ok(());
+        }
+        let data = _download(&self._file_url(arch, file))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Download the pmu-events files describing the CPU `cpu` of the local architecture into
+    /// `cache_dir`.
+    ///
+    /// Only the mapfile, the architecture standard events and the files mapped to `cpu` are
+    /// retrieved. Files already present in `cache_dir` are not downloaded again. Returns the
+    /// directory to construct a `Pmu` from.
+    pub fn fetch<P: AsRef<Path>>(&self, cpu: &str, cache_dir: P) -> Result<PathBuf> {
+        let arch = _arch_dir()?;
+        let dest = cache_dir.as_ref().join(arch);
+        self._fetch_file(arch, "mapfile.csv", &dest)?;
+
+        let mut dirs = vec![String::new()];
+        let mapfile = std::fs::read_to_string(dest.join("mapfile.csv"))?;
+        for entry in mapfile
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(MapfileEntry::parse)
+            .filter(|e| e.matches(cpu))
+        {
+            if entry.file.ends_with(".json") {
+                self._fetch_file(arch, &entry.file, &dest)?;
+            } else {
+                dirs.push(entry.file);
+            }
+        }
+        for dir in dirs.iter() {
+            let listing = _download(&self._listing_url(arch, dir))?;
+            for f in _json_files(&listing)? {
+                let file = if dir.is_empty() {
+                    f
+                } else {
+                    format!("{}/{}", dir, f)
+                };
+                self._fetch_file(arch, &file, &dest)?;
+            }
+        }
+        Ok(dest)
+    }
+}
+
+impl Pmu {
+    /// Load PMU event information for local CPU from pmu-events files downloaded from `repo`
+    /// into `cache_dir`.
+    ///
+    /// See `EventsRepo::fetch`.
+    pub fn from_local_cpu_fetched<P: AsRef<Path>>(repo: &EventsRepo, cache_dir: P) -> Result<Self> {
+        let cpu_str = crate::arch::get_cpu_string();
+        let path = repo.fetch(&cpu_str, cache_dir)?;
+        Pmu::from_cpu_str(cpu_str, path.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_repo_urls() {
+        let repo = EventsRepo {
+            git_ref: "v5.10".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            repo._file_url("x86", "skylakex/cache.json"),
+            "https://raw.githubusercontent.com/torvalds/linux/v5.10/tools/perf/pmu-events/arch/x86/skylakex/cache.json"
+        );
+        assert_eq!(
+            repo._listing_url("x86", "skylakex"),
+            "https://api.github.com/repos/torvalds/linux/contents/tools/perf/pmu-events/arch/x86/skylakex?ref=v5.10"
+        );
+    }
+
+    #[test]
+    fn test_json_files() {
+        let listing = br#"[
+            {"name": "cache.json", "type": "file"},
+            {"name": "README", "type": "file"},
+            {"name": "other.json", "type": "dir"}
+        ]"#;
+        assert_eq!(_json_files(listing).unwrap(), vec!["cache.json"]);
+        assert!(_json_files(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_download() {
+        let file = std::env::temp_dir().join(format!("perf-utils-fetch-{}", std::process::id()));
+        std::fs::write(&file, "[]").unwrap();
+        let url = format!("file://{}", file.display());
+        if let Ok(data) = _download(&url) {
+            assert_eq!(data, b"[]");
+            assert!(_download(&format!("{}.missing", url)).is_err());
+        }
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -1,11 +1,12 @@
 #![allow(missing_docs)]
 
-use crate::Result;
+use crate::{Error, Result};
 use lazy_static::lazy_static;
 use pest::iterators::{Pair, Pairs};
 use pest::prec_climber::{Assoc, Operator, PrecClimber};
 use pest::Parser;
 use pest_derive::*;
+use std::collections::HashMap;
 
 /// Helper struct to parse metric event expressions.
 #[derive(Parser)]
@@ -17,19 +18,76 @@ lazy_static! {
     static ref CLIMBER: PrecClimber<Rule> = {
         PrecClimber::new(vec![
             Operator::new(Rule::comma, Assoc::Left),
+            Operator::new(Rule::or, Assoc::Left),
+            Operator::new(Rule::and, Assoc::Left),
+            Operator::new(Rule::eq, Assoc::Left)
+                | Operator::new(Rule::lt, Assoc::Left)
+                | Operator::new(Rule::gt, Assoc::Left),
             Operator::new(Rule::add, Assoc::Left) | Operator::new(Rule::sub, Assoc::Left),
             Operator::new(Rule::mul, Assoc::Left) | Operator::new(Rule::div, Assoc::Left),
         ])
     };
 }
 
+/// Values of the runtime constants used in metric expressions, e.g., `#SMT_on`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MetricConstants {
+    /// Simultaneous multithreading is active (`#SMT_on`).
+    pub smt_on: bool,
+    /// Number of physical cores (`#num_cores`).
+    pub num_cores: usize,
+    /// Number of physical packages (`#num_packages`).
+    pub num_packages: usize,
+    /// Events are collected by event-based sampling rather than counting (`#EBS_Mode`).
+    pub ebs_mode: bool,
+}
+
+impl MetricConstants {
+    /// Get the constants from the topology of the local CPU.
+    ///
+    /// Events are assumed to be counted, i.e., `#EBS_Mode` is false.
+    pub fn from_local_cpu() -> Result<Self> {
+        Ok(MetricConstants {
+            smt_on: crate::util::smt_active()?,
+            num_cores: crate::util::num_cores()?,
+            num_packages: crate::util::package_cpus()?.len(),
+            ebs_mode: false,
+        })
+    }
+
+    /// Get the value of the constant `name`, with or without the leading `#`.
+    ///
+    /// Names are matched ignoring case. Returns `None` for unknown constants.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        let name = name.trim_start_matches('#').to_ascii_lowercase();
+        match name.as_str() {
+            "smt_on" => Some(self.smt_on as u8 as f64),
+            "num_cores" => Some(self.num_cores as f64),
+            "num_packages" => Some(self.num_packages as f64),
+            "ebs_mode" => Some(self.ebs_mode as u8 as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Convert the outcome of a comparison or logical operation into a value of an expression.
+fn _from_bool(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
 /// Parsed (sub)expression from a `PmuEvent` dealing with derived events.
 #[derive(Debug, Clone, PartialEq)]
 pub enum MetricExpr {
     /// A constant number.
-    Num(f32),
+    Num(f64),
     /// A PMU event.
     Var(String),
+    /// A runtime constant such as `#SMT_on`, named without the leading `#`.
+    Const(String),
     /// Addition operator.
     Add(Box<MetricExpr>, Box<MetricExpr>),
     /// Subtraction operator.
@@ -38,10 +96,26 @@ pub enum MetricExpr {
     Mul(Box<MetricExpr>, Box<MetricExpr>),
     /// Division operator.
     Div(Box<MetricExpr>, Box<MetricExpr>),
-    /// If-Else block
+    /// Less than comparison, evaluating to `1` if true and `0` otherwise.
+    Lt(Box<MetricExpr>, Box<MetricExpr>),
+    /// Greater than comparison, evaluating to `1` if true and `0` otherwise.
+    Gt(Box<MetricExpr>, Box<MetricExpr>),
+    /// Equality comparison, evaluating to `1` if true and `0` otherwise.
+    Eq(Box<MetricExpr>, Box<MetricExpr>),
+    /// Logical and of two expressions, which are true if not `0`.
+    And(Box<MetricExpr>, Box<MetricExpr>),
+    /// Logical or of two expressions, which are true if not `0`.
+    Or(Box<MetricExpr>, Box<MetricExpr>),
+    /// If-Else block `a if cond else b`, holding `a`, `cond` and `b` in this order.
     If(Box<MetricExpr>, Box<MetricExpr>, Box<MetricExpr>),
     /// Min block
     Min(Box<MetricExpr>),
+    /// Max block of a comma separated expression.
+    Max(Box<MetricExpr>),
+    /// Ratio `d_ratio(a, b)` of two expressions, which is `0` if `b` is `0`.
+    DRatio(Box<MetricExpr>, Box<MetricExpr>),
+    /// Number of sources, e.g., uncore boxes, a PMU event is aggregated from.
+    SourceCount(String),
     /// Comma seperated expression to be used with `Min`.
     Comma(Box<MetricExpr>, Box<MetricExpr>),
     /// Empty.
@@ -57,12 +131,28 @@ impl MetricExpr {
 
     /// Recursive call to transform `Pair` objects into `MetricExpr`s.
     fn _to_expr(expr: Pairs<Rule>) -> MetricExpr {
-        CLIMBER.climb(
-            expr,
+        let mut pairs: Vec<Pair<Rule>> = expr.collect();
+        // A trailing conditional applies to the whole expression
+        let ife = match pairs.last() {
+            Some(p) if p.as_rule() == Rule::ife => pairs.pop(),
+            _ => None,
+        };
+        let value = CLIMBER.climb(
+            pairs.into_iter(),
             |pair: Pair<Rule>| match pair.as_rule() {
                 Rule::num => MetricExpr::Num(pair.as_str().parse().unwrap()),
+                Rule::constant => MetricExpr::Const(pair.as_str()[1..].into()),
                 Rule::ident => MetricExpr::Var(pair.as_str().into()),
                 Rule::min => MetricExpr::Min(Box::new(MetricExpr::_to_expr(pair.into_inner()))),
+                Rule::max => MetricExpr::Max(Box::new(MetricExpr::_to_expr(pair.into_inner()))),
+                Rule::d_ratio => match MetricExpr::_to_expr(pair.into_inner()) {
+                    MetricExpr::Comma(a, b) => MetricExpr::DRatio(a, b),
+                    // Evaluates to 0 like a ratio with a missing denominator
+                    a => MetricExpr::DRatio(Box::new(a), Box::new(MetricExpr::None)),
+                },
+                Rule::source_count => {
+                    MetricExpr::SourceCount(pair.into_inner().as_str().trim().into())
+                }
                 Rule::expr => MetricExpr::_to_expr(pair.into_inner()),
                 _ => unreachable!(),
             },
@@ -72,9 +162,149 @@ impl MetricExpr {
                 Rule::mul => MetricExpr::Mul(Box::new(lhs), Box::new(rhs)),
                 Rule::div => MetricExpr::Div(Box::new(lhs), Box::new(rhs)),
                 Rule::comma => MetricExpr::Comma(Box::new(lhs), Box::new(rhs)),
+                Rule::lt => MetricExpr::Lt(Box::new(lhs), Box::new(rhs)),
+                Rule::gt => MetricExpr::Gt(Box::new(lhs), Box::new(rhs)),
+                Rule::eq => MetricExpr::Eq(Box::new(lhs), Box::new(rhs)),
+                Rule::and => MetricExpr::And(Box::new(lhs), Box::new(rhs)),
+                Rule::or => MetricExpr::Or(Box::new(lhs), Box::new(rhs)),
                 _ => unreachable!(),
             },
-        )
+        );
+        match ife {
+            Some(ife) => {
+                let mut inner = ife.into_inner();
+                let cond = MetricExpr::_to_expr(inner.next().unwrap().into_inner());
+                let other = inner
+                    .next()
+                    .map_or(MetricExpr::None, |e| MetricExpr::_to_expr(e.into_inner()));
+                MetricExpr::If(Box::new(value), Box::new(cond), Box::new(other))
+            }
+            None => value,
+        }
+    }
+
+    /// Get names of all runtime constants used in this expression, without the leading `#`.
+    pub fn get_constants(&self) -> Vec<&String> {
+        match self {
+            MetricExpr::Const(ref x) => vec![x],
+            MetricExpr::Add(ref a, ref b)
+            | MetricExpr::Sub(ref a, ref b)
+            | MetricExpr::Mul(ref a, ref b)
+            | MetricExpr::Div(ref a, ref b)
+            | MetricExpr::Lt(ref a, ref b)
+            | MetricExpr::Gt(ref a, ref b)
+            | MetricExpr::Eq(ref a, ref b)
+            | MetricExpr::And(ref a, ref b)
+            | MetricExpr::Or(ref a, ref b)
+            | MetricExpr::DRatio(ref a, ref b)
+            | MetricExpr::Comma(ref a, ref b) => {
+                let mut tmp = a.get_constants();
+                tmp.extend(b.get_constants());
+                tmp
+            }
+            MetricExpr::Min(ref a) | MetricExpr::Max(ref a) => a.get_constants(),
+            MetricExpr::If(ref a, ref b, ref c) => {
+                let mut tmp = a.get_constants();
+                tmp.extend(b.get_constants());
+                tmp.extend(c.get_constants());
+                tmp
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Evaluate this expression given the `values` of the events it uses.
+    ///
+    /// Runtime constants such as `#SMT_on` are resolved from the topology of the local CPU, see
+    /// `MetricConstants::from_local_cpu`.
+    pub fn evaluate(&self, values: &HashMap<String, f64>) -> Result<f64> {
+        let constants = if self.get_constants().is_empty() {
+            MetricConstants::default()
+        } else {
+            MetricConstants::from_local_cpu()?
+        };
+        self.evaluate_with(values, &constants)
+    }
+
+    /// Evaluate this expression given the `values` of the events it uses and the values of the
+    /// runtime `constants`.
+    ///
+    /// Fails if the value of an event or constant is missing.
+    pub fn evaluate_with(
+        &self,
+        values: &HashMap<String, f64>,
+        constants: &MetricConstants,
+    ) -> Result<f64> {
+        let eval = |e: &MetricExpr| e.evaluate_with(values, constants);
+        match self {
+            MetricExpr::Num(n) => Ok(*n),
+            MetricExpr::Var(ref x) => values
+                .get(x)
+                .copied()
+                .ok_or_else(|| Error::InvalidEvent(format!("No value for event {}", x))),
+            MetricExpr::Const(ref x) => constants
+                .get(x)
+                .ok_or_else(|| Error::InvalidEvent(format!("Unknown constant #{}", x))),
+            MetricExpr::Add(ref a, ref b) => Ok(eval(a)? + eval(b)?),
+            MetricExpr::Sub(ref a, ref b) => Ok(eval(a)? - eval(b)?),
+            MetricExpr::Mul(ref a, ref b) => Ok(eval(a)? * eval(b)?),
+            MetricExpr::Div(ref a, ref b) => Ok(eval(a)? / eval(b)?),
+            MetricExpr::Lt(ref a, ref b) => Ok(_from_bool(eval(a)? < eval(b)?)),
+            MetricExpr::Gt(ref a, ref b) => Ok(_from_bool(eval(a)? > eval(b)?)),
+            MetricExpr::Eq(ref a, ref b) => Ok(_from_bool(eval(a)? == eval(b)?)),
+            MetricExpr::And(ref a, ref b) => Ok(_from_bool(eval(a)? != 0.0 && eval(b)? != 0.0)),
+            MetricExpr::Or(ref a, ref b) => Ok(_from_bool(eval(a)? != 0.0 || eval(b)? != 0.0)),
+            MetricExpr::If(ref a, ref cond, ref b) => {
+                if eval(cond)? != 0.0 {
+                    eval(a)
+                } else {
+                    eval(b)
+                }
+            }
+            MetricExpr::Min(ref a) => Ok(a
+                ._eval_list(values, constants)?
+                .into_iter()
+                .fold(f64::INFINITY, f64::min)),
+            MetricExpr::Max(ref a) => Ok(a
+                ._eval_list(values, constants)?
+                .into_iter()
+                .fold(f64::NEG_INFINITY, f64::max)),
+            MetricExpr::DRatio(ref a, ref b) => {
+                let b = eval(b)?;
+                if b == 0.0 {
+                    Ok(0.0)
+                } else {
+                    Ok(eval(a)? / b)
+                }
+            }
+            MetricExpr::SourceCount(ref x) => {
+                let name = format!("source_count({})", x);
+                values
+                    .get(&name)
+                    .copied()
+                    .ok_or_else(|| Error::InvalidEvent(format!("No value for {}", name)))
+            }
+            MetricExpr::Comma(_, _) => Err(Error::InvalidEvent(
+                "Comma separated expression outside of min".into(),
+            )),
+            MetricExpr::None => Ok(0.0),
+        }
+    }
+
+    /// Evaluate the elements of a comma separated expression.
+    fn _eval_list(
+        &self,
+        values: &HashMap<String, f64>,
+        constants: &MetricConstants,
+    ) -> Result<Vec<f64>> {
+        match self {
+            MetricExpr::Comma(ref a, ref b) => {
+                let mut tmp = a._eval_list(values, constants)?;
+                tmp.extend(b._eval_list(values, constants)?);
+                Ok(tmp)
+            }
+            _ => Ok(vec![self.evaluate_with(values, constants)?]),
+        }
     }
 
     /// Get names of all counters used in this expression.
@@ -94,11 +324,141 @@ impl MetricExpr {
             MetricExpr::Mul(ref a, ref b) => body!(a, b),
             MetricExpr::Div(ref a, ref b) => body!(a, b),
             MetricExpr::Min(ref a) => body!(a),
+            MetricExpr::Max(ref a) => body!(a),
+            MetricExpr::DRatio(ref a, ref b) => body!(a, b),
+            MetricExpr::SourceCount(ref x) => vec![x],
+            MetricExpr::Lt(ref a, ref b) => body!(a, b),
+            MetricExpr::Gt(ref a, ref b) => body!(a, b),
+            MetricExpr::Eq(ref a, ref b) => body!(a, b),
+            MetricExpr::And(ref a, ref b) => body!(a, b),
+            MetricExpr::Or(ref a, ref b) => body!(a, b),
             MetricExpr::Comma(ref a, ref b) => body!(a, b),
             MetricExpr::If(ref a, ref b, ref c) => body!(a, b, c),
             _ => vec![],
         }
     }
+
+    /// Get the direct subexpressions of this expression.
+    ///
+    /// The children of `If` are in the same order as its fields, i.e., the condition second.
+    pub fn children(&self) -> Vec<&MetricExpr> {
+        match self {
+            MetricExpr::Add(ref a, ref b)
+            | MetricExpr::Sub(ref a, ref b)
+            | MetricExpr::Mul(ref a, ref b)
+            | MetricExpr::Div(ref a, ref b)
+            | MetricExpr::Lt(ref a, ref b)
+            | MetricExpr::Gt(ref a, ref b)
+            | MetricExpr::Eq(ref a, ref b)
+            | MetricExpr::And(ref a, ref b)
+            | MetricExpr::Or(ref a, ref b)
+            | MetricExpr::DRatio(ref a, ref b)
+            | MetricExpr::Comma(ref a, ref b) => vec![a, b],
+            MetricExpr::Min(ref a) | MetricExpr::Max(ref a) => vec![a],
+            MetricExpr::If(ref a, ref b, ref c) => vec![a, b, c],
+            _ => vec![],
+        }
+    }
+
+    /// Let `visitor` visit this expression.
+    pub fn accept<V: MetricVisitor + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit(self);
+    }
+
+    /// Let `visitor` visit all direct subexpressions of this expression.
+    pub fn walk<V: MetricVisitor + ?Sized>(&self, visitor: &mut V) {
+        for c in self.children() {
+            visitor.visit(c);
+        }
+    }
+
+    /// Replace every direct subexpression `c` of this expression with `f(c)`.
+    pub fn map_children<F>(self, mut f: F) -> MetricExpr
+    where
+        F: FnMut(MetricExpr) -> MetricExpr,
+    {
+        let mut g = |e: Box<MetricExpr>| Box::new(f(*e));
+        match self {
+            MetricExpr::Add(a, b) => MetricExpr::Add(g(a), g(b)),
+            MetricExpr::Sub(a, b) => MetricExpr::Sub(g(a), g(b)),
+            MetricExpr::Mul(a, b) => MetricExpr::Mul(g(a), g(b)),
+            MetricExpr::Div(a, b) => MetricExpr::Div(g(a), g(b)),
+            MetricExpr::Lt(a, b) => MetricExpr::Lt(g(a), g(b)),
+            MetricExpr::Gt(a, b) => MetricExpr::Gt(g(a), g(b)),
+            MetricExpr::Eq(a, b) => MetricExpr::Eq(g(a), g(b)),
+            MetricExpr::And(a, b) => MetricExpr::And(g(a), g(b)),
+            MetricExpr::Or(a, b) => MetricExpr::Or(g(a), g(b)),
+            MetricExpr::DRatio(a, b) => MetricExpr::DRatio(g(a), g(b)),
+            MetricExpr::Comma(a, b) => MetricExpr::Comma(g(a), g(b)),
+            MetricExpr::Min(a) => MetricExpr::Min(g(a)),
+            MetricExpr::Max(a) => MetricExpr::Max(g(a)),
+            MetricExpr::If(a, b, c) => MetricExpr::If(g(a), g(b), g(c)),
+            e => e,
+        }
+    }
+
+    /// Rewrite this expression bottom-up, replacing every subexpression `e` with `f(e)` after
+    /// its children have been rewritten.
+    pub fn transform<F>(self, f: &mut F) -> MetricExpr
+    where
+        F: FnMut(MetricExpr) -> MetricExpr,
+    {
+        let e = self.map_children(|c| c.transform(f));
+        f(e)
+    }
+
+    /// Replace the events named in `aliases` by their expressions, e.g., to expand the metrics
+    /// this expression refers to.
+    ///
+    /// Expressions in `aliases` are inserted as they are, i.e., aliases used by them are not
+    /// replaced.
+    pub fn substitute(self, aliases: &HashMap<String, MetricExpr>) -> MetricExpr {
+        self.transform(&mut |e| match e {
+            MetricExpr::Var(ref x) => aliases.get(x).cloned().unwrap_or(e),
+            e => e,
+        })
+    }
+
+    /// Simplify this expression by evaluating all subexpressions that do not depend on events or
+    /// runtime constants, and by selecting the branch of conditionals with a constant condition.
+    pub fn fold_constants(self) -> MetricExpr {
+        self.transform(&mut |e| match e {
+            MetricExpr::If(a, cond, b) => match *cond {
+                MetricExpr::Num(c) if c != 0.0 => *a,
+                MetricExpr::Num(_) => *b,
+                cond => MetricExpr::If(a, Box::new(cond), b),
+            },
+            MetricExpr::Num(_) | MetricExpr::Comma(_, _) | MetricExpr::None => e,
+            e if e._is_constant() => {
+                match e.evaluate_with(&HashMap::new(), &MetricConstants::default()) {
+                    Ok(v) => MetricExpr::Num(v),
+                    Err(_) => e,
+                }
+            }
+            e => e,
+        })
+    }
+
+    /// Check if the value of this expression does not depend on events or runtime constants.
+    fn _is_constant(&self) -> bool {
+        match self {
+            MetricExpr::Var(_) | MetricExpr::Const(_) | MetricExpr::SourceCount(_) => false,
+            e => e.children().iter().all(|c| c._is_constant()),
+        }
+    }
+}
+
+/// Visitor over the subexpressions of a `MetricExpr`, e.g., to analyze or compile expressions.
+///
+/// Start visiting an expression with `MetricExpr::accept`.
+pub trait MetricVisitor {
+    /// Visit `expr`.
+    ///
+    /// The default implementation visits all direct subexpressions of `expr`. Implementations
+    /// overriding this method should call `MetricExpr::walk` to visit the subexpressions.
+    fn visit(&mut self, expr: &MetricExpr) {
+        expr.walk(self);
+    }
 }
 
 impl Default for MetricExpr {
@@ -139,4 +499,188 @@ mod tests {
             .collect();
         assert_eq!(test_strings.len(), events.len());
     }
+
+    #[test]
+    fn test_metric_evaluate() {
+        let mut values = HashMap::new();
+        for &(name, value) in &[
+            ("IDQ_UOPS_NOT_DELIVERED.CORE", 400.0),
+            ("UOPS_ISSUED.ANY", 1000.0),
+            ("UOPS_RETIRED.RETIRE_SLOTS", 800.0),
+            ("INT_MISC.RECOVERY_CYCLES", 25.0),
+            ("cycles", 1000.0),
+        ] {
+            values.insert(name.to_string(), value);
+        }
+        let eval = |s: &str| {
+            MetricExpr::parse_str(s)
+                .unwrap()
+                .evaluate_with(&values, &MetricConstants::default())
+                .unwrap()
+        };
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 - 4 - 2"), 2.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("min( 5, cycles / 100, 20 )"), 5.0);
+        assert_eq!(eval("1 if 0 else 2"), 2.0);
+        assert_eq!(eval("1 if 0"), 0.0);
+        // Backend bound of the TopDown method
+        let backend = eval(
+            "1 - ( (IDQ_UOPS_NOT_DELIVERED.CORE / (4 * cycles)) + (( UOPS_ISSUED.ANY - UOPS_RETIRED.RETIRE_SLOTS + 4 * INT_MISC.RECOVERY_CYCLES ) / (4 * cycles)) + (UOPS_RETIRED.RETIRE_SLOTS / (4 * cycles)) )",
+        );
+        assert!((backend - 0.625).abs() < 1e-9);
+        assert!(MetricExpr::parse_str("1 , 2")
+            .unwrap()
+            .evaluate(&values)
+            .is_err());
+    }
+
+    #[test]
+    fn test_metric_comparisons() {
+        let mut values = HashMap::new();
+        values.insert("tma_backend_bound".to_string(), 0.3);
+        values.insert("tma_memory_bound".to_string(), 0.1);
+        let eval = |s: &str| {
+            MetricExpr::parse_str(s)
+                .unwrap()
+                .evaluate_with(&values, &MetricConstants::default())
+                .unwrap()
+        };
+        assert_eq!(eval("1 < 2"), 1.0);
+        assert_eq!(eval("1 > 2"), 0.0);
+        assert_eq!(eval("2 == 1 + 1"), 1.0);
+        assert_eq!(eval("1 < 2 & 3 < 2"), 0.0);
+        assert_eq!(eval("1 < 2 | 3 < 2"), 1.0);
+        assert_eq!(
+            eval("tma_backend_bound > 0.2 & tma_memory_bound > 0.2 | tma_memory_bound == 0.1"),
+            1.0
+        );
+        assert_eq!(
+            eval("tma_memory_bound / tma_backend_bound if tma_backend_bound > 0.2 else 0"),
+            0.1 / 0.3
+        );
+        let expr = MetricExpr::parse_str("tma_backend_bound > 0.2 & #SMT_on").unwrap();
+        assert_eq!(expr.get_counters(), vec!["tma_backend_bound"]);
+        assert_eq!(expr.get_constants(), vec!["SMT_on"]);
+    }
+
+    #[test]
+    fn test_metric_functions() {
+        let mut values = HashMap::new();
+        values.insert("A".to_string(), 6.0);
+        values.insert("B".to_string(), 3.0);
+        values.insert("ZERO".to_string(), 0.0);
+        values.insert("source_count(UNC_CHA_CLOCKTICKS)".to_string(), 4.0);
+        values.insert("UNC_CHA_CLOCKTICKS".to_string(), 100.0);
+        let eval = |s: &str| {
+            MetricExpr::parse_str(s)
+                .unwrap()
+                .evaluate_with(&values, &MetricConstants::default())
+                .unwrap()
+        };
+        assert_eq!(eval("max(A, B)"), 6.0);
+        assert_eq!(eval("max(1, A, B * 3)"), 9.0);
+        assert_eq!(eval("min(A, B, 2)"), 2.0);
+        assert_eq!(eval("min(A, B) * 2 + 1"), 7.0);
+        assert_eq!(eval("d_ratio(A, B)"), 2.0);
+        assert_eq!(eval("d_ratio(A, ZERO)"), 0.0);
+        assert_eq!(eval("d_ratio(A)"), 0.0);
+        assert_eq!(
+            eval("UNC_CHA_CLOCKTICKS / source_count(UNC_CHA_CLOCKTICKS)"),
+            25.0
+        );
+        let expr = MetricExpr::parse_str("max_latency + 1").unwrap();
+        assert_eq!(expr.get_counters(), vec!["max_latency"]);
+        let expr = MetricExpr::parse_str("d_ratio(A, max(B, source_count(C)))").unwrap();
+        assert_eq!(expr.get_counters(), vec!["A", "B", "C"]);
+        let expr = MetricExpr::parse_str("cpu@UOPS_ISSUED.ANY\\,cmask\\=1@ / A").unwrap();
+        assert_eq!(expr.get_counters()[0], "cpu@UOPS_ISSUED.ANY\\,cmask\\=1@");
+    }
+
+    #[test]
+    fn test_metric_visitor() {
+        /// Collects the names of all events in order.
+        struct Events(Vec<String>);
+        impl MetricVisitor for Events {
+            fn visit(&mut self, expr: &MetricExpr) {
+                if let MetricExpr::Var(ref x) = expr {
+                    self.0.push(x.clone());
+                }
+                expr.walk(self);
+            }
+        }
+
+        let expr = MetricExpr::parse_str("min(A, B / 2) if #SMT_on else C").unwrap();
+        let mut events = Events(Vec::new());
+        expr.accept(&mut events);
+        assert_eq!(events.0, vec!["A", "B", "C"]);
+        assert_eq!(expr.children().len(), 3);
+
+        let renamed = expr.transform(&mut |e| match e {
+            MetricExpr::Var(x) => MetricExpr::Var(x.to_lowercase()),
+            e => e,
+        });
+        assert_eq!(renamed.get_counters(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_metric_rewrite() {
+        let mut aliases = HashMap::new();
+        aliases.insert(
+            "SLOTS".to_string(),
+            MetricExpr::parse_str("4 * CYCLES").unwrap(),
+        );
+        let expr = MetricExpr::parse_str("UOPS / SLOTS")
+            .unwrap()
+            .substitute(&aliases);
+        assert_eq!(expr, MetricExpr::parse_str("UOPS / (4 * CYCLES)").unwrap());
+
+        let fold = |s: &str| MetricExpr::parse_str(s).unwrap().fold_constants();
+        assert_eq!(fold("1 + 2 * 3"), MetricExpr::Num(7.0));
+        assert_eq!(fold("min(1, 2 * 3, 0.5)"), MetricExpr::Num(0.5));
+        assert_eq!(fold("A * (2 + 2)"), MetricExpr::parse_str("A * 4").unwrap());
+        assert_eq!(fold("A if 1 > 2 else B"), MetricExpr::Var("B".into()));
+        assert_eq!(
+            fold("A * 2 if #SMT_on else B"),
+            MetricExpr::parse_str("A * 2 if #SMT_on else B").unwrap()
+        );
+        assert_eq!(fold("d_ratio(1, 0)"), MetricExpr::Num(0.0));
+    }
+
+    #[test]
+    fn test_metric_constants() {
+        let expr = MetricExpr::parse_str("A / 2 if #SMT_on else A").unwrap();
+        assert_eq!(expr.get_counters(), vec!["A", "A"]);
+        assert_eq!(expr.get_constants(), vec!["SMT_on"]);
+        let mut values = HashMap::new();
+        values.insert("A".to_string(), 10.0);
+        let mut constants = MetricConstants {
+            smt_on: true,
+            num_cores: 4,
+            num_packages: 2,
+            ebs_mode: false,
+        };
+        assert_eq!(expr.evaluate_with(&values, &constants).unwrap(), 5.0);
+        constants.smt_on = false;
+        assert_eq!(expr.evaluate_with(&values, &constants).unwrap(), 10.0);
+
+        let expr = MetricExpr::parse_str("A / (#num_cores * #num_packages)").unwrap();
+        assert_eq!(expr.evaluate_with(&values, &constants).unwrap(), 1.25);
+        let expr = MetricExpr::parse_str("min( 1 , A / 4 ) if #EBS_Mode else 3").unwrap();
+        assert_eq!(expr.evaluate_with(&values, &constants).unwrap(), 3.0);
+        constants.ebs_mode = true;
+        assert_eq!(expr.evaluate_with(&values, &constants).unwrap(), 1.0);
+
+        assert!(MetricExpr::parse_str("#unknown")
+            .unwrap()
+            .evaluate_with(&values, &constants)
+            .is_err());
+        assert!(MetricExpr::parse_str("B")
+            .unwrap()
+            .evaluate(&values)
+            .is_err());
+        let cores = MetricExpr::parse_str("#num_cores").unwrap();
+        assert!(cores.evaluate(&values).unwrap() >= 1.0);
+    }
 }
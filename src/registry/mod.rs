@@ -2,15 +2,36 @@
 
 use crate::perf::PerfVersion;
 use derive_more::{Index, IndexMut, IntoIterator};
-use log::error;
+use log::{error, warn};
 use regex::Regex;
-use std::io::{BufRead, BufReader};
+use std::collections::BTreeMap;
 
 mod events;
-pub use events::{HPCEvent, MetricEvent, PmuEvent, RawEvent};
+pub use events::{
+    CoreType, HPCEvent, HybridGroup, MetricEvent, PmuEvent, PmuEventBuilder, RawEvent,
+};
 
 mod metrics;
-pub use metrics::{MetricExpr, Rule};
+pub use metrics::{MetricConstants, MetricExpr, MetricVisitor, Rule};
+
+mod dependencies;
+pub use dependencies::MetricDependencies;
+
+mod constraints;
+pub use constraints::{CounterLimits, GroupPlanner, PlannedGroup};
+
+mod offcore;
+pub use offcore::{OffcoreRequest, OffcoreResponse, OffcoreSnoop, OffcoreSupplier};
+
+pub mod topdown;
+
+mod cache;
+
+mod source;
+use source::EventSource;
+
+mod fetch;
+pub use fetch::EventsRepo;
 
 /// Provides the ability to parse and interact with CPU specific PMU counters using their JSON descriptions.
 #[derive(Default, Debug, Index, IndexMut, IntoIterator)]
@@ -26,11 +47,170 @@ pub struct Pmu {
     raw_events: Vec<RawEvent>,
 }
 
-/// Check if `entry` is a JSON file.
-fn _is_json_file(entry: &std::fs::DirEntry) -> crate::Result<(bool, String)> {
-    let file_name = entry.file_name().into_string().unwrap();
-    let is_js = entry.metadata()?.is_file() && file_name.ends_with(".json");
-    Ok((is_js, file_name))
+/// Type of the events in the files listed by a row of `mapfile.csv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventType {
+    /// Events of the core PMU (which may also include uncore events).
+    Core,
+    /// Events of uncore PMUs only.
+    Uncore,
+}
+
+/// Row of `mapfile.csv` mapping a CPU to its event files.
+#[derive(Debug)]
+struct MapfileEntry {
+    /// Regex matching the CPU strings this row applies to.
+    cpuid: Regex,
+    /// File or directory holding the JSON event files, relative to the mapfile.
+    file: String,
+    /// Type of the events in `file`.
+    event_type: EventType,
+}
+
+impl MapfileEntry {
+    /// Parse a row of the mapfile of the form `Family-model,Version,Filename,EventType`.
+    ///
+    /// Rows with an unknown event type are skipped. A missing type is assumed to be `core`.
+    fn parse(line: &str) -> Option<Self> {
+        let splits: Vec<&str> = line.split(',').map(str::trim).collect();
+        if splits.len() < 3 {
+            return None;
+        }
+        let event_type = match splits.get(3) {
+            None | Some(&"core") => EventType::Core,
+            Some(&"uncore") => EventType::Uncore,
+            Some(t) => {
+                warn!("Skipping mapfile entry {} of unknown type {}", splits[2], t);
+                return None;
+            }
+        };
+        Some(MapfileEntry {
+            cpuid: Regex::new(splits[0]).ok()?,
+            file: splits[2].into(),
+            event_type,
+        })
+    }
+
+    /// Check if this row applies to the CPU identified by `cpu`.
+    ///
+    /// The regex has to match from the beginning of `cpu`, up to its end or a `-` separating
+    /// fields (e.g., the stepping) the row does not specify.
+    fn matches(&self, cpu: &str) -> bool {
+        match self.cpuid.find(cpu) {
+            Some(m) if m.start() == 0 => m.end() == cpu.len() || cpu[m.end()..].starts_with('-'),
+            _ => false,
+        }
+    }
+
+    /// Get the JSON files of this row in the tree `source` holding the mapfile.
+    ///
+    /// The row may either name a JSON file or a directory of JSON files.
+    fn json_files(&self, source: EventSource) -> Vec<String> {
+        source.json_files(&self.file)
+    }
+}
+
+/// Get the rows of `mapfile` applying to the CPU identified by `cpu`.
+fn _mapfile_entries(mapfile: &str, cpu: &str) -> Vec<MapfileEntry> {
+    mapfile
+        .lines()
+        // Remove comments and empty lines
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('\n'))
+        .filter_map(MapfileEntry::parse)
+        .filter(|e| e.matches(cpu))
+        .collect()
+}
+
+/// Read the events in the JSON file `f` of `source`, using the name of the file as their topic.
+fn _read_json_events(source: EventSource, f: &str) -> Vec<RawEvent> {
+    let s = source.read(f).unwrap_or_else(|_| String::default());
+    let mut j: Vec<RawEvent> = match serde_json::from_str(&s) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Could not parse JSON file -- {:?}", e);
+            vec![]
+        }
+    };
+    j.iter_mut().for_each(|x| {
+        // Add the file name as a topic
+        let fname = std::path::Path::new(&f)
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or("");
+        x.entry(String::from("Topic"))
+            .or_insert_with(|| String::from(&fname[0..fname.len() - 5]));
+    });
+    j
+}
+
+/// Fill in the encoding of `event` if it references an architecture standard event through
+/// `ArchStdEvent`.
+///
+/// Fields of `event` take precedence over those of the standard event in `std_events`.
+fn _resolve_arch_std_event(event: RawEvent, std_events: &[RawEvent]) -> RawEvent {
+    let name = match event.get("ArchStdEvent") {
+        Some(n) => n,
+        None => return event,
+    };
+    let std = std_events
+        .iter()
+        .find(|x| matches!(x.get("EventName"), Some(n) if n.eq_ignore_ascii_case(name)));
+    match std {
+        Some(std) => {
+            let mut resolved = std.clone();
+            resolved.remove("Topic");
+            resolved.extend(event.into_iter().filter(|(k, _)| k != "ArchStdEvent"));
+            resolved
+        }
+        None => {
+            warn!("Could not resolve architecture standard event {}", name);
+            event
+        }
+    }
+}
+
+/// Normalize an event name for fuzzy matching, i.e., ignore case and treat `.`, `-`, `/`, `:` and
+/// whitespace like `_`.
+fn _normalize_name(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c {
+            '.' | '-' | '/' | ':' => '_',
+            c if c.is_whitespace() => '_',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Check if all characters of `query` appear in `name` in order.
+fn _is_subsequence(query: &str, name: &str) -> bool {
+    let mut chars = name.chars();
+    query.chars().all(|q| chars.any(|c| c == q))
+}
+
+/// Rank how well the normalized `name` matches the normalized `query`.
+///
+/// Lower ranks are better matches: an exact match, a prefix, a substring, all `_`-separated words
+/// of `query` in any order, and finally all characters of `query` in order. Returns `None` if
+/// `name` does not match at all.
+fn _match_rank(name: &str, query: &str) -> Option<usize> {
+    if name == query {
+        Some(0)
+    } else if name.starts_with(query) {
+        Some(1)
+    } else if name.contains(query) {
+        Some(2)
+    } else if query
+        .split('_')
+        .filter(|w| !w.is_empty())
+        .all(|w| name.contains(w))
+    {
+        Some(3)
+    } else if _is_subsequence(query, name) {
+        Some(4)
+    } else {
+        None
+    }
 }
 
 impl Pmu {
@@ -41,89 +221,157 @@ impl Pmu {
     }
 
     /// Load CPU-specific PMU information from the specified path.
+    ///
+    /// The version of `perf` is detected once per process, see `PerfVersion::cached`.
     pub fn from_cpu_str(cpu: String, path: String) -> crate::Result<Self> {
-        // Check for global events
-        let mut json_files: Vec<String> = std::fs::read_dir(&path)?
-            .filter_map(Result::ok)
-            .filter_map(|x| match _is_json_file(&x) {
-                Ok((true, f)) => Some(f),
-                _ => None,
-            })
-            .collect();
+        Pmu::from_cpu_str_with_version(cpu, path, PerfVersion::cached())
+    }
 
-        // Check mapfile for paths
-        let mapfile = std::fs::File::open(format!("{}/{}", &path, "mapfile.csv"))?;
-        let mapped_files = BufReader::new(mapfile)
-            .lines()
-            // Remove bad lines
-            .filter_map(Result::ok)
-            // Remove comments and empty lines
-            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('\n'))
-            // Get filename from file
-            .filter_map(|l| {
-                let splits: Vec<&str> = l.split(',').collect();
-                Regex::new(splits[0]).ok().and_then(|ref x| {
-                    if x.is_match(&cpu) {
-                        Some(String::from(splits[2]))
-                    } else {
-                        None
-                    }
-                })
-            })
-            // Check if mapfile entry is a file or a directory... in case of directory read it
-            .flat_map(|f: String| {
-                let full_path = format!("{}/{}", path, f);
-                if std::path::Path::new(&full_path).is_file() {
-                    vec![full_path]
-                } else {
-                    std::fs::read_dir(&full_path)
-                        .map(|x| x.filter_map(Result::ok).collect())
-                        .unwrap_or_else(|_| vec![])
-                        .iter()
-                        .filter_map(|x| match _is_json_file(x) {
-                            Ok((true, x)) => Some(format!("{}/{}", &full_path, x)),
-                            _ => None,
-                        })
-                        .collect()
-                }
-            });
-        json_files.extend(mapped_files);
+    /// Load CPU-specific PMU information from the specified path, generating events for the
+    /// `perf` version `version` instead of detecting it.
+    pub fn from_cpu_str_with_version(
+        cpu: String,
+        path: String,
+        version: &PerfVersion,
+    ) -> crate::Result<Self> {
+        let raw_events = Pmu::_load_raw_events(&cpu, &path)?;
+        Ok(Pmu::_from_raw_events(cpu, raw_events, version))
+    }
 
-        let raw_events: Vec<RawEvent> = json_files
-            .iter()
-            .flat_map(|f| {
-                let s = std::fs::read_to_string(f).unwrap_or_else(|_| String::default());
-                let mut j: Vec<RawEvent> = match serde_json::from_str(&s) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!("Could not parse JSON file -- {:?}", e);
-                        vec![]
-                    }
-                };
-                j.iter_mut().for_each(|x| {
-                    // Add the file name as a topic
-                    let fname = std::path::Path::new(&f)
-                        .file_name()
-                        .and_then(|x| x.to_str())
-                        .unwrap_or("");
-                    x.entry(String::from("Topic"))
-                        .or_insert_with(|| String::from(&fname[0..fname.len() - 5]));
-                });
-                j
-            })
-            .collect();
+    /// Load PMU event information for local CPU from the pmu-events tree embedded into the binary.
+    ///
+    /// Requires the `embed-events` feature.
+    #[cfg(feature = "embed-events")]
+    pub fn from_local_cpu_embedded() -> crate::Result<Self> {
+        let cpu_str = crate::arch::get_cpu_string();
+        Pmu::from_cpu_str_embedded(cpu_str)
+    }
+
+    /// Load CPU-specific PMU information from the pmu-events tree embedded into the binary.
+    ///
+    /// Requires the `embed-events` feature.
+    #[cfg(feature = "embed-events")]
+    pub fn from_cpu_str_embedded(cpu: String) -> crate::Result<Self> {
+        let raw_events = Pmu::_load_raw_events_from(&cpu, EventSource::Embedded)?;
+        Ok(Pmu::_from_raw_events(
+            cpu,
+            raw_events,
+            PerfVersion::cached(),
+        ))
+    }
 
-        // Construct the Pmu
-        let version = PerfVersion::get_details_from_tool()?;
-        Ok(Pmu {
+    /// Construct a `Pmu` by parsing `raw_events`.
+    fn _from_raw_events(cpu: String, raw_events: Vec<RawEvent>, version: &PerfVersion) -> Self {
+        Pmu {
             cpu_str: cpu,
             events: raw_events
                 .iter()
-                .map(|x| PmuEvent::from_raw_event(x, &version))
+                .map(|x| PmuEvent::from_raw_event(x, version))
                 .filter_map(std::result::Result::ok)
                 .collect(),
             raw_events,
-        })
+        }
+    }
+
+    /// Read the JSON descriptions of the events of CPU `cpu` from the directory `path`.
+    fn _load_raw_events(cpu: &str, path: &str) -> crate::Result<Vec<RawEvent>> {
+        Pmu::_load_raw_events_from(cpu, EventSource::Dir(path))
+    }
+
+    /// Read the JSON descriptions of the events of CPU `cpu` from the pmu-events tree `source`.
+    fn _load_raw_events_from(cpu: &str, source: EventSource) -> crate::Result<Vec<RawEvent>> {
+        // Check for architecture standard events shared by all CPUs
+        let std_events: Vec<RawEvent> = source
+            .json_files("")
+            .iter()
+            .flat_map(|f| _read_json_events(source, f))
+            .collect();
+
+        // Check mapfile for paths
+        let mapfile = source.read("mapfile.csv")?;
+        Ok(_mapfile_entries(&mapfile, cpu)
+            .into_iter()
+            .flat_map(|e| {
+                e.json_files(source)
+                    .into_iter()
+                    .map(move |f| (e.event_type, f))
+            })
+            .flat_map(|(ty, f)| {
+                _read_json_events(source, &f).into_iter().filter(move |x| {
+                    // Events listed as uncore must name the PMU counting them
+                    let ok = ty == EventType::Core || x.contains_key("Unit");
+                    if !ok {
+                        warn!("Skipping uncore event without a unit in {}", f);
+                    }
+                    ok
+                })
+            })
+            .map(|x| _resolve_arch_std_event(x, &std_events))
+            .collect())
+    }
+
+    /// Add the event aliases exported by all PMUs through sysfs, e.g., `msr/tsc/`, to the events
+    /// parsed from the JSON files.
+    ///
+    /// Aliases that take parameters (e.g., `event=0x12,umask=?`) are skipped. Returns the number of
+    /// events added.
+    pub fn load_sysfs_events(&mut self) -> crate::Result<usize> {
+        let before = self.events.len();
+        for pmu in crate::perf::sysfs::pmus()? {
+            for (name, terms) in crate::perf::sysfs::pmu_events(&pmu)? {
+                if terms.contains('?') {
+                    continue;
+                }
+                let evt = PmuEvent::from_sysfs(&pmu, &name, &terms);
+                if !self.events.iter().any(|e| e.name == evt.name) {
+                    self.events.push(evt);
+                }
+            }
+        }
+        Ok(self.events.len() - before)
+    }
+
+    /// Add all static tracepoints of the kernel, e.g., `sched:sched_switch`, to the events.
+    ///
+    /// Requires the tracing filesystem to be mounted and readable. Returns the number of events
+    /// added.
+    pub fn load_tracepoint_events(&mut self) -> crate::Result<usize> {
+        let before = self.events.len();
+        for name in crate::perf::tracefs::tracepoints()? {
+            let id = crate::perf::tracefs::tracepoint_id(&name)?;
+            let evt = PmuEvent::from_tracepoint(&name, id);
+            if !self.events.iter().any(|e| e.name == evt.name) {
+                self.events.push(evt);
+            }
+        }
+        Ok(self.events.len() - before)
+    }
+
+    /// Add the events of all dynamic PMUs registered with the kernel, i.e., the sysfs event
+    /// aliases of PMUs such as `power`, `cstate_core` or `msr` (see `load_sysfs_events`) and the
+    /// tracepoints (see `load_tracepoint_events`).
+    ///
+    /// Tracepoints are skipped with a warning if the tracing filesystem is not available.
+    /// Breakpoints are not listed, as they are defined by the address they watch. Returns the
+    /// number of events added.
+    pub fn load_dynamic_events(&mut self) -> crate::Result<usize> {
+        let mut num = self.load_sysfs_events()?;
+        match self.load_tracepoint_events() {
+            Ok(n) => num += n,
+            Err(e) => warn!("Could not load tracepoints: {}", e),
+        }
+        Ok(num)
+    }
+
+    /// Get the events that can be counted on the cores of type `ty` of a hybrid CPU.
+    ///
+    /// Events of the other type of cores are dropped and core events are bound to the PMU of `ty`,
+    /// see `PmuEvent::for_core_type`. Metrics should be resolved against the returned events.
+    pub fn core_type_events(&self, ty: CoreType) -> Vec<PmuEvent> {
+        self.events
+            .iter()
+            .filter_map(|e| e.for_core_type(ty))
+            .collect()
     }
 
     /// Filter all `PmuEvent`s using `predicate`.
@@ -134,6 +382,13 @@ impl Pmu {
         self.events.iter().filter(predicate).collect()
     }
 
+    /// Get all `PmuEvent`s that can be counted on the local machine.
+    ///
+    /// This opens every event for a moment, see `PmuEvent::is_supported`.
+    pub fn supported_events(&self) -> Vec<&PmuEvent> {
+        self.filter_events(|x| x.is_supported(Some(&self.events)))
+    }
+
     /// Search for `PmuEvent`s by name.
     ///
     /// The `name` field of the function serves as a regex.
@@ -141,6 +396,64 @@ impl Pmu {
         let re = Regex::new(name)?;
         Ok(self.filter_events(|x| re.is_match(&x.name)))
     }
+
+    /// Search for `PmuEvent`s whose name is similar to `query`, best matches first.
+    ///
+    /// Matching ignores case and does not distinguish separators, e.g., `inst_retired.any`,
+    /// `INST-RETIRED-ANY` and `inst retired any` are all the same. Partial names, words in a
+    /// different order, and abbreviations (all characters in order, e.g., `instany`) match as
+    /// well but are ranked lower. Among equally good matches shorter names come first.
+    pub fn search(&self, query: &str) -> Vec<&PmuEvent> {
+        let query = _normalize_name(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut found: Vec<(usize, &PmuEvent)> = self
+            .events
+            .iter()
+            .filter_map(|e| _match_rank(&_normalize_name(&e.name), &query).map(|r| (r, e)))
+            .collect();
+        found.sort_by(|(r1, e1), (r2, e2)| {
+            r1.cmp(r2)
+                .then(e1.name.len().cmp(&e2.name.len()))
+                .then(e1.name.cmp(&e2.name))
+        });
+        found.into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// Get the topics of all events, e.g., `pipeline` or `cache`, sorted and without duplicates.
+    pub fn topics(&self) -> Vec<&str> {
+        let mut topics: Vec<&str> = self.events.iter().map(|e| e.topic.as_str()).collect();
+        topics.sort_unstable();
+        topics.dedup();
+        topics
+    }
+
+    /// Get all events grouped by their topic.
+    pub fn events_by_topic(&self) -> BTreeMap<&str, Vec<&PmuEvent>> {
+        let mut groups: BTreeMap<&str, Vec<&PmuEvent>> = BTreeMap::new();
+        for evt in self.events.iter() {
+            groups.entry(evt.topic.as_str()).or_default().push(evt);
+        }
+        groups
+    }
+
+    /// Get all metrics belonging to the metric group `group`, e.g., `TopdownL1`.
+    pub fn find_by_metric_group(&self, group: &str) -> Vec<&PmuEvent> {
+        self.filter_events(|x| x.metric_groups().contains(&group))
+    }
+
+    /// Get the names of all metric groups, sorted and without duplicates.
+    pub fn metric_groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self
+            .events
+            .iter()
+            .flat_map(PmuEvent::metric_groups)
+            .collect();
+        groups.sort_unstable();
+        groups.dedup();
+        groups
+    }
 }
 
 #[cfg(test)]
@@ -154,6 +467,220 @@ mod tests {
         assert!(pmu.is_ok());
     }
 
+    #[test]
+    fn test_mapfile_entry() {
+        let e = MapfileEntry::parse("GenuineIntel-6-55-[01234],v1.20,skylakex,core").unwrap();
+        assert_eq!(e.event_type, EventType::Core);
+        assert_eq!(e.file, "skylakex");
+        assert!(e.matches("GenuineIntel-6-55-4"));
+        assert!(!e.matches("GenuineIntel-6-55-7"));
+
+        let e = MapfileEntry::parse("GenuineIntel-6-3F,v17,haswellx/uncore.json,uncore").unwrap();
+        assert_eq!(e.event_type, EventType::Uncore);
+        assert!(e.matches("GenuineIntel-6-3F-2"));
+        assert!(!e.matches("GenuineIntel-6-3F2-2"));
+        assert!(!e.matches("XGenuineIntel-6-3F-2"));
+
+        let e = MapfileEntry::parse("GenuineIntel-6-3F,v17,haswellx").unwrap();
+        assert_eq!(e.event_type, EventType::Core);
+        assert!(MapfileEntry::parse("hisilicon,v1,hisi,sys").is_none());
+        assert!(MapfileEntry::parse("GenuineIntel-6-3F").is_none());
+    }
+
+    #[test]
+    fn test_pmu_with_version() {
+        let dir = std::env::temp_dir().join(format!("perf-utils-version-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("skylakex")).unwrap();
+        std::fs::write(
+            dir.join("mapfile.csv"),
+            "GenuineIntel-6-55-[01234],v1,skylakex,core\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("skylakex/pipeline.json"),
+            r#"[{"EventName": "INST_RETIRED.ANY_P", "EventCode": "0xC0", "UMask": "0x00"}]"#,
+        )
+        .unwrap();
+
+        let path = dir.to_string_lossy().into_owned();
+        let version = PerfVersion::new(3, 0);
+        let pmu =
+            Pmu::from_cpu_str_with_version("GenuineIntel-6-55-4".into(), path, &version).unwrap();
+        assert_eq!(pmu.events.len(), 1);
+        assert_eq!(pmu.events[0].to_perf_string(&version, None), "rC0");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn raw(fields: &[(&str, &str)]) -> RawEvent {
+        fields
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_arch_std_event() {
+        let std_events = vec![raw(&[
+            ("EventName", "L1D_CACHE_REFILL"),
+            ("EventCode", "0x03"),
+            ("BriefDescription", "Level 1 data cache refill"),
+            ("Topic", "armv8-common-event"),
+        ])];
+        let evt = _resolve_arch_std_event(
+            raw(&[
+                ("ArchStdEvent", "l1d_cache_refill"),
+                ("BriefDescription", "L1D refill"),
+                ("Topic", "cache"),
+            ]),
+            &std_events,
+        );
+        assert_eq!(evt.get("EventName").unwrap(), "L1D_CACHE_REFILL");
+        assert_eq!(evt.get("EventCode").unwrap(), "0x03");
+        assert_eq!(evt.get("BriefDescription").unwrap(), "L1D refill");
+        assert_eq!(evt.get("Topic").unwrap(), "cache");
+        assert!(!evt.contains_key("ArchStdEvent"));
+
+        let evt = _resolve_arch_std_event(raw(&[("ArchStdEvent", "UNKNOWN")]), &std_events);
+        assert!(!evt.contains_key("EventName"));
+    }
+
+    #[test]
+    fn test_metric_groups() {
+        let metric = |name, group| {
+            raw(&[
+                ("MetricName", name),
+                ("MetricExpr", "INST_RETIRED.ANY / CPU_CLK_UNHALTED.THREAD"),
+                ("MetricGroup", group),
+                ("Topic", "metrics"),
+            ])
+        };
+        let pmu = Pmu::_from_raw_events(
+            "GenuineIntel-6-55-4".into(),
+            vec![
+                metric("tma_retiring", "TopdownL1;tma_L1_group"),
+                metric("IPC", "Ret;Summary"),
+                metric("CPI", "Pipeline,Summary"),
+            ],
+            &PerfVersion::new(5, 15),
+        );
+        assert_eq!(
+            pmu.metric_groups(),
+            vec!["Pipeline", "Ret", "Summary", "TopdownL1", "tma_L1_group"]
+        );
+        let names: Vec<&str> = pmu
+            .find_by_metric_group("Summary")
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["IPC", "CPI"]);
+        assert_eq!(pmu.find_by_metric_group("TopdownL1").len(), 1);
+        assert!(pmu.find_by_metric_group("Topdown").is_empty());
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(_normalize_name(" Inst-Retired.ANY "), "inst_retired_any");
+        assert_eq!(_match_rank("inst_retired_any", "inst_retired_any"), Some(0));
+        assert_eq!(
+            _match_rank("inst_retired_any_p", "inst_retired_any"),
+            Some(1)
+        );
+        assert_eq!(_match_rank("br_inst_retired_all", "inst_retired"), Some(2));
+        assert_eq!(_match_rank("inst_retired_any", "any_inst"), Some(3));
+        assert_eq!(_match_rank("inst_retired_any", "instany"), Some(4));
+        assert_eq!(_match_rank("inst_retired_any", "cycles"), None);
+
+        let event = |name| raw(&[("EventName", name), ("EventCode", "0xC0"), ("Topic", "t")]);
+        let pmu = Pmu::_from_raw_events(
+            "GenuineIntel-6-55-4".into(),
+            vec![
+                event("BR_INST_RETIRED.ALL_BRANCHES"),
+                event("INST_RETIRED.ANY_P"),
+                event("INST_RETIRED.ANY"),
+                event("CPU_CLK_UNHALTED.THREAD"),
+            ],
+            &PerfVersion::new(5, 15),
+        );
+        let names =
+            |query| -> Vec<String> { pmu.search(query).iter().map(|e| e.name.clone()).collect() };
+        assert_eq!(
+            names("inst-retired"),
+            vec![
+                "INST_RETIRED.ANY",
+                "INST_RETIRED.ANY_P",
+                "BR_INST_RETIRED.ALL_BRANCHES"
+            ]
+        );
+        assert_eq!(names("Inst_Retired.Any")[0], "INST_RETIRED.ANY");
+        assert_eq!(names("unhalted cpu"), vec!["CPU_CLK_UNHALTED.THREAD"]);
+        assert!(names("").is_empty());
+        assert!(names("l2_rqsts").is_empty());
+    }
+
+    #[test]
+    fn test_topics() {
+        let version = PerfVersion::new(5, 15);
+        let pmu = Pmu::_from_raw_events(
+            "GenuineIntel-6-55-4".into(),
+            vec![
+                raw(&[
+                    ("EventName", "INST_RETIRED.ANY"),
+                    ("EventCode", "0xC0"),
+                    ("Topic", "pipeline"),
+                ]),
+                raw(&[
+                    ("EventName", "L1D.REPLACEMENT"),
+                    ("EventCode", "0x51"),
+                    ("UMask", "0x01"),
+                    ("Topic", "cache"),
+                ]),
+                raw(&[
+                    ("EventName", "BR_INST_RETIRED.ALL_BRANCHES"),
+                    ("EventCode", "0xC4"),
+                    ("Topic", "pipeline"),
+                ]),
+            ],
+            &version,
+        );
+        assert_eq!(pmu.topics(), vec!["cache", "pipeline"]);
+        let groups = pmu.events_by_topic();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["cache"][0].name, "L1D.REPLACEMENT");
+        let names: Vec<&str> = groups["pipeline"].iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["INST_RETIRED.ANY", "BR_INST_RETIRED.ALL_BRANCHES"]
+        );
+    }
+
+    #[test]
+    fn test_load_sysfs_events() {
+        let mut pmu = Pmu::default();
+        let num = pmu.load_sysfs_events().unwrap();
+        assert_eq!(num, pmu.events.len());
+        assert_eq!(pmu.load_sysfs_events().unwrap(), 0);
+        for evt in pmu.events.iter() {
+            assert!(evt.name.ends_with('/'));
+            let attrs = evt.to_perf_event_attr(None).unwrap();
+            assert_eq!(attrs.len(), 1);
+            assert_ne!(attrs[0].type_, 0);
+        }
+    }
+
+    #[test]
+    fn test_load_dynamic_events() {
+        let mut pmu = Pmu::default();
+        let num = pmu.load_dynamic_events().unwrap();
+        assert_eq!(num, pmu.events.len());
+        assert_eq!(pmu.load_dynamic_events().unwrap(), 0);
+        for evt in pmu.events.iter() {
+            let attrs = evt.to_perf_event_attr(None).unwrap();
+            assert_eq!(attrs.len(), 1);
+            assert_ne!(attrs[0].type_, 0);
+        }
+    }
+
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_pmu_query() {
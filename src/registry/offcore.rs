@@ -0,0 +1,248 @@
+//! Composition of `OFFCORE_RESPONSE` events from the request, supplier and snoop bit groups.
+//!
+//! The layout of the response MSR is the one of Sandy Bridge to Broadwell, as in the offcore
+//! matrix of the `perf` tool. See the Intel SDM, volume 3, chapter "Off-core Response Performance
+//! Monitoring", for the exact meaning of the bits on a given microarchitecture.
+
+use crate::registry::PmuEvent;
+
+/// Type of the requests counted by an offcore response event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffcoreRequest {
+    /// Demand data reads.
+    DemandDataRd,
+    /// Demand reads for ownership (RFO).
+    DemandRfo,
+    /// Demand instruction fetches.
+    DemandIfetch,
+    /// Writebacks of modified lines.
+    Writeback,
+    /// Data reads of the L2 prefetchers.
+    PfDataRd,
+    /// RFOs of the L2 prefetchers.
+    PfRfo,
+    /// Instruction fetches of the L2 prefetchers.
+    PfIfetch,
+    /// Data reads of the L2 prefetchers into the LLC only.
+    PfLlcDataRd,
+    /// RFOs of the L2 prefetchers into the LLC only.
+    PfLlcRfo,
+    /// Instruction fetches of the L2 prefetchers into the LLC only.
+    PfLlcIfetch,
+    /// Bus lock and split lock requests.
+    BusLocks,
+    /// Streaming stores.
+    StreamingStores,
+    /// Any other request.
+    Other,
+}
+
+impl OffcoreRequest {
+    /// Get the bits of the response MSR selecting this request type.
+    pub fn bits(self) -> u64 {
+        let bit = match self {
+            OffcoreRequest::DemandDataRd => 0,
+            OffcoreRequest::DemandRfo => 1,
+            OffcoreRequest::DemandIfetch => 2,
+            OffcoreRequest::Writeback => 3,
+            OffcoreRequest::PfDataRd => 4,
+            OffcoreRequest::PfRfo => 5,
+            OffcoreRequest::PfIfetch => 6,
+            OffcoreRequest::PfLlcDataRd => 7,
+            OffcoreRequest::PfLlcRfo => 8,
+            OffcoreRequest::PfLlcIfetch => 9,
+            OffcoreRequest::BusLocks => 10,
+            OffcoreRequest::StreamingStores => 11,
+            OffcoreRequest::Other => 15,
+        };
+        1 << bit
+    }
+}
+
+/// Supplier of the data of the responses counted by an offcore response event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffcoreSupplier {
+    /// Any response, regardless of supplier and snoop.
+    Any,
+    /// No supplier information is available.
+    None,
+    /// Hit a modified line in the LLC.
+    LlcHitM,
+    /// Hit an exclusive line in the LLC.
+    LlcHitE,
+    /// Hit a shared line in the LLC.
+    LlcHitS,
+    /// Hit a forwarding line in the LLC.
+    LlcHitF,
+    /// Missed the LLC and was served by local DRAM.
+    LocalDram,
+    /// Missed the LLC and was served by a remote socket.
+    Remote,
+}
+
+impl OffcoreSupplier {
+    /// Get the bits of the response MSR selecting this supplier.
+    pub fn bits(self) -> u64 {
+        match self {
+            OffcoreSupplier::Any => 1 << 16,
+            OffcoreSupplier::None => 1 << 17,
+            OffcoreSupplier::LlcHitM => 1 << 18,
+            OffcoreSupplier::LlcHitE => 1 << 19,
+            OffcoreSupplier::LlcHitS => 1 << 20,
+            OffcoreSupplier::LlcHitF => 1 << 21,
+            OffcoreSupplier::LocalDram => 1 << 22,
+            OffcoreSupplier::Remote => 0xFF << 23,
+        }
+    }
+}
+
+/// Snoop response of the responses counted by an offcore response event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffcoreSnoop {
+    /// No snoop information is available.
+    None,
+    /// No snoop was needed to satisfy the request.
+    NotNeeded,
+    /// The snoop missed in all other caches.
+    Miss,
+    /// The snoop hit another cache, which did not forward the data.
+    NoForward,
+    /// The snoop hit another cache, which forwarded the data.
+    Forward,
+    /// The snoop hit a modified line in another cache.
+    HitM,
+    /// The target was not DRAM, e.g., memory mapped IO.
+    NonDram,
+}
+
+impl OffcoreSnoop {
+    /// Get the bits of the response MSR selecting this snoop response.
+    pub fn bits(self) -> u64 {
+        let bit = match self {
+            OffcoreSnoop::None => 31,
+            OffcoreSnoop::NotNeeded => 32,
+            OffcoreSnoop::Miss => 33,
+            OffcoreSnoop::NoForward => 34,
+            OffcoreSnoop::Forward => 35,
+            OffcoreSnoop::HitM => 36,
+            OffcoreSnoop::NonDram => 37,
+        };
+        1 << bit
+    }
+}
+
+/// Bits of the response MSR selecting any supplier except `OffcoreSupplier::Any`.
+const ALL_SUPPLIERS: u64 = 0x7FFE_0000;
+
+/// Bits of the response MSR selecting any snoop response.
+const ALL_SNOOPS: u64 = 0x7F << 31;
+
+/// Event code and unit mask of `OFFCORE_RESPONSE_0`, which is programmed through MSR `0x1A6`.
+const OFFCORE_RESPONSE_0: (u64, u64) = (0xB7, 0x01);
+
+/// Composition of an `OFFCORE_RESPONSE` event from the requests, suppliers and snoop responses to
+/// be counted.
+///
+/// A response is counted if its request matches any of `requests` and both its supplier and its
+/// snoop response match any of the selected ones. If only suppliers or only snoop responses are
+/// selected, any value of the other group matches. If neither are selected, any response is
+/// counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffcoreResponse {
+    /// Bits of the selected requests.
+    requests: u64,
+    /// Bits of the selected suppliers.
+    suppliers: u64,
+    /// Bits of the selected snoop responses.
+    snoops: u64,
+}
+
+impl OffcoreResponse {
+    /// Create a composition without any selected request.
+    pub fn new() -> Self {
+        OffcoreResponse::default()
+    }
+
+    /// Count the requests of type `request`.
+    pub fn request(mut self, request: OffcoreRequest) -> Self {
+        self.requests |= request.bits();
+        self
+    }
+
+    /// Count responses supplied by `supplier`.
+    pub fn supplier(mut self, supplier: OffcoreSupplier) -> Self {
+        self.suppliers |= supplier.bits();
+        self
+    }
+
+    /// Count responses with the snoop response `snoop`.
+    pub fn snoop(mut self, snoop: OffcoreSnoop) -> Self {
+        self.snoops |= snoop.bits();
+        self
+    }
+
+    /// Get the value to be programmed into the response MSR.
+    pub fn msr_value(&self) -> u64 {
+        let response = match (self.suppliers, self.snoops) {
+            (0, 0) => OffcoreSupplier::Any.bits(),
+            (0, snoops) => ALL_SUPPLIERS | snoops,
+            (suppliers, 0) if suppliers & OffcoreSupplier::Any.bits() != 0 => suppliers,
+            (suppliers, 0) => suppliers | ALL_SNOOPS,
+            (suppliers, snoops) => suppliers | snoops,
+        };
+        self.requests | response
+    }
+
+    /// Create the `OFFCORE_RESPONSE` event named `name` counting the selected responses.
+    pub fn to_event<S: Into<String>>(&self, name: S) -> PmuEvent {
+        let (event, umask) = OFFCORE_RESPONSE_0;
+        PmuEvent::builder(name, event)
+            .umask(umask)
+            .topic("cache")
+            .offcore_rsp(self.msr_value())
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offcore_response() {
+        let rsp = OffcoreResponse::new()
+            .request(OffcoreRequest::DemandDataRd)
+            .request(OffcoreRequest::PfDataRd);
+        assert_eq!(rsp.msr_value(), 0x1_0011);
+
+        // OFFCORE_RESPONSE.DEMAND_DATA_RD.LLC_MISS.LOCAL_DRAM of Sandy Bridge
+        let rsp = OffcoreResponse::new()
+            .request(OffcoreRequest::DemandDataRd)
+            .supplier(OffcoreSupplier::LocalDram)
+            .snoop(OffcoreSnoop::None)
+            .snoop(OffcoreSnoop::NotNeeded)
+            .snoop(OffcoreSnoop::Miss)
+            .snoop(OffcoreSnoop::NoForward)
+            .snoop(OffcoreSnoop::Forward)
+            .snoop(OffcoreSnoop::HitM)
+            .snoop(OffcoreSnoop::NonDram);
+        assert_eq!(rsp.msr_value(), 0x3F_8040_0001);
+        let same = OffcoreResponse::new()
+            .request(OffcoreRequest::DemandDataRd)
+            .supplier(OffcoreSupplier::LocalDram);
+        assert_eq!(same.msr_value(), rsp.msr_value());
+
+        let hitm = OffcoreResponse::new()
+            .request(OffcoreRequest::DemandRfo)
+            .snoop(OffcoreSnoop::HitM);
+        assert_eq!(hitm.msr_value(), 0x10_7FFE_0002);
+
+        if cfg!(target_arch = "x86_64") {
+            let evt = rsp.to_event("OFFCORE_RESPONSE.DEMAND_DATA_RD.LOCAL_DRAM");
+            assert_eq!(evt.topic, "cache");
+            let attrs = evt.to_perf_event_attr(None).unwrap();
+            assert_eq!(attrs[0].config, 0x01B7);
+            assert_eq!(unsafe { attrs[0].__bindgen_anon_3.config1 }, 0x3F_8040_0001);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Access to the files of a pmu-events tree, either on disk or embedded into the binary.
+
+#[cfg(feature = "embed-events")]
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/embedded_events.rs"));
+}
+
+/// Location of a pmu-events tree, i.e., a directory holding `mapfile.csv` and JSON event files.
+///
+/// Paths of files in the tree are relative to its root and separated by `/`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum EventSource<'a> {
+    /// Tree stored in a directory.
+    Dir(&'a str),
+    /// Tree embedded into the binary at build time.
+    #[cfg(feature = "embed-events")]
+    Embedded,
+}
+
+impl<'a> EventSource<'a> {
+    /// Read the contents of the file `file`.
+    pub fn read(&self, file: &str) -> crate::Result<String> {
+        match *self {
+            EventSource::Dir(path) => Ok(std::fs::read_to_string(format!("{}/{}", path, file))?),
+            #[cfg(feature = "embed-events")]
+            EventSource::Embedded => embedded::EMBEDDED_EVENTS
+                .iter()
+                .find(|(f, _)| *f == file)
+                .map(|(_, c)| String::from(*c))
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into()),
+        }
+    }
+
+    /// Get the JSON files named by `file`.
+    ///
+    /// If `file` is a directory, this returns the JSON files directly inside it. An empty `file`
+    /// refers to the root of the tree.
+    pub fn json_files(&self, file: &str) -> Vec<String> {
+        let prefix = if file.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", file)
+        };
+        let mut files: Vec<String> = match *self {
+            EventSource::Dir(path) => {
+                let full_path = format!("{}/{}", path, file);
+                if std::path::Path::new(&full_path).is_file() {
+                    return vec![file.into()];
+                }
+                std::fs::read_dir(&full_path)
+                    .map(|x| x.filter_map(Result::ok).collect())
+                    .unwrap_or_else(|_| vec![])
+                    .iter()
+                    .filter(|x| x.metadata().map(|m| m.is_file()).unwrap_or(false))
+                    .filter_map(|x| x.file_name().into_string().ok())
+                    .map(|x| format!("{}{}", prefix, x))
+                    .collect()
+            }
+            #[cfg(feature = "embed-events")]
+            EventSource::Embedded => {
+                if embedded::EMBEDDED_EVENTS.iter().any(|(f, _)| *f == file) {
+                    return vec![file.into()];
+                }
+                embedded::EMBEDDED_EVENTS
+                    .iter()
+                    .filter_map(|(f, _)| f.strip_prefix(prefix.as_str()))
+                    .filter(|f| !f.contains('/'))
+                    .map(|f| format!("{}{}", prefix, f))
+                    .collect()
+            }
+        };
+        files.retain(|f| f.ends_with(".json"));
+        files.sort();
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_source_dir() {
+        let dir = std::env::temp_dir().join(format!("perf-utils-source-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("cpu")).unwrap();
+        std::fs::write(dir.join("mapfile.csv"), "GenuineIntel-6-55,v1,cpu,core\n").unwrap();
+        std::fs::write(dir.join("std.json"), "[]").unwrap();
+        std::fs::write(dir.join("cpu/cache.json"), "[]").unwrap();
+        std::fs::write(dir.join("cpu/README"), "").unwrap();
+
+        let path = dir.to_string_lossy();
+        let source = EventSource::Dir(&path);
+        assert!(source
+            .read("mapfile.csv")
+            .unwrap()
+            .starts_with("GenuineIntel"));
+        assert!(source.read("missing.csv").is_err());
+        assert_eq!(source.json_files(""), vec!["std.json"]);
+        assert_eq!(source.json_files("cpu"), vec!["cpu/cache.json"]);
+        assert_eq!(source.json_files("cpu/cache.json"), vec!["cpu/cache.json"]);
+        assert!(source.json_files("missing").is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "embed-events")]
+    #[test]
+    fn test_event_source_embedded() {
+        let source = EventSource::Embedded;
+        assert!(source.read("mapfile.csv").is_ok());
+        assert!(source.json_files("").iter().all(|f| !f.contains('/')));
+    }
+}
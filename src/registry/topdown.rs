@@ -0,0 +1,392 @@
+//! Top-down microarchitecture analysis of Intel CPUs.
+//!
+//! The top-down method attributes each issue slot of the CPU to one of four categories at its
+//! first level: slots that were not filled by the frontend, slots wasted on mis-speculated uops,
+//! slots retiring uops, and slots stalled in the backend. Deeper levels break each category down
+//! further, e.g., backend bound slots into memory and core bound slots.
+
+use crate::perf::sysfs;
+use crate::registry::events::_count_events;
+use crate::registry::{CoreType, MetricDependencies, Pmu, PmuEvent};
+use crate::{Error, Result};
+use nix::libc;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Events of the `PERF_METRICS` MSR of Icelake and later CPUs, led by the slots counter.
+const PERF_METRICS_EVENTS: [&str; 5] = [
+    "TOPDOWN.SLOTS",
+    "PERF_METRICS.FRONTEND_BOUND",
+    "PERF_METRICS.BAD_SPECULATION",
+    "PERF_METRICS.RETIRING",
+    "PERF_METRICS.BACKEND_BOUND",
+];
+
+/// Events used to derive level 1 on CPUs without `PERF_METRICS`.
+const CLASSIC_EVENTS: [&str; 5] = [
+    "CPU_CLK_UNHALTED.THREAD",
+    "IDQ_UOPS_NOT_DELIVERED.CORE",
+    "UOPS_ISSUED.ANY",
+    "UOPS_RETIRED.RETIRE_SLOTS",
+    "INT_MISC.RECOVERY_CYCLES",
+];
+
+/// Issue slots per cycle of the cores without `PERF_METRICS`.
+const CLASSIC_SLOTS_PER_CYCLE: f64 = 4.0;
+
+/// First level of the top-down hierarchy.
+///
+/// Each field is the fraction of all issue slots attributed to the category, so the fields add
+/// up to `1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level1 {
+    /// Slots that were not filled because the frontend did not deliver uops.
+    pub frontend_bound: f64,
+    /// Slots wasted on uops that were not retired, e.g., due to branch mispredictions.
+    pub bad_speculation: f64,
+    /// Slots retiring uops.
+    pub retiring: f64,
+    /// Slots that were not filled because the backend could not accept uops.
+    pub backend_bound: f64,
+}
+
+impl Level1 {
+    /// Compute level 1 from the `values` of `PERF_METRICS_EVENTS`.
+    ///
+    /// The kernel reports each metric scaled to the number of slots.
+    fn _from_perf_metrics(values: &HashMap<String, f64>) -> Result<Self> {
+        let v = _values(values, &PERF_METRICS_EVENTS)?;
+        let slots = _slots(v[0])?;
+        Ok(Level1 {
+            frontend_bound: v[1] / slots,
+            bad_speculation: v[2] / slots,
+            retiring: v[3] / slots,
+            backend_bound: v[4] / slots,
+        })
+    }
+
+    /// Compute level 1 from the `values` of `CLASSIC_EVENTS`.
+    ///
+    /// The backend is assumed to be responsible for all slots not attributed to other
+    /// categories.
+    fn _from_classic(values: &HashMap<String, f64>) -> Result<Self> {
+        let v = _values(values, &CLASSIC_EVENTS)?;
+        let slots = _slots(CLASSIC_SLOTS_PER_CYCLE * v[0])?;
+        let frontend_bound = v[1] / slots;
+        let bad_speculation = ((v[2] - v[3] + CLASSIC_SLOTS_PER_CYCLE * v[4]) / slots).max(0.0);
+        let retiring = v[3] / slots;
+        Ok(Level1 {
+            frontend_bound,
+            bad_speculation,
+            retiring,
+            backend_bound: (1.0 - frontend_bound - bad_speculation - retiring).max(0.0),
+        })
+    }
+}
+
+/// Get the values of the events `names` in order.
+fn _values(values: &HashMap<String, f64>, names: &[&str]) -> Result<Vec<f64>> {
+    names
+        .iter()
+        .map(|&n| {
+            values
+                .get(n)
+                .copied()
+                .ok_or_else(|| Error::InvalidEvent(format!("No value for event {}", n)))
+        })
+        .collect()
+}
+
+/// Check that any `slots` were counted.
+fn _slots(slots: f64) -> Result<f64> {
+    if slots > 0.0 {
+        Ok(slots)
+    } else {
+        Err(Error::InvalidEvent("No issue slots were counted".into()))
+    }
+}
+
+/// Find the events `names` in `events` of the CPU `cpu_str`.
+fn _find<'a>(events: &'a [PmuEvent], cpu_str: &str, names: &[&str]) -> Result<Vec<&'a PmuEvent>> {
+    names
+        .iter()
+        .map(|&n| {
+            events
+                .iter()
+                .find(|e| e.name == n)
+                .ok_or_else(|| Error::InvalidEvent(format!("{} not found for {}", n, cpu_str)))
+        })
+        .collect()
+}
+
+/// Get the events of `pmu` used to measure level 1.
+///
+/// The types of cores of a hybrid CPU differ in their number of issue slots per cycle, and only
+/// the performance cores have `PERF_METRICS`. So on a `hybrid` CPU, only the performance cores
+/// are measured, with all events bound to their PMU to be counted as one group.
+fn _level1_events(pmu: &Pmu, hybrid: bool) -> Cow<'_, [PmuEvent]> {
+    if hybrid {
+        Cow::Owned(pmu.core_type_events(CoreType::Performance))
+    } else {
+        Cow::Borrowed(&pmu.events)
+    }
+}
+
+/// Measure the first level of the top-down hierarchy for `duration`.
+///
+/// Uses the `PERF_METRICS` events of `pmu` on Icelake and later CPUs, and derives the fractions
+/// from the classic set of four events (plus cycles) otherwise. The events are counted as in
+/// `MetricEvent::collect`, i.e., on the process `target`, or on all CPUs if `target` is `-1`.
+/// Only the performance cores of a hybrid CPU are measured.
+pub fn level1(pmu: &Pmu, target: libc::pid_t, duration: Duration) -> Result<Level1> {
+    let hybrid = !sysfs::hybrid_pmus().unwrap_or_default().is_empty();
+    let events = _level1_events(pmu, hybrid);
+    match _find(&events, &pmu.cpu_str, &PERF_METRICS_EVENTS) {
+        Ok(evts) => Level1::_from_perf_metrics(&_count_events("topdown", &evts, target, duration)?),
+        Err(_) => {
+            let evts = _find(&events, &pmu.cpu_str, &CLASSIC_EVENTS)?;
+            Level1::_from_classic(&_count_events("topdown", &evts, target, duration)?)
+        }
+    }
+}
+
+/// Node of the top-down hierarchy.
+#[derive(Debug, Clone)]
+pub struct Node<'a> {
+    /// Metric calculating the fraction of slots attributed to this node.
+    pub metric: &'a PmuEvent,
+    /// Level of the node in the hierarchy, starting at `1`.
+    pub level: usize,
+    /// Nodes breaking down this node at the next level.
+    pub children: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    /// Create the node of `metric` at `level` with all nodes below it in `pmu`.
+    ///
+    /// `ancestors` holds the names of the metrics above this node.
+    fn _new(
+        pmu: &'a Pmu,
+        metric: &'a PmuEvent,
+        level: usize,
+        ancestors: &mut Vec<&'a str>,
+    ) -> Self {
+        ancestors.push(&metric.name);
+        let mut children = Vec::new();
+        for m in pmu.find_by_metric_group(&format!("{}_group", metric.name)) {
+            if m.is_metric && !ancestors.contains(&m.name.as_str()) {
+                children.push(Node::_new(pmu, m, level + 1, ancestors));
+            }
+        }
+        ancestors.pop();
+        Node {
+            metric,
+            level,
+            children,
+        }
+    }
+
+    /// Get the node of the metric `name` in the subtree of this node.
+    pub fn find(&self, name: &str) -> Option<&Node<'a>> {
+        if self.metric.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| c.find(name))
+    }
+}
+
+/// Hierarchy of the top-down metrics of a CPU.
+///
+/// The hierarchy is built from the metric groups of the metrics, following the convention of the
+/// JSON files of the kernel: the metrics at level 1 belong to the group `TopdownL1`, and the
+/// metrics breaking down a metric `tma_x` belong to the group `tma_x_group`.
+#[derive(Debug, Clone)]
+pub struct Hierarchy<'a> {
+    /// PMU holding the metrics and the events they are derived from.
+    pmu: &'a Pmu,
+    /// Nodes at level 1.
+    roots: Vec<Node<'a>>,
+}
+
+/// Values of the metrics of sibling nodes measured at one level of a drill-down.
+#[derive(Debug, Clone)]
+pub struct DrillDownLevel {
+    /// Level of the nodes.
+    pub level: usize,
+    /// Names of the metrics and their values.
+    pub values: Vec<(String, f64)>,
+}
+
+impl DrillDownLevel {
+    /// Get the metric with the largest value, which is expanded at the next level.
+    pub fn dominant(&self) -> Option<&(String, f64)> {
+        self.values.iter().fold(None, |max, v| match max {
+            Some(m) if m.1 >= v.1 => Some(m),
+            _ => Some(v),
+        })
+    }
+}
+
+impl<'a> Hierarchy<'a> {
+    /// Build the hierarchy of the top-down metrics in `pmu`.
+    ///
+    /// The hierarchy is empty if `pmu` does not have any top-down metrics.
+    pub fn from_pmu(pmu: &'a Pmu) -> Self {
+        let roots = pmu
+            .find_by_metric_group("TopdownL1")
+            .into_iter()
+            .filter(|m| m.is_metric)
+            .map(|m| Node::_new(pmu, m, 1, &mut Vec::new()))
+            .collect();
+        Hierarchy { pmu, roots }
+    }
+
+    /// Get the nodes at level 1.
+    pub fn roots(&self) -> &[Node<'a>] {
+        &self.roots
+    }
+
+    /// Get the node of the metric `name`.
+    pub fn find(&self, name: &str) -> Option<&Node<'a>> {
+        self.roots.iter().find_map(|r| r.find(name))
+    }
+
+    /// Drill down the hierarchy, expanding only the dominant node at each level.
+    ///
+    /// Starting at level 1, the metrics of all siblings are counted together for `duration` as
+    /// in `MetricEvent::collect`, and the node with the largest value is expanded at the next
+    /// level. This needs far fewer counters than measuring the whole hierarchy at once. Stops
+    /// after `max_level` levels or at a leaf of the hierarchy.
+    pub fn drill_down(
+        &self,
+        target: libc::pid_t,
+        duration: Duration,
+        max_level: usize,
+    ) -> Result<Vec<DrillDownLevel>> {
+        let mut levels = Vec::new();
+        let mut nodes = &self.roots;
+        while !nodes.is_empty() && levels.len() < max_level {
+            let metrics: Vec<&PmuEvent> = nodes.iter().map(|n| n.metric).collect();
+            let values =
+                MetricDependencies::resolve(self.pmu, &metrics)?.collect(target, duration)?;
+            let level = DrillDownLevel {
+                level: levels.len() + 1,
+                values: metrics.iter().map(|m| m.name.clone()).zip(values).collect(),
+            };
+            let dominant = level.dominant().map(|(name, _)| name.clone());
+            levels.push(level);
+            nodes = match dominant.and_then(|d| nodes.iter().find(|n| n.metric.name == d)) {
+                Some(n) => &n.children,
+                None => break,
+            };
+        }
+        Ok(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::PerfVersion;
+    use crate::registry::RawEvent;
+
+    fn values(names: &[&str], vals: &[f64]) -> HashMap<String, f64> {
+        names
+            .iter()
+            .map(|n| n.to_string())
+            .zip(vals.iter().copied())
+            .collect()
+    }
+
+    fn metric(name: &str, expr: &str, group: &str) -> PmuEvent {
+        let mut raw = RawEvent::new();
+        raw.insert("MetricName".into(), name.into());
+        raw.insert("MetricExpr".into(), expr.into());
+        raw.insert("MetricGroup".into(), group.into());
+        raw.insert("Topic".into(), "metrics".into());
+        PmuEvent::from_raw_event(&raw, &PerfVersion::new(5, 15)).unwrap()
+    }
+
+    #[test]
+    fn test_topdown_hierarchy() {
+        let mut pmu = Pmu::default();
+        pmu.events
+            .push(PmuEvent::from_sysfs("software", "TASK_CLOCK", "config=1"));
+        pmu.events[0].name = "TASK_CLOCK".into();
+        let metrics = [
+            ("tma_a", "TASK_CLOCK / TASK_CLOCK", "TopdownL1;tma_L1_group"),
+            ("tma_b", "0 * TASK_CLOCK", "TopdownL1;tma_L1_group"),
+            ("tma_a1", "0 * TASK_CLOCK + 0.3", "TopdownL2;tma_a_group"),
+            ("tma_a2", "tma_a - tma_a1", "TopdownL2;tma_a_group"),
+            ("tma_b1", "tma_b", "TopdownL2;tma_b_group"),
+            ("tma_a2x", "tma_a2 / 2", "TopdownL3;tma_a2_group"),
+        ];
+        for (name, expr, group) in metrics.iter() {
+            pmu.events.push(metric(name, expr, group));
+        }
+
+        let h = Hierarchy::from_pmu(&pmu);
+        assert_eq!(h.roots().len(), 2);
+        assert_eq!(h.roots()[0].children.len(), 2);
+        let node = h.find("tma_a2x").unwrap();
+        assert_eq!(node.level, 3);
+        assert!(node.children.is_empty());
+        assert!(Hierarchy::from_pmu(&Pmu::default()).roots().is_empty());
+
+        let levels = h.drill_down(0, Duration::from_millis(1), 2).unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[0].dominant().unwrap(), &("tma_a".to_string(), 1.0));
+        assert_eq!(levels[1].level, 2);
+        assert_eq!(levels[1].dominant().unwrap().0, "tma_a2");
+        assert_eq!(
+            h.drill_down(0, Duration::from_millis(1), 5).unwrap().len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_topdown_level1() {
+        let l1 = Level1::_from_perf_metrics(&values(
+            &PERF_METRICS_EVENTS,
+            &[1000.0, 200.0, 100.0, 400.0, 300.0],
+        ))
+        .unwrap();
+        assert_eq!(
+            l1,
+            Level1 {
+                frontend_bound: 0.2,
+                bad_speculation: 0.1,
+                retiring: 0.4,
+                backend_bound: 0.3,
+            }
+        );
+
+        let l1 = Level1::_from_classic(&values(
+            &CLASSIC_EVENTS,
+            &[250.0, 200.0, 450.0, 400.0, 12.5],
+        ))
+        .unwrap();
+        assert_eq!(l1.frontend_bound, 0.2);
+        assert_eq!(l1.bad_speculation, 0.1);
+        assert_eq!(l1.retiring, 0.4);
+        assert!((l1.backend_bound - 0.3).abs() < 1e-9);
+
+        assert!(Level1::_from_classic(&values(&CLASSIC_EVENTS, &[0.0; 5])).is_err());
+        assert!(level1(&Pmu::default(), 0, Duration::from_millis(1)).is_err());
+    }
+
+    #[test]
+    fn test_topdown_level1_hybrid() {
+        let mut pmu = Pmu::default();
+        let cycles = PmuEvent::builder(CLASSIC_EVENTS[0], 0x3c).build();
+        pmu.events
+            .push(cycles.for_core_type(CoreType::Efficiency).unwrap());
+        pmu.events.push(cycles);
+        assert_eq!(_level1_events(&pmu, false).len(), 2);
+        let events = _level1_events(&pmu, true);
+        let found = _find(&events, &pmu.cpu_str, &CLASSIC_EVENTS[..1]).unwrap();
+        assert_eq!(found[0].core_type(), Some(CoreType::Performance));
+        assert!(_find(&events, &pmu.cpu_str, &CLASSIC_EVENTS).is_err());
+    }
+}
@@ -17,6 +17,152 @@ pub fn hexdump(buf: &[u8]) -> String {
     lines.join("\n")
 }
 
+/// Parse a list of CPUs in the kernel's `cpulist` format, e.g., `0-3,5,7-8`.
+pub fn parse_cpu_list(list: &str) -> crate::Result<Vec<i32>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let mut bounds = range.splitn(2, '-');
+        let start: i32 = bounds.next().unwrap().parse()?;
+        let end: i32 = match bounds.next() {
+            Some(end) => end.parse()?,
+            None => start,
+        };
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+/// Get the list of online CPUs.
+pub fn online_cpus() -> crate::Result<Vec<i32>> {
+    parse_cpu_list(&std::fs::read_to_string("/sys/devices/system/cpu/online")?)
+}
+
+/// Read the topology attribute `name` of CPU `cpu`, e.g., `physical_package_id`.
+fn _topology(cpu: i32, name: &str) -> crate::Result<String> {
+    Ok(std::fs::read_to_string(format!(
+        "/sys/devices/system/cpu/cpu{}/topology/{}",
+        cpu, name
+    ))?
+    .trim()
+    .into())
+}
+
+/// Get the location of each online CPU as `(cpu, package, die, core)` from `cpuid`.
+///
+/// Used if the topology is not reported in sysfs, e.g., in some containers.
+fn _cpuid_topology() -> Option<Vec<(i32, i32, i32, i32)>> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let topology = crate::arch::Topology::detect().ok()?;
+        Some(
+            topology
+                .cpus()
+                .iter()
+                .map(|c| (c.cpu as i32, c.package as i32, c.die as i32, c.core as i32))
+                .collect(),
+        )
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        None
+    }
+}
+
+/// Get the physical package (socket) of each online CPU as `(cpu, package)`.
+pub fn cpu_packages() -> crate::Result<Vec<(i32, i32)>> {
+    let sysfs = online_cpus()?
+        .into_iter()
+        .map(|cpu| Ok((cpu, _topology(cpu, "physical_package_id")?.parse()?)))
+        .collect::<crate::Result<Vec<_>>>();
+    sysfs.or_else(|e| {
+        let topology = _cpuid_topology().ok_or(e)?;
+        Ok(topology.into_iter().map(|(c, p, _, _)| (c, p)).collect())
+    })
+}
+
+/// Get the first online CPU of each physical package (socket) as `(package, cpu)`, sorted by
+/// package.
+pub fn package_cpus() -> crate::Result<Vec<(i32, i32)>> {
+    let mut packages: Vec<(i32, i32)> = Vec::new();
+    for (cpu, id) in cpu_packages()? {
+        if !packages.iter().any(|&(p, _)| p == id) {
+            packages.push((id, cpu));
+        }
+    }
+    packages.sort_unstable();
+    Ok(packages)
+}
+
+/// Get the core of each online CPU as `(package, die, core)`.
+///
+/// Core IDs are only unique within a die of a package.
+fn _cpu_cores() -> crate::Result<Vec<(i32, i32, i32)>> {
+    let sysfs = online_cpus()?
+        .into_iter()
+        .map(|cpu| {
+            Ok((
+                _topology(cpu, "physical_package_id")?.parse()?,
+                _topology(cpu, "die_id")
+                    .unwrap_or_default()
+                    .parse()
+                    .unwrap_or(0),
+                _topology(cpu, "core_id")?.parse()?,
+            ))
+        })
+        .collect::<crate::Result<Vec<_>>>();
+    sysfs.or_else(|e| {
+        let topology = _cpuid_topology().ok_or(e)?;
+        Ok(topology.into_iter().map(|(_, p, d, c)| (p, d, c)).collect())
+    })
+}
+
+/// Get the number of physical cores with at least one online CPU.
+pub fn num_cores() -> crate::Result<usize> {
+    let mut cores = _cpu_cores()?;
+    cores.sort_unstable();
+    cores.dedup();
+    Ok(cores.len())
+}
+
+/// Check if simultaneous multithreading (hyper-threading) is active, i.e., some core runs more
+/// than one online CPU.
+pub fn smt_active() -> crate::Result<bool> {
+    match std::fs::read_to_string("/sys/devices/system/cpu/smt/active") {
+        Ok(active) => Ok(active.trim() == "1"),
+        Err(_) => {
+            let mut cores = _cpu_cores()?;
+            let cpus = cores.len();
+            cores.sort_unstable();
+            cores.dedup();
+            Ok(cores.len() < cpus)
+        }
+    }
+}
+
+/// Get the IDs of all threads of the process `pid`.
+pub fn process_threads(pid: i32) -> crate::Result<Vec<i32>> {
+    let mut tids: Vec<i32> = std::fs::read_dir(format!("/proc/{}/task", pid))?
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    tids.sort_unstable();
+    Ok(tids)
+}
+
+/// Check if the process `pid` has exited, i.e., it does not exist or is a zombie.
+pub fn process_exited(pid: i32) -> crate::Result<bool> {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // The state follows the command name, which may itself contain parentheses
+        Ok(stat) => Ok(matches!(
+            stat.rsplit(')')
+                .next()
+                .and_then(|s| s.trim_start().chars().next()),
+            Some('Z') | Some('X')
+        )),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e.into()),
+    }
+}
+
 /// Wrappers around rust's volatile reads and writes to memory.
 macro_rules! volatile {
     ($id: expr) => {
@@ -26,3 +172,53 @@ macro_rules! volatile {
         unsafe { ::std::ptr::write_volatile(&mut $id, $val) };
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0\n").unwrap(), vec![0]);
+        assert_eq!(
+            parse_cpu_list("0-3,5,7-8").unwrap(),
+            vec![0, 1, 2, 3, 5, 7, 8]
+        );
+        assert!(parse_cpu_list("0-a").is_err());
+    }
+
+    #[test]
+    fn test_cpu_topology() {
+        let cores = num_cores().unwrap();
+        assert!(cores >= package_cpus().unwrap().len());
+        assert!(cores <= online_cpus().unwrap().len());
+        if smt_active().unwrap() {
+            assert!(cores < online_cpus().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_package_cpus() {
+        let cpus = cpu_packages().unwrap();
+        assert_eq!(cpus.len(), online_cpus().unwrap().len());
+        if let Some(topology) = _cpuid_topology() {
+            let packages: Vec<(i32, i32)> = topology.iter().map(|&(c, p, _, _)| (c, p)).collect();
+            assert_eq!(packages, cpus);
+        }
+        let packages = package_cpus().unwrap();
+        assert!(!packages.is_empty());
+        let online = online_cpus().unwrap();
+        assert!(packages.iter().all(|(_, cpu)| online.contains(cpu)));
+        assert!(packages.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_process_threads() {
+        let pid = std::process::id() as i32;
+        let tids = process_threads(pid).unwrap();
+        assert!(tids.contains(&pid));
+        assert!(process_threads(-1).is_err());
+        assert!(!process_exited(pid).unwrap());
+        assert!(process_exited(-1).unwrap());
+    }
+}